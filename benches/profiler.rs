@@ -72,6 +72,13 @@ fn criterion_benchmark(c: &mut Criterion) {
         engine_config: EngineConfig {
             cache_enabled: false,
             compaction_enabled: true,
+            parse_tree_enabled: false,
+            cache_config: kbnf::engine::CacheConfig {
+                max_entries: None,
+                max_bytes: None,
+                eviction: kbnf::engine::CacheEviction::Lru,
+            },
+            worker_threads: 1,
         },
         ..Default::default()
     };
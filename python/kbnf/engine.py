@@ -4,8 +4,9 @@ import typing
 import importlib
 import sys
 _torch_fast_mask_enabled = sys.maxsize.bit_length() == 63
-from .kbnf import InternalEngine, AcceptTokenResult, Vocabulary,Config
+from .kbnf import InternalEngine, InternalEngineBatch, AcceptTokenResult, Vocabulary,Config
 _slice_converters = []
+_batch_slice_converters = []
 _fast_mask_logits = []
 
 def _try_register_slice_converter(module_name:str,
@@ -18,6 +19,16 @@ def _try_register_slice_converter(module_name:str,
     except ImportError:
         pass
 
+def _try_register_batch_slice_converter(module_name:str,
+                        obtain_converter:typing.Callable[[types.ModuleType],
+                                                        typing.Callable[[typing.Any],
+                                                                        typing.Optional[typing.Tuple[typing.Any,int,int]]]]):
+    try:
+        module = importlib.import_module(module_name)
+        _batch_slice_converters.append(obtain_converter(module))
+    except ImportError:
+        pass
+
 def _try_register_fast_mask_logits(module_name:str,
                         fast_mask_logits:typing.Callable[[types.ModuleType],
                                                         typing.Callable[[typing.Any, "Engine"],
@@ -88,6 +99,31 @@ def _numpy_slice_converter(module:types.ModuleType):
         return None
     return convert_slice
 
+def _torch_batch_slice_converter(module:types.ModuleType):
+    def convert_slice(tensor:typing.Any)->typing.Optional[typing.Tuple[typing.Any,int,int]]:
+        if isinstance(tensor, module.Tensor):
+            assert tensor.dim() == 2,\
+            f"Only tensors with shape (batch,n) are supported, while the actual tensor shape is {tensor.shape}"
+            tensor = tensor.to(device="cpu",dtype=module.float32,memory_format=module.contiguous_format)
+            ptr = tensor.data_ptr()
+            assert ptr % 4 == 0, f"The tensor data pointer which points to {ptr} is not aligned to 4 bytes"
+            return tensor, ptr, tensor.numel()
+        return None
+    return convert_slice
+
+def _numpy_batch_slice_converter(module:types.ModuleType):
+    def convert_slice(array:typing.Any)->typing.Optional[typing.Tuple[typing.Any,int,int]]:
+        if isinstance(array, module.ndarray):
+            assert array.ndim == 2,\
+            f"Only array with shape (batch,n) are supported, while the actual array shape is {array.shape}"
+            if (array.dtype != module.float32 or not array.flags["CARRAY"]):
+                array = array.astype(module.float32, order="C")
+            ptr = array.ctypes.data
+            assert ptr % 4 == 0, f"The tensor data pointer which points to {ptr} is not aligned to 4 bytes"
+            return array, ptr, array.size
+        return None
+    return convert_slice
+
 def _convert_logits_to_slice(logits:typing.Any)->typing.Tuple[typing.Any,int,int]:
     for converter in _slice_converters:
         converted = converter(logits)
@@ -95,6 +131,13 @@ def _convert_logits_to_slice(logits:typing.Any)->typing.Tuple[typing.Any,int,int
             return converted
     raise TypeError(f"Unsupported type of logits: {type(logits)}")
 
+def _convert_batch_logits_to_slice(logits:typing.Any)->typing.Tuple[typing.Any,int,int]:
+    for converter in _batch_slice_converters:
+        converted = converter(logits)
+        if converted is not None:
+            return converted
+    raise TypeError(f"Unsupported type of logits: {type(logits)}")
+
 def _mask_logits_fast(logits:typing.Any,engine:"Engine")->typing.Optional[typing.Any]:
     for masker in _fast_mask_logits:
         masked = masker(logits, engine)
@@ -180,7 +223,45 @@ This method may raise the following exceptions:
     def __copy__(self):
         return super().__copy__()
 
+class EngineBatch(InternalEngineBatch):
+    def __init__(self, engines, worker_threads=1): # signature is only needed for python runtime type checking
+        super().__init__()
+
+    def mask_logits(self, logits):
+        """
+Masks the logits of the whole batch based on the last computed token IDs of each engine.
+The i-th row of the logits is masked by the i-th engine.
+
+# Arguments
+
+* `logits`: The logits to be masked. `numpy.ndarray` is supported by default.
+`torch.Tensor` is supported if PyTorch is installed. The shape of the logits should be `(batch, n)`,
+where `n` is at least the vocabulary size.
+The logits will be updated in-place if:
+    * The logits data type is `float32`.
+    * The underlying data buffer are contiguous AND on CPU.
+    * The data pointer is aligned to 4 bytes.
+
+# Returns
+
+The masked logits. The shape of the returned logits is the same as the input logits.
+The returned logits is the same object as the input logits if the input logits is updated in-place.
+Otherwise, a new object with the same type as the input logits is returned.
+
+# Exceptions
+
+This method may raise the following exceptions:
+    * TypeError: When the logits type is not supported.
+    * AssertionError: When the logits shape is not supported or the memory allocator returns an unaligned pointer.
+    * ValueError: When the logits shape does not match the batch size or the vocabulary size.
+        """
+        logits, ptr, size = _convert_batch_logits_to_slice(logits)
+        super().mask_logits(ptr, size)
+        return logits
+
 _try_register_slice_converter("torch", _torch_slice_converter)
 _try_register_slice_converter("numpy", _numpy_slice_converter)
+_try_register_batch_slice_converter("torch", _torch_batch_slice_converter)
+_try_register_batch_slice_converter("numpy", _numpy_batch_slice_converter)
 if _torch_fast_mask_enabled:
     _try_register_fast_mask_logits("torch", _torch_fast_mask_logits)
\ No newline at end of file
@@ -0,0 +1,159 @@
+//! This module contains the helpers to write and read the binary formats of the library,
+//! e.g. the compiled grammar and the exported mask cache.
+
+/// The error type for errors in reading a binary format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BinaryError {
+    /// The input does not start with the magic bytes of the format.
+    InvalidMagic,
+    /// The input is written in another version of the format.
+    UnsupportedVersion(u32),
+    /// The input ends unexpectedly or contains invalid values.
+    Corrupted,
+}
+
+/// Appends the values of a binary format to a buffer.
+#[derive(Debug, Default)]
+pub(crate) struct BinaryWriter {
+    buffer: Vec<u8>,
+}
+
+impl BinaryWriter {
+    /// Creates a writer with the magic bytes and the version of a format.
+    pub(crate) fn new(magic: &[u8; 8], version: u32) -> Self {
+        let mut writer = Self::default();
+        writer.buffer.extend_from_slice(magic);
+        writer.write_u32(version);
+        writer
+    }
+
+    pub(crate) fn write_u8(&mut self, value: u8) {
+        self.buffer.push(value);
+    }
+
+    pub(crate) fn write_u32(&mut self, value: u32) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn write_u64(&mut self, value: u64) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn write_usize(&mut self, value: usize) {
+        self.write_u64(value as u64);
+    }
+
+    /// Writes the length of `bytes` followed by `bytes`.
+    pub(crate) fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_usize(bytes.len());
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Writes a section of `len` bytes filled by `write`, where the section starts at an offset aligned to 4 bytes.
+    ///
+    /// The alignment allows the section to be read without realigning it when the whole buffer is aligned, e.g. memory mapped.
+    pub(crate) fn write_aligned<E>(
+        &mut self,
+        len: usize,
+        write: impl FnOnce(&mut [u8]) -> Result<usize, E>,
+    ) -> Result<(), E> {
+        self.write_usize(len);
+        self.buffer.resize(self.buffer.len().next_multiple_of(4), 0);
+        let start = self.buffer.len();
+        self.buffer.resize(start + len, 0);
+        write(&mut self.buffer[start..])?;
+        Ok(())
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        self.buffer
+    }
+}
+
+/// Reads the values of a binary format from a buffer.
+#[derive(Debug)]
+pub(crate) struct BinaryReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BinaryReader<'a> {
+    /// Creates a reader after validating the magic bytes and the version of a format.
+    pub(crate) fn new(bytes: &'a [u8], magic: &[u8; 8], version: u32) -> Result<Self, BinaryError> {
+        if !bytes.starts_with(magic) {
+            return Err(BinaryError::InvalidMagic);
+        }
+        let mut reader = Self {
+            bytes,
+            position: magic.len(),
+        };
+        let actual_version = reader.read_u32()?;
+        if actual_version != version {
+            return Err(BinaryError::UnsupportedVersion(actual_version));
+        }
+        Ok(reader)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], BinaryError> {
+        let array = self
+            .bytes
+            .get(self.position..self.position + N)
+            .ok_or(BinaryError::Corrupted)?
+            .try_into()
+            .unwrap();
+        self.position += N;
+        Ok(array)
+    }
+
+    pub(crate) fn read_u8(&mut self) -> Result<u8, BinaryError> {
+        self.read_array::<1>().map(|[value]| value)
+    }
+
+    pub(crate) fn read_u32(&mut self) -> Result<u32, BinaryError> {
+        self.read_array().map(u32::from_le_bytes)
+    }
+
+    pub(crate) fn read_u64(&mut self) -> Result<u64, BinaryError> {
+        self.read_array().map(u64::from_le_bytes)
+    }
+
+    pub(crate) fn read_usize(&mut self) -> Result<usize, BinaryError> {
+        self.read_u64()?
+            .try_into()
+            .map_err(|_| BinaryError::Corrupted)
+    }
+
+    /// Reads a value written by [`BinaryWriter::write_bytes`].
+    pub(crate) fn read_bytes(&mut self) -> Result<&'a [u8], BinaryError> {
+        let len = self.read_usize()?;
+        let bytes = self
+            .bytes
+            .get(self.position..)
+            .and_then(|bytes| bytes.get(..len))
+            .ok_or(BinaryError::Corrupted)?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    /// Reads a section written by [`BinaryWriter::write_aligned`].
+    pub(crate) fn read_aligned(&mut self) -> Result<&'a [u8], BinaryError> {
+        let len = self.read_usize()?;
+        self.position = self.position.next_multiple_of(4);
+        let bytes = self
+            .bytes
+            .get(self.position..)
+            .and_then(|bytes| bytes.get(..len))
+            .ok_or(BinaryError::Corrupted)?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    /// Checks that every byte of the buffer is read.
+    pub(crate) fn finish(self) -> Result<(), BinaryError> {
+        if self.position == self.bytes.len() {
+            Ok(())
+        } else {
+            Err(BinaryError::Corrupted)
+        }
+    }
+}
@@ -0,0 +1,218 @@
+//! This module contains the [`MaskCache`] struct, which stores the allowed token IDs computed for engine states.
+use std::collections::BTreeMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use ahash::AHashMap;
+use fixedbitset_stack::FixedBitSet;
+
+use crate::engine::{CacheConfig, CacheEviction};
+use crate::engine_like::CacheStats;
+
+/// The allowed token IDs computed from an engine state, along with the bookkeeping for eviction.
+#[derive(Debug)]
+pub(crate) struct CacheEntry {
+    pub(crate) token_ids: FixedBitSet,
+    /// The estimated memory usage of the entry in bytes, including its key.
+    size: usize,
+    /// The tick of the last access, which is unique among the entries.
+    last_used: AtomicU64,
+    uses: AtomicU64,
+    /// The priority the entry is stored with in [`MaskCacheInner::order`].
+    queued: Priority,
+}
+
+/// The eviction priority of an entry, where the entry with the lowest priority is evicted first.
+///
+/// The priorities are unique among the entries since the ticks are unique.
+type Priority = (u64, u64);
+
+impl CacheEntry {
+    fn priority(&self, eviction: CacheEviction) -> Priority {
+        let last_used = self.last_used.load(Ordering::Relaxed);
+        match eviction {
+            CacheEviction::Lru => (last_used, 0),
+            CacheEviction::Lfu => (self.uses.load(Ordering::Relaxed), last_used),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct MaskCacheInner<K> {
+    pub(crate) map: AHashMap<Arc<K>, CacheEntry>,
+    /// The keys ordered by the priorities they are queued with.
+    ///
+    /// A hit only updates the entry under a read lock, so the queued priority may be lower than the current one.
+    /// Since the priorities only grow, such an entry is requeued when it reaches the front rather than evicted.
+    order: BTreeMap<Priority, Arc<K>>,
+    bytes: usize,
+    clock: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: u64,
+}
+
+impl<K> Default for MaskCacheInner<K> {
+    fn default() -> Self {
+        Self {
+            map: AHashMap::default(),
+            order: BTreeMap::new(),
+            bytes: 0,
+            clock: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: 0,
+        }
+    }
+}
+
+impl<K> MaskCacheInner<K>
+where
+    K: Hash + Eq,
+{
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn exceeds(&self, config: &CacheConfig) -> bool {
+        config
+            .max_entries
+            .is_some_and(|max_entries| self.map.len() > max_entries)
+            || config
+                .max_bytes
+                .is_some_and(|max_bytes| self.bytes > max_bytes)
+    }
+
+    fn evict(&mut self, eviction: CacheEviction) {
+        while let Some((queued, key)) = self.order.pop_first() {
+            // Every queued key is in the map.
+            let entry = self.map.get_mut(&key).unwrap();
+            let priority = entry.priority(eviction);
+            if priority != queued {
+                entry.queued = priority;
+                self.order.insert(priority, key);
+                continue;
+            }
+            let entry = self.map.remove(&key).unwrap();
+            self.bytes -= entry.size;
+            self.evictions += 1;
+            return;
+        }
+    }
+}
+
+/// A map from engine states to the allowed token IDs computed from them.
+///
+/// Cloning a [`MaskCache`] creates a handle to the same entries,
+/// so that engines cloned or forked from one another can reuse the masks computed by each other, even across threads.
+#[derive(Debug)]
+pub(crate) struct MaskCache<K> {
+    inner: Arc<RwLock<MaskCacheInner<K>>>,
+}
+
+impl<K> Default for MaskCache<K> {
+    fn default() -> Self {
+        Self {
+            inner: Arc::default(),
+        }
+    }
+}
+
+impl<K> Clone for MaskCache<K> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<K> MaskCache<K>
+where
+    K: Hash + Eq,
+{
+    /// Unions the allowed token IDs stored for `key` into `token_ids`.
+    ///
+    /// Returns `false` if the cache does not contain `key`.
+    pub(crate) fn union_into(&self, key: &K, token_ids: &mut FixedBitSet) -> bool {
+        let inner = self.read();
+        match inner.map.get(key) {
+            Some(entry) => {
+                token_ids.union_with(&entry.token_ids);
+                entry.last_used.store(inner.tick(), Ordering::Relaxed);
+                entry.uses.fetch_add(1, Ordering::Relaxed);
+                inner.hits.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            None => {
+                inner.misses.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+    /// Inserts the allowed token IDs for `key` and evicts entries until the cache fits in the limits of `config`.
+    ///
+    /// `size` is the estimated memory usage of the entry in bytes.
+    pub(crate) fn insert(&self, key: K, token_ids: FixedBitSet, size: usize, config: &CacheConfig) {
+        let mut inner = self.write();
+        let mut entry = CacheEntry {
+            token_ids,
+            size,
+            last_used: AtomicU64::new(inner.tick()),
+            uses: AtomicU64::new(0),
+            queued: (0, 0),
+        };
+        entry.queued = entry.priority(config.eviction);
+        let key = Arc::new(key);
+        let queued = entry.queued;
+        if let Some(old_entry) = inner.map.insert(key.clone(), entry) {
+            inner.order.remove(&old_entry.queued);
+            inner.bytes -= old_entry.size;
+        }
+        inner.order.insert(queued, key);
+        inner.bytes += size;
+        while inner.exceeds(config) {
+            inner.evict(config.eviction);
+        }
+    }
+}
+
+impl<K> MaskCache<K> {
+    /// Locks the entries for reading.
+    ///
+    /// A poisoned lock is recovered since the entries are only written by a single insertion.
+    pub(crate) fn read(&self) -> RwLockReadGuard<'_, MaskCacheInner<K>> {
+        self.inner.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, MaskCacheInner<K>> {
+        self.inner.write().unwrap_or_else(PoisonError::into_inner)
+    }
+    /// Calls `f` with every entry, from the least recently used to the most recently used.
+    pub(crate) fn for_each_by_recency(&self, mut f: impl FnMut(&K, &FixedBitSet)) {
+        let inner = self.read();
+        let mut entries: Vec<_> = inner.map.iter().collect();
+        entries.sort_unstable_by_key(|(_, entry)| entry.last_used.load(Ordering::Relaxed));
+        for (key, entry) in entries {
+            f(key, &entry.token_ids);
+        }
+    }
+    /// Removes all the entries. The statistics are preserved.
+    pub(crate) fn clear(&self) {
+        let mut inner = self.write();
+        inner.map.clear();
+        inner.order.clear();
+        inner.bytes = 0;
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        let inner = self.read();
+        CacheStats {
+            hits: inner.hits.load(Ordering::Relaxed),
+            misses: inner.misses.load(Ordering::Relaxed),
+            evictions: inner.evictions,
+            entries: inner.map.len(),
+            bytes: inner.bytes,
+        }
+    }
+}
@@ -0,0 +1,79 @@
+//! This module contains the versioned binary format of a compiled [`Grammar`](crate::grammar::Grammar).
+//!
+//! A compiled grammar stores the grammar in HIR form together with its DFAs and the tables derived from the vocabulary,
+//! so that an [`Engine`](crate::engine::Engine) can be created without parsing the KBNF grammar
+//! or compiling the regular expressions again.
+//! The suffix automata of substrings are cheap to build, so only their strings are stored and the automata are rebuilt when loading.
+//! See [`Engine::to_compiled`](crate::engine::Engine::to_compiled) and [`Engine::from_compiled`](crate::engine::Engine::from_compiled).
+//!
+//! All the integers are stored in little endian, except the DFAs which are stored in the native endian
+//! so that they can be deserialized without conversion.
+//! Hence, a compiled grammar can only be loaded on a platform with the same endianness as the one that compiled it.
+use kbnf_regex_automata::util::wire::{DeserializeError, SerializeError};
+
+use crate::binary::{BinaryError, BinaryReader, BinaryWriter};
+
+/// The magic bytes at the start of every compiled grammar.
+const MAGIC: &[u8; 8] = b"KBNFGRAM";
+/// The version of the compiled grammar format.
+///
+/// The version is bumped whenever the layout of the format changes,
+/// and compiled grammars of other versions are rejected.
+pub const COMPILED_GRAMMAR_VERSION: u32 = 1;
+
+#[derive(Debug, thiserror::Error)]
+/// The error type for errors in compiling or loading a compiled grammar.
+pub enum CompiledGrammarError {
+    #[error("The input is not a compiled grammar.")]
+    /// The input does not start with the magic bytes of a compiled grammar.
+    InvalidMagic,
+    #[error("The compiled grammar's format version is {0}, while the supported version is {1}.")]
+    /// The compiled grammar is created by an incompatible version of the library.
+    UnsupportedVersion(u32, u32),
+    #[error("The compiled grammar is created with a different vocabulary.")]
+    /// The fingerprint of the vocabulary does not match the one the grammar is compiled with.
+    VocabularyMismatch,
+    #[error("The compiled grammar is truncated or corrupted.")]
+    /// The compiled grammar ends unexpectedly or contains invalid values.
+    Corrupted,
+    #[error("Regex serialization error: {0}")]
+    /// Error when serializing a DFA.
+    DfaSerializeError(#[from] SerializeError),
+    #[error("Regex deserialization error: {0}")]
+    /// Error when deserializing a DFA, which usually means the compiled grammar is created on a platform with different endianness.
+    DfaDeserializeError(#[from] DeserializeError),
+}
+
+impl From<BinaryError> for CompiledGrammarError {
+    fn from(error: BinaryError) -> Self {
+        match error {
+            BinaryError::InvalidMagic => CompiledGrammarError::InvalidMagic,
+            BinaryError::UnsupportedVersion(version) => {
+                CompiledGrammarError::UnsupportedVersion(version, COMPILED_GRAMMAR_VERSION)
+            }
+            BinaryError::Corrupted => CompiledGrammarError::Corrupted,
+        }
+    }
+}
+
+/// Creates a writer with the header of a compiled grammar.
+pub(crate) fn write_header(engine_kind: u8, vocabulary_fingerprint: u64) -> BinaryWriter {
+    let mut writer = BinaryWriter::new(MAGIC, COMPILED_GRAMMAR_VERSION);
+    writer.write_u8(engine_kind);
+    writer.write_u64(vocabulary_fingerprint);
+    writer
+}
+
+/// Validates the header of a compiled grammar.
+///
+/// # Returns
+///
+/// The reader positioned after the header, along with the engine kind and the vocabulary fingerprint in the header.
+pub(crate) fn read_header(
+    bytes: &[u8],
+) -> Result<(BinaryReader<'_>, u8, u64), CompiledGrammarError> {
+    let mut reader = BinaryReader::new(bytes, MAGIC, COMPILED_GRAMMAR_VERSION)?;
+    let engine_kind = reader.read_u8()?;
+    let vocabulary_fingerprint = reader.read_u64()?;
+    Ok((reader, engine_kind, vocabulary_fingerprint))
+}
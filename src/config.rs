@@ -4,7 +4,7 @@ use kbnf_syntax::regex::FiniteStateAutomatonConfig;
 use pyo3::pyclass;
 use serde::{Deserialize, Serialize};
 
-use crate::engine::EngineConfig;
+use crate::engine::{CacheConfig, CacheEviction, EngineConfig};
 #[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 #[derive(Debug, Clone)]
@@ -12,12 +12,18 @@ use wasm_bindgen::prelude::*;
 pub struct InternalConfig {
     /// The configuration of the regular expressions.
     pub regex_config: FiniteStateAutomatonConfig,
+    /// The type of the Finite State Automaton the regular expressions are compiled into.
+    pub fsa_type: Fsa,
+    /// The capacity in bytes of the cache of each lazy DFA, or `None` for the default capacity.
+    pub lazy_dfa_cache_capacity: Option<usize>,
     /// The configuration about how to compress terminals in the grammar.
     pub compression_config: kbnf_syntax::config::CompressionConfig,
     /// The configuration of the engine itself.
     pub engine_config: EngineConfig,
     /// The start nonterminal of the grammar.
     pub start_nonterminal: String,
+    /// The configuration of when the engine terminates.
+    pub termination_config: TerminationConfig,
 }
 /// The configuration of the [`Engine`](crate::engine::Engine) struct. This should suffice most scenarios.
 #[cfg_attr(feature = "python", pyclass)]
@@ -40,6 +46,9 @@ pub struct Config {
     pub expected_output_length: usize,
     /// The configuration of the terminals compression.
     pub compression_config: CompressionConfig,
+    /// The configuration of when the engine terminates.
+    /// The default is [`TerminationMode::Eager`] without any EOS token.
+    pub termination_config: TerminationConfig,
 }
 /// The type of the Finite State Automaton to be used.
 #[cfg_attr(feature = "python", pyclass(eq, eq_int))]
@@ -51,6 +60,17 @@ pub enum Fsa {
     /// It is the fastest type of finite automaton, but it is also the most memory-consuming.
     /// In particular, construction time and space required could be exponential in the worst case.
     Dfa,
+    /// The sparse Deterministic Finite Automaton.
+    /// It is built the same way as [`Fsa::Dfa`] and then stores only the transitions that do not lead to the dead state,
+    /// so it usually takes much less memory than [`Fsa::Dfa`] in exchange for slower transitions.
+    /// The construction could still be exponential in the worst case.
+    SparseDfa,
+    /// The lazy Deterministic Finite Automaton, also known as the hybrid NFA/DFA.
+    /// It determinizes the states only when the input reaches them and stores them in a bounded cache,
+    /// so it never blows up, which makes it the choice for untrusted regular expressions.
+    /// Once the cache is full, the input that needs a new state is rejected.
+    /// The tokens are never classified in advance for its states, regardless of [`RegexConfig::min_tokens_required_for_eager_regex_cache`].
+    LazyDfa,
 }
 /// The configuration of regular expressions.
 #[cfg_attr(feature = "python", pyclass)]
@@ -61,12 +81,15 @@ pub struct RegexConfig {
     /// The maximum memory usage in bytes allowed when compiling the regex.
     /// If the memory usage exceeds this limit, an error will be returned.
     /// The default is `None`, which means no limit for dfa.
+    /// For [`Fsa::LazyDfa`], this is the capacity of the cache of each regex instead,
+    /// and `None` means the default capacity of 2 MiB.
     pub max_memory_usage: Option<usize>,
     /// The type of the Finite State Automaton to be used.
     /// The default is [`Fsa::Dfa`].
     pub fsa_type: Fsa,
-    /// The number of tokens required to cache the accepted tokens for a given regex state.
-    /// `None` means that the cache will be disabled.
+    /// The number of tokens a regex or substrings state must accept for the tokens to be classified for that state in advance.
+    /// Terminals are always classified since their classifications are small.
+    /// `None` means that no regex or substrings state will be classified.
     /// The default is `Some(1000)`.
     pub min_tokens_required_for_eager_regex_cache: Option<usize>,
 }
@@ -81,6 +104,45 @@ pub struct CompressionConfig {
     pub min_terminals: usize,
 }
 
+/// When the engine terminates.
+#[cfg_attr(feature = "python", pyclass(eq, eq_int))]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Copy)]
+pub enum TerminationMode {
+    /// The engine finishes as soon as the start nonterminal is completed and no more input can be accepted.
+    /// This is how KBNF behaves by default, and the EOS token IDs are ignored.
+    Eager,
+    /// The engine finishes only when one of the EOS token IDs is accepted.
+    ///
+    /// The EOS token IDs are allowed exactly when the engine is accepting,
+    /// i.e. when the start nonterminal is completed with the current input,
+    /// so the model decides whether to stop or to continue a grammar that can be extended.
+    Eos,
+}
+
+/// The configuration of when the engine terminates.
+#[cfg_attr(feature = "python", pyclass)]
+#[cfg_attr(feature = "python", pyo3(get_all, set_all))]
+#[cfg_attr(feature = "wasm", wasm_bindgen(inspectable, getter_with_clone))]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct TerminationConfig {
+    /// The termination mode. The default is [`TerminationMode::Eager`].
+    pub mode: TerminationMode,
+    /// The token IDs that end the generation in [`TerminationMode::Eos`].
+    /// Token IDs out of the vocabulary are ignored.
+    /// The default is empty.
+    pub eos_token_ids: Vec<u32>,
+}
+
+impl Default for TerminationConfig {
+    fn default() -> Self {
+        Self {
+            mode: TerminationMode::Eager,
+            eos_token_ids: Vec::new(),
+        }
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
@@ -92,23 +154,36 @@ impl Default for Config {
             engine_config: EngineConfig {
                 cache_enabled: true,
                 compaction_enabled: true,
+                parse_tree_enabled: false,
+                cache_config: CacheConfig {
+                    max_entries: None,
+                    max_bytes: None,
+                    eviction: CacheEviction::Lru,
+                },
+                worker_threads: 1,
             },
             start_nonterminal: "start".to_string(),
             compression_config: CompressionConfig { min_terminals: 5 },
             expected_output_length: u32::MAX as usize,
+            termination_config: TerminationConfig::default(),
         }
     }
 }
 impl Config {
     /// Converts the configuration to the internal configuration.
     pub fn internal_config(self) -> InternalConfig {
-        let regex_config = match self.regex_config.fsa_type {
-            Fsa::Dfa => FiniteStateAutomatonConfig::Dfa(
-                kbnf_regex_automata::dfa::dense::Config::new()
-                    .dfa_size_limit(self.regex_config.max_memory_usage)
-                    .start_kind(kbnf_regex_automata::dfa::StartKind::Both),
-            ),
+        // Sparse DFAs are converted from the dense DFAs compiled by kbnf-syntax,
+        // while kbnf-syntax only compiles the markers of the regexes into dense DFAs for lazy DFAs,
+        // so the memory limit, which is the cache capacity of lazy DFAs, does not apply to them.
+        let dfa_size_limit = match self.regex_config.fsa_type {
+            Fsa::Dfa | Fsa::SparseDfa => self.regex_config.max_memory_usage,
+            Fsa::LazyDfa => None,
         };
+        let regex_config = FiniteStateAutomatonConfig::Dfa(
+            kbnf_regex_automata::dfa::dense::Config::new()
+                .dfa_size_limit(dfa_size_limit)
+                .start_kind(kbnf_regex_automata::dfa::StartKind::Both),
+        );
         let compression_config = kbnf_syntax::config::CompressionConfig {
             min_terminals: self.compression_config.min_terminals,
             regex_config: FiniteStateAutomatonConfig::Dfa(
@@ -117,9 +192,12 @@ impl Config {
         };
         InternalConfig {
             regex_config,
+            fsa_type: self.regex_config.fsa_type,
+            lazy_dfa_cache_capacity: self.regex_config.max_memory_usage,
             compression_config,
             engine_config: self.engine_config,
             start_nonterminal: self.start_nonterminal,
+            termination_config: self.termination_config,
         }
     }
 }
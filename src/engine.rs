@@ -8,8 +8,13 @@ use serde::{Deserialize, Serialize};
 #[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 
+use crate::compiled_grammar::{self, CompiledGrammarError};
 use crate::{
-    config::Config, engine_base::EngineBase, engine_like::EngineLike, grammar::Grammar, utils,
+    config::{Config, Fsa},
+    engine_base::EngineBase,
+    engine_like::EngineLike,
+    grammar::Grammar,
+    utils,
     vocabulary::Vocabulary,
 };
 
@@ -29,6 +34,49 @@ pub struct EngineConfig {
     /// speeds up the engine in most cases. In particular, cache usually requires compaction to be effective.
     /// It is enabled by default.
     pub compaction_enabled: bool,
+    /// Whether the back-pointers of the Earley items are recorded so that [`EngineLike::parse_tree`] can recover the parse tree.
+    /// Recording takes memory proportional to the input length times the number of items in an Earley set.
+    /// It is disabled by default.
+    pub parse_tree_enabled: bool,
+    /// The limits and the eviction policy of the cache.
+    /// The cache is unbounded by default.
+    pub cache_config: CacheConfig,
+    /// The number of threads used to compute the allowed token IDs.
+    /// The allowed first bytes are partitioned across the threads, which speeds up the computation
+    /// when many tokens start with different allowed bytes, e.g. in an open regex like `#".*"`.
+    /// The threads are spawned once when the engine is created and shared by its clones and forks.
+    /// Values above 1 require the `parallel` feature, without which the engine cannot be created. It is 1 by default.
+    pub worker_threads: usize,
+}
+/// The configuration of the cache of the [`Engine`].
+///
+/// The cache is shared by cloned and forked engines, which should be created with the same configuration.
+#[cfg_attr(feature = "python", pyclass)]
+#[cfg_attr(feature = "python", pyo3(get_all, set_all))]
+#[cfg_attr(feature = "wasm", wasm_bindgen(inspectable))]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Copy)]
+pub struct CacheConfig {
+    /// The maximum number of entries in the cache.
+    /// The default is `None`, which means no limit.
+    pub max_entries: Option<usize>,
+    /// The maximum estimated memory usage of the cache in bytes.
+    /// Each entry is estimated as the 16-byte digest of the engine state it is computed from
+    /// plus one bit per token in the vocabulary.
+    /// The default is `None`, which means no limit.
+    pub max_bytes: Option<usize>,
+    /// Which entry to evict when the cache exceeds its limits.
+    /// The default is [`CacheEviction::Lru`].
+    pub eviction: CacheEviction,
+}
+/// The policy of choosing the entry to evict from the cache.
+#[cfg_attr(feature = "python", pyclass(eq, eq_int))]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Copy)]
+pub enum CacheEviction {
+    /// Evicts the least recently used entry.
+    Lru,
+    /// Evicts the least frequently used entry, breaking ties by recency.
+    Lfu,
 }
 #[derive(Debug, Clone)]
 /// An enum that represents the common type combinations of [`EngineBase`].
@@ -66,6 +114,9 @@ pub enum CreateEngineError {
     at least one nonterminal has more than 65536 alternations or repetitions, and/or the expected output length is more than 2^32.")]
     /// The grammar and/or config's value range is not supported by the Engine.
     InvalidInputError,
+    #[error("{0}")] // inherits the error message from the wrapped CompiledGrammarError
+    /// A wrapper for the [`CompiledGrammarError`] error type.
+    CompiledGrammarError(#[from] CompiledGrammarError),
 }
 
 impl Engine {
@@ -119,14 +170,23 @@ impl Engine {
         let tsp = config.expected_output_length;
         let regex_config = config.regex_config;
         let internal_config = config.internal_config();
-        let grammar =
-            utils::construct_kbnf_syntax_grammar(kbnf_syntax_grammar_str, internal_config.clone())?;
+        let kbnf_syntax_grammar_str =
+            utils::replace_special_token_references(kbnf_syntax_grammar_str, &vocabulary)?;
+        let grammar = utils::construct_kbnf_syntax_grammar(
+            &kbnf_syntax_grammar_str,
+            internal_config.clone(),
+        )?;
         if grammar.is_empty() {
             return Err(CreateEngineError::EmptyGrammarError);
         }
         let td = utils::find_max_dotted_position_from_kbnf_syntax_grammar(&grammar);
         let tp = utils::find_max_production_id_from_kbnf_syntax_grammar(&grammar);
-        let ts = utils::find_max_state_id_from_kbnf_syntax_grammar(&grammar);
+        let mut ts = utils::find_max_state_id_from_kbnf_syntax_grammar(&grammar);
+        // The state IDs of a sparse DFA are offsets in its transitions,
+        // and a lazy DFA may determinize any number of states while matching.
+        if regex_config.fsa_type != Fsa::Dfa && !grammar.id_to_regex.is_empty() {
+            ts = ts.max(u32::MAX as usize);
+        }
         let engine = if Self::check_id_length(&grammar, u8::MAX.into())
             && td <= u8::MAX.into()
             && tp <= u8::MAX.into()
@@ -140,6 +200,7 @@ impl Engine {
                 vocabulary,
                 grammar,
                 internal_config.engine_config,
+                internal_config.termination_config,
             )?)
         } else if Self::check_id_length(&grammar, u8::MAX.into())
             && td <= u8::MAX.into()
@@ -154,6 +215,7 @@ impl Engine {
                 vocabulary,
                 grammar,
                 internal_config.engine_config,
+                internal_config.termination_config,
             )?)
         } else if Self::check_id_length(&grammar, u16::MAX.into())
             && td <= u16::MAX.into()
@@ -168,6 +230,7 @@ impl Engine {
                 vocabulary,
                 grammar,
                 internal_config.engine_config,
+                internal_config.termination_config,
             )?)
         } else {
             return Err(CreateEngineError::InvalidInputError);
@@ -176,6 +239,155 @@ impl Engine {
     }
 }
 
+impl Engine {
+    /// Serializes the grammar of the engine, along with the tables computed from its vocabulary,
+    /// into the compiled grammar format.
+    ///
+    /// The result can be stored on disk and loaded by [`Engine::from_compiled`] with the same vocabulary,
+    /// which skips parsing the KBNF grammar, compiling the regular expressions and classifying the tokens.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<u8>` - The compiled grammar.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`CompiledGrammarError`] when a DFA cannot be serialized.
+    pub fn to_compiled(&self) -> Result<Vec<u8>, CompiledGrammarError> {
+        let engine_kind = match &self.union {
+            EngineUnion::U8U8U8U8U32(_) => 0,
+            EngineUnion::U8U8U16U16U16(_) => 1,
+            EngineUnion::U16U16U32U32U32(_) => 2,
+        };
+        let mut writer = compiled_grammar::write_header(engine_kind, self.vocab().fingerprint());
+        match &self.union {
+            EngineUnion::U8U8U8U8U32(engine) => engine.grammar().write_compiled(&mut writer)?,
+            EngineUnion::U8U8U16U16U16(engine) => engine.grammar().write_compiled(&mut writer)?,
+            EngineUnion::U16U16U32U32U32(engine) => engine.grammar().write_compiled(&mut writer)?,
+        }
+        Ok(writer.finish())
+    }
+
+    /// Create a new [`Engine`] from a compiled grammar created by [`Engine::to_compiled`], a [`Vocabulary`], and a [`Config`].
+    ///
+    /// The regex config and the compression config in `config` are ignored,
+    /// since they only take effect when the grammar is compiled.
+    /// Loading copies every DFA into the grammar, so `compiled_grammar` can be dropped once the engine is created.
+    /// A dense DFA has to be read from an address aligned to 4 bytes, so each dense DFA is copied once more
+    /// into an aligned buffer first unless `compiled_grammar` is aligned to 4 bytes, e.g. memory mapped from a file.
+    ///
+    /// # Arguments
+    ///
+    /// * `compiled_grammar` - The compiled grammar.
+    /// * `vocabulary` - The [`Vocabulary`] object the grammar is compiled with.
+    /// * `config` - The [`Config`] object.
+    ///
+    /// # Returns
+    ///
+    /// * [`Engine`] - The new [`Engine`] object.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`CreateEngineError`] when the compiled grammar is invalid, created with a different vocabulary,
+    /// or the config's value range is not supported by the Engine.
+    pub fn from_compiled(
+        compiled_grammar: &[u8],
+        vocabulary: Vocabulary,
+        config: Config,
+    ) -> Result<Engine, CreateEngineError> {
+        let tsp = config.expected_output_length;
+        let internal_config = config.internal_config();
+        let (mut reader, engine_kind, vocabulary_fingerprint) =
+            compiled_grammar::read_header(compiled_grammar)?;
+        if vocabulary_fingerprint != vocabulary.fingerprint() {
+            return Err(CompiledGrammarError::VocabularyMismatch.into());
+        }
+        let engine = match engine_kind {
+            0 if tsp <= u8::MAX.into() => {
+                let grammar: Grammar<u8> = Grammar::read_compiled(&mut reader, &vocabulary)?;
+                reader.finish().map_err(CompiledGrammarError::from)?;
+                EngineUnion::U8U8U8U8U32(EngineBase::new(
+                    Arc::new(vocabulary),
+                    Arc::new(grammar),
+                    internal_config.engine_config,
+                    internal_config.termination_config,
+                )?)
+            }
+            1 if tsp <= u16::MAX.into() => {
+                let grammar: Grammar<u8> = Grammar::read_compiled(&mut reader, &vocabulary)?;
+                reader.finish().map_err(CompiledGrammarError::from)?;
+                EngineUnion::U8U8U16U16U16(EngineBase::new(
+                    Arc::new(vocabulary),
+                    Arc::new(grammar),
+                    internal_config.engine_config,
+                    internal_config.termination_config,
+                )?)
+            }
+            2 if tsp <= u32::MAX as usize => {
+                let grammar: Grammar<u16> = Grammar::read_compiled(&mut reader, &vocabulary)?;
+                reader.finish().map_err(CompiledGrammarError::from)?;
+                EngineUnion::U16U16U32U32U32(EngineBase::new(
+                    Arc::new(vocabulary),
+                    Arc::new(grammar),
+                    internal_config.engine_config,
+                    internal_config.termination_config,
+                )?)
+            }
+            0..=2 => return Err(CreateEngineError::InvalidInputError),
+            _ => return Err(CompiledGrammarError::Corrupted.into()),
+        };
+        Ok(Self { union: engine })
+    }
+
+    /// Creates a new [`Engine`] that continues from the current state.
+    ///
+    /// The forked engine shares the parsing history and the checkpoints with this engine
+    /// until either of them accepts new input, and it shares the cache with this engine for its whole lifetime.
+    /// A cloned engine shares only the cache.
+    /// See [`EngineBase::fork`] for more details.
+    ///
+    /// # Returns
+    ///
+    /// * [`Engine`] - The forked [`Engine`] object.
+    pub fn fork(&self) -> Engine {
+        let union = match &self.union {
+            EngineUnion::U8U8U8U8U32(engine) => EngineUnion::U8U8U8U8U32(engine.fork()),
+            EngineUnion::U8U8U16U16U16(engine) => EngineUnion::U8U8U16U16U16(engine.fork()),
+            EngineUnion::U16U16U32U32U32(engine) => EngineUnion::U16U16U32U32U32(engine.fork()),
+        };
+        Self { union }
+    }
+
+    /// Makes this engine use the cache of `other`, so that the allowed token IDs computed by either engine
+    /// are reused by both, even across threads.
+    /// See [`EngineBase::share_cache_with`] for more details.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The engine whose cache will be shared.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`ShareCacheError`](crate::engine_like::ShareCacheError) when the grammars or the vocabularies of the engines are different.
+    pub fn share_cache_with(
+        &mut self,
+        other: &Engine,
+    ) -> Result<(), crate::engine_like::ShareCacheError> {
+        match (&mut self.union, &other.union) {
+            (EngineUnion::U8U8U8U8U32(engine), EngineUnion::U8U8U8U8U32(other)) => {
+                engine.share_cache_with(other)
+            }
+            (EngineUnion::U8U8U16U16U16(engine), EngineUnion::U8U8U16U16U16(other)) => {
+                engine.share_cache_with(other)
+            }
+            (EngineUnion::U16U16U32U32U32(engine), EngineUnion::U16U16U32U32U32(other)) => {
+                engine.share_cache_with(other)
+            }
+            _ => Err(crate::engine_like::ShareCacheError::IncompatibleGrammar),
+        }
+    }
+}
+
 macro_rules! match_engine_union {
     ($e:path[$s:expr$(,$p:ident)*]) => {
         match $s {
@@ -203,10 +415,46 @@ impl EngineLike for Engine {
         match_engine_union!(EngineLike::try_accept_new_bytes[&mut self.union, bytes])
     }
 
+    fn try_accept_new_token_with_diagnostic(
+        &mut self,
+        token_id: u32,
+    ) -> Result<crate::AcceptTokenResult, crate::engine_like::AcceptTokenDiagnosticError> {
+        match_engine_union!(EngineLike::try_accept_new_token_with_diagnostic[&mut self.union, token_id])
+    }
+
+    fn try_accept_new_bytes_with_diagnostic(
+        &mut self,
+        bytes: &[u8],
+    ) -> Result<crate::AcceptTokenResult, crate::engine_like::AcceptTokenDiagnosticError> {
+        match_engine_union!(EngineLike::try_accept_new_bytes_with_diagnostic[&mut self.union, bytes])
+    }
+
     fn compute_allowed_token_ids(&mut self) {
         match_engine_union!(EngineLike::compute_allowed_token_ids[&mut self.union])
     }
 
+    fn compute_allowed_token_ids_with_budget(&mut self, max_remaining_bytes: usize) {
+        match_engine_union!(
+            EngineLike::compute_allowed_token_ids_with_budget[&mut self.union, max_remaining_bytes]
+        )
+    }
+
+    fn compute_forced_bytes(&mut self, max_length: usize) -> Vec<u8> {
+        match_engine_union!(EngineLike::compute_forced_bytes[&mut self.union, max_length])
+    }
+
+    fn shortest_completion(&mut self) -> Option<Vec<u8>> {
+        match_engine_union!(EngineLike::shortest_completion[&mut self.union])
+    }
+
+    fn compute_allowed_next_bytes(&mut self) -> Vec<u8> {
+        match_engine_union!(EngineLike::compute_allowed_next_bytes[&mut self.union])
+    }
+
+    fn expected_symbols(&self) -> Vec<crate::engine_like::ExpectedSymbol> {
+        match_engine_union!(EngineLike::expected_symbols[&self.union])
+    }
+
     fn mask_logits(&self, logits: &mut [f32]) -> Result<(), crate::engine_like::MaskLogitsError> {
         match_engine_union!(EngineLike::mask_logits[&self.union, logits])
     }
@@ -219,6 +467,10 @@ impl EngineLike for Engine {
         match_engine_union!(EngineLike::update_logits[&mut self.union, token_id, logits])
     }
 
+    fn verify_tokens(&mut self, token_ids: &[u32]) -> (usize, Vec<fixedbitset_stack::FixedBitSet>) {
+        match_engine_union!(EngineLike::verify_tokens[&mut self.union, token_ids])
+    }
+
     fn allowed_token_ids_from_last_computation(&self) -> &fixedbitset_stack::FixedBitSet {
         match_engine_union!(EngineLike::allowed_token_ids_from_last_computation[&self.union])
     }
@@ -241,10 +493,81 @@ impl EngineLike for Engine {
         match_engine_union!(EngineLike::is_finished[&self.union])
     }
 
+    fn is_accepting(&self) -> bool {
+        match_engine_union!(EngineLike::is_accepting[&self.union])
+    }
+
     fn reset(&mut self) {
         match_engine_union!(EngineLike::reset[&mut self.union])
     }
 
+    fn cache_stats(&self) -> crate::engine_like::CacheStats {
+        match_engine_union!(EngineLike::cache_stats[&self.union])
+    }
+
+    fn clear_cache(&mut self) {
+        match_engine_union!(EngineLike::clear_cache[&mut self.union])
+    }
+
+    fn export_cache(&self) -> Vec<u8> {
+        match_engine_union!(EngineLike::export_cache[&self.union])
+    }
+
+    fn import_cache(
+        &mut self,
+        bytes: &[u8],
+    ) -> Result<usize, crate::engine_like::ImportCacheError> {
+        match_engine_union!(EngineLike::import_cache[&mut self.union, bytes])
+    }
+
+    fn warm_up(&self, corpus: &[&[u8]]) -> usize {
+        match_engine_union!(EngineLike::warm_up[&self.union, corpus])
+    }
+
+    fn checkpoint(&mut self) -> crate::engine_like::Checkpoint {
+        match_engine_union!(EngineLike::checkpoint[&mut self.union])
+    }
+
+    fn rollback_to(
+        &mut self,
+        checkpoint: crate::engine_like::Checkpoint,
+    ) -> Result<(), crate::engine_like::RollbackError> {
+        match_engine_union!(EngineLike::rollback_to[&mut self.union, checkpoint])
+    }
+
+    fn discard_checkpoint(
+        &mut self,
+        checkpoint: crate::engine_like::Checkpoint,
+    ) -> Result<(), crate::engine_like::RollbackError> {
+        match_engine_union!(EngineLike::discard_checkpoint[&mut self.union, checkpoint])
+    }
+
+    fn state_fingerprint(&self) -> u64 {
+        match_engine_union!(EngineLike::state_fingerprint[&self.union])
+    }
+
+    fn parse_tree(&self) -> Option<crate::parse_tree::ParseTree> {
+        match_engine_union!(EngineLike::parse_tree[&self.union])
+    }
+
+    fn subscribe_nonterminal(
+        &mut self,
+        nonterminal: &str,
+    ) -> Result<(), crate::engine_like::SubscribeNonterminalError> {
+        match_engine_union!(EngineLike::subscribe_nonterminal[&mut self.union, nonterminal])
+    }
+
+    fn unsubscribe_nonterminal(
+        &mut self,
+        nonterminal: &str,
+    ) -> Result<(), crate::engine_like::SubscribeNonterminalError> {
+        match_engine_union!(EngineLike::unsubscribe_nonterminal[&mut self.union, nonterminal])
+    }
+
+    fn drain_nonterminal_events(&mut self) -> Vec<crate::engine_like::NonterminalEvent> {
+        match_engine_union!(EngineLike::drain_nonterminal_events[&mut self.union])
+    }
+
     fn into_boxed_engine(self) -> Box<dyn EngineLike> {
         match_engine_union!(EngineLike::into_boxed_engine[self.union])
     }
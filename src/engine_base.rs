@@ -1,36 +1,75 @@
 //! This module contains the implementation of the [`Engine`](crate::engine::Engine) struct and is intended for advanced usages.
 use ahash::{AHashMap, AHashSet};
 use fixedbitset_stack::FixedBitSet;
-use jaggedarray::jagged_array::JaggedArray;
 use jaggedarray::jagged_array::JaggedArrayViewTrait;
-use jaggedarray::JaggedArrayMutViewTrait;
-use kbnf_regex_automata::dfa::Automaton;
-use kbnf_regex_automata::util::primitives::StateID;
-use kbnf_syntax::regex::FiniteStateAutomaton;
+use kbnf_regex_automata::Anchored;
 use num::{
     cast::AsPrimitive,
     traits::{ConstOne, ConstZero, NumAssign, NumOps},
     Num,
 };
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 use std::fmt::Debug;
+use std::hash::Hash;
 use std::hint::unreachable_unchecked;
 use std::sync::Arc;
 
+use crate::binary::{BinaryError, BinaryReader, BinaryWriter};
+use crate::cache::MaskCache;
+use crate::config::{TerminationConfig, TerminationMode};
 use crate::engine::EngineConfig;
+use crate::engine_like::AcceptTokenDiagnosticError;
+use crate::engine_like::AcceptTokenError;
+use crate::engine_like::CacheStats;
+use crate::engine_like::Checkpoint;
 use crate::engine_like::EngineLike;
+use crate::engine_like::ExpectedSymbol;
+use crate::engine_like::ImportCacheError;
+use crate::engine_like::NonterminalEvent;
+use crate::engine_like::NonterminalEventKind;
+use crate::engine_like::RejectionDiagnostic;
+use crate::engine_like::RollbackError;
+use crate::engine_like::ShareCacheError;
+use crate::engine_like::SubscribeNonterminalError;
 use crate::engine_like::WriteBufferError;
-use crate::grammar::RegexType;
+use crate::parse_tree::{Chart, ChartColumn, ChartItem, ParseTree};
+use crate::regex::FiniteStateAutomaton;
 use crate::utils;
-use crate::utils::dispatch_by_dfa_state_status;
 use crate::utils::ByteSet;
-use crate::vocabulary::TokenIterItem;
+use crate::utils::FsaStateStatus;
+use crate::utils::StableDigest;
+#[cfg(feature = "parallel")]
+use crate::worker_pool::WorkerPool;
 use crate::AcceptTokenResult;
 use crate::{
     grammar::{Grammar, HIRNode, NonterminalID},
     vocabulary::Vocabulary,
 };
-type EarleySets<TN, TD, TP, TSP, TS> = JaggedArray<EarleyItem<TN, TD, TP, TSP, TS>, Vec<usize>, 2>;
 const USIZE_WIDTH: usize = std::mem::size_of::<usize>();
+/// The magic bytes at the start of every exported mask cache.
+const MASK_CACHE_MAGIC: &[u8; 8] = b"KBNFMASK";
+/// The version of the exported mask cache format.
+const MASK_CACHE_VERSION: u32 = 1;
+/// A symbol accepted by the Earley recognizer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InputSymbol {
+    /// A byte, which is matched by terminals, regexes and substrings.
+    Byte(u8),
+    /// A special token with its token ID and the length of its content in bytes,
+    /// which is only matched by the special token nodes referencing it.
+    SpecialToken(u32, usize),
+}
+
+impl InputSymbol {
+    /// Returns the number of bytes the symbol spans in the input.
+    fn len(self) -> usize {
+        match self {
+            InputSymbol::Byte(_) => 1,
+            InputSymbol::SpecialToken(_, len) => len,
+        }
+    }
+}
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 struct EarleyItem<TN, TD, TP, TSP, TS>
 where
@@ -68,6 +107,11 @@ where
     TP: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
     TSP: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
     TS: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
+    TN: Send + Sync,
+    TD: Send + Sync,
+    TP: Send + Sync,
+    TSP: Send + Sync,
+    TS: Send + Sync,
     usize: num::traits::AsPrimitive<TN>
         + num::traits::AsPrimitive<TD>
         + num::traits::AsPrimitive<TP>
@@ -104,22 +148,12 @@ where
                 HIRNode::Terminal(_) => format!("[{}]", self.state_id.as_()),
                 &HIRNode::RegexString(id)
                 | &HIRNode::EarlyEndRegexString(id)
-                | &HIRNode::RegexComplement(id) => match engine.grammar.regex(id) {
-                    FiniteStateAutomaton::Dfa(dfa) => {
-                        format!(
-                            "[{}({})]",
-                            self.state_id.as_(),
-                            utils::check_dfa_state_status(
-                                EngineBase::<TN, TD, TP, TSP, TS>::from_state_id_to_dfa_state_id(
-                                    self.state_id,
-                                    dfa.stride2()
-                                ),
-                                dfa
-                            )
-                        )
-                    }
-                },
-                HIRNode::Nonterminal(_) => String::new(),
+                | &HIRNode::RegexComplement(id) => format!(
+                    "[{}({})]",
+                    self.state_id.as_(),
+                    engine.grammar.regex(id).status(self.state_id.as_())
+                ),
+                HIRNode::Nonterminal(_) | HIRNode::SpecialToken(_) => String::new(),
                 HIRNode::Substrings(_) => {
                     format!("[{}]", self.state_id.as_())
                 }
@@ -180,6 +214,62 @@ struct ToBeCompletedItemDebugStruct {
     start_position: usize,
 }
 
+/// A nonterminal completed along a chain of Leo items, linked to the next one towards the topmost item.
+#[derive(Debug)]
+pub(crate) struct LeoLink<TN, TD, TP, TS>
+where
+    TN: Num + AsPrimitive<usize> + ConstOne + ConstZero,
+{
+    pub(crate) nonterminal_id: NonterminalID<TN>,
+    /// The byte offset where the nonterminal starts.
+    pub(crate) start: usize,
+    /// The item that waits for the nonterminal where it starts and is completed by it,
+    /// which is `None` only for the topmost link.
+    pub(crate) parent: Option<ChartItem<TN, TD, TP, TS>>,
+    pub(crate) next: Option<Arc<LeoLink<TN, TD, TP, TS>>>,
+}
+
+impl<TN, TD, TP, TS> Drop for LeoLink<TN, TD, TP, TS>
+where
+    TN: Num + AsPrimitive<usize> + ConstOne + ConstZero,
+{
+    fn drop(&mut self) {
+        // A right recursion chains a link per byte, so dropping the chain recursively could overflow the stack.
+        let mut next = self.next.take();
+        while let Some(link) = next {
+            match Arc::try_unwrap(link) {
+                Ok(mut link) => next = link.next.take(),
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+/// The topmost item completed through a Leo item, together with the nonterminals the chain folds.
+#[derive(Debug, Clone)]
+struct LeoItem<TN, TD, TP, TSP, TS>
+where
+    TN: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
+    TSP: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
+{
+    topmost_item: ToBeCompletedItem<TN, TSP>,
+    /// The completed nonterminals from the one of the Leo item itself to the one of the topmost item.
+    /// The links are shared with the Leo items of the previous columns.
+    chain: Arc<LeoLink<TN, TD, TP, TS>>,
+}
+
+/// A [`NonterminalEvent`] recorded while accepting a token, which is emitted only if the whole token is accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct PendingNonterminalEvent<TN>
+where
+    TN: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
+{
+    kind: NonterminalEventKind,
+    nonterminal_id: NonterminalID<TN>,
+    start: usize,
+    end: usize,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 struct Dotted<TN, TSP>
 where
@@ -255,6 +345,11 @@ where
     TP: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
     TSP: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
     TS: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
+    TN: Send + Sync,
+    TD: Send + Sync,
+    TP: Send + Sync,
+    TSP: Send + Sync,
+    TS: Send + Sync,
     usize: num::traits::AsPrimitive<TN>
         + num::traits::AsPrimitive<TD>
         + num::traits::AsPrimitive<TP>
@@ -273,7 +368,7 @@ where
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 enum PostDotItemsDebugStruct {
     LeoEligible(EarleyItemDebugStruct),
     NormalItems(Vec<EarleyItemDebugStruct>),
@@ -299,9 +394,197 @@ pub enum CreateEngineBaseError {
     )]
     /// The substrings length exceeds the maximum substrings length allowed by the current size of StateID(TS).
     SubstringsTooLarge(usize, usize),
+    #[error(
+        "{0} worker threads are requested, but the parallel feature is disabled.
+     Consider enabling the parallel feature or setting worker_threads to 1."
+    )]
+    /// More than one worker thread is requested while the `parallel` feature is disabled.
+    ParallelFeatureDisabled(usize),
+}
+#[allow(clippy::type_complexity)]
+#[derive(Clone)]
+/// An Earley set together with the postdot items and the Leo items of its column.
+///
+/// The columns are shared by forks through [`Arc`].
+/// Accepting new input only pushes new columns, so a column is copied only when compaction modifies its Leo items.
+struct Column<TI, TD, TP, TSP, TS>
+where
+    TI: Num
+        + AsPrimitive<usize>
+        + ConstOne
+        + ConstZero
+        + Eq
+        + std::hash::Hash
+        + PartialEq
+        + std::fmt::Debug
+        + PartialOrd
+        + num::Bounded
+        + std::convert::TryFrom<usize>
+        + NumAssign,
+    TD: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
+    TP: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
+    TSP: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
+    TS: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
+    usize: num::traits::AsPrimitive<TI>
+        + num::traits::AsPrimitive<TD>
+        + num::traits::AsPrimitive<TP>
+        + num::traits::AsPrimitive<TSP>,
+{
+    earley_set: Vec<EarleyItem<TI, TD, TP, TSP, TS>>,
+    // Maybe a smallvec will be better. Profiling is needed to make a decision.
+    // I feel like copying the item is better than add a reference to the item since the item is relatively small(<=16 bytes)
+    // Memory pool actually makes the performance worse. Maybe it will be better if there is a lot of postdot items for a single Dotted.
+    postdot_items: AHashMap<NonterminalID<TI>, PostDotItems<TI, TD, TP, TSP, TS>>,
+    /// The topmost items completed through the Leo items of the column, which are resolved when the column is created,
+    /// along with the nonterminals completed on the way.
+    // Maybe we could do a tree-like search to broaden the definition of leo items later.
+    leo_items: AHashMap<NonterminalID<TI>, LeoItem<TI, TD, TP, TSP, TS>>,
+    /// The byte offset of the column, which differs from its index once the Earley sets are compacted.
+    offset: usize,
+    /// The digests of what completing each postdot nonterminal at the column leads to.
+    /// They are computed when the column is accepted and are empty in the columns created for simulation.
+    contexts: AHashMap<NonterminalID<TI>, u128>,
+    /// The digest of the Earley set, which is the [`StateSignature`] while the column is the last one.
+    digest: u128,
+}
+
+impl<TI, TD, TP, TSP, TS> Column<TI, TD, TP, TSP, TS>
+where
+    TI: Num
+        + AsPrimitive<usize>
+        + ConstOne
+        + ConstZero
+        + Eq
+        + std::hash::Hash
+        + PartialEq
+        + std::fmt::Debug
+        + PartialOrd
+        + num::Bounded
+        + std::convert::TryFrom<usize>
+        + NumAssign,
+    TD: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
+    TP: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
+    TSP: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
+    TS: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
+    usize: num::traits::AsPrimitive<TI>
+        + num::traits::AsPrimitive<TD>
+        + num::traits::AsPrimitive<TP>
+        + num::traits::AsPrimitive<TSP>,
+{
+    fn new() -> Self {
+        Self {
+            earley_set: Vec::new(),
+            postdot_items: AHashMap::default(),
+            leo_items: AHashMap::default(),
+            offset: 0,
+            contexts: AHashMap::default(),
+            digest: 0,
+        }
+    }
+
+    /// Clears the column while keeping its allocations.
+    fn clear(&mut self) {
+        self.earley_set.clear();
+        self.postdot_items.clear();
+        self.leo_items.clear();
+        self.offset = 0;
+        self.contexts.clear();
+        self.digest = 0;
+    }
+}
+
+#[allow(clippy::type_complexity)]
+#[derive(Clone)]
+/// The buffers used while creating a column, which are not part of the parsing state.
+///
+/// Every engine owns its buffers, so simulating tokens never modifies the columns shared with other engines.
+struct Buffers<TI, TD, TP, TSP, TS>
+where
+    TI: Num
+        + AsPrimitive<usize>
+        + ConstOne
+        + ConstZero
+        + Eq
+        + std::hash::Hash
+        + PartialEq
+        + std::fmt::Debug
+        + PartialOrd
+        + num::Bounded
+        + std::convert::TryFrom<usize>
+        + NumAssign,
+    TD: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
+    TP: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
+    TSP: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
+    TS: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
+    usize: num::traits::AsPrimitive<TI>
+        + num::traits::AsPrimitive<TD>
+        + num::traits::AsPrimitive<TP>
+        + num::traits::AsPrimitive<TSP>,
+{
+    to_be_completed_items: AHashSet<ToBeCompletedItem<TI, TSP>>,
+    to_be_completed_items_buffer: AHashSet<ToBeCompletedItem<TI, TSP>>,
+    deduplication_buffer: AHashSet<EarleyItem<TI, TD, TP, TSP, TS>>,
+    already_predicted_nonterminals: FixedBitSet,
+    /// The nonterminals visited while digesting a column.
+    digested_nonterminals: FixedBitSet,
+    digest_stack: Vec<NonterminalID<TI>>,
+    /// The columns removed after simulating tokens, which are reused to avoid allocations.
+    spare_columns: Vec<Column<TI, TD, TP, TSP, TS>>,
+}
+
+impl<TI, TD, TP, TSP, TS> Buffers<TI, TD, TP, TSP, TS>
+where
+    TI: Num
+        + AsPrimitive<usize>
+        + ConstOne
+        + ConstZero
+        + Eq
+        + std::hash::Hash
+        + PartialEq
+        + std::fmt::Debug
+        + PartialOrd
+        + num::Bounded
+        + std::convert::TryFrom<usize>
+        + NumAssign,
+    TD: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
+    TP: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
+    TSP: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
+    TS: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
+    usize: num::traits::AsPrimitive<TI>
+        + num::traits::AsPrimitive<TD>
+        + num::traits::AsPrimitive<TP>
+        + num::traits::AsPrimitive<TSP>,
+{
+    fn new(nonterminals_size: usize) -> Self {
+        Self {
+            to_be_completed_items: AHashSet::default(),
+            to_be_completed_items_buffer: AHashSet::default(),
+            deduplication_buffer: AHashSet::default(),
+            already_predicted_nonterminals: FixedBitSet::with_capacity(nonterminals_size),
+            digested_nonterminals: FixedBitSet::with_capacity(nonterminals_size),
+            digest_stack: Vec::new(),
+            spare_columns: Vec::new(),
+        }
+    }
+
+    /// Takes a cleared column to create the next column.
+    fn take_column(&mut self) -> Column<TI, TD, TP, TSP, TS> {
+        self.spare_columns.pop().unwrap_or_else(Column::new)
+    }
+
+    /// Keeps the column for reuse if no other engine holds it.
+    fn recycle_column(&mut self, column: Arc<Column<TI, TD, TP, TSP, TS>>) {
+        if let Ok(mut column) = Arc::try_unwrap(column) {
+            column.clear();
+            self.spare_columns.push(column);
+        }
+    }
 }
+
+#[allow(clippy::type_complexity)]
 #[derive(Clone)]
-struct StagedChanges<TI, TSP>
+/// A change to the parsing state, recorded so that a rejected token and [`EngineLike::rollback_to`] can undo it.
+enum Change<TI, TD, TP, TSP, TS>
 where
     TI: Num
         + AsPrimitive<usize>
@@ -315,10 +598,69 @@ where
         + num::Bounded
         + std::convert::TryFrom<usize>
         + NumAssign,
+    TD: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
+    TP: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
     TSP: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
+    TS: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
+    usize: num::traits::AsPrimitive<TI>
+        + num::traits::AsPrimitive<TD>
+        + num::traits::AsPrimitive<TP>
+        + num::traits::AsPrimitive<TSP>,
 {
-    postdot_items_since_last_commit: AHashSet<Dotted<TI, TSP>>,
-    earley_sets_len_since_last_commit: usize,
+    /// A column is pushed after the last one.
+    PushColumn,
+    /// The columns are removed from the end by compaction.
+    Compact(Vec<Arc<Column<TI, TD, TP, TSP, TS>>>),
+    /// A Leo item of a column is set by compaction, replacing the previous one.
+    LeoItem {
+        column: usize,
+        nonterminal_id: NonterminalID<TI>,
+        previous: Option<LeoItem<TI, TD, TP, TSP, TS>>,
+    },
+    /// Whether the engine was accepting before the change.
+    Finished(bool),
+    /// Whether an EOS token was accepted before the change.
+    EosAccepted(bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A position-independent signature of the part of an engine state that determines the allowed token IDs, used as the cache key.
+///
+/// It is the digest of the last Earley set stored in its column, where every start position is replaced by the digest of
+/// what completing the item's nonterminal there leads to, so states that differ only in dead history or by a shift of the columns
+/// share the same signature.
+struct StateSignature {
+    digest: u128,
+}
+
+impl std::hash::Hash for StateSignature {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        // The digest is already uniformly distributed.
+        state.write_u64(self.digest as u64);
+    }
+}
+
+impl StateSignature {
+    /// Estimates the memory usage in bytes of a cache entry keyed by a signature,
+    /// which is the digest plus one bit per token in the vocabulary.
+    fn entry_size(vocab_size: usize) -> usize {
+        std::mem::size_of::<Self>() + vocab_size.div_ceil(8)
+    }
+
+    /// Writes the signature in the exported cache format.
+    fn write(&self, writer: &mut BinaryWriter) {
+        writer.write_u64(self.digest as u64);
+        writer.write_u64((self.digest >> 64) as u64);
+    }
+
+    /// Reads a signature written by [`StateSignature::write`].
+    fn read(reader: &mut BinaryReader) -> Result<Self, BinaryError> {
+        let low = reader.read_u64()?;
+        let high = reader.read_u64()?;
+        Ok(Self {
+            digest: ((high as u128) << 64) | low as u128,
+        })
+    }
 }
 
 #[allow(clippy::type_complexity)]
@@ -351,23 +693,39 @@ where
     grammar: Arc<Grammar<TI>>,
     allowed_first_bytes: ByteSet,
     allowed_token_ids: FixedBitSet,
-    earley_sets: EarleySets<TI, TD, TP, TSP, TS>,
-    cache: AHashMap<EarleySets<TI, TD, TP, TSP, TS>, FixedBitSet>,
-    to_be_completed_items: AHashSet<ToBeCompletedItem<TI, TSP>>,
-    to_be_completed_items_buffer: AHashSet<ToBeCompletedItem<TI, TSP>>,
-    deduplication_buffer: AHashSet<EarleyItem<TI, TD, TP, TSP, TS>>,
-    // Maybe a smallvec will be better. Profiling is needed to make a decision.
-    // I feel like copying the item is better than add a reference to the item since the item is relatively small(<=16 bytes)
-    // Memory pool actually makes the performance worse. Maybe it will be better if there is a lot of postdot items for a single Dotted.
-    postdot_items: AHashMap<Dotted<TI, TSP>, PostDotItems<TI, TD, TP, TSP, TS>>,
-    postdot_items_since_last_commit: AHashSet<Dotted<TI, TSP>>,
-    // Maybe we could do a tree-like search to broaden the definition of leo items later.
-    column_to_postdot_nonterminals: AHashMap<TSP, AHashSet<NonterminalID<TI>>>,
-    leo_items: AHashMap<Dotted<TI, TSP>, ToBeCompletedItem<TI, TSP>>,
-    leo_items_buffer: Vec<ToBeCompletedItem<TI, TSP>>,
-    already_predicted_nonterminals: FixedBitSet,
+    /// The positions in trie order of the tokens accepted by the token classifications.
+    accepted_token_positions: FixedBitSet,
+    /// The positions in trie order of the tokens that must be simulated on the Earley sets.
+    undetermined_token_positions: FixedBitSet,
+    /// The columns of the Earley sets, which are shared with the forks of the engine.
+    columns: Vec<Arc<Column<TI, TD, TP, TSP, TS>>>,
+    /// Whether the start nonterminal is completed, i.e. the engine is accepting.
+    /// In [`TerminationMode::Eager`] it stays set once set, while in [`TerminationMode::Eos`] it only reflects the last Earley set.
     finished: bool,
+    /// Whether an EOS token is accepted in [`TerminationMode::Eos`].
+    eos_accepted: bool,
+    /// The back-pointers of the accepted bytes and special tokens since the last reset.
+    /// Only recorded when the parse tree is enabled.
+    chart: Chart<TI, TD, TP, TS>,
+    cache: MaskCache<StateSignature>,
+    buffers: Buffers<TI, TD, TP, TSP, TS>,
     config: EngineConfig,
+    /// The threads that compute the allowed token IDs, which are shared with the clones and forks of the engine.
+    /// Only created when [`EngineConfig::worker_threads`] is above 1.
+    #[cfg(feature = "parallel")]
+    worker_pool: Option<Arc<WorkerPool>>,
+    termination_config: TerminationConfig,
+    /// The checkpoints paired with the length of `changes` when they are created.
+    checkpoints: Vec<(Checkpoint, usize)>,
+    /// The changes since the first checkpoint, or only the changes of the token being accepted when there are no checkpoints.
+    changes: Vec<Change<TI, TD, TP, TSP, TS>>,
+    next_checkpoint_id: u64,
+    subscribed_nonterminals: FixedBitSet,
+    /// The subscribed nonterminals paired with the nonterminals that can begin them, including themselves.
+    subscribed_left_corners: Vec<(NonterminalID<TI>, FixedBitSet)>,
+    first_byte_nonterminals: FixedBitSet,
+    pending_nonterminal_events: Vec<PendingNonterminalEvent<TI>>,
+    nonterminal_events: Vec<NonterminalEvent>,
 }
 
 impl<TI, TD, TP, TSP, TS> Debug for EngineBase<TI, TD, TP, TSP, TS>
@@ -389,6 +747,11 @@ where
     TP: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
     TSP: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
     TS: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
+    TI: Send + Sync,
+    TD: Send + Sync,
+    TP: Send + Sync,
+    TSP: Send + Sync,
+    TS: Send + Sync,
     usize: num::traits::AsPrimitive<TI>
         + num::traits::AsPrimitive<TD>
         + num::traits::AsPrimitive<TP>
@@ -396,6 +759,42 @@ where
         + num::traits::AsPrimitive<TS>,
 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut postdot_items: Vec<_> = self
+            .columns
+            .iter()
+            .enumerate()
+            .flat_map(|(i, column)| {
+                column.postdot_items.iter().map(move |(k, v)| {
+                    (
+                        Dotted::<TI, TSP> {
+                            postdot_nonterminal_id: *k,
+                            column: i.as_(),
+                        }
+                        .to_debug_form(&self.grammar),
+                        v.to_debug_form(self),
+                    )
+                })
+            })
+            .collect();
+        postdot_items.sort();
+        let mut leo_items: Vec<_> = self
+            .columns
+            .iter()
+            .enumerate()
+            .flat_map(|(i, column)| {
+                column.leo_items.iter().map(move |(k, v)| {
+                    (
+                        Dotted::<TI, TSP> {
+                            postdot_nonterminal_id: *k,
+                            column: i.as_(),
+                        }
+                        .to_debug_form(&self.grammar),
+                        v.topmost_item.to_debug_form(&self.grammar),
+                    )
+                })
+            })
+            .collect();
+        leo_items.sort();
         f.debug_struct("EngineBase")
             .field("grammar", &self.grammar)
             .field(
@@ -405,82 +804,42 @@ where
             .field("allowed_token_ids", {
                 &self.get_display_form_from_token_ids(&self.allowed_token_ids)
             })
-            .field(
-                "earley_sets",
-                &self.get_display_form_from_earley_sets(&self.earley_sets),
-            )
+            .field("earley_sets", &self.get_display_form_from_earley_sets())
             .field(
                 "cache",
-                &utils::get_deterministic_display_form_from_hash_map(&self.cache, |(k, v)| {
-                    (
-                        self.get_display_form_from_earley_sets(k),
-                        (self.get_display_form_from_token_ids(v),),
-                    )
-                }),
+                &utils::get_deterministic_display_form_from_hash_map(
+                    &self.cache.read().map,
+                    |(k, v)| {
+                        (
+                            Self::get_display_form_from_state_signature(k),
+                            (self.get_display_form_from_token_ids(&v.token_ids),),
+                        )
+                    },
+                ),
             )
             .field("to_be_completed_items", {
                 &utils::get_deterministic_display_form_from_hash_set(
-                    &self.to_be_completed_items,
+                    &self.buffers.to_be_completed_items,
                     |x| x.to_debug_form(&self.grammar),
                 )
             })
             .field("to_be_completed_items_buffer", {
                 &utils::get_deterministic_display_form_from_hash_set(
-                    &self.to_be_completed_items_buffer,
+                    &self.buffers.to_be_completed_items_buffer,
                     |x| x.to_debug_form(&self.grammar),
                 )
             })
             .field("deduplication_buffer", {
                 &utils::get_deterministic_display_form_from_hash_set(
-                    &self.deduplication_buffer,
+                    &self.buffers.deduplication_buffer,
                     |x| x.to_debug_form(self),
                 )
             })
-            .field("postdot_items", {
-                &utils::get_deterministic_display_form_from_hash_map(
-                    &self.postdot_items,
-                    |(k, v)| (k.to_debug_form(&self.grammar), v.to_debug_form(self)),
-                )
-            })
-            .field(
-                "column_to_postdot_items",
-                &utils::get_deterministic_display_form_from_hash_map(
-                    &self.column_to_postdot_nonterminals,
-                    |(k, v)| {
-                        (
-                            k.as_(),
-                            utils::get_deterministic_display_form_from_hash_set(v, |x| {
-                                x.to_display_form(&self.grammar)
-                            }),
-                        )
-                    },
-                ),
-            )
-            .field("postdot_items_since_last_commit", {
-                &utils::get_deterministic_display_form_from_hash_set(
-                    &self.postdot_items_since_last_commit,
-                    |x| x.to_debug_form(&self.grammar),
-                )
-            })
-            .field("leo_items", {
-                &utils::get_deterministic_display_form_from_hash_map(&self.leo_items, |(k, v)| {
-                    (
-                        k.to_debug_form(&self.grammar),
-                        v.to_debug_form(&self.grammar),
-                    )
-                })
-            })
-            .field(
-                "leo_items_buffer",
-                &self
-                    .leo_items_buffer
-                    .iter()
-                    .map(|x| x.to_debug_form(&self.grammar))
-                    .collect::<Vec<_>>(),
-            )
+            .field("postdot_items", &postdot_items)
+            .field("leo_items", &leo_items)
             .field(
                 "already_predicted_nonterminals",
-                &utils::get_display_form_from_bitset(&self.already_predicted_nonterminals),
+                &utils::get_display_form_from_bitset(&self.buffers.already_predicted_nonterminals),
             )
             .field("finished", &self.finished)
             .field("config", &self.config)
@@ -508,6 +867,11 @@ where
     TP: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
     TSP: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
     TS: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
+    TI: Send + Sync,
+    TD: Send + Sync,
+    TP: Send + Sync,
+    TSP: Send + Sync,
+    TS: Send + Sync,
     usize: num::traits::AsPrimitive<TI>
         + num::traits::AsPrimitive<TD>
         + num::traits::AsPrimitive<TP>
@@ -516,6 +880,17 @@ where
 {
     const STATE_ID_TYPE_SIZE: usize = std::mem::size_of::<TS>();
     const STATE_ID_TYPE_BIT: u32 = (Self::STATE_ID_TYPE_SIZE * 8) as u32;
+
+    /// Gets the sizes of the value types, which determine the layout of the cache keys.
+    fn value_type_sizes() -> [u8; 5] {
+        [
+            std::mem::size_of::<TI>() as u8,
+            std::mem::size_of::<TD>() as u8,
+            std::mem::size_of::<TP>() as u8,
+            std::mem::size_of::<TSP>() as u8,
+            std::mem::size_of::<TS>() as u8,
+        ]
+    }
     /// Create a new [EngineBase](crate::engine_base::EngineBase).
     ///
     /// # Arguments
@@ -523,6 +898,7 @@ where
     /// * `vocabulary` - The vocabulary of the language model.
     /// * `grammar` - The grammar of the language model.
     /// * `config` - The specific config of the engine.
+    /// * `termination_config` - The config of when the engine terminates.
     ///
     /// # Returns
     ///
@@ -531,7 +907,8 @@ where
     /// # Errors
     ///
     /// Returns an error if the terminal length, regex length, excepted length
-    /// or repetition in regex exceeds the maximum allowed by the current size of StateID(TS).
+    /// or repetition in regex exceeds the maximum allowed by the current size of StateID(TS),
+    /// or if more than one worker thread is requested while the `parallel` feature is disabled.
     ///
     /// # Panics
     ///
@@ -540,6 +917,7 @@ where
         vocabulary: Arc<Vocabulary>,
         grammar: Arc<Grammar<TI>>,
         config: EngineConfig,
+        termination_config: TerminationConfig,
     ) -> Result<Self, CreateEngineBaseError> {
         // Verify necessary conditions
         assert!(
@@ -548,58 +926,153 @@ where
             Self::STATE_ID_TYPE_SIZE,
             USIZE_WIDTH
         );
+        #[cfg(not(feature = "parallel"))]
+        if config.worker_threads > 1 {
+            return Err(CreateEngineBaseError::ParallelFeatureDisabled(
+                config.worker_threads,
+            ));
+        }
         Self::validate_ts_size_for_terminals(&grammar)?;
         Self::validate_ts_size_for_regexes(&grammar)?;
         Self::validate_ts_size_for_suffix_automata(&grammar)?;
         // Init fields
         let allowed_first_bytes = ByteSet::with_capacity(u8::MAX as usize);
         let allowed_token_ids = FixedBitSet::with_capacity(vocabulary.vocab_size());
-        let earley_sets = JaggedArray::new();
-        let cache = AHashMap::default();
-        let to_be_completed_items = AHashSet::default();
-        let already_predicted_nonterminals =
-            FixedBitSet::with_capacity(grammar.nonterminals_size());
-        let postdot_items = AHashMap::default();
+        let cache = MaskCache::default();
+        let buffers = Buffers::new(grammar.nonterminals_size());
+        let subscribed_nonterminals = FixedBitSet::with_capacity(grammar.nonterminals_size());
+        let first_byte_nonterminals = FixedBitSet::with_capacity(grammar.nonterminals_size());
+        let token_positions_len = vocabulary.token_positions_len();
         let mut engine = Self {
             vocabulary,
             grammar,
             allowed_first_bytes,
             allowed_token_ids,
-            earley_sets,
+            accepted_token_positions: FixedBitSet::with_capacity(token_positions_len),
+            undetermined_token_positions: FixedBitSet::with_capacity(token_positions_len),
+            columns: Vec::new(),
+            finished: false,
+            eos_accepted: false,
+            chart: Chart::new(),
             cache,
-            to_be_completed_items,
-            already_predicted_nonterminals,
+            buffers,
             config,
-            postdot_items,
-            leo_items: AHashMap::default(),
-            finished: false,
-            to_be_completed_items_buffer: AHashSet::default(),
-            leo_items_buffer: Vec::new(),
-            postdot_items_since_last_commit: AHashSet::default(),
-            deduplication_buffer: AHashSet::default(),
-            column_to_postdot_nonterminals: AHashMap::default(),
+            #[cfg(feature = "parallel")]
+            worker_pool: (config.worker_threads > 1)
+                .then(|| Arc::new(WorkerPool::new(config.worker_threads))),
+            termination_config,
+            checkpoints: Vec::new(),
+            changes: Vec::new(),
+            next_checkpoint_id: 0,
+            subscribed_nonterminals,
+            subscribed_left_corners: Vec::new(),
+            first_byte_nonterminals,
+            pending_nonterminal_events: Vec::new(),
+            nonterminal_events: Vec::new(),
         };
         engine.reset();
         Ok(engine)
     }
 
-    fn get_display_form_from_earley_sets(
-        &self,
-        sets: &EarleySets<TI, TD, TP, TSP, TS>,
-    ) -> Vec<Vec<EarleyItemDebugStruct>> {
-        let mut res = Vec::with_capacity(sets.len());
-        for i in 0..sets.len() {
-            let set = sets.view::<1, 1>([i]);
-            let mut set_res = Vec::with_capacity(set.len());
-            for j in 0..set.len() {
-                set_res.push(set[[j]].to_debug_form(self));
-            }
-            res.push(set_res);
+    /// Creates a new [EngineBase](crate::engine_base::EngineBase) that continues from the current state.
+    ///
+    /// Unlike [`Clone::clone`], the forked engine shares every Earley set with this engine
+    /// instead of copying them, and gets its own empty buffers for the new Earley sets.
+    /// The checkpoints are kept along with the changes recorded since the first of them.
+    /// Accepting input only appends new Earley sets, so the shared ones are never copied
+    /// except when compaction updates the Leo items of one of them.
+    /// Like [`Clone::clone`], it shares the cache with this engine for its whole lifetime.
+    /// This makes forking cheap enough to be done for every hypothesis in beam search or parallel sampling.
+    ///
+    /// # Returns
+    ///
+    /// A new [EngineBase](crate::engine_base::EngineBase) instance.
+    pub fn fork(&self) -> Self {
+        Self {
+            vocabulary: self.vocabulary.clone(),
+            grammar: self.grammar.clone(),
+            allowed_first_bytes: self.allowed_first_bytes.clone(),
+            allowed_token_ids: self.allowed_token_ids.clone(),
+            accepted_token_positions: FixedBitSet::with_capacity(
+                self.vocabulary.token_positions_len(),
+            ),
+            undetermined_token_positions: FixedBitSet::with_capacity(
+                self.vocabulary.token_positions_len(),
+            ),
+            columns: self.columns.clone(),
+            finished: self.finished,
+            eos_accepted: self.eos_accepted,
+            chart: self.chart.clone(),
+            cache: self.cache.clone(),
+            buffers: Buffers::new(self.grammar.nonterminals_size()),
+            config: self.config,
+            #[cfg(feature = "parallel")]
+            worker_pool: self.worker_pool.clone(),
+            termination_config: self.termination_config.clone(),
+            checkpoints: self.checkpoints.clone(),
+            changes: self.changes.clone(),
+            next_checkpoint_id: self.next_checkpoint_id,
+            subscribed_nonterminals: self.subscribed_nonterminals.clone(),
+            subscribed_left_corners: self.subscribed_left_corners.clone(),
+            first_byte_nonterminals: FixedBitSet::with_capacity(self.grammar.nonterminals_size()),
+            pending_nonterminal_events: Vec::new(),
+            nonterminal_events: Vec::new(),
         }
-        res
     }
-    fn get_display_form_from_token_ids(
-        &self,
+
+    /// Gets the grammar of the engine.
+    pub fn grammar(&self) -> &Arc<Grammar<TI>> {
+        &self.grammar
+    }
+
+    /// Makes this engine use the cache of `other`, so that the allowed token IDs computed by either engine
+    /// are reused by both, even across threads. The entries in the current cache of this engine are dropped.
+    ///
+    /// Engines cloned or forked from one another already share the cache.
+    /// This method allows engines created separately from identical grammars and vocabularies to share it as well,
+    /// which are compared by [`Grammar::fingerprint`] and [`Vocabulary::fingerprint`].
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The engine whose cache will be shared.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`ShareCacheError`] when the grammars or the vocabularies of the engines are different,
+    /// since the cached token IDs are only meaningful for the grammar and the vocabulary they are computed with.
+    pub fn share_cache_with(&mut self, other: &Self) -> Result<(), ShareCacheError> {
+        if !Arc::ptr_eq(&self.grammar, &other.grammar)
+            && self.grammar.fingerprint() != other.grammar.fingerprint()
+        {
+            return Err(ShareCacheError::IncompatibleGrammar);
+        }
+        if !Arc::ptr_eq(&self.vocabulary, &other.vocabulary)
+            && self.vocabulary.fingerprint() != other.vocabulary.fingerprint()
+        {
+            return Err(ShareCacheError::IncompatibleVocabulary);
+        }
+        self.cache = other.cache.clone();
+        Ok(())
+    }
+
+    fn get_display_form_from_state_signature(signature: &StateSignature) -> String {
+        format!("{:032x}", signature.digest)
+    }
+
+    fn get_display_form_from_earley_sets(&self) -> Vec<Vec<EarleyItemDebugStruct>> {
+        self.columns
+            .iter()
+            .map(|column| {
+                column
+                    .earley_set
+                    .iter()
+                    .map(|item| item.to_debug_form(self))
+                    .collect()
+            })
+            .collect()
+    }
+    fn get_display_form_from_token_ids(
+        &self,
         bitset: &fixedbitset_stack::FixedBitSet,
     ) -> Vec<String> {
         bitset
@@ -624,12 +1097,15 @@ where
         let regexes = grammar.id_to_regexes();
         let max: usize = 2usize.saturating_pow(Self::STATE_ID_TYPE_BIT) - 1;
         for fsa in regexes {
-            match fsa {
-                FiniteStateAutomaton::Dfa(dfa) => {
-                    if dfa.state_len() > max {
-                        return Err(CreateEngineBaseError::RegexTooLarge(dfa.state_len(), max));
-                    }
-                }
+            // The state IDs of a sparse DFA are offsets in its transitions,
+            // and a lazy DFA may determinize any number of states while matching.
+            let len = match fsa {
+                FiniteStateAutomaton::Dfa(dfa) => dfa.state_len(),
+                FiniteStateAutomaton::SparseDfa(dfa) => dfa.memory_usage(),
+                FiniteStateAutomaton::LazyDfa(_) => u32::MAX as usize,
+            };
+            if len > max {
+                return Err(CreateEngineBaseError::RegexTooLarge(len, max));
             }
         }
         Ok(())
@@ -649,18 +1125,17 @@ where
         }
         Ok(())
     }
-    /// Run prediction stage of Earley algorithm on last Earley set and current `already_predicted_nonterminals` content
+    /// Run prediction stage of Earley algorithm on the new Earley set and current `already_predicted_nonterminals` content
     fn predict(
         grammar: &Grammar<TI>,
-        earley_sets: &mut EarleySets<TI, TD, TP, TSP, TS>,
+        column: &mut Column<TI, TD, TP, TSP, TS>,
+        earley_set_index: usize,
         already_predicted_nonterminals: &mut FixedBitSet,
     ) {
-        let earley_set_index = earley_sets.len() - 1;
-        let mut earley_set_len =
-            unsafe { earley_sets.view_unchecked::<1, 1>([earley_set_index]).len() };
         let mut i = 0;
-        while i < earley_set_len {
-            let item = unsafe { *earley_sets.get_unchecked([earley_set_index, i]) };
+        while i < column.earley_set.len() {
+            // SAFETY: i < column.earley_set.len() ensures the index is valid
+            let item = unsafe { *column.earley_set.get_unchecked(i) };
             // SAFETY: Earley algorithm guarantees item is a valid index
             let node = unsafe {
                 *grammar.node_unchecked(
@@ -670,9 +1145,9 @@ where
                 )
             };
             if let HIRNode::Nonterminal(nonterminal_id) = node {
-                earley_set_len += Self::predict_nonterminal(
+                Self::predict_nonterminal(
                     grammar,
-                    earley_sets,
+                    &mut column.earley_set,
                     already_predicted_nonterminals,
                     nonterminal_id,
                     earley_set_index,
@@ -686,36 +1161,11 @@ where
     fn initialize_state_id_based_on_node(grammar: &Grammar<TI>, node: HIRNode<TI>) -> TS {
         match node {
             HIRNode::RegexString(id) | HIRNode::EarlyEndRegexString(id) => {
-                let fsa = grammar.regex(id);
-                match fsa {
-                    FiniteStateAutomaton::Dfa(dfa) => {
-                        // SAFETY: start_error will not happen since that will result in an error in Grammar::new() method
-                        let start = unsafe {
-                            dfa.start_state(
-                                &kbnf_regex_automata::util::start::Config::new()
-                                    .anchored(kbnf_regex_automata::Anchored::Yes),
-                            )
-                            .unwrap_unchecked()
-                        };
-                        Self::from_dfa_state_id_to_state_id(start, dfa.stride2())
-                    }
-                }
-            }
-            HIRNode::RegexComplement(regex_id) => {
-                let fsa = grammar.regex(regex_id);
-                match fsa {
-                    FiniteStateAutomaton::Dfa(dfa) => {
-                        // SAFETY: start_error will not happen since that will result in an error in Grammar::new() method
-                        let start = unsafe{dfa
-                            .start_state(
-                                &kbnf_regex_automata::util::start::Config::new()
-                                    .anchored(kbnf_regex_automata::Anchored::No),
-                            )
-                            .unwrap_unchecked()};
-                        Self::from_dfa_state_id_to_state_id(start, dfa.stride2())
-                    }
-                }
+                Self::from_regex_state_to_state_id(grammar.regex(id).start_state(Anchored::Yes))
             }
+            HIRNode::RegexComplement(regex_id) => Self::from_regex_state_to_state_id(
+                grammar.regex(regex_id).start_state(Anchored::No),
+            ),
             HIRNode::Substrings(_) => {
                 Self::from_suffix_automaton_node_id_to_state_id(general_sam::SAM_ROOT_NODE_ID)
             }
@@ -723,17 +1173,15 @@ where
         }
     }
 
-    /// Predict one nonterminal according to Earley algorithm on the last Earley set.
+    /// Predict one nonterminal according to Earley algorithm on the new Earley set.
     /// This function ensures no duplication happens.
-    ///
-    /// Returns the number of items added to the Earley set.
     fn predict_nonterminal(
         grammar: &Grammar<TI>,
-        earley_sets: &mut EarleySets<TI, TD, TP, TSP, TS>,
+        earley_set: &mut Vec<EarleyItem<TI, TD, TP, TSP, TS>>,
         already_predicted_nonterminals: &mut FixedBitSet,
         nonterminal_id: NonterminalID<TI>,
         earley_set_index: usize,
-    ) -> usize {
+    ) {
         let nid = nonterminal_id.0.as_();
         if !already_predicted_nonterminals.contains(nid) {
             already_predicted_nonterminals.insert(nid);
@@ -743,29 +1191,27 @@ where
             // - 0 is always valid since no nonterminal could have an empty production.
             let productions =
                 unsafe { grammar.rules().view_unchecked::<2, 1>([nid, 0]) }.as_slice();
-            earley_sets.buffer_reserve(productions.len());
+            earley_set.reserve(productions.len());
             for (j, node) in productions.iter().copied().enumerate() {
                 let production_index = j.as_();
-                let new_item = EarleyItem {
+                earley_set.push(EarleyItem {
                     nonterminal_id,
                     dot_position: TD::ZERO,
                     production_index,
                     start_position: earley_set_index.as_(),
                     state_id: Self::initialize_state_id_based_on_node(grammar, node),
-                };
-                // SAFETY: line 853 guarantees the buffer has enough capacity
-                unsafe { earley_sets.push_to_last_row_unchecked(new_item) };
+                });
             }
-            productions.len()
-        } else {
-            0
         }
     }
     /// This function requires the last Earley set has been created and fully predicted.
-    fn update_allowed_first_bytes(&mut self) {
+    ///
+    /// Special tokens are not bytes, so they add nothing to `allowed_first_bytes`.
+    /// Returns whether any item in the last Earley set waits on a special token.
+    fn update_allowed_first_bytes(&mut self) -> bool {
         self.allowed_first_bytes.clear();
-        let earley_set_index = self.earley_sets.len() - 1;
-        let earley_set = self.earley_sets.view::<1, 1>([earley_set_index]).as_slice();
+        let mut special_token_pending = false;
+        let earley_set = self.columns.last().unwrap().earley_set.as_slice();
         for item in earley_set.iter().copied() {
             let node = *self.grammar.node(
                 item.nonterminal_id,
@@ -780,27 +1226,17 @@ where
                 HIRNode::RegexString(regex_id) | HIRNode::EarlyEndRegexString(regex_id) => {
                     if let Some(first_bytes) = self.grammar.first_bytes_from_regex(
                         regex_id,
-                        Self::from_state_id_to_dfa_state_id(
-                            item.state_id,
-                            match self.grammar.regex(regex_id) {
-                                FiniteStateAutomaton::Dfa(dfa) => dfa.stride2(),
-                            },
-                        ),
+                        Self::from_state_id_to_regex_state(item.state_id),
                     ) {
-                        self.allowed_first_bytes.union_with(first_bytes);
+                        self.allowed_first_bytes.union_with(&first_bytes);
                     }
                 }
                 HIRNode::RegexComplement(regex_id) => {
                     if let Some(first_bytes) = self.grammar.complement_first_bytes_from_regex(
                         regex_id,
-                        Self::from_state_id_to_dfa_state_id(
-                            item.state_id,
-                            match self.grammar.regex(regex_id) {
-                                FiniteStateAutomaton::Dfa(dfa) => dfa.stride2(),
-                            },
-                        ),
+                        Self::from_state_id_to_regex_state(item.state_id),
                     ) {
-                        self.allowed_first_bytes.union_with(first_bytes);
+                        self.allowed_first_bytes.union_with(&first_bytes);
                     }
                 }
                 HIRNode::Substrings(_) => {
@@ -809,9 +1245,11 @@ where
                         .first_bytes_from_suffix_automaton(item.state_id.as_());
                     self.allowed_first_bytes.union_with(first_bytes);
                 }
-                _ => {}
+                HIRNode::SpecialToken(_) => special_token_pending = true,
+                HIRNode::Nonterminal(_) => {}
             }
         }
+        special_token_pending
     }
     #[inline]
     fn item_should_be_completed(
@@ -837,22 +1275,26 @@ where
         true
     }
 
+    /// Advances the item over its postdot node and returns the advanced item.
+    ///
+    /// A completed item is returned with its dot after its last node and a zero state.
     fn advance_item<T>(
         grammar: &Grammar<TI>,
         to_be_completed_items: &mut AHashSet<ToBeCompletedItem<TI, TSP>>,
         add_to_earley_set: T,
         mut item: EarleyItem<TI, TD, TP, TSP, TS>,
-    ) where
+    ) -> EarleyItem<TI, TD, TP, TSP, TS>
+    where
         T: FnOnce(EarleyItem<TI, TD, TP, TSP, TS>),
     {
         let new_dotted_position = item.dot_position + TD::ONE;
+        item.dot_position = new_dotted_position;
         if !Self::item_should_be_completed(
             grammar,
             item.nonterminal_id,
             new_dotted_position,
             item.production_index,
         ) {
-            item.dot_position = new_dotted_position;
             item.state_id = Self::initialize_state_id_based_on_node(
                 grammar,
                 // SAFETY:
@@ -869,31 +1311,30 @@ where
             );
             add_to_earley_set(item);
         } else {
+            item.state_id = TS::ZERO;
             to_be_completed_items.insert(ToBeCompletedItem {
                 nonterminal_id: item.nonterminal_id,
                 start_position: item.start_position,
             });
         }
+        item
     }
 
     #[inline]
-    /// # Safety
-    ///
-    /// earley_sets must has enough capacity to push one new item.
-    unsafe fn advance_item_normal_unchecked(
+    fn advance_item_normal(
         grammar: &Grammar<TI>,
-        earley_sets: &mut EarleySets<TI, TD, TP, TSP, TS>,
+        earley_set: &mut Vec<EarleyItem<TI, TD, TP, TSP, TS>>,
         to_be_completed_items: &mut AHashSet<ToBeCompletedItem<TI, TSP>>,
         item: EarleyItem<TI, TD, TP, TSP, TS>,
-    ) {
+    ) -> EarleyItem<TI, TD, TP, TSP, TS> {
         Self::advance_item(
             grammar,
             to_be_completed_items,
             |new_item| {
-                earley_sets.push_to_last_row_unchecked(new_item);
+                earley_set.push(new_item);
             },
             item,
-        );
+        )
     }
 
     #[inline]
@@ -905,16 +1346,13 @@ where
         index.as_()
     }
     #[inline]
-    fn from_dfa_state_id_to_state_id(state_id: StateID, stride2: usize) -> TS {
-        // SAFETY: StateID is a u32 due to #[repr(transparent)] attribute
-        let id: u32 = unsafe { std::mem::transmute(state_id) };
-        // SAFETY: id is guaranteed to be representable as a state_id or an error will be returned in Self::new() method
-        ((id >> stride2) as usize).as_()
+    fn from_regex_state_to_state_id(state: usize) -> TS {
+        // SAFETY: state is guaranteed to be representable as a state_id or an error will be returned in Self::new() method
+        state.as_()
     }
     #[inline]
-    fn from_state_id_to_dfa_state_id(state_id: TS, stride2: usize) -> StateID {
-        // SAFETY: StateID is a u32 due to #[repr(transparent)] attribute
-        unsafe { std::mem::transmute((state_id.as_() << stride2) as u32) }
+    fn from_state_id_to_regex_state(state_id: TS) -> usize {
+        state_id.as_()
     }
     #[inline]
     fn from_suffix_automaton_node_id_to_state_id(node_id: usize) -> TS {
@@ -925,22 +1363,21 @@ where
         state_id.as_()
     }
 
+    /// Scans the current Earley set with a byte.
+    ///
+    /// `on_scanned` is called with every scanned item and the item it is advanced to.
     fn scan(
         grammar: &Grammar<TI>,
-        earley_sets: &mut EarleySets<TI, TD, TP, TSP, TS>,
+        earley_set: &[EarleyItem<TI, TD, TP, TSP, TS>],
+        new_earley_set: &mut Vec<EarleyItem<TI, TD, TP, TSP, TS>>,
         to_be_completed_items: &mut AHashSet<ToBeCompletedItem<TI, TSP>>,
+        mut on_scanned: impl FnMut(EarleyItem<TI, TD, TP, TSP, TS>, EarleyItem<TI, TD, TP, TSP, TS>),
         byte: u8,
     ) {
-        let earley_set_index: usize = earley_sets.len() - 1; // Interestingly usize seems to be faster than i32
-                                                             // SAFETY: earley_set_index is guaranteed to be valid since earley_sets is never empty
-        let earley_set_len =
-            unsafe { earley_sets.view_unchecked::<1, 1>([earley_set_index]).len() };
-        earley_sets.new_row::<0>();
         // Each regex or excepted will add at most two item to the next Earley set
-        earley_sets.buffer_reserve(earley_set_len * 2);
-        for i in 0..earley_set_len {
-            // SAFETY: 0<i<earley_set_len and earley sets is never empty ensures the index is valid
-            let mut item = unsafe { *earley_sets.get_unchecked([earley_set_index, i]) };
+        new_earley_set.reserve(earley_set.len() * 2);
+        for previous in earley_set.iter().copied() {
+            let mut item = previous;
             // SAFETY:
             // item.nonterminal_id is guaranteed to be valid since it always comes from the grammar, in other words, the jagged array.
             // item.dot_position and item.production_index either come from predict_nonterminal or advance_item,
@@ -964,96 +1401,67 @@ where
                             // interestingly faster than <
                             let new_state_index = Self::from_index_to_state_id(index);
                             item.state_id = new_state_index;
-                            earley_sets.push_to_last_row(item);
+                            new_earley_set.push(item);
+                            on_scanned(previous, item);
                         } else {
-                            // SAFETY: line 1055 ensures earley_sets has enough capacity to push one new item
-                            unsafe {
-                                Self::advance_item_normal_unchecked(
-                                    grammar,
-                                    earley_sets,
-                                    to_be_completed_items,
-                                    item,
-                                )
-                            };
+                            let item = Self::advance_item_normal(
+                                grammar,
+                                new_earley_set,
+                                to_be_completed_items,
+                                item,
+                            );
+                            on_scanned(previous, item);
                         }
                     }
                 }
                 HIRNode::RegexString(regex_id) | HIRNode::EarlyEndRegexString(regex_id) => {
                     // SAFETY: regex_id is guaranteed to be valid since it always comes from the grammar, in other words, the jagged array.
                     let regex = unsafe { grammar.regex_unchecked(regex_id) };
-                    match regex {
-                        FiniteStateAutomaton::Dfa(dfa) => {
-                            let mut state_id =
-                                Self::from_state_id_to_dfa_state_id(item.state_id, dfa.stride2());
-                            state_id = dfa.next_state(state_id, byte);
-                            dispatch_by_dfa_state_status!(
-                                state_id,
-                                dfa,
-                                accept=>{
-                                    // SAFETY: line 1055 ensures earley_sets has enough capacity to push one new item
-                                    unsafe{Self::advance_item_normal_unchecked(
-                                        grammar,
-                                        earley_sets,
-                                        to_be_completed_items,
-                                        item,
-                                    )};
-                                    // Only keep for normal regex
-                                    if let HIRNode::RegexString(_) = node
-                                    {
-                                        let state_id = Self::from_dfa_state_id_to_state_id(
-                                            state_id,
-                                            dfa.stride2(),
-                                        );
-                                        item.state_id = state_id;
-                                        // SAFETY: line 1055 ensures earley_sets has enough capacity to push one new item
-                                        unsafe{earley_sets.push_to_last_row_unchecked(item)};
-                                    }
-                                },
-                                reject=>{},
-                                in_progress=>
-                                {
-                                    let state_id = Self::from_dfa_state_id_to_state_id(
-                                        state_id,
-                                        dfa.stride2(),
-                                    );
-                                    item.state_id = state_id;
-                                    // SAFETY: line 1055 ensures earley_sets has enough capacity to push one new item
-                                    unsafe{earley_sets.push_to_last_row_unchecked(item)};
-                                }
+                    let (state, status) =
+                        regex.next_state(Self::from_state_id_to_regex_state(item.state_id), byte);
+                    match status {
+                        FsaStateStatus::Accept => {
+                            on_scanned(
+                                previous,
+                                Self::advance_item_normal(
+                                    grammar,
+                                    new_earley_set,
+                                    to_be_completed_items,
+                                    item,
+                                ),
                             );
+                            // Only keep for normal regex
+                            if let HIRNode::RegexString(_) = node {
+                                item.state_id = Self::from_regex_state_to_state_id(state);
+                                new_earley_set.push(item);
+                                on_scanned(previous, item);
+                            }
+                        }
+                        FsaStateStatus::Reject => {}
+                        FsaStateStatus::InProgress => {
+                            item.state_id = Self::from_regex_state_to_state_id(state);
+                            new_earley_set.push(item);
+                            on_scanned(previous, item);
                         }
                     }
                 }
                 HIRNode::RegexComplement(regex_id) => {
                     let regex = unsafe { grammar.regex_unchecked(regex_id) };
-                    match regex {
-                        FiniteStateAutomaton::Dfa(dfa) => {
-                            let mut state_id =
-                                Self::from_state_id_to_dfa_state_id(item.state_id, dfa.stride2());
-                            state_id = dfa.next_state(state_id, byte);
-                            dispatch_by_dfa_state_status!(
-                                state_id,
-                                dfa,
-                                accept=>{},
-                                reject=>{},
-                                in_progress=>{
-                                    // SAFETY: line 1055 ensures earley_sets has enough capacity to push one new item
-                                    unsafe{Self::advance_item_normal_unchecked(
-                                        grammar,
-                                        earley_sets,
-                                        to_be_completed_items,
-                                        item,
-                                    )};
-                                    let state_id = Self::from_dfa_state_id_to_state_id(
-                                        state_id,
-                                        dfa.stride2(),
-                                    );
-                                    item.state_id = state_id;
-                                    // SAFETY: line 1055 ensures earley_sets has enough capacity to push one new item
-                                    unsafe{earley_sets.push_to_last_row_unchecked(item)};
-                                }
-                            );
-                        }
+                    let (state, status) =
+                        regex.next_state(Self::from_state_id_to_regex_state(item.state_id), byte);
+                    if status == FsaStateStatus::InProgress {
+                        on_scanned(
+                            previous,
+                            Self::advance_item_normal(
+                                grammar,
+                                new_earley_set,
+                                to_be_completed_items,
+                                item,
+                            ),
+                        );
+                        item.state_id = Self::from_regex_state_to_state_id(state);
+                        new_earley_set.push(item);
+                        on_scanned(previous, item);
                     }
                 }
                 HIRNode::Substrings(suffix_automata_id) => {
@@ -1064,41 +1472,65 @@ where
                     state.feed([byte]);
                     if !state.is_nil() {
                         // is one substring
-                        // SAFETY: line 1055 ensures earley_sets has enough capacity to push one new item
-                        unsafe {
-                            Self::advance_item_normal_unchecked(
-                                grammar,
-                                earley_sets,
-                                to_be_completed_items,
-                                item,
-                            )
-                        };
+                        let advanced_item = Self::advance_item_normal(
+                            grammar,
+                            new_earley_set,
+                            to_be_completed_items,
+                            item,
+                        );
+                        on_scanned(previous, advanced_item);
                         let state_id =
                             Self::from_suffix_automaton_node_id_to_state_id(state.node_id);
                         item.state_id = state_id;
-                        // SAFETY: line 1055 ensures earley_sets has enough capacity to push one new item
-                        unsafe { earley_sets.push_to_last_row_unchecked(item) };
+                        new_earley_set.push(item);
+                        on_scanned(previous, item);
                     }
                 }
-                HIRNode::Nonterminal(_) => {}
+                HIRNode::Nonterminal(_) | HIRNode::SpecialToken(_) => {}
             }
         }
     }
-    fn update_postdot_items(
+
+    /// Scans the current Earley set with a special token, which only advances the items whose postdot node references it.
+    ///
+    /// `on_scanned` is called as in [`Self::scan`].
+    fn scan_special_token(
         grammar: &Grammar<TI>,
-        earley_sets: &mut EarleySets<TI, TD, TP, TSP, TS>,
-        postdot_items: &mut AHashMap<Dotted<TI, TSP>, PostDotItems<TI, TD, TP, TSP, TS>>,
-        added_postdot_items: &mut AHashSet<Dotted<TI, TSP>>,
-        mut insert_column_to_postdot_nonterminal: impl FnMut(Dotted<TI, TSP>),
+        earley_set: &[EarleyItem<TI, TD, TP, TSP, TS>],
+        new_earley_set: &mut Vec<EarleyItem<TI, TD, TP, TSP, TS>>,
+        to_be_completed_items: &mut AHashSet<ToBeCompletedItem<TI, TSP>>,
+        mut on_scanned: impl FnMut(EarleyItem<TI, TD, TP, TSP, TS>, EarleyItem<TI, TD, TP, TSP, TS>),
+        token_id: u32,
     ) {
-        let earley_set_index = earley_sets.len() - 1;
-        // SAFETY: earley_set_index is guaranteed to be valid since earley_sets is never empty
-        let earley_set = unsafe {
-            earley_sets
-                .view_unchecked::<1, 1>([earley_set_index])
-                .as_slice()
-        };
         for item in earley_set.iter().copied() {
+            // SAFETY: the item is valid as explained in Self::scan
+            let node = unsafe {
+                *grammar.node_unchecked(
+                    item.nonterminal_id,
+                    item.dot_position,
+                    item.production_index,
+                )
+            };
+            if let HIRNode::SpecialToken(special_token_id) = node {
+                if grammar.special_token(special_token_id) == token_id {
+                    let advanced_item = Self::advance_item_normal(
+                        grammar,
+                        new_earley_set,
+                        to_be_completed_items,
+                        item,
+                    );
+                    on_scanned(item, advanced_item);
+                }
+            }
+        }
+    }
+    /// Groups the items of the new Earley set by their postdot nonterminals and resolves the Leo items of the new column.
+    fn update_postdot_items(
+        grammar: &Grammar<TI>,
+        columns: &[Arc<Column<TI, TD, TP, TSP, TS>>],
+        column: &mut Column<TI, TD, TP, TSP, TS>,
+    ) {
+        for item in column.earley_set.iter().copied() {
             // SAFETY:
             // item.nonterminal_id is guaranteed to be valid since it always comes from the grammar, in other words, the jagged array.
             // item.dot_position and item.production_index either come from predict_nonterminal or advance_item,
@@ -1111,15 +1543,9 @@ where
                 )
             };
             if let HIRNode::Nonterminal(nonterminal) = node {
-                let postdot = Dotted {
-                    postdot_nonterminal_id: nonterminal,
-                    column: earley_set_index.as_(),
-                };
-                insert_column_to_postdot_nonterminal(postdot);
-                match postdot_items.entry(postdot) {
+                match column.postdot_items.entry(nonterminal) {
                     std::collections::hash_map::Entry::Occupied(mut entry) => {
                         let mut_ref = entry.get_mut();
-                        // add_column_to_postdot_nonterminal(postdot);
                         match mut_ref {
                             &mut PostDotItems::LeoEligible(old_item) => {
                                 *mut_ref = PostDotItems::NormalItems(vec![old_item, item]);
@@ -1131,12 +1557,11 @@ where
                     }
                     std::collections::hash_map::Entry::Vacant(entry) => {
                         entry.insert(PostDotItems::LeoEligible(item));
-                        added_postdot_items.insert(postdot);
                     }
                 }
             }
         }
-        for v in postdot_items.values_mut() {
+        for v in column.postdot_items.values_mut() {
             if let &mut PostDotItems::LeoEligible(item) = v {
                 if !Self::item_should_be_completed(
                     grammar,
@@ -1149,82 +1574,234 @@ where
                 }
             }
         }
-    }
-    fn try_leo_complete_item(
-        leo_items_buffer: &mut Vec<ToBeCompletedItem<TI, TSP>>,
-        leo_items: &mut AHashMap<Dotted<TI, TSP>, ToBeCompletedItem<TI, TSP>>,
-        postdot_items: &AHashMap<Dotted<TI, TSP>, PostDotItems<TI, TD, TP, TSP, TS>>,
-        mut topmost_item: ToBeCompletedItem<TI, TSP>,
-    ) -> Option<ToBeCompletedItem<TI, TSP>> {
-        loop {
-            let dotted = Dotted {
-                postdot_nonterminal_id: topmost_item.nonterminal_id,
-                column: topmost_item.start_position,
+        let earley_set_index = columns.len();
+        for (&nonterminal, items) in column.postdot_items.iter() {
+            let PostDotItems::LeoEligible(item) = items else {
+                continue;
             };
-            if let Some(leo_item) = leo_items.get(&dotted) {
-                leo_items_buffer.push(topmost_item);
-                topmost_item = *leo_item;
-                break;
-            }
-            match postdot_items.get(&dotted) {
-                Some(v) => match v {
-                    &PostDotItems::LeoEligible(leo_item) => {
-                        leo_items_buffer.push(topmost_item);
+            let mut topmost_item = ToBeCompletedItem {
+                nonterminal_id: item.nonterminal_id,
+                start_position: item.start_position,
+            };
+            // The nonterminals completed within the new column, which all start at it,
+            // paired with the items waiting for them.
+            let mut links = vec![(nonterminal, *item)];
+            let mut chain = None;
+            // The chain of Leo items within the new column is bounded by its postdot nonterminals,
+            // and the Leo items of the previous columns are already resolved.
+            for _ in 0..column.postdot_items.len() {
+                let start_position = topmost_item.start_position.as_();
+                if start_position < earley_set_index {
+                    if let Some(leo_item) = columns[start_position]
+                        .leo_items
+                        .get(&topmost_item.nonterminal_id)
+                    {
+                        topmost_item = leo_item.topmost_item;
+                        chain = Some(leo_item.chain.clone());
+                    }
+                    break;
+                }
+                match column.postdot_items.get(&topmost_item.nonterminal_id) {
+                    Some(&PostDotItems::LeoEligible(item))
+                        if item.nonterminal_id != nonterminal =>
+                    {
+                        links.push((topmost_item.nonterminal_id, item));
                         topmost_item = ToBeCompletedItem {
-                            nonterminal_id: leo_item.nonterminal_id,
-                            start_position: leo_item.start_position,
+                            nonterminal_id: item.nonterminal_id,
+                            start_position: item.start_position,
                         };
                     }
-                    PostDotItems::NormalItems(_) => {
-                        break;
-                    }
-                },
-                None => {
-                    // We reach the beginning of the Earley sets
-                    break;
+                    _ => break,
                 }
-            };
+            }
+            let mut chain = chain.unwrap_or_else(|| {
+                let start_position = topmost_item.start_position.as_();
+                Arc::new(LeoLink {
+                    nonterminal_id: topmost_item.nonterminal_id,
+                    start: columns
+                        .get(start_position)
+                        .map_or(column.offset, |column| column.offset),
+                    parent: None,
+                    next: None,
+                })
+            });
+            for &(nonterminal_id, item) in links.iter().rev() {
+                chain = Arc::new(LeoLink {
+                    nonterminal_id,
+                    start: column.offset,
+                    parent: Some(Self::chart_item(columns, column.offset, item)),
+                    next: Some(chain),
+                });
+            }
+            column.leo_items.insert(
+                nonterminal,
+                LeoItem {
+                    topmost_item,
+                    chain,
+                },
+            );
         }
-        if leo_items_buffer.is_empty() {
-            None
-        } else {
-            leo_items.reserve(leo_items_buffer.len());
-            for leo_item in leo_items_buffer.iter().copied() {
-                // Very interestingly, this is faster than leo_items_buffer.drain()
-                let dotted = Dotted {
-                    postdot_nonterminal_id: leo_item.nonterminal_id,
-                    column: leo_item.start_position,
+    }
+    /// Converts the item of the column at `offset` to a [`ChartItem`], whose start position is a byte offset.
+    fn chart_item(
+        columns: &[Arc<Column<TI, TD, TP, TSP, TS>>],
+        offset: usize,
+        item: EarleyItem<TI, TD, TP, TSP, TS>,
+    ) -> ChartItem<TI, TD, TP, TS> {
+        ChartItem {
+            nonterminal_id: item.nonterminal_id,
+            dot_position: item.dot_position,
+            production_index: item.production_index,
+            state_id: item.state_id,
+            // The items predicted in the column start at it.
+            start: columns
+                .get(item.start_position.as_())
+                .map_or(offset, |column| column.offset),
+        }
+    }
+    fn item_digest(item: &EarleyItem<TI, TD, TP, TSP, TS>, start: u128) -> u128 {
+        let mut digest = StableDigest::new();
+        digest.write_u64(item.nonterminal_id.0.as_() as u64);
+        digest.write_u64(item.dot_position.as_() as u64);
+        digest.write_u64(item.production_index.as_() as u64);
+        digest.write_u64(item.state_id.as_() as u64);
+        digest.write_u128(start);
+        digest.finish()
+    }
+
+    /// Digests the items of a column, where `first_column` tells whether it is column zero,
+    /// since completing the start nonterminal there finishes the input.
+    fn column_digest(
+        first_column: bool,
+        nonterminal_id: Option<NonterminalID<TI>>,
+        items: u128,
+    ) -> u128 {
+        let mut digest = StableDigest::new();
+        digest.write_u64(first_column as u64);
+        digest.write_u64(nonterminal_id.map_or(u64::MAX, |id| id.0.as_() as u64));
+        digest.write_u128(items);
+        digest.finish()
+    }
+
+    /// Gets the digest of what completing the nonterminal that starts at the column leads to, following its Leo item.
+    fn context(
+        columns: &[Arc<Column<TI, TD, TP, TSP, TS>>],
+        column: usize,
+        nonterminal_id: NonterminalID<TI>,
+    ) -> u128 {
+        let (column, nonterminal_id) = match columns[column].leo_items.get(&nonterminal_id) {
+            Some(leo_item) => (
+                leo_item.topmost_item.start_position.as_(),
+                leo_item.topmost_item.nonterminal_id,
+            ),
+            None => (column, nonterminal_id),
+        };
+        columns[column]
+            .contexts
+            .get(&nonterminal_id)
+            .copied()
+            .unwrap_or_else(|| Self::column_digest(column == 0, Some(nonterminal_id), 0))
+    }
+
+    /// Computes the digests of the last column from the digests of the columns before it.
+    ///
+    /// An item that starts at the last column itself has its start replaced by a fixed digest,
+    /// so the context of a nonterminal covers every postdot item it reaches through such items.
+    fn update_digests(
+        columns: &mut [Arc<Column<TI, TD, TP, TSP, TS>>],
+        buffers: &mut Buffers<TI, TD, TP, TSP, TS>,
+    ) {
+        // The start of the items that start at the column itself, whose contexts are the postdot items of the column
+        const OWN_COLUMN: u128 = u128::MAX;
+        // SAFETY: the columns are never empty
+        let (column, columns) = columns.split_last_mut().unwrap();
+        // SAFETY: the column is just pushed, so no other engine holds it yet
+        let column = Arc::get_mut(column).unwrap();
+        let index = columns.len();
+        let start_digest = |item: &EarleyItem<TI, TD, TP, TSP, TS>| {
+            let start = item.start_position.as_();
+            if start == index {
+                OWN_COLUMN
+            } else {
+                Self::context(columns, start, item.nonterminal_id)
+            }
+        };
+        let mut contexts = std::mem::take(&mut column.contexts);
+        let Buffers {
+            digested_nonterminals: visited,
+            digest_stack: stack,
+            ..
+        } = buffers;
+        for &nonterminal_id in column.postdot_items.keys() {
+            let mut items = 0u128;
+            stack.push(nonterminal_id);
+            visited.insert(nonterminal_id.0.as_());
+            while let Some(postdot_nonterminal_id) = stack.pop() {
+                let postdot_items = match column.postdot_items.get(&postdot_nonterminal_id) {
+                    Some(PostDotItems::LeoEligible(item)) => std::slice::from_ref(item),
+                    Some(PostDotItems::NormalItems(items)) => items.as_slice(),
+                    None => &[],
                 };
-                leo_items.insert(dotted, topmost_item);
+                for item in postdot_items {
+                    items = items.wrapping_add(Self::item_digest(item, start_digest(item)));
+                    if item.start_position.as_() == index
+                        && !visited.put(item.nonterminal_id.0.as_())
+                    {
+                        stack.push(item.nonterminal_id);
+                    }
+                }
             }
-            leo_items_buffer.clear();
-            Some(topmost_item)
+            visited.clear();
+            contexts.insert(
+                nonterminal_id,
+                Self::column_digest(index == 0, Some(nonterminal_id), items),
+            );
         }
+        column.contexts = contexts;
+        let items = column.earley_set.iter().fold(0u128, |items, item| {
+            items.wrapping_add(Self::item_digest(item, start_digest(item)))
+        });
+        column.digest = Self::column_digest(index == 0, None, items);
+    }
+
+    #[inline]
+    fn try_leo_complete_item(
+        columns: &[Arc<Column<TI, TD, TP, TSP, TS>>],
+        item: ToBeCompletedItem<TI, TSP>,
+    ) -> Option<&LeoItem<TI, TD, TP, TSP, TS>> {
+        columns[item.start_position.as_()]
+            .leo_items
+            .get(&item.nonterminal_id)
     }
+    /// Advances the items waiting for the completed item.
+    ///
+    /// `on_advanced` is called with every advanced item and the item it is advanced to.
     #[allow(clippy::type_complexity)]
     fn earley_complete_one_item(
         grammar: &Grammar<TI>,
+        columns: &[Arc<Column<TI, TD, TP, TSP, TS>>],
         to_be_completed_item: ToBeCompletedItem<TI, TSP>,
-        postdot_items: &AHashMap<Dotted<TI, TSP>, PostDotItems<TI, TD, TP, TSP, TS>>,
         to_be_completed_items_buffer: &mut AHashSet<ToBeCompletedItem<TI, TSP>>,
         deduplication_buffer: &mut AHashSet<EarleyItem<TI, TD, TP, TSP, TS>>,
         is_finished: &mut bool,
+        mut on_advanced: impl FnMut(EarleyItem<TI, TD, TP, TSP, TS>, EarleyItem<TI, TD, TP, TSP, TS>),
     ) {
-        if let Some(postdot) = postdot_items.get(&Dotted {
-            postdot_nonterminal_id: to_be_completed_item.nonterminal_id,
-            column: to_be_completed_item.start_position,
-        }) {
+        if let Some(postdot) = columns[to_be_completed_item.start_position.as_()]
+            .postdot_items
+            .get(&to_be_completed_item.nonterminal_id)
+        {
             match postdot {
                 PostDotItems::NormalItems(items) => {
                     for item in items.iter().copied() {
-                        Self::advance_item(
+                        let advanced_item = Self::advance_item(
                             grammar,
                             to_be_completed_items_buffer,
                             |item| {
                                 deduplication_buffer.insert(item);
                             }, // Maybe we do not need to deduplicate in to_be_completed_items_buffer. Profiling is needed.
                             item,
-                        )
+                        );
+                        on_advanced(item, advanced_item);
                     }
                 }
                 PostDotItems::LeoEligible(_) => {
@@ -1241,112 +1818,141 @@ where
         }
     }
 
+    /// Completes the items in `to_be_completed_items` into the new column.
+    ///
+    /// `on_complete` is called with every completed nonterminal and the byte offset where it starts,
+    /// including the nonterminals folded by the Leo items.
+    /// Following a chain of Leo items takes time linear in its length,
+    /// so `on_complete` is [None] when the completed nonterminals are not needed.
+    /// The back-pointers of the advanced items and the followed chains are recorded in `chart` if any.
     fn complete(
         grammar: &Grammar<TI>,
-        earley_sets: &mut EarleySets<TI, TD, TP, TSP, TS>,
-        to_be_completed_items: &mut AHashSet<ToBeCompletedItem<TI, TSP>>,
-        to_be_completed_items_buffer: &mut AHashSet<ToBeCompletedItem<TI, TSP>>,
-        leo_items: &mut AHashMap<Dotted<TI, TSP>, ToBeCompletedItem<TI, TSP>>,
-        leo_items_buffer: &mut Vec<ToBeCompletedItem<TI, TSP>>,
-        postdot_items: &AHashMap<Dotted<TI, TSP>, PostDotItems<TI, TD, TP, TSP, TS>>,
-        deduplication_buffer: &mut AHashSet<EarleyItem<TI, TD, TP, TSP, TS>>,
+        columns: &[Arc<Column<TI, TD, TP, TSP, TS>>],
+        column: &mut Column<TI, TD, TP, TSP, TS>,
+        buffers: &mut Buffers<TI, TD, TP, TSP, TS>,
         finished: &mut bool,
+        mut on_complete: Option<impl FnMut(NonterminalID<TI>, usize)>,
+        mut chart: Option<&mut ChartColumn<TI, TD, TP, TS>>,
     ) {
+        let Buffers {
+            to_be_completed_items,
+            to_be_completed_items_buffer,
+            deduplication_buffer,
+            ..
+        } = buffers;
         to_be_completed_items_buffer.clear();
         while !to_be_completed_items.is_empty() {
             for item in to_be_completed_items.drain() {
-                if let Some(topmost_item) =
-                    Self::try_leo_complete_item(leo_items_buffer, leo_items, postdot_items, item)
-                {
-                    Self::earley_complete_one_item(
-                        grammar,
-                        topmost_item,
-                        postdot_items,
-                        to_be_completed_items_buffer,
-                        deduplication_buffer,
-                        finished,
-                    );
-                } else {
-                    Self::earley_complete_one_item(
-                        grammar,
-                        item,
-                        postdot_items,
-                        to_be_completed_items_buffer,
-                        deduplication_buffer,
-                        finished,
-                    );
+                let start = columns[item.start_position.as_()].offset;
+                let mut to_be_completed_item = item;
+                if let Some(leo_item) = Self::try_leo_complete_item(columns, item) {
+                    if let Some(on_complete) = on_complete.as_mut() {
+                        // The chain starts with the nonterminal of the item, whose start may be folded by compaction.
+                        let mut link = Some(&*leo_item.chain);
+                        while let Some(current) = link {
+                            on_complete(current.nonterminal_id, current.start);
+                            link = current.next.as_deref();
+                        }
+                    }
+                    if let Some(chart) = chart.as_deref_mut() {
+                        chart.record_leo_chain(start, leo_item.chain.clone());
+                    }
+                    to_be_completed_item = leo_item.topmost_item;
+                } else if let Some(on_complete) = on_complete.as_mut() {
+                    on_complete(item.nonterminal_id, start);
                 }
+                let previous_offset = columns[to_be_completed_item.start_position.as_()].offset;
+                Self::earley_complete_one_item(
+                    grammar,
+                    columns,
+                    to_be_completed_item,
+                    to_be_completed_items_buffer,
+                    deduplication_buffer,
+                    finished,
+                    |previous, item| {
+                        if let Some(chart) = chart.as_deref_mut() {
+                            chart.record(
+                                previous_offset,
+                                Self::chart_item(columns, column.offset, previous),
+                                Self::chart_item(columns, column.offset, item),
+                            );
+                        }
+                    },
+                );
             }
             std::mem::swap(to_be_completed_items, to_be_completed_items_buffer);
         }
-        for item in deduplication_buffer.drain() {
-            earley_sets.push_to_last_row(item);
-        }
+        column.earley_set.extend(deduplication_buffer.drain());
     }
 
-    fn revert_change(
-        earley_sets: &mut EarleySets<TI, TD, TP, TSP, TS>,
-        postdot_items: &mut AHashMap<Dotted<TI, TSP>, PostDotItems<TI, TD, TP, TSP, TS>>,
-        added_postdot_items: &mut AHashSet<Dotted<TI, TSP>>,
-        leo_items: &mut AHashMap<Dotted<TI, TSP>, ToBeCompletedItem<TI, TSP>>,
-        mut column_to_postdot_nonterminal_operation: impl FnMut(TSP),
-        earley_set_length: usize,
-        finished: &mut bool,
+    /// Removes the columns after `len` and keeps them for reuse.
+    fn truncate_columns(
+        columns: &mut Vec<Arc<Column<TI, TD, TP, TSP, TS>>>,
+        buffers: &mut Buffers<TI, TD, TP, TSP, TS>,
+        len: usize,
     ) {
-        earley_sets.truncate::<0>(earley_set_length);
-        *finished = false;
-        for postdot in added_postdot_items.iter() {
-            // interestingly, this is faster than drain
-            postdot_items.remove(postdot);
-            leo_items.remove(postdot);
-            column_to_postdot_nonterminal_operation(postdot.column);
+        while columns.len() > len {
+            // The loop condition ensures the columns are not empty
+            let column = columns.pop().unwrap();
+            buffers.recycle_column(column);
         }
-        added_postdot_items.clear();
-    }
-    #[inline]
-    fn commit_change(postdot_items_since_last_commit: &mut AHashSet<Dotted<TI, TSP>>) {
-        postdot_items_since_last_commit.clear();
     }
     #[inline]
     fn is_rejected(
-        earley_sets: &EarleySets<TI, TD, TP, TSP, TS>,
+        column: &Column<TI, TD, TP, TSP, TS>,
         to_be_completed_items: &AHashSet<ToBeCompletedItem<TI, TSP>>,
     ) -> bool {
-        earley_sets.view::<1, 1>([earley_sets.len() - 1]).is_empty()
-            && to_be_completed_items.is_empty()
+        column.earley_set.is_empty() && to_be_completed_items.is_empty()
     }
-    /// Compact the Earley sets by removing the Earley sets that are not reachable from the last Earley set
+    /// Compact the Earley sets by removing the Earley sets that are not reachable from the new Earley set
+    ///
+    /// The Leo items set and the columns removed are recorded in `changes`,
+    /// and the folded start positions are recorded in `chart` if any.
     fn compact(
-        earley_sets: &mut EarleySets<TI, TD, TP, TSP, TS>,
-        leo_items: &mut AHashMap<Dotted<TI, TSP>, ToBeCompletedItem<TI, TSP>>,
-        postdot_items: &mut AHashMap<Dotted<TI, TSP>, PostDotItems<TI, TD, TP, TSP, TS>>,
-        column_to_postdot_nonterminals: &mut AHashMap<TSP, AHashSet<NonterminalID<TI>>>,
+        columns: &mut Vec<Arc<Column<TI, TD, TP, TSP, TS>>>,
+        column: &mut Column<TI, TD, TP, TSP, TS>,
+        changes: &mut Vec<Change<TI, TD, TP, TSP, TS>>,
+        mut chart: Option<&mut ChartColumn<TI, TD, TP, TS>>,
     ) {
-        let earley_set_index = earley_sets.len() - 1;
-        let mut view = earley_sets.view_mut::<1, 1>([earley_set_index]);
-        let earley_set = view.as_slice_mut();
+        let earley_set_index = columns.len();
         let mut max_start_position = 0;
-        for item in earley_set.iter_mut() {
+        for item in column.earley_set.iter_mut() {
             let mut start_position = item.start_position.as_();
-            if let Some(leo_item) = leo_items
-                .get(&Dotted {
-                    postdot_nonterminal_id: item.nonterminal_id,
-                    column: item.start_position,
-                })
-                .copied()
+            if let Some(leo_item) = columns[start_position]
+                .leo_items
+                .get(&item.nonterminal_id)
+                .cloned()
             {
                 // the chain of leo items allows us to fold the start position
-                item.start_position = leo_item.start_position;
-                if item.nonterminal_id != leo_item.nonterminal_id {
-                    leo_items.insert(
-                        Dotted {
-                            postdot_nonterminal_id: item.nonterminal_id,
-                            column: item.start_position,
-                        },
-                        leo_item,
+                let topmost_item = leo_item.topmost_item;
+                let original_item = *item;
+                item.start_position = topmost_item.start_position;
+                if let Some(chart) = chart.as_deref_mut() {
+                    chart.record_fold(
+                        Self::chart_item(columns, column.offset, original_item),
+                        Self::chart_item(columns, column.offset, *item),
                     );
                 }
-                start_position = leo_item.start_position.as_();
+                start_position = topmost_item.start_position.as_();
+                // The Leo item is kept at the folded start position even if it leads to itself,
+                // so that its chain still records the nonterminals folded by compaction.
+                if !columns[start_position]
+                    .leo_items
+                    .get(&item.nonterminal_id)
+                    .is_some_and(|previous| {
+                        previous.topmost_item == topmost_item
+                            && Arc::ptr_eq(&previous.chain, &leo_item.chain)
+                    })
+                {
+                    let previous = Arc::make_mut(&mut columns[start_position])
+                        .leo_items
+                        .insert(item.nonterminal_id, leo_item);
+                    changes.push(Change::LeoItem {
+                        column: start_position,
+                        nonterminal_id: item.nonterminal_id,
+                        previous,
+                    });
+                }
             }
             if start_position > max_start_position {
                 max_start_position = start_position;
@@ -1355,212 +1961,870 @@ where
         if max_start_position + 1 == earley_set_index {
             return;
         }
-        earley_sets.remove_rows(max_start_position + 1..earley_set_index);
-        for index in max_start_position + 1..earley_set_index {
-            if let Some(nonterminals) = column_to_postdot_nonterminals.remove(&index.as_()) {
-                for nonterminal in nonterminals.into_iter() {
-                    let dotted: Dotted<TI, TSP> = Dotted {
-                        postdot_nonterminal_id: nonterminal,
-                        column: index.as_(),
-                    };
-                    postdot_items.remove(&dotted);
-                    leo_items.remove(&dotted);
-                }
-            }
-        }
+        changes.push(Change::Compact(columns.split_off(max_start_position + 1)));
     }
 
-    fn accept_byte(
+    /// Accepts one symbol by creating a new column after the last one.
+    ///
+    /// When the symbol is rejected, no column is created and `finished` may be set.
+    /// `on_created` is called with the new column after its completion, before it is compacted.
+    /// The Earley sets are compacted only when `compaction` holds the change log that records the compaction.
+    /// The back-pointers of the new column are pushed to `chart` if any.
+    fn accept_symbol(
         grammar: &Grammar<TI>,
-        earley_sets: &mut EarleySets<TI, TD, TP, TSP, TS>,
-        to_be_completed_items: &mut AHashSet<ToBeCompletedItem<TI, TSP>>,
-        to_be_completed_items_buffer: &mut AHashSet<ToBeCompletedItem<TI, TSP>>,
-        leo_items: &mut AHashMap<Dotted<TI, TSP>, ToBeCompletedItem<TI, TSP>>,
-        leo_items_buffer: &mut Vec<ToBeCompletedItem<TI, TSP>>,
-        postdot_items: &mut AHashMap<Dotted<TI, TSP>, PostDotItems<TI, TD, TP, TSP, TS>>,
-        added_postdot_items: &mut AHashSet<Dotted<TI, TSP>>,
-        remove_column_to_postdot_nonterminal_operation: impl FnMut(TSP),
-        insert_column_to_postdot_nonterminal: impl FnMut(Dotted<TI, TSP>),
-        already_predicted_nonterminals: &mut FixedBitSet,
-        deduplication_buffer: &mut AHashSet<EarleyItem<TI, TD, TP, TSP, TS>>,
-        previous_earley_set_length: usize,
+        columns: &mut Vec<Arc<Column<TI, TD, TP, TSP, TS>>>,
+        buffers: &mut Buffers<TI, TD, TP, TSP, TS>,
         finished: &mut bool,
-        compact: impl FnOnce(
-            &mut EarleySets<TI, TD, TP, TSP, TS>,
-            &mut AHashMap<Dotted<TI, TSP>, ToBeCompletedItem<TI, TSP>>,
-            &mut AHashMap<Dotted<TI, TSP>, PostDotItems<TI, TD, TP, TSP, TS>>,
-        ),
-        byte: u8,
+        compaction: Option<&mut Vec<Change<TI, TD, TP, TSP, TS>>>,
+        on_complete: Option<impl FnMut(NonterminalID<TI>, usize)>,
+        on_created: impl FnOnce(&[Arc<Column<TI, TD, TP, TSP, TS>>], &Column<TI, TD, TP, TSP, TS>),
+        chart: Option<&mut Chart<TI, TD, TP, TS>>,
+        symbol: InputSymbol,
     ) -> Result<(), crate::engine_like::AcceptTokenError> {
-        Self::scan(grammar, earley_sets, to_be_completed_items, byte); // scan the current Earley set and creates the next Earley set
-        if Self::is_rejected(earley_sets, to_be_completed_items) {
-            Self::revert_change(
-                earley_sets,
-                postdot_items,
-                added_postdot_items,
-                leo_items,
-                remove_column_to_postdot_nonterminal_operation,
-                previous_earley_set_length,
-                finished,
-            );
+        let mut column = buffers.take_column();
+        // SAFETY: the columns are never empty
+        let previous_column = columns.last().unwrap();
+        column.offset = previous_column.offset + symbol.len();
+        let mut chart_column = chart.is_some().then(|| ChartColumn::new(column.offset));
+        let on_scanned = |previous, item| {
+            if let Some(chart_column) = chart_column.as_mut() {
+                chart_column.record(
+                    previous_column.offset,
+                    Self::chart_item(columns, column.offset, previous),
+                    Self::chart_item(columns, column.offset, item),
+                );
+            }
+        };
+        // scan the current Earley set and creates the next Earley set
+        match symbol {
+            InputSymbol::Byte(byte) => Self::scan(
+                grammar,
+                &previous_column.earley_set,
+                &mut column.earley_set,
+                &mut buffers.to_be_completed_items,
+                on_scanned,
+                byte,
+            ),
+            InputSymbol::SpecialToken(token_id, _) => Self::scan_special_token(
+                grammar,
+                &previous_column.earley_set,
+                &mut column.earley_set,
+                &mut buffers.to_be_completed_items,
+                on_scanned,
+                token_id,
+            ),
+        }
+        if Self::is_rejected(&column, &buffers.to_be_completed_items) {
+            column.clear();
+            buffers.spare_columns.push(column);
             return Err(crate::engine_like::AcceptTokenError::Rejected);
         }
         Self::complete(
             grammar,
-            earley_sets,
-            to_be_completed_items,
-            to_be_completed_items_buffer,
-            leo_items,
-            leo_items_buffer,
-            postdot_items,
-            deduplication_buffer,
+            columns,
+            &mut column,
+            buffers,
             finished,
+            on_complete,
+            chart_column.as_mut(),
         ); // complete the next Earley set
-        compact(earley_sets, leo_items, postdot_items);
-        Self::predict(grammar, earley_sets, already_predicted_nonterminals); // predict the next Earley set
-        Self::update_postdot_items(
+        on_created(columns, &column);
+        if let Some(changes) = compaction {
+            Self::compact(columns, &mut column, changes, chart_column.as_mut());
+        }
+        Self::predict(
             grammar,
-            earley_sets,
-            postdot_items,
-            added_postdot_items,
-            insert_column_to_postdot_nonterminal,
-        ); // update postdot items for the next Earley set
+            &mut column,
+            columns.len(),
+            &mut buffers.already_predicted_nonterminals,
+        ); // predict the next Earley set
+        Self::update_postdot_items(grammar, columns, &mut column); // update postdot items for the next Earley set
+        columns.push(Arc::new(column));
+        if let (Some(chart), Some(chart_column)) = (chart, chart_column) {
+            chart.push(chart_column);
+        }
         Ok(())
     }
 
-    fn add_tokens_from_eager_regex_cache(&mut self) -> bool {
-        let cache = &self.grammar.regex_to_token_ids;
-        let last_earley_set_index = self.earley_sets.len() - 1;
-        let last_earley_set = self
-            .earley_sets
-            .view::<1, 1>([last_earley_set_index])
-            .as_slice();
-        let mut changed = false;
-        for item in last_earley_set.iter().copied() {
-            let node = *self.grammar.node(
+    /// Finds the first byte of the rejected bytes that cannot be accepted and the symbols expected before it.
+    fn diagnose_rejection(&self, bytes: &[u8]) -> RejectionDiagnostic {
+        // The fork shares the Earley sets, so probing the bytes on it leaves this engine untouched.
+        let mut engine = self.fork();
+        let offset = bytes
+            .iter()
+            .position(|&byte| engine.try_accept_new_bytes(&[byte]).is_err())
+            .unwrap_or(bytes.len() - 1);
+        RejectionDiagnostic {
+            offset,
+            byte: bytes[offset],
+            expected: engine.expected_symbols(),
+        }
+    }
+
+    /// Adds the special tokens referenced by the postdot nodes of the last Earley set to `allowed_token_ids`.
+    fn add_special_tokens(&mut self) {
+        let last_earley_set = self.columns.last().unwrap().earley_set.as_slice();
+        for item in last_earley_set.iter() {
+            if let HIRNode::SpecialToken(special_token_id) = *self.grammar.node(
                 item.nonterminal_id,
                 item.dot_position,
                 item.production_index,
-            );
-            let regex_id;
-            let regex_type;
-            match node {
-                HIRNode::RegexString(id) => {
-                    regex_id = id;
-                    regex_type = RegexType::Normal;
-                }
-                HIRNode::EarlyEndRegexString(id) => {
-                    regex_id = id;
-                    regex_type = RegexType::Early;
-                }
-                HIRNode::RegexComplement(id) => {
-                    regex_id = id;
-                    regex_type = RegexType::Complement;
-                }
-                _ => continue,
-            }
-            let dfa = self.grammar.regex(regex_id);
-            let stride2 = match dfa {
-                FiniteStateAutomaton::Dfa(dfa) => dfa.stride2(),
-            };
-            let state_id = Self::from_state_id_to_dfa_state_id(item.state_id, stride2);
-            if let Some(token_ids) = cache.get(&(regex_id, state_id, regex_type)) {
-                self.allowed_token_ids.union_with(token_ids);
-                changed = true;
+            ) {
+                self.allowed_token_ids
+                    .insert(self.grammar.special_token(special_token_id) as usize);
             }
         }
-        changed
     }
 
-    fn accept_bytes(
-        grammar: &Grammar<TI>,
-        earley_sets: &mut EarleySets<TI, TD, TP, TSP, TS>,
-        to_be_completed_items: &mut AHashSet<ToBeCompletedItem<TI, TSP>>,
-        to_be_completed_items_buffer: &mut AHashSet<ToBeCompletedItem<TI, TSP>>,
-        leo_items: &mut AHashMap<Dotted<TI, TSP>, ToBeCompletedItem<TI, TSP>>,
-        leo_items_buffer: &mut Vec<ToBeCompletedItem<TI, TSP>>,
-        postdot_items: &mut AHashMap<Dotted<TI, TSP>, PostDotItems<TI, TD, TP, TSP, TS>>,
-        added_postdot_items: &mut AHashSet<Dotted<TI, TSP>>,
-        already_predicted_nonterminals: &mut FixedBitSet,
-        deduplication_buffer: &mut AHashSet<EarleyItem<TI, TD, TP, TSP, TS>>,
-        column_to_postdot_nonterminals: *mut AHashMap<TSP, AHashSet<NonterminalID<TI>>>,
-        config: &EngineConfig,
-        finished: &mut bool,
-        bytes: impl Iterator<Item = u8>,
-    ) -> Result<crate::engine_like::AcceptTokenResult, crate::engine_like::AcceptTokenError> {
-        let len = earley_sets.len();
-        if config.compaction_enabled {
-            for byte in bytes {
-                Self::accept_byte(
-                    grammar,
-                    earley_sets,
-                    to_be_completed_items,
-                    to_be_completed_items_buffer,
-                    leo_items,
-                    leo_items_buffer,
-                    postdot_items,
-                    added_postdot_items,
-                    |column| {
-                        unsafe { &mut *column_to_postdot_nonterminals }.remove(&column);
-                    },
-                    |dotted| {
-                        // SAFETY: this closure will only be called in `update_postdot_items`
-                        // and never run simultaneously with the other closures there
-                        match unsafe { &mut *column_to_postdot_nonterminals }.entry(dotted.column) {
-                            std::collections::hash_map::Entry::Occupied(mut entry) => {
-                                entry.get_mut().insert(dotted.postdot_nonterminal_id);
-                            }
-                            std::collections::hash_map::Entry::Vacant(entry) => {
-                                entry
-                                    .insert(AHashSet::new())
-                                    .insert(dotted.postdot_nonterminal_id);
-                            }
-                        };
-                    },
-                    already_predicted_nonterminals,
-                    deduplication_buffer,
-                    len,
-                    finished,
-                    |earley_sets, leo_items, postdot_items| {
-                        // SAFETY: this closure will only be called in `accept_byte`
-                        // and never run simultaneously with the closures above
-                        Self::compact(earley_sets, leo_items, postdot_items, unsafe {
-                            &mut *column_to_postdot_nonterminals
-                        })
-                    },
-                    byte,
-                )?;
-            }
-        } else {
-            for byte in bytes {
-                Self::accept_byte(
-                    grammar,
-                    earley_sets,
-                    to_be_completed_items,
-                    to_be_completed_items_buffer,
-                    leo_items,
-                    leo_items_buffer,
-                    postdot_items,
-                    added_postdot_items,
-                    |_| {},
-                    |_| {},
-                    already_predicted_nonterminals,
-                    deduplication_buffer,
-                    len,
-                    finished,
-                    |_, _, _| {},
-                    byte,
-                )?;
+    /// Computes the allowed token IDs of the grammar, reusing and filling the cache when it is enabled.
+    fn compute_allowed_token_ids_from_grammar(&mut self) {
+        let signature = self.config.cache_enabled.then(|| StateSignature {
+            digest: self.columns.last().unwrap().digest,
+        });
+        if let Some(signature) = &signature {
+            if self
+                .cache
+                .union_into(signature, &mut self.allowed_token_ids)
+            {
+                return;
             }
         }
-        Self::commit_change(added_postdot_items);
-        if *finished {
-            Ok(crate::engine_like::AcceptTokenResult::Finished)
+        self.add_tokens_from_token_classifications();
+        self.update_allowed_first_bytes();
+        #[cfg(feature = "parallel")]
+        if let Some(worker_pool) = self.worker_pool.clone() {
+            self.add_tokens_from_first_bytes_in_parallel(&worker_pool);
         } else {
-            Ok(crate::engine_like::AcceptTokenResult::Ongoing)
+            self.add_tokens_from_first_bytes();
+        }
+        #[cfg(not(feature = "parallel"))]
+        self.add_tokens_from_first_bytes();
+        self.add_special_tokens();
+        if let Some(signature) = signature {
+            self.cache.insert(
+                signature,
+                self.allowed_token_ids.clone(),
+                StateSignature::entry_size(self.allowed_token_ids.len()),
+                &self.config.cache_config,
+            );
         }
     }
-}
+
+    /// Sets the EOS token IDs in `self.allowed_token_ids` according to whether the engine is accepting.
+    ///
+    /// The EOS tokens are not cached since they only depend on whether the engine is accepting.
+    fn update_allowed_eos_token_ids(&mut self) {
+        if self.termination_config.mode == TerminationMode::Eos {
+            for &token_id in self.termination_config.eos_token_ids.iter() {
+                if self.is_eos_token(token_id) {
+                    self.allowed_token_ids.set(token_id as usize, self.finished);
+                }
+            }
+        }
+    }
+
+    /// Computes the allowed token IDs after the allowed token IDs from last computation were computed
+    /// for a last column with `digest`.
+    ///
+    /// The token IDs from the grammar only depend on the digest of the last column,
+    /// so they are kept as they are when the digest is unchanged and only the EOS token IDs are updated.
+    /// `digest` is set to the digest of the last column afterwards, or [None] if the engine is finished.
+    fn update_allowed_token_ids(&mut self, digest: &mut Option<u128>) {
+        let current = (!self.is_finished()).then(|| self.columns.last().unwrap().digest);
+        if current.is_some() && current == *digest {
+            self.update_allowed_eos_token_ids();
+        } else {
+            self.compute_allowed_token_ids();
+            *digest = current;
+        }
+    }
+
+    /// Checks if `token_id` is an EOS token in [`TerminationMode::Eos`].
+    fn is_eos_token(&self, token_id: u32) -> bool {
+        self.termination_config.mode == TerminationMode::Eos
+            && self.termination_config.eos_token_ids.contains(&token_id)
+            && self.vocabulary.token(token_id).is_some()
+    }
+
+    /// Computes the minimal number of bytes the item needs to complete its nonterminal, or [usize::MAX] if it cannot.
+    fn item_min_remaining_length(
+        grammar: &Grammar<TI>,
+        item: &EarleyItem<TI, TD, TP, TSP, TS>,
+    ) -> usize {
+        let node = *grammar.node(
+            item.nonterminal_id,
+            item.dot_position,
+            item.production_index,
+        );
+        let length = match node {
+            HIRNode::Terminal(terminal_id) => {
+                grammar.terminal(terminal_id).len() - Self::from_state_id_to_index(item.state_id)
+            }
+            HIRNode::RegexString(regex_id) | HIRNode::EarlyEndRegexString(regex_id) => grammar
+                .regex_min_length(
+                    regex_id,
+                    grammar
+                        .regex(regex_id)
+                        .dfa_state_id(Self::from_state_id_to_regex_state(item.state_id)),
+                ),
+            HIRNode::RegexComplement(regex_id) => grammar.regex_complement_min_length(
+                regex_id,
+                grammar
+                    .regex(regex_id)
+                    .dfa_state_id(Self::from_state_id_to_regex_state(item.state_id)),
+            ),
+            HIRNode::Substrings(suffix_automata_id) => grammar.suffix_automaton_min_length(
+                suffix_automata_id,
+                Self::from_state_id_to_suffix_automaton_node_id(item.state_id),
+            ),
+            HIRNode::Nonterminal(_) | HIRNode::SpecialToken(_) => grammar.node_min_length(node),
+        };
+        length.saturating_add(grammar.production_suffix_min_length(
+            item.nonterminal_id,
+            item.dot_position.as_() + 1,
+            item.production_index.as_(),
+        ))
+    }
+
+    /// Computes the minimal number of bytes needed to complete the start nonterminal from the last Earley set,
+    /// or [usize::MAX] if it cannot be completed.
+    ///
+    /// An item in the last Earley set completes its nonterminal after its remaining nodes,
+    /// and a completed nonterminal advances the items waiting for it,
+    /// so the minimal number is the length of the shortest path to the completed start nonterminal,
+    /// which is found by Dijkstra's algorithm.
+    fn min_completion_length(
+        grammar: &Grammar<TI>,
+        columns: &[Arc<Column<TI, TD, TP, TSP, TS>>],
+        finished: bool,
+    ) -> usize {
+        if finished {
+            return 0;
+        }
+        let last_earley_set_index = columns.len() - 1;
+        let mut heap = BinaryHeap::new();
+        for item in columns[last_earley_set_index].earley_set.iter() {
+            let start_position: usize = item.start_position.as_();
+            // A predicted item never needs fewer bytes than the item predicting it.
+            if start_position == last_earley_set_index && start_position != 0 {
+                continue;
+            }
+            let length = Self::item_min_remaining_length(grammar, item);
+            if length != usize::MAX {
+                heap.push(Reverse((
+                    length,
+                    item.nonterminal_id.0.as_(),
+                    start_position,
+                )));
+            }
+        }
+        let mut visited = AHashSet::default();
+        while let Some(Reverse((length, nonterminal_id, start_position))) = heap.pop() {
+            if !visited.insert((nonterminal_id, start_position)) {
+                continue;
+            }
+            let nonterminal_id = NonterminalID(nonterminal_id.as_());
+            if nonterminal_id == grammar.get_start_nonterminal_id() && start_position == 0 {
+                return length;
+            }
+            let column = &columns[start_position];
+            // Mirror how the completion follows the Leo items.
+            if let Some(leo_item) = column.leo_items.get(&nonterminal_id).filter(|leo_item| {
+                leo_item.topmost_item.nonterminal_id != nonterminal_id
+                    || leo_item.topmost_item.start_position.as_() != start_position
+            }) {
+                heap.push(Reverse((
+                    length,
+                    leo_item.topmost_item.nonterminal_id.0.as_(),
+                    leo_item.topmost_item.start_position.as_(),
+                )));
+                continue;
+            }
+            match column.postdot_items.get(&nonterminal_id) {
+                Some(PostDotItems::LeoEligible(item)) => {
+                    heap.push(Reverse((
+                        length,
+                        item.nonterminal_id.0.as_(),
+                        item.start_position.as_(),
+                    )));
+                }
+                Some(PostDotItems::NormalItems(items)) => {
+                    for item in items.iter() {
+                        let suffix_length = grammar.production_suffix_min_length(
+                            item.nonterminal_id,
+                            item.dot_position.as_() + 1,
+                            item.production_index.as_(),
+                        );
+                        if suffix_length != usize::MAX {
+                            heap.push(Reverse((
+                                length.saturating_add(suffix_length),
+                                item.nonterminal_id.0.as_(),
+                                item.start_position.as_(),
+                            )));
+                        }
+                    }
+                }
+                None => {}
+            }
+        }
+        usize::MAX
+    }
+
+    /// Removes the token IDs from `self.allowed_token_ids` after which the start nonterminal cannot be completed
+    /// within `max_remaining_bytes` bytes, counting the bytes of the token itself.
+    fn remove_tokens_over_budget(&mut self, max_remaining_bytes: usize) {
+        let mut within_budget = FixedBitSet::with_capacity(self.allowed_token_ids.len());
+        if self.termination_config.mode == TerminationMode::Eos {
+            // The EOS tokens add no bytes and are only allowed when the engine is accepting.
+            for &token_id in self.termination_config.eos_token_ids.iter() {
+                if self.is_eos_token(token_id) {
+                    within_budget.insert(token_id as usize);
+                }
+            }
+        }
+        self.update_allowed_first_bytes();
+        for byte in self.allowed_first_bytes.ones() {
+            Self::add_tokens_within_budget_from_first_byte(
+                &self.grammar,
+                &self.vocabulary,
+                &mut self.columns,
+                &mut self.buffers,
+                max_remaining_bytes,
+                &mut within_budget,
+                byte as u8,
+            );
+        }
+        let original_len = self.columns.len();
+        for token_id in self.allowed_token_ids.ones() {
+            if within_budget.contains(token_id)
+                || !self.vocabulary.is_special_token(token_id as u32)
+            {
+                continue;
+            }
+            let length = self
+                .vocabulary
+                .token(token_id as u32)
+                .map_or(0, |token| token.0.len());
+            let mut finished = false;
+            if Self::accept_symbol(
+                &self.grammar,
+                &mut self.columns,
+                &mut self.buffers,
+                &mut finished,
+                None,
+                None::<fn(_, _)>,
+                |_, _| {},
+                None,
+                InputSymbol::SpecialToken(token_id as u32, length),
+            )
+            .is_err()
+            {
+                continue;
+            }
+            let min_completion_length =
+                Self::min_completion_length(&self.grammar, &self.columns, finished);
+            if length.saturating_add(min_completion_length) <= max_remaining_bytes {
+                within_budget.insert(token_id);
+            }
+            Self::truncate_columns(&mut self.columns, &mut self.buffers, original_len);
+        }
+        self.allowed_token_ids.intersect_with(&within_budget);
+    }
+
+    /// Adds the allowed token IDs starting with `byte` to `allowed_token_ids`.
+    ///
+    /// The columns pushed for the simulation are removed before returning.
+    /// Only the subtrees of the token trie that contain tokens in `undetermined_token_positions` are simulated.
+    fn add_tokens_from_first_byte(
+        grammar: &Grammar<TI>,
+        vocabulary: &Vocabulary,
+        columns: &mut Vec<Arc<Column<TI, TD, TP, TSP, TS>>>,
+        buffers: &mut Buffers<TI, TD, TP, TSP, TS>,
+        undetermined_token_positions: &FixedBitSet,
+        allowed_token_ids: &mut FixedBitSet,
+        byte: u8,
+    ) {
+        let nodes = vocabulary.token_trie(byte);
+        if nodes.first().is_none_or(|root| {
+            !undetermined_token_positions
+                .contains_any_in_range(vocabulary.subtree_token_positions(root))
+        }) {
+            return;
+        }
+        let original_len = columns.len();
+        // The simulation must not change whether the engine is accepting.
+        let mut finished = false;
+        Self::accept_symbol(
+            grammar,
+            columns,
+            buffers,
+            &mut finished,
+            None,
+            None::<fn(_, _)>,
+            |_, _| {},
+            None,
+            InputSymbol::Byte(byte),
+        )
+        .unwrap();
+        // The root represents the first byte, which is already accepted.
+        for &token_id in vocabulary.token_ids_of_trie_node(&nodes[0]) {
+            allowed_token_ids.insert(token_id as usize);
+        }
+        let mut i = 1;
+        while i < nodes.len() {
+            let node = &nodes[i];
+            if !undetermined_token_positions
+                .contains_any_in_range(vocabulary.subtree_token_positions(node))
+            {
+                // Every token in the subtree is already classified
+                i = node.skip as usize;
+                continue;
+            }
+            // Backtrack to the parent of the node
+            Self::truncate_columns(columns, buffers, original_len + node.depth as usize);
+            if Self::accept_symbol(
+                grammar,
+                columns,
+                buffers,
+                &mut finished,
+                None,
+                None::<fn(_, _)>,
+                |_, _| {},
+                None,
+                InputSymbol::Byte(node.byte),
+            )
+            .is_err()
+            {
+                // Every token in the subtree shares the rejected prefix
+                i = node.skip as usize;
+                continue;
+            }
+            for &token_id in vocabulary.token_ids_of_trie_node(node) {
+                allowed_token_ids.insert(token_id as usize);
+            }
+            i += 1;
+        }
+        Self::truncate_columns(columns, buffers, original_len);
+    }
+
+    /// Adds the token IDs starting with `byte` to `within_budget`
+    /// if the start nonterminal can be completed within `max_remaining_bytes` bytes after them, counting their own bytes.
+    ///
+    /// The columns pushed for the simulation are removed before returning.
+    /// A completion after a token is also a completion after each of its prefixes,
+    /// so the subtree of a trie node is skipped once the node does not fit in the budget.
+    fn add_tokens_within_budget_from_first_byte(
+        grammar: &Grammar<TI>,
+        vocabulary: &Vocabulary,
+        columns: &mut Vec<Arc<Column<TI, TD, TP, TSP, TS>>>,
+        buffers: &mut Buffers<TI, TD, TP, TSP, TS>,
+        max_remaining_bytes: usize,
+        within_budget: &mut FixedBitSet,
+        byte: u8,
+    ) {
+        let nodes = vocabulary.token_trie(byte);
+        if nodes.is_empty() {
+            return;
+        }
+        let original_len = columns.len();
+        // Whether the start nonterminal is completed at the last Earley set
+        let mut finished = false;
+        if Self::accept_symbol(
+            grammar,
+            columns,
+            buffers,
+            &mut finished,
+            None,
+            None::<fn(_, _)>,
+            |_, _| {},
+            None,
+            InputSymbol::Byte(byte),
+        )
+        .is_err()
+        {
+            return;
+        }
+        let fits = |columns: &[_], finished, length: usize| {
+            length.saturating_add(Self::min_completion_length(grammar, columns, finished))
+                <= max_remaining_bytes
+        };
+        if fits(columns, finished, 1) {
+            // The root represents the first byte, which is already accepted.
+            for &token_id in vocabulary.token_ids_of_trie_node(&nodes[0]) {
+                within_budget.insert(token_id as usize);
+            }
+            let mut i = 1;
+            while i < nodes.len() {
+                let node = &nodes[i];
+                // Backtrack to the parent of the node
+                Self::truncate_columns(columns, buffers, original_len + node.depth as usize);
+                finished = false;
+                if Self::accept_symbol(
+                    grammar,
+                    columns,
+                    buffers,
+                    &mut finished,
+                    None,
+                    None::<fn(_, _)>,
+                    |_, _| {},
+                    None,
+                    InputSymbol::Byte(node.byte),
+                )
+                .is_err()
+                {
+                    // Every token in the subtree shares the rejected prefix
+                    i = node.skip as usize;
+                    continue;
+                }
+                // The node is below the root, so its bytes include the first byte.
+                if !fits(columns, finished, node.depth as usize + 1) {
+                    // Every token in the subtree needs at least as many bytes as the node
+                    i = node.skip as usize;
+                    continue;
+                }
+                for &token_id in vocabulary.token_ids_of_trie_node(node) {
+                    within_budget.insert(token_id as usize);
+                }
+                i += 1;
+            }
+        }
+        Self::truncate_columns(columns, buffers, original_len);
+    }
+
+    /// Adds the allowed token IDs starting with any of the allowed first bytes to `self.allowed_token_ids`.
+    fn add_tokens_from_first_bytes(&mut self) {
+        for byte in self.allowed_first_bytes.ones() {
+            Self::add_tokens_from_first_byte(
+                &self.grammar,
+                &self.vocabulary,
+                &mut self.columns,
+                &mut self.buffers,
+                &self.undetermined_token_positions,
+                &mut self.allowed_token_ids,
+                byte as u8,
+            );
+        }
+    }
+
+    /// Adds the allowed token IDs starting with any of the allowed first bytes to `self.allowed_token_ids`,
+    /// partitioning the first bytes across the threads of the worker pool.
+    ///
+    /// Each thread simulates the tokens on its own list of the shared columns with its own buffers,
+    /// so the result is identical to [`EngineBase::add_tokens_from_first_bytes`].
+    #[cfg(feature = "parallel")]
+    fn add_tokens_from_first_bytes_in_parallel(&mut self, worker_pool: &WorkerPool) {
+        let first_bytes: Arc<[u8]> = self
+            .allowed_first_bytes
+            .ones()
+            .map(|byte| byte as u8)
+            .collect();
+        let worker_threads = worker_pool.threads().min(first_bytes.len());
+        if worker_threads <= 1 {
+            self.add_tokens_from_first_bytes();
+            return;
+        }
+        let next_byte_index = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let undetermined_token_positions = Arc::new(self.undetermined_token_positions.clone());
+        let jobs = (0..worker_threads).map(|_| {
+            let first_bytes = first_bytes.clone();
+            let next_byte_index = next_byte_index.clone();
+            let grammar = self.grammar.clone();
+            let vocabulary = self.vocabulary.clone();
+            let mut columns = self.columns.clone();
+            let undetermined_token_positions = undetermined_token_positions.clone();
+            let mut allowed_token_ids = self.allowed_token_ids.clone();
+            move || {
+                let mut buffers = Buffers::new(grammar.nonterminals_size());
+                loop {
+                    let index = next_byte_index.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    let Some(&byte) = first_bytes.get(index) else {
+                        break;
+                    };
+                    Self::add_tokens_from_first_byte(
+                        &grammar,
+                        &vocabulary,
+                        &mut columns,
+                        &mut buffers,
+                        &undetermined_token_positions,
+                        &mut allowed_token_ids,
+                        byte,
+                    );
+                }
+                allowed_token_ids
+            }
+        });
+        for token_ids in worker_pool.run(jobs) {
+            self.allowed_token_ids.union_with(&token_ids);
+        }
+    }
+
+    /// Adds the tokens accepted by the token classifications of the items in the last Earley set to `self.allowed_token_ids`,
+    /// and stores the positions of the tokens whose acceptance depends on the rest of the Earley sets
+    /// in `self.undetermined_token_positions`.
+    fn add_tokens_from_token_classifications(&mut self) {
+        self.accepted_token_positions.clear();
+        self.undetermined_token_positions.clear();
+        let last_earley_set = self.columns.last().unwrap().earley_set.as_slice();
+        let mut classified = true;
+        for item in last_earley_set.iter().copied() {
+            let node = *self.grammar.node(
+                item.nonterminal_id,
+                item.dot_position,
+                item.production_index,
+            );
+            let state = match node {
+                HIRNode::Terminal(_) => Self::from_state_id_to_index(item.state_id),
+                HIRNode::RegexString(regex_id)
+                | HIRNode::EarlyEndRegexString(regex_id)
+                | HIRNode::RegexComplement(regex_id) => self
+                    .grammar
+                    .regex(regex_id)
+                    .dfa_state_id(Self::from_state_id_to_regex_state(item.state_id))
+                    .as_usize(),
+                HIRNode::Substrings(_) => {
+                    Self::from_state_id_to_suffix_automaton_node_id(item.state_id)
+                }
+                HIRNode::Nonterminal(_) | HIRNode::SpecialToken(_) => continue,
+            };
+            match self.grammar.token_classifications.get(&(node, state)) {
+                Some(classification) => {
+                    classification
+                        .accepted
+                        .union_into(&mut self.accepted_token_positions);
+                    classification
+                        .undetermined
+                        .union_into(&mut self.undetermined_token_positions);
+                }
+                // Without the classification, none of the tokens can be ruled out.
+                None => classified = false,
+            }
+        }
+        if !classified {
+            self.undetermined_token_positions.insert_range(..);
+        }
+        // A token accepted within one node is allowed whatever the other nodes do with it.
+        self.undetermined_token_positions
+            .difference_with(&self.accepted_token_positions);
+        for position in self.accepted_token_positions.ones() {
+            self.allowed_token_ids
+                .insert(self.vocabulary.token_id_at_position(position) as usize);
+        }
+    }
+
+    fn record_completed_nonterminal(
+        subscribed_nonterminals: &FixedBitSet,
+        first_byte_nonterminals: &mut FixedBitSet,
+        pending_nonterminal_events: &mut Vec<PendingNonterminalEvent<TI>>,
+        nonterminal_id: NonterminalID<TI>,
+        start: usize,
+        previous_offset: usize,
+        end: usize,
+    ) {
+        if start == previous_offset {
+            first_byte_nonterminals.insert(nonterminal_id.0.as_());
+        }
+        if subscribed_nonterminals.contains(nonterminal_id.0.as_()) {
+            pending_nonterminal_events.push(PendingNonterminalEvent {
+                kind: NonterminalEventKind::Completed,
+                nonterminal_id,
+                start,
+                end,
+            });
+        }
+    }
+
+    /// Records the subscribed nonterminals that are expected at the previous Earley set
+    /// and begin with a nonterminal that has consumed the last byte.
+    fn record_started_nonterminals(
+        grammar: &Grammar<TI>,
+        columns: &[Arc<Column<TI, TD, TP, TSP, TS>>],
+        column: &Column<TI, TD, TP, TSP, TS>,
+        subscribed_left_corners: &[(NonterminalID<TI>, FixedBitSet)],
+        first_byte_nonterminals: &mut FixedBitSet,
+        pending_nonterminal_events: &mut Vec<PendingNonterminalEvent<TI>>,
+    ) {
+        let previous_column_index = columns.len() - 1;
+        for item in column.earley_set.iter() {
+            if item.start_position.as_() == previous_column_index {
+                first_byte_nonterminals.insert(item.nonterminal_id.0.as_());
+            }
+        }
+        if first_byte_nonterminals.is_clear() {
+            return;
+        }
+        let previous_column = &columns[previous_column_index];
+        for (nonterminal_id, left_corners) in subscribed_left_corners.iter() {
+            let expected = previous_column.postdot_items.contains_key(nonterminal_id)
+                || (previous_column_index == 0
+                    && *nonterminal_id == grammar.get_start_nonterminal_id());
+            if expected && !left_corners.is_disjoint(first_byte_nonterminals) {
+                pending_nonterminal_events.push(PendingNonterminalEvent {
+                    kind: NonterminalEventKind::Started,
+                    nonterminal_id: *nonterminal_id,
+                    start: previous_column.offset,
+                    end: column.offset,
+                });
+            }
+        }
+        first_byte_nonterminals.clear();
+    }
+
+    /// Emits the events recorded while accepting a token if the token is accepted and discards them otherwise.
+    fn commit_nonterminal_events(
+        grammar: &Grammar<TI>,
+        pending_nonterminal_events: &mut Vec<PendingNonterminalEvent<TI>>,
+        nonterminal_events: &mut Vec<NonterminalEvent>,
+        accepted: bool,
+    ) {
+        if !accepted {
+            pending_nonterminal_events.clear();
+            return;
+        }
+        // A nonterminal may start and complete at the same byte, in which case it starts first.
+        pending_nonterminal_events.sort_by_key(|event| (event.end, event.kind));
+        for event in pending_nonterminal_events.drain(..) {
+            nonterminal_events.push(NonterminalEvent {
+                kind: event.kind,
+                nonterminal: grammar
+                    .nonterminal_str(event.nonterminal_id)
+                    .unwrap_or_default()
+                    .to_string(),
+                start: event.start,
+                end: event.end,
+            });
+        }
+    }
+
+    fn left_corners(grammar: &Grammar<TI>, nonterminal_id: NonterminalID<TI>) -> FixedBitSet {
+        let mut left_corners = FixedBitSet::with_capacity(grammar.nonterminals_size());
+        let mut stack = vec![nonterminal_id];
+        left_corners.insert(nonterminal_id.0.as_());
+        while let Some(nonterminal_id) = stack.pop() {
+            // SAFETY: nonterminal_id always comes from the grammar
+            let view = unsafe { grammar.dotted_productions(nonterminal_id) };
+            for node in view.view::<1, 1>([0]).as_slice() {
+                if let &HIRNode::Nonterminal(id) = node {
+                    if !left_corners.put(id.0.as_()) {
+                        stack.push(id);
+                    }
+                }
+            }
+        }
+        left_corners
+    }
+
+    /// Undoes the changes recorded after `position` in the change log, from the latest to the earliest.
+    fn undo_changes(&mut self, position: usize) {
+        while self.changes.len() > position {
+            // SAFETY: the change log is longer than `position`
+            match self.changes.pop().unwrap() {
+                Change::PushColumn => {
+                    // SAFETY: every pushed column is recorded after the column before it
+                    let column = self.columns.pop().unwrap();
+                    self.buffers.recycle_column(column);
+                    if self.config.parse_tree_enabled {
+                        self.chart.pop();
+                    }
+                }
+                Change::Compact(removed_columns) => self.columns.extend(removed_columns),
+                Change::LeoItem {
+                    column,
+                    nonterminal_id,
+                    previous,
+                } => {
+                    let leo_items = &mut Arc::make_mut(&mut self.columns[column]).leo_items;
+                    match previous {
+                        Some(leo_item) => leo_items.insert(nonterminal_id, leo_item),
+                        None => leo_items.remove(&nonterminal_id),
+                    };
+                }
+                Change::Finished(finished) => self.finished = finished,
+                Change::EosAccepted(eos_accepted) => self.eos_accepted = eos_accepted,
+            }
+        }
+    }
+
+    /// Drops the change log when there is no checkpoint to roll back to.
+    fn forget_changes(&mut self) {
+        if self.checkpoints.is_empty() {
+            self.changes.clear();
+        }
+    }
+
+    /// Accepts the symbols one by one, recording the changes in `changes`.
+    ///
+    /// When a symbol is rejected, the changes made by the symbols before it are left for the caller to undo.
+    fn accept_symbols(
+        grammar: &Grammar<TI>,
+        columns: &mut Vec<Arc<Column<TI, TD, TP, TSP, TS>>>,
+        buffers: &mut Buffers<TI, TD, TP, TSP, TS>,
+        changes: &mut Vec<Change<TI, TD, TP, TSP, TS>>,
+        chart: &mut Chart<TI, TD, TP, TS>,
+        subscribed_nonterminals: &FixedBitSet,
+        subscribed_left_corners: &[(NonterminalID<TI>, FixedBitSet)],
+        first_byte_nonterminals: *mut FixedBitSet,
+        pending_nonterminal_events: *mut Vec<PendingNonterminalEvent<TI>>,
+        config: &EngineConfig,
+        eager: bool,
+        finished: &mut bool,
+        symbols: impl Iterator<Item = InputSymbol>,
+    ) -> Result<crate::engine_like::AcceptTokenResult, crate::engine_like::AcceptTokenError> {
+        changes.push(Change::Finished(*finished));
+        let events_enabled = !subscribed_left_corners.is_empty();
+        for symbol in symbols {
+            if !eager {
+                // Only the last Earley set determines whether the engine is accepting.
+                *finished = false;
+            }
+            // SAFETY: the columns are never empty
+            let previous_offset = columns.last().unwrap().offset;
+            let end = previous_offset + symbol.len();
+            // SAFETY: the pointers are only dereferenced in the closures below,
+            // which never run simultaneously
+            let on_complete = events_enabled.then_some(|nonterminal_id, start| {
+                Self::record_completed_nonterminal(
+                    subscribed_nonterminals,
+                    unsafe { &mut *first_byte_nonterminals },
+                    unsafe { &mut *pending_nonterminal_events },
+                    nonterminal_id,
+                    start,
+                    previous_offset,
+                    end,
+                );
+            });
+            let on_created = |columns: &[_], column: &_| {
+                if events_enabled {
+                    Self::record_started_nonterminals(
+                        grammar,
+                        columns,
+                        column,
+                        subscribed_left_corners,
+                        unsafe { &mut *first_byte_nonterminals },
+                        unsafe { &mut *pending_nonterminal_events },
+                    );
+                }
+            };
+            Self::accept_symbol(
+                grammar,
+                columns,
+                buffers,
+                finished,
+                config.compaction_enabled.then_some(&mut *changes),
+                on_complete,
+                on_created,
+                config.parse_tree_enabled.then_some(&mut *chart),
+                symbol,
+            )?;
+            Self::update_digests(columns, buffers);
+            changes.push(Change::PushColumn);
+        }
+        if eager && *finished {
+            Ok(crate::engine_like::AcceptTokenResult::Finished)
+        } else {
+            Ok(crate::engine_like::AcceptTokenResult::Ongoing)
+        }
+    }
+}
 
 impl<TI, TD, TP, TSP, TS> crate::engine_like::sealed::Sealed for EngineBase<TI, TD, TP, TSP, TS>
 where
@@ -1608,6 +2872,11 @@ where
     TP: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
     TSP: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
     TS: Num + AsPrimitive<usize> + ConstOne + ConstZero + Eq + std::hash::Hash + PartialEq,
+    TI: Send + Sync,
+    TD: Send + Sync,
+    TP: Send + Sync,
+    TSP: Send + Sync,
+    TS: Send + Sync,
     usize: num::traits::AsPrimitive<TI>
         + num::traits::AsPrimitive<TD>
         + num::traits::AsPrimitive<TP>
@@ -1621,28 +2890,63 @@ where
         if self.is_finished() {
             return Err(crate::engine_like::AcceptTokenError::Finished);
         }
-        let token = match self.vocabulary.token(token_id) {
+        let vocabulary = self.vocabulary.clone();
+        let token = match vocabulary.token(token_id) {
             Some(token) => token,
             None => return Err(crate::engine_like::AcceptTokenError::UnknownTokenID),
         };
-        let token_iter = token.0.iter().copied();
-        let ptr = &mut self.column_to_postdot_nonterminals as *mut _;
-        Self::accept_bytes(
+        if self.is_eos_token(token_id) {
+            // The EOS token is not part of the input and only ends the generation.
+            if !self.finished {
+                return Err(crate::engine_like::AcceptTokenError::Rejected);
+            }
+            self.changes.push(Change::EosAccepted(self.eos_accepted));
+            self.eos_accepted = true;
+            self.forget_changes();
+            return Ok(crate::engine_like::AcceptTokenResult::Finished);
+        }
+        // A special token is accepted as a whole rather than as the bytes of its content.
+        let special_token = self
+            .vocabulary
+            .is_special_token(token_id)
+            .then_some(InputSymbol::SpecialToken(token_id, token.0.len()));
+        let bytes: &[u8] = if special_token.is_some() {
+            &[]
+        } else {
+            &token.0
+        };
+        let symbols = special_token
+            .into_iter()
+            .chain(bytes.iter().copied().map(InputSymbol::Byte));
+        // A rejected token leaves the engine as if it had never been tried.
+        let position = self.changes.len();
+        let result = Self::accept_symbols(
             &self.grammar,
-            &mut self.earley_sets,
-            &mut self.to_be_completed_items,
-            &mut self.to_be_completed_items_buffer,
-            &mut self.leo_items,
-            &mut self.leo_items_buffer,
-            &mut self.postdot_items,
-            &mut self.postdot_items_since_last_commit,
-            &mut self.already_predicted_nonterminals,
-            &mut self.deduplication_buffer,
-            ptr,
+            &mut self.columns,
+            &mut self.buffers,
+            &mut self.changes,
+            &mut self.chart,
+            &self.subscribed_nonterminals,
+            &self.subscribed_left_corners,
+            &mut self.first_byte_nonterminals,
+            &mut self.pending_nonterminal_events,
             &self.config,
+            self.termination_config.mode == TerminationMode::Eager,
             &mut self.finished,
-            token_iter,
-        )
+            symbols,
+        );
+        Self::commit_nonterminal_events(
+            &self.grammar,
+            &mut self.pending_nonterminal_events,
+            &mut self.nonterminal_events,
+            result.is_ok(),
+        );
+        if result.is_err() {
+            self.undo_changes(position);
+        }
+        let result = result?;
+        self.forget_changes();
+        Ok(result)
     }
 
     fn try_accept_new_bytes(
@@ -1652,24 +2956,80 @@ where
         if self.is_finished() {
             return Err(crate::engine_like::AcceptTokenError::Finished);
         }
-        let ptr = &mut self.column_to_postdot_nonterminals
-            as *mut AHashMap<TSP, AHashSet<NonterminalID<TI>>>;
-        Self::accept_bytes(
+        // A rejected token leaves the engine as if it had never been tried.
+        let position = self.changes.len();
+        let result = Self::accept_symbols(
             &self.grammar,
-            &mut self.earley_sets,
-            &mut self.to_be_completed_items,
-            &mut self.to_be_completed_items_buffer,
-            &mut self.leo_items,
-            &mut self.leo_items_buffer,
-            &mut self.postdot_items,
-            &mut self.postdot_items_since_last_commit,
-            &mut self.already_predicted_nonterminals,
-            &mut self.deduplication_buffer,
-            ptr,
+            &mut self.columns,
+            &mut self.buffers,
+            &mut self.changes,
+            &mut self.chart,
+            &self.subscribed_nonterminals,
+            &self.subscribed_left_corners,
+            &mut self.first_byte_nonterminals,
+            &mut self.pending_nonterminal_events,
             &self.config,
+            self.termination_config.mode == TerminationMode::Eager,
             &mut self.finished,
-            bytes.iter().copied(),
-        )
+            bytes.iter().copied().map(InputSymbol::Byte),
+        );
+        Self::commit_nonterminal_events(
+            &self.grammar,
+            &mut self.pending_nonterminal_events,
+            &mut self.nonterminal_events,
+            result.is_ok(),
+        );
+        if result.is_err() {
+            self.undo_changes(position);
+        }
+        let result = result?;
+        self.forget_changes();
+        Ok(result)
+    }
+
+    fn try_accept_new_token_with_diagnostic(
+        &mut self,
+        token_id: u32,
+    ) -> Result<AcceptTokenResult, AcceptTokenDiagnosticError> {
+        match self.try_accept_new_token(token_id) {
+            Ok(result) => Ok(result),
+            Err(AcceptTokenError::UnknownTokenID) => {
+                Err(AcceptTokenDiagnosticError::UnknownTokenID)
+            }
+            Err(AcceptTokenError::Finished) => Err(AcceptTokenDiagnosticError::Finished),
+            Err(AcceptTokenError::Rejected) => {
+                let vocabulary = self.vocabulary.clone();
+                // The token exists since it is rejected rather than unknown.
+                let token = vocabulary.token(token_id).unwrap();
+                if vocabulary.is_special_token(token_id) {
+                    // A special token is rejected as a whole, so the rejection is at its first byte.
+                    return Err(AcceptTokenDiagnosticError::Rejected(RejectionDiagnostic {
+                        offset: 0,
+                        byte: token.0.first().copied().unwrap_or_default(),
+                        expected: self.expected_symbols(),
+                    }));
+                }
+                Err(AcceptTokenDiagnosticError::Rejected(
+                    self.diagnose_rejection(&token.0),
+                ))
+            }
+        }
+    }
+
+    fn try_accept_new_bytes_with_diagnostic(
+        &mut self,
+        bytes: &[u8],
+    ) -> Result<AcceptTokenResult, AcceptTokenDiagnosticError> {
+        match self.try_accept_new_bytes(bytes) {
+            Ok(result) => Ok(result),
+            Err(AcceptTokenError::UnknownTokenID) => {
+                Err(AcceptTokenDiagnosticError::UnknownTokenID)
+            }
+            Err(AcceptTokenError::Finished) => Err(AcceptTokenDiagnosticError::Finished),
+            Err(AcceptTokenError::Rejected) => Err(AcceptTokenDiagnosticError::Rejected(
+                self.diagnose_rejection(bytes),
+            )),
+        }
     }
 
     fn compute_allowed_token_ids(&mut self) {
@@ -1677,186 +3037,130 @@ where
         if self.is_finished() {
             return;
         }
-        if self.config.cache_enabled {
-            if let Some(allowed_ids) = self.cache.get(&self.earley_sets) {
-                self.allowed_token_ids.union_with(allowed_ids);
-                return;
-            }
-        }
-        let mut eager_cache = false;
-        if !self.grammar.regex_to_token_ids.is_empty() {
-            eager_cache = self.add_tokens_from_eager_regex_cache();
+        self.compute_allowed_token_ids_from_grammar();
+        self.update_allowed_eos_token_ids();
+    }
+
+    fn compute_allowed_token_ids_with_budget(&mut self, max_remaining_bytes: usize) {
+        self.compute_allowed_token_ids();
+        if !self.allowed_token_ids.is_clear() {
+            self.remove_tokens_over_budget(max_remaining_bytes);
         }
-        let original_earley_set_len = self.earley_sets.len();
-        self.update_allowed_first_bytes();
-        let mut invalid_next_bytes = ByteSet::with_capacity(256);
-        for byte in self.allowed_first_bytes.ones() {
-            invalid_next_bytes.clear();
-            Self::accept_byte(
-                &self.grammar,
-                &mut self.earley_sets,
-                &mut self.to_be_completed_items,
-                &mut self.to_be_completed_items_buffer,
-                &mut self.leo_items,
-                &mut self.leo_items_buffer,
-                &mut self.postdot_items,
-                &mut self.postdot_items_since_last_commit,
-                |_| {},
-                |_| {},
-                &mut self.already_predicted_nonterminals,
-                &mut self.deduplication_buffer,
-                original_earley_set_len,
-                &mut self.finished,
-                |_, _, _| {},
-                byte as u8,
-            )
-            .unwrap();
-            let mut staged_changes = StagedChanges {
-                earley_sets_len_since_last_commit: original_earley_set_len,
-                postdot_items_since_last_commit: self.postdot_items_since_last_commit.clone(),
-            };
-            let len = self.earley_sets.len();
-            Self::commit_change(&mut self.postdot_items_since_last_commit);
-            let mut current_token_id: usize = usize::MAX;
-            let mut token_iter = self.vocabulary.normal_tokens_from_first_byte(byte as u8);
-            let mut rejected = true;
-            let mut accepted = false;
-            let mut second_byte_unseen = false;
-            while let Some(token_byte) = token_iter.next() {
-                match token_byte {
-                    TokenIterItem::TokenByte(token_byte) => {
-                        let token_byte = token_byte.get();
-                        if second_byte_unseen
-                        // SAFETY: invalid_next_bytes preallocates 256 bytes on the stack
-                            && unsafe { invalid_next_bytes.contains_unchecked(token_byte.into()) }
-                        {
-                            rejected = true;
-                            token_iter.next_token();
-                            continue;
-                        }
-                        if Self::accept_byte(
-                            &self.grammar,
-                            &mut self.earley_sets,
-                            &mut self.to_be_completed_items,
-                            &mut self.to_be_completed_items_buffer,
-                            &mut self.leo_items,
-                            &mut self.leo_items_buffer,
-                            &mut self.postdot_items,
-                            &mut self.postdot_items_since_last_commit,
-                            |_| {},
-                            |_| {},
-                            &mut self.already_predicted_nonterminals,
-                            &mut self.deduplication_buffer,
-                            len,
-                            &mut self.finished,
-                            |_, _, _| {},
-                            token_byte,
-                        )
-                        .is_err()
-                        // The token is rejected
-                        {
-                            if second_byte_unseen {
-                                // SAFETY: invalid_next_bytes preallocates 256 bytes on the stack
-                                unsafe { invalid_next_bytes.insert_unchecked(token_byte.into()) };
-                            }
-                            rejected = true;
-                            token_iter.next_token();
-                        }
-                        second_byte_unseen = false;
-                    }
-                    TokenIterItem::NewToken => {
-                        // The token is accepted
-                        second_byte_unseen = true;
-                        if !accepted && !rejected {
-                            Self::revert_change(
-                                &mut self.earley_sets,
-                                &mut self.postdot_items,
-                                &mut self.postdot_items_since_last_commit,
-                                &mut self.leo_items,
-                                |_| {},
-                                len,
-                                &mut self.finished,
-                            );
-                            self.allowed_token_ids.insert(current_token_id);
-                        }
-                        current_token_id = token_iter.current_token_id();
-                        rejected = false;
-                        accepted = eager_cache && self.allowed_token_ids.contains(current_token_id);
-                        if accepted {
-                            token_iter.next_token();
-                        }
-                    }
+    }
+
+    fn compute_forced_bytes(&mut self, max_length: usize) -> Vec<u8> {
+        // The fork shares the Earley sets, so following the forced bytes on it leaves this engine untouched.
+        let mut engine = self.fork();
+        let mut forced_bytes = Vec::new();
+        while forced_bytes.len() < max_length && !engine.is_accepting() {
+            // A pending special token is an alternative to every byte, so no byte is forced.
+            if engine.update_allowed_first_bytes() {
+                break;
+            }
+            let byte = {
+                let mut first_bytes = engine.allowed_first_bytes.ones();
+                match (first_bytes.next(), first_bytes.next()) {
+                    (Some(byte), None) => byte as u8,
+                    _ => break,
                 }
+            };
+            if engine.try_accept_new_bytes(&[byte]).is_err() {
+                break;
             }
-            // reach the end of the token iterator, revert the last token's change
-            Self::revert_change(
-                &mut self.earley_sets,
-                &mut self.postdot_items,
-                &mut self.postdot_items_since_last_commit,
-                &mut self.leo_items,
-                |_| {},
-                len,
-                &mut self.finished,
-            );
-            if !rejected && !accepted {
-                self.allowed_token_ids.insert(current_token_id);
-            }
-            Self::revert_change(
-                &mut self.earley_sets,
-                &mut self.postdot_items,
-                &mut staged_changes.postdot_items_since_last_commit,
-                &mut self.leo_items,
-                |_| {},
-                staged_changes.earley_sets_len_since_last_commit,
-                &mut self.finished,
-            )
+            forced_bytes.push(byte);
+        }
+        forced_bytes
+    }
+
+    fn shortest_completion(&mut self) -> Option<Vec<u8>> {
+        if self.is_finished() || self.is_accepting() {
+            return Some(Vec::new());
         }
-        for (token_id, token) in self.vocabulary.tokens_containing_separators() {
-            let mut accepted = true;
-            for byte in token.0.iter().copied() {
-                if Self::accept_byte(
-                    &self.grammar,
-                    &mut self.earley_sets,
-                    &mut self.to_be_completed_items,
-                    &mut self.to_be_completed_items_buffer,
-                    &mut self.leo_items,
-                    &mut self.leo_items_buffer,
-                    &mut self.postdot_items,
-                    &mut self.postdot_items_since_last_commit,
-                    |_| {},
-                    |_| {},
-                    &mut self.already_predicted_nonterminals,
-                    &mut self.deduplication_buffer,
-                    original_earley_set_len,
-                    &mut self.finished,
-                    |_, _, _| {},
-                    byte,
+        // The fork shares the Earley sets, so following the completion on it leaves this engine untouched.
+        let mut engine = self.fork();
+        let mut min_length = Self::min_completion_length(&engine.grammar, &engine.columns, false);
+        let mut completion = Vec::new();
+        // Every step accepts the byte that leaves the fewest bytes to complete the start nonterminal.
+        // The minimal lengths are exact for bytes, so the remaining length decreases by one per step
+        // unless the shortest completion goes through a special token.
+        while min_length != 0 && min_length != usize::MAX {
+            engine.update_allowed_first_bytes();
+            let original_len = engine.columns.len();
+            let mut best = None;
+            for byte in engine.allowed_first_bytes.ones() {
+                let mut finished = false;
+                if Self::accept_symbol(
+                    &engine.grammar,
+                    &mut engine.columns,
+                    &mut engine.buffers,
+                    &mut finished,
+                    None,
+                    None::<fn(_, _)>,
+                    |_, _| {},
+                    None,
+                    InputSymbol::Byte(byte as u8),
                 )
-                .is_err()
-                // The token is rejected
+                .is_ok()
                 {
-                    accepted = false;
-                    break;
+                    let length =
+                        Self::min_completion_length(&engine.grammar, &engine.columns, finished);
+                    if length < min_length
+                        && best.is_none_or(|(_, best_length)| length < best_length)
+                    {
+                        best = Some((byte as u8, length));
+                    }
+                    Self::truncate_columns(&mut engine.columns, &mut engine.buffers, original_len);
                 }
             }
-            if accepted {
-                self.allowed_token_ids.insert(token_id as usize);
-                Self::revert_change(
-                    &mut self.earley_sets,
-                    &mut self.postdot_items,
-                    &mut self.postdot_items_since_last_commit,
-                    &mut self.leo_items,
-                    |_| {},
-                    original_earley_set_len,
-                    &mut self.finished,
-                );
+            let Some((byte, length)) = best else {
+                break;
+            };
+            if engine.try_accept_new_bytes(&[byte]).is_err() {
+                break;
             }
+            completion.push(byte);
+            min_length = length;
         }
-        Self::commit_change(&mut self.postdot_items_since_last_commit);
-        if self.config.cache_enabled {
-            self.cache
-                .insert(self.earley_sets.clone(), self.allowed_token_ids.clone());
+        (min_length == 0).then_some(completion)
+    }
+
+    fn compute_allowed_next_bytes(&mut self) -> Vec<u8> {
+        if self.is_finished() {
+            return Vec::new();
         }
+        // Special tokens are not bytes, so a pending special token is not reflected here.
+        self.update_allowed_first_bytes();
+        self.allowed_first_bytes
+            .ones()
+            .map(|byte| byte as u8)
+            .collect()
+    }
+
+    fn expected_symbols(&self) -> Vec<ExpectedSymbol> {
+        if self.is_finished() {
+            return Vec::new();
+        }
+        let mut expected: Vec<_> = self
+            .columns
+            .last()
+            .unwrap()
+            .earley_set
+            .iter()
+            .map(|item| ExpectedSymbol {
+                symbol: self
+                    .grammar
+                    .node(
+                        item.nonterminal_id,
+                        item.dot_position,
+                        item.production_index,
+                    )
+                    .to_display_form(&self.grammar),
+                rule: item.to_debug_form(self).dotted_rule,
+            })
+            .collect();
+        expected.sort();
+        expected.dedup();
+        expected
     }
 
     fn mask_logits(&self, logits: &mut [f32]) -> Result<(), crate::engine_like::MaskLogitsError> {
@@ -1909,6 +3213,24 @@ where
         Ok(result)
     }
 
+    fn verify_tokens(&mut self, token_ids: &[u32]) -> (usize, Vec<FixedBitSet>) {
+        let mut masks = Vec::with_capacity(token_ids.len() + 1);
+        let mut digest = None;
+        for (accepted, &token_id) in token_ids.iter().enumerate() {
+            self.update_allowed_token_ids(&mut digest);
+            masks.push(self.allowed_token_ids.clone());
+            // The mask is exact, so a token outside of it is rejected without scanning its bytes.
+            if !self.allowed_token_ids.contains(token_id as usize)
+                || self.try_accept_new_token(token_id).is_err()
+            {
+                return (accepted, masks);
+            }
+        }
+        self.update_allowed_token_ids(&mut digest);
+        masks.push(self.allowed_token_ids.clone());
+        (token_ids.len(), masks)
+    }
+
     fn allowed_token_ids_from_last_computation(&self) -> &FixedBitSet {
         &self.allowed_token_ids
     }
@@ -1940,43 +3262,236 @@ where
     }
 
     fn is_finished(&self) -> bool {
+        match self.termination_config.mode {
+            TerminationMode::Eager => self.finished,
+            TerminationMode::Eos => self.eos_accepted,
+        }
+    }
+
+    fn is_accepting(&self) -> bool {
         self.finished
     }
 
     fn reset(&mut self) {
-        self.earley_sets.clear();
-        self.to_be_completed_items.clear();
-        self.to_be_completed_items_buffer.clear();
-        self.leo_items.clear();
-        self.leo_items_buffer.clear();
-        self.postdot_items.clear();
-        self.postdot_items_since_last_commit.clear();
-        self.deduplication_buffer.clear();
-        self.column_to_postdot_nonterminals.clear();
-        self.already_predicted_nonterminals.clear();
+        // Columns held by other engines are left to them.
+        Self::truncate_columns(&mut self.columns, &mut self.buffers, 0);
+        self.buffers.to_be_completed_items.clear();
+        self.buffers.to_be_completed_items_buffer.clear();
+        self.buffers.deduplication_buffer.clear();
+        self.buffers.already_predicted_nonterminals.clear();
         self.finished = false;
+        self.eos_accepted = false;
+        self.chart.reset();
+        self.pending_nonterminal_events.clear();
+        self.nonterminal_events.clear();
         self.allowed_token_ids.clear();
         self.allowed_first_bytes.clear();
-        self.earley_sets.new_row::<0>();
+        self.checkpoints.clear();
+        self.changes.clear();
+        let mut column = self.buffers.take_column();
         Self::predict_nonterminal(
             &self.grammar,
-            &mut self.earley_sets,
-            &mut self.already_predicted_nonterminals,
+            &mut column.earley_set,
+            &mut self.buffers.already_predicted_nonterminals,
             self.grammar.get_start_nonterminal_id(),
             0,
         ); // init the first Earley set
         Self::predict(
             &self.grammar,
-            &mut self.earley_sets,
-            &mut self.already_predicted_nonterminals,
+            &mut column,
+            0,
+            &mut self.buffers.already_predicted_nonterminals,
         ); // run a full prediction for the first earley set
-        Self::update_postdot_items(
-            &self.grammar,
-            &mut self.earley_sets,
-            &mut self.postdot_items,
-            &mut AHashSet::default(), // We will never need to revert the engine's state since it is the initialization
-            |_| {},                   // column zero should never be removed
-        );
+        Self::update_postdot_items(&self.grammar, &[], &mut column);
+        self.columns.push(Arc::new(column));
+        Self::update_digests(&mut self.columns, &mut self.buffers);
+    }
+
+    fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    fn clear_cache(&mut self) {
+        self.cache.clear();
+    }
+
+    fn export_cache(&self) -> Vec<u8> {
+        let vocab_size = self.vocabulary.vocab_size();
+        let mut writer = BinaryWriter::new(MASK_CACHE_MAGIC, MASK_CACHE_VERSION);
+        for type_size in Self::value_type_sizes() {
+            writer.write_u8(type_size);
+        }
+        writer.write_u64(self.grammar.fingerprint());
+        writer.write_u64(self.vocabulary.fingerprint());
+        writer.write_usize(vocab_size);
+        let mut entries = BinaryWriter::default();
+        let mut len = 0;
+        self.cache.for_each_by_recency(|signature, token_ids| {
+            let mut bitmap = vec![0u8; vocab_size.div_ceil(8)];
+            for token_id in token_ids.ones() {
+                bitmap[token_id / 8] |= 1 << (token_id % 8);
+            }
+            signature.write(&mut entries);
+            entries.write_bytes(&bitmap);
+            len += 1;
+        });
+        writer.write_usize(len);
+        let mut bytes = writer.finish();
+        bytes.extend(entries.finish());
+        bytes
+    }
+
+    fn import_cache(&mut self, bytes: &[u8]) -> Result<usize, ImportCacheError> {
+        let vocab_size = self.vocabulary.vocab_size();
+        let mut reader = BinaryReader::new(bytes, MASK_CACHE_MAGIC, MASK_CACHE_VERSION)?;
+        for type_size in Self::value_type_sizes() {
+            if reader.read_u8()? != type_size {
+                return Err(ImportCacheError::IncompatibleEngine);
+            }
+        }
+        if reader.read_u64()? != self.grammar.fingerprint() {
+            return Err(ImportCacheError::IncompatibleGrammar);
+        }
+        if reader.read_u64()? != self.vocabulary.fingerprint() || reader.read_usize()? != vocab_size
+        {
+            return Err(ImportCacheError::IncompatibleVocabulary);
+        }
+        let len = reader.read_usize()?;
+        let mut entries = Vec::new();
+        for _ in 0..len {
+            let signature = StateSignature::read(&mut reader)?;
+            let bitmap = reader.read_bytes()?;
+            if bitmap.len() != vocab_size.div_ceil(8) {
+                return Err(ImportCacheError::InvalidFormat);
+            }
+            let mut token_ids = FixedBitSet::with_capacity(vocab_size);
+            for (i, &byte) in bitmap.iter().enumerate() {
+                for bit in 0..8 {
+                    if byte & (1 << bit) != 0 {
+                        let token_id = i * 8 + bit;
+                        if token_id >= vocab_size {
+                            return Err(ImportCacheError::InvalidFormat);
+                        }
+                        token_ids.insert(token_id);
+                    }
+                }
+            }
+            entries.push((signature, token_ids));
+        }
+        reader.finish()?;
+        for (signature, token_ids) in entries {
+            self.cache.insert(
+                signature,
+                token_ids,
+                StateSignature::entry_size(vocab_size),
+                &self.config.cache_config,
+            );
+        }
+        Ok(len)
+    }
+
+    fn warm_up(&self, corpus: &[&[u8]]) -> usize {
+        // The clone shares the cache, so the computed token IDs are stored for this engine as well.
+        let mut engine = self.clone();
+        corpus
+            .iter()
+            .filter(|sample| {
+                engine.reset();
+                let Some(token_ids) = self.vocabulary.tokenize_greedily(sample) else {
+                    return false;
+                };
+                let accepted = token_ids.into_iter().all(|token_id| {
+                    engine.compute_allowed_token_ids();
+                    engine.try_accept_new_token(token_id).is_ok()
+                });
+                engine.compute_allowed_token_ids();
+                accepted
+            })
+            .count()
+    }
+
+    fn checkpoint(&mut self) -> Checkpoint {
+        let checkpoint = Checkpoint {
+            id: self.next_checkpoint_id,
+        };
+        self.next_checkpoint_id += 1;
+        self.checkpoints.push((checkpoint, self.changes.len()));
+        checkpoint
+    }
+
+    fn rollback_to(&mut self, checkpoint: Checkpoint) -> Result<(), RollbackError> {
+        let index = self
+            .checkpoints
+            .iter()
+            .position(|(x, _)| *x == checkpoint)
+            .ok_or(RollbackError::InvalidCheckpoint)?;
+        self.checkpoints.truncate(index + 1);
+        self.undo_changes(self.checkpoints[index].1);
+        self.allowed_token_ids.clear();
+        Ok(())
+    }
+
+    fn discard_checkpoint(&mut self, checkpoint: Checkpoint) -> Result<(), RollbackError> {
+        let index = self
+            .checkpoints
+            .iter()
+            .position(|(x, _)| *x == checkpoint)
+            .ok_or(RollbackError::InvalidCheckpoint)?;
+        self.checkpoints.remove(index);
+        self.forget_changes();
+        Ok(())
+    }
+
+    fn state_fingerprint(&self) -> u64 {
+        // The digest of the last column depends neither on the order of the items nor on the positions of the columns,
+        // so engines that reach the same state through different inputs get the same fingerprint.
+        let mut digest = StableDigest::new();
+        digest.write_u128(self.columns.last().unwrap().digest);
+        digest.write_u64(self.finished as u64 | (self.eos_accepted as u64) << 1);
+        digest.finish() as u64
+    }
+
+    fn parse_tree(&self) -> Option<ParseTree> {
+        if !self.config.parse_tree_enabled {
+            return None;
+        }
+        self.chart.parse_tree(&self.grammar)
+    }
+
+    fn subscribe_nonterminal(
+        &mut self,
+        nonterminal: &str,
+    ) -> Result<(), SubscribeNonterminalError> {
+        let nonterminal_id = self
+            .grammar
+            .nonterminal_id(nonterminal)
+            .ok_or(SubscribeNonterminalError::UnknownNonterminal)?;
+        if !self.subscribed_nonterminals.put(nonterminal_id.0.as_()) {
+            self.subscribed_left_corners.push((
+                nonterminal_id,
+                Self::left_corners(&self.grammar, nonterminal_id),
+            ));
+        }
+        Ok(())
+    }
+
+    fn unsubscribe_nonterminal(
+        &mut self,
+        nonterminal: &str,
+    ) -> Result<(), SubscribeNonterminalError> {
+        let nonterminal_id = self
+            .grammar
+            .nonterminal_id(nonterminal)
+            .ok_or(SubscribeNonterminalError::UnknownNonterminal)?;
+        self.subscribed_nonterminals
+            .set(nonterminal_id.0.as_(), false);
+        self.subscribed_left_corners
+            .retain(|(id, _)| *id != nonterminal_id);
+        Ok(())
+    }
+
+    fn drain_nonterminal_events(&mut self) -> Vec<NonterminalEvent> {
+        std::mem::take(&mut self.nonterminal_events)
     }
 
     fn into_boxed_engine(self) -> Box<dyn EngineLike> {
@@ -0,0 +1,241 @@
+//! This module contains the [`EngineBatch`] struct, which drives a batch of [`Engine`]s in one call.
+#[cfg(feature = "parallel")]
+use std::sync::Arc;
+
+use displaydoc::Display;
+#[cfg(feature = "python")]
+use pyo3::pyclass;
+
+use crate::engine::Engine;
+use crate::engine_like::{
+    AcceptTokenError, AcceptTokenResult, EngineLike, MaskLogitsError, WriteBufferError,
+};
+#[cfg(feature = "parallel")]
+use crate::worker_pool::WorkerPool;
+
+#[cfg_attr(feature = "python", pyclass(eq, eq_int))]
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash)]
+/// Represents the error when an [`EngineBatch`] is created or driven with inputs of the wrong shape.
+pub enum EngineBatchError {
+    /// The engines do not share the same vocabulary.
+    IncompatibleVocabulary,
+    /// The number of token IDs is not equal to the number of engines in the batch.
+    InvalidBatchSize,
+    /// More than one worker thread is requested while the `parallel` feature is disabled.
+    ParallelFeatureDisabled,
+}
+
+#[cfg_attr(feature = "python", pyclass(subclass))]
+#[cfg_attr(feature = "python", pyo3(name = "InternalEngineBatch"))]
+#[derive(Debug, Clone)]
+/// A batch of [`Engine`]s, one per sequence, that are driven together.
+///
+/// The engines may have different grammars but must share the same vocabulary,
+/// so that the masks of the whole batch can be written into one contiguous `[batch, vocab]` buffer.
+pub struct EngineBatch {
+    engines: Vec<Engine>,
+    vocab_size: usize,
+    worker_threads: usize,
+    /// The threads that compute the allowed token IDs of the engines, which are shared with the clones of the batch.
+    /// Only created when `worker_threads` is above 1.
+    #[cfg(feature = "parallel")]
+    worker_pool: Option<Arc<WorkerPool>>,
+}
+
+impl EngineBatch {
+    /// Creates a new [`EngineBatch`] from the given engines.
+    ///
+    /// # Arguments
+    ///
+    /// * `engines` - The engines in the batch. The i-th engine corresponds to the i-th row of the masks.
+    /// * `worker_threads` - The number of threads used to compute the allowed token IDs of the engines.
+    ///   The threads are spawned once here and shared by the clones of the batch.
+    ///   Values above 1 require the `parallel` feature.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`EngineBatchError::IncompatibleVocabulary`] when the engines do not share the same vocabulary,
+    /// or an [`EngineBatchError::ParallelFeatureDisabled`] when more than one worker thread is requested
+    /// while the `parallel` feature is disabled.
+    pub fn new(engines: Vec<Engine>, worker_threads: usize) -> Result<Self, EngineBatchError> {
+        #[cfg(not(feature = "parallel"))]
+        if worker_threads > 1 {
+            return Err(EngineBatchError::ParallelFeatureDisabled);
+        }
+        if let Some(first) = engines.first() {
+            let fingerprint = first.vocab().fingerprint();
+            if engines
+                .iter()
+                .skip(1)
+                .any(|engine| engine.vocab().fingerprint() != fingerprint)
+            {
+                return Err(EngineBatchError::IncompatibleVocabulary);
+            }
+        }
+        let vocab_size = engines
+            .first()
+            .map(|engine| engine.vocab().vocab_size())
+            .unwrap_or(0);
+        Ok(Self {
+            engines,
+            vocab_size,
+            worker_threads,
+            #[cfg(feature = "parallel")]
+            worker_pool: (worker_threads > 1).then(|| Arc::new(WorkerPool::new(worker_threads))),
+        })
+    }
+
+    /// Gets the engines in the batch.
+    pub fn engines(&self) -> &[Engine] {
+        &self.engines
+    }
+
+    /// Gets the mutable engines in the batch.
+    pub fn engines_mut(&mut self) -> &mut [Engine] {
+        &mut self.engines
+    }
+
+    /// Gets the number of engines in the batch.
+    pub fn len(&self) -> usize {
+        self.engines.len()
+    }
+
+    /// Checks if the batch is empty.
+    pub fn is_empty(&self) -> bool {
+        self.engines.is_empty()
+    }
+
+    /// Gets the vocabulary size shared by the engines.
+    pub fn vocab_size(&self) -> usize {
+        self.vocab_size
+    }
+
+    /// Gets the number of threads used to compute the allowed token IDs of the engines.
+    pub fn worker_threads(&self) -> usize {
+        self.worker_threads
+    }
+
+    /// Tries to accept one new token for each engine.
+    ///
+    /// # Arguments
+    ///
+    /// * `token_ids` - The token IDs, where the i-th token ID is accepted by the i-th engine.
+    ///
+    /// # Returns
+    ///
+    /// The result of accepting the token for each engine. An engine that rejects its token keeps its state.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`EngineBatchError::InvalidBatchSize`] when the number of token IDs is not equal to the batch size.
+    /// No engine is updated in this case.
+    pub fn try_accept_new_tokens(
+        &mut self,
+        token_ids: &[u32],
+    ) -> Result<Vec<Result<AcceptTokenResult, AcceptTokenError>>, EngineBatchError> {
+        if token_ids.len() != self.engines.len() {
+            return Err(EngineBatchError::InvalidBatchSize);
+        }
+        Ok(self
+            .engines
+            .iter_mut()
+            .zip(token_ids)
+            .map(|(engine, &token_id)| engine.try_accept_new_token(token_id))
+            .collect())
+    }
+
+    /// Computes the allowed token IDs of every engine.
+    ///
+    /// When the batch has more than one worker thread, the engines are partitioned across the threads.
+    pub fn compute_allowed_token_ids(&mut self) {
+        #[cfg(feature = "parallel")]
+        if let Some(worker_pool) = self.worker_pool.as_deref() {
+            if self.engines.len() > 1 {
+                let chunk_size = self.engines.len().div_ceil(worker_pool.threads());
+                // The jobs of the pool cannot borrow the engines, so the engines are moved to the threads and back.
+                let mut engines = std::mem::take(&mut self.engines);
+                let mut chunks = Vec::new();
+                while !engines.is_empty() {
+                    let rest = engines.split_off(chunk_size.min(engines.len()));
+                    chunks.push(std::mem::replace(&mut engines, rest));
+                }
+                let jobs = chunks.into_iter().map(|mut engines| {
+                    move || {
+                        for engine in engines.iter_mut() {
+                            engine.compute_allowed_token_ids();
+                        }
+                        engines
+                    }
+                });
+                self.engines = worker_pool.run(jobs).into_iter().flatten().collect();
+                return;
+            }
+        }
+        for engine in self.engines.iter_mut() {
+            engine.compute_allowed_token_ids();
+        }
+    }
+
+    /// Masks the logits of the whole batch based on the last computed token IDs of each engine.
+    ///
+    /// # Arguments
+    ///
+    /// * `logits` - The row-major `[batch, n]` logits, where `n` is at least the vocabulary size.
+    ///   The i-th row is masked by the i-th engine.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`MaskLogitsError`] when the logits length is not a multiple of the batch size
+    /// or the rows are shorter than the vocabulary size.
+    /// The logits array is not updated in this case.
+    pub fn mask_logits(&self, logits: &mut [f32]) -> Result<(), MaskLogitsError> {
+        if self.engines.is_empty() {
+            return Ok(());
+        }
+        let row_len = logits.len() / self.engines.len();
+        if !logits.len().is_multiple_of(self.engines.len()) || row_len < self.vocab_size {
+            return Err(MaskLogitsError::InvalidLogitsLength);
+        }
+        for (engine, row) in self.engines.iter().zip(logits.chunks_exact_mut(row_len)) {
+            engine.mask_logits(row)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the packed bitmask of the allowed token IDs of the whole batch.
+    ///
+    /// The i-th row holds `ceil(vocab_size / 32)` words, and the token ID `t` of the i-th engine is allowed
+    /// if and only if the bit `t % 32` of the word `t / 32` in the i-th row is set.
+    ///
+    /// # Arguments
+    ///
+    /// * `bitmask` - The row-major `[batch, ceil(vocab_size / 32)]` buffer.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`WriteBufferError`] when the buffer is smaller than the batch size times the row length.
+    /// The buffer is not updated in this case.
+    pub fn write_bitmask(&self, bitmask: &mut [u32]) -> Result<(), WriteBufferError> {
+        let row_len = self.vocab_size.div_ceil(32);
+        if bitmask.len() < row_len * self.engines.len() {
+            return Err(WriteBufferError::BufferTooSmall);
+        }
+        if row_len == 0 {
+            return Ok(());
+        }
+        for (engine, row) in self.engines.iter().zip(bitmask.chunks_exact_mut(row_len)) {
+            row.fill(0);
+            for token_id in engine.allowed_token_ids_from_last_computation().ones() {
+                row[token_id / 32] |= 1 << (token_id % 32);
+            }
+        }
+        Ok(())
+    }
+
+    /// Resets every engine to its initial state.
+    pub fn reset(&mut self) {
+        for engine in self.engines.iter_mut() {
+            engine.reset();
+        }
+    }
+}
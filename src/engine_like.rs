@@ -6,9 +6,12 @@ use displaydoc::Display;
 use fixedbitset_stack::FixedBitSet;
 #[cfg(feature = "python")]
 use pyo3::pyclass;
+use serde::Serialize;
 #[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 
+use crate::binary::BinaryError;
+use crate::parse_tree::ParseTree;
 use crate::vocabulary::Vocabulary;
 #[cfg_attr(feature = "python", pyclass(eq, eq_int))]
 #[cfg_attr(feature = "wasm", wasm_bindgen)]
@@ -22,6 +25,58 @@ pub enum AcceptTokenError {
     /// The [`EngineLike`] is finished, as defined by its grammar. No more tokens can be accepted.
     Finished,
 }
+/// Represents the error with diagnostics when an [`EngineLike`] tries to accept a token or bytes.
+///
+/// Unlike [`AcceptTokenError`], it explains why the input is rejected, which is useful for debugging grammars and prompts.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, thiserror::Error)]
+pub enum AcceptTokenDiagnosticError {
+    #[error("The input token id does not exist in the vocabulary.")]
+    /// The input token id does not exist in the vocabulary of the [`EngineLike`].
+    UnknownTokenID,
+    #[error("{0}")]
+    /// The input is rejected and the [`EngineLike`]'s internal states are not updated.
+    Rejected(RejectionDiagnostic),
+    #[error("The engine is finished, as defined by its grammar. No more tokens can be accepted.")]
+    /// The [`EngineLike`] is finished, as defined by its grammar. No more tokens can be accepted.
+    Finished,
+}
+/// Describes where and why an [`EngineLike`] rejects its input.
+#[cfg_attr(feature = "python", pyclass(get_all))]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct RejectionDiagnostic {
+    /// The offset of the rejected byte in the token or bytes.
+    pub offset: usize,
+    /// The rejected byte.
+    pub byte: u8,
+    /// The symbols that could have been accepted instead of the rejected byte.
+    pub expected: Vec<ExpectedSymbol>,
+}
+impl std::fmt::Display for RejectionDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The byte may be part of a multi-byte UTF-8 sequence, so it is escaped instead of decoded as a character.
+        write!(
+            f,
+            "The byte '{}' at offset {} is rejected. Expected one of:",
+            self.byte.escape_ascii(),
+            self.offset
+        )?;
+        for symbol in self.expected.iter() {
+            write!(f, "\n    {} in {}", symbol.symbol, symbol.rule)?;
+        }
+        Ok(())
+    }
+}
+/// A symbol that an [`EngineLike`] expects to accept next.
+#[cfg_attr(feature = "python", pyclass(get_all))]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub struct ExpectedSymbol {
+    /// The terminal, regex, substrings or nonterminal, as rendered by [`HIRNode::to_display_form`](crate::grammar::HIRNode::to_display_form).
+    pub symbol: String,
+    /// The dotted rule that expects the symbol, where the dot marks the position of the symbol.
+    /// The rule is rendered from the grammar after simplification,
+    /// so it may be an alternative or a part of a rule in the KBNF source.
+    pub rule: String,
+}
 #[cfg_attr(feature = "python", pyclass(eq, eq_int))]
 #[cfg_attr(feature = "wasm", wasm_bindgen)]
 #[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash)]
@@ -64,6 +119,113 @@ pub enum UpdateLogitsError {
     /// The input logits array is not of the expected length according to the vocabulary.
     InvalidLogitsLength,
 }
+#[cfg_attr(feature = "python", pyclass(eq, eq_int))]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash)]
+/// Represents the error when an [`EngineLike`] tries to roll back to a [`Checkpoint`].
+pub enum RollbackError {
+    /// The checkpoint does not belong to the [`EngineLike`]'s current session.
+    /// It may have been discarded, invalidated by rolling back to an earlier checkpoint, or created before the last reset.
+    InvalidCheckpoint,
+}
+
+#[cfg_attr(feature = "python", pyclass(eq, eq_int))]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash)]
+/// Represents the error when an engine tries to share the cache of another engine.
+pub enum ShareCacheError {
+    /// The grammars of the engines are different.
+    IncompatibleGrammar,
+    /// The vocabularies of the engines are different.
+    IncompatibleVocabulary,
+}
+
+#[cfg_attr(feature = "python", pyclass(eq, eq_int))]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash)]
+/// Represents the error when an [`EngineLike`] tries to import a cache exported by [`EngineLike::export_cache`].
+pub enum ImportCacheError {
+    /// The input is not an exported cache, or it is truncated or corrupted.
+    InvalidFormat,
+    /// The exported cache is created by an incompatible version of the library.
+    UnsupportedVersion,
+    /// The exported cache is created by an engine with different value ranges.
+    IncompatibleEngine,
+    /// The exported cache is created with a different grammar.
+    IncompatibleGrammar,
+    /// The exported cache is created with a different vocabulary.
+    IncompatibleVocabulary,
+}
+
+impl From<BinaryError> for ImportCacheError {
+    fn from(error: BinaryError) -> Self {
+        match error {
+            BinaryError::InvalidMagic | BinaryError::Corrupted => ImportCacheError::InvalidFormat,
+            BinaryError::UnsupportedVersion(_) => ImportCacheError::UnsupportedVersion,
+        }
+    }
+}
+
+#[cfg_attr(feature = "python", pyclass(eq))]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// An opaque handle to a state saved by [`EngineLike::checkpoint`].
+pub struct Checkpoint {
+    pub(crate) id: u64,
+}
+
+#[cfg_attr(feature = "python", pyclass(get_all))]
+#[cfg_attr(feature = "wasm", wasm_bindgen(inspectable))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+/// The statistics of the cache of an [`EngineLike`], shared by the engines cloned or forked from one another.
+pub struct CacheStats {
+    /// The number of times the allowed token IDs are found in the cache.
+    pub hits: u64,
+    /// The number of times the allowed token IDs are not found in the cache and have to be computed.
+    pub misses: u64,
+    /// The number of entries evicted to keep the cache within its limits.
+    pub evictions: u64,
+    /// The number of entries in the cache.
+    pub entries: usize,
+    /// The estimated memory usage of the entries in bytes.
+    pub bytes: usize,
+}
+#[cfg_attr(feature = "python", pyclass(eq, eq_int))]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash)]
+/// Represents the error when an [`EngineLike`] tries to subscribe to a nonterminal.
+pub enum SubscribeNonterminalError {
+    /// The nonterminal does not exist in the grammar of the [`EngineLike`].
+    UnknownNonterminal,
+}
+#[cfg_attr(feature = "python", pyclass(eq, eq_int))]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+/// Represents the kind of a [`NonterminalEvent`].
+pub enum NonterminalEventKind {
+    /// The nonterminal has consumed its first byte.
+    Started,
+    /// The nonterminal can end at the last accepted byte.
+    Completed,
+}
+/// An event emitted by an [`EngineLike`] when a subscribed nonterminal starts or completes in the accepted bytes.
+///
+/// A [`NonterminalEventKind::Started`] event is emitted once for each occurrence of the nonterminal,
+/// while a [`NonterminalEventKind::Completed`] event is emitted every time the occurrence can end,
+/// so a nonterminal like `digits::=#'[0-9]+';` completes after every digit.
+/// A right-recursive nonterminal like `C::='c'|'c' C;` completes every enclosing occurrence along with the innermost one.
+#[cfg_attr(feature = "python", pyclass(get_all))]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct NonterminalEvent {
+    /// The kind of the event.
+    pub kind: NonterminalEventKind,
+    /// The name of the nonterminal, as returned by [`Grammar::nonterminal_str`](crate::grammar::Grammar::nonterminal_str).
+    pub nonterminal: String,
+    /// The byte offset where the nonterminal starts, inclusive.
+    pub start: usize,
+    /// The byte offset of the accepted bytes when the event is emitted, exclusive.
+    pub end: usize,
+}
 pub(crate) mod sealed {
     pub trait Sealed {}
 }
@@ -105,9 +267,117 @@ pub trait EngineLike: sealed::Sealed {
     fn try_accept_new_bytes(&mut self, bytes: &[u8])
         -> Result<AcceptTokenResult, AcceptTokenError>;
 
+    /// Tries to accept a new token with the given token ID, explaining why it is rejected if it is.
+    ///
+    /// It is as fast as [`EngineLike::try_accept_new_token`] when the token is accepted.
+    ///
+    /// # Arguments
+    ///
+    /// * `token_id` - The ID of the token to be accepted.
+    ///
+    /// # Returns
+    ///
+    /// * [`AcceptTokenResult`] - The result of accepting the token.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`AcceptTokenDiagnosticError`] when a token is not accepted. Check the error type docs for more details.
+    /// The [`EngineLike`] internal states are not updated in this case.
+    fn try_accept_new_token_with_diagnostic(
+        &mut self,
+        token_id: u32,
+    ) -> Result<AcceptTokenResult, AcceptTokenDiagnosticError>;
+
+    /// Tries to accept new bytes, explaining why they are rejected if they are.
+    ///
+    /// It is as fast as [`EngineLike::try_accept_new_bytes`] when the bytes are accepted.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The bytes to be accepted.
+    ///
+    /// # Returns
+    ///
+    /// * [`AcceptTokenResult`] - The result of accepting the bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`AcceptTokenDiagnosticError`] when the bytes are not accepted. Check the error type docs for more details.
+    fn try_accept_new_bytes_with_diagnostic(
+        &mut self,
+        bytes: &[u8],
+    ) -> Result<AcceptTokenResult, AcceptTokenDiagnosticError>;
+
     /// Computes the allowed token IDs based on current states.
     fn compute_allowed_token_ids(&mut self);
 
+    /// Computes the allowed token IDs based on current states,
+    /// excluding the tokens after which the grammar cannot be completed within `max_remaining_bytes` bytes,
+    /// counting the bytes of the token itself.
+    ///
+    /// This prevents the language model from wandering into a state that cannot be completed within its output budget,
+    /// e.g. an unclosed deep nesting. A budget in tokens can be converted to `max_remaining_bytes`
+    /// by multiplying it with the maximum token length in bytes, which never excludes a token that can be completed in time.
+    /// Unlike [`EngineLike::compute_allowed_token_ids`], every allowed token is simulated on the current state,
+    /// so this method is considerably slower and the result is not cached.
+    /// The allowed token IDs are empty if the grammar cannot be completed within the budget at all.
+    fn compute_allowed_token_ids_with_budget(&mut self, max_remaining_bytes: usize);
+
+    /// Computes the longest byte string that every input accepted by the engine from now on must start with.
+    ///
+    /// This is useful for jump-forward decoding,
+    /// where the forced bytes are accepted in bulk by [`EngineLike::try_accept_new_bytes`] without calling the language model.
+    /// [`Vocabulary::tokenize_greedily`] can be used to turn the bytes into tokens.
+    /// The [`EngineLike`] internal states are not updated.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_length` - The maximum number of bytes to compute.
+    ///   This guards against grammars that force an infinite continuation.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<u8>` - The forced bytes. It is empty if the engine is finished or more than one byte can follow.
+    ///   The extraction also stops where a special token may follow, since it is an alternative to every byte.
+    fn compute_forced_bytes(&mut self, max_length: usize) -> Vec<u8>;
+
+    /// Computes the shortest byte string that, appended to the current input, completes the grammar.
+    ///
+    /// This is useful to close an output truncated by a token limit into a valid one.
+    /// The completion follows the shortest path through nonterminals, terminals, regular expressions and substrings.
+    /// Once the returned bytes are accepted by [`EngineLike::try_accept_new_bytes`],
+    /// the engine is finished in [`TerminationMode::Eager`](crate::config::TerminationMode::Eager) mode
+    /// or accepting in [`TerminationMode::Eos`](crate::config::TerminationMode::Eos) mode,
+    /// where an EOS token then finishes it.
+    /// The [`EngineLike`] internal states are not updated.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Vec<u8>>` - The shortest completion, which is empty if the engine is already finished or accepting.
+    ///   [`None`] if the grammar cannot be completed by bytes alone, for example when a special token is required.
+    fn shortest_completion(&mut self) -> Option<Vec<u8>>;
+
+    /// Computes the bytes that can be accepted next.
+    ///
+    /// Special tokens are not bytes, so a special token that the grammar allows next is not reported.
+    /// Use [`EngineLike::expected_symbols`] or the token mask to detect it.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<u8>` - The allowed bytes in ascending order. It is empty if the engine is finished.
+    fn compute_allowed_next_bytes(&mut self) -> Vec<u8>;
+
+    /// Lists the grammar symbols that the engine is waiting on,
+    /// which is useful for grammar-aware autocompletion.
+    ///
+    /// A terminal that is partially matched is listed as a whole.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<ExpectedSymbol>` - The expected terminals, regexes, substrings and nonterminals,
+    ///   each paired with a rule that expects it. It is empty if the engine is finished.
+    fn expected_symbols(&self) -> Vec<ExpectedSymbol>;
+
     /// Masks the logits based on last computed token IDs.
     /// These token IDs can also be obtained from [`EngineLike::allowed_token_ids_from_last_computation`].
     ///
@@ -146,6 +416,27 @@ pub trait EngineLike: sealed::Sealed {
         logits: &mut [f32],
     ) -> Result<AcceptTokenResult, UpdateLogitsError>;
 
+    /// Accepts the given draft tokens in order until one of them is rejected,
+    /// computing the allowed token IDs before each of them.
+    ///
+    /// A draft token is checked against the allowed token IDs before it is accepted,
+    /// and the allowed token IDs are only computed again when accepting it changed the state they depend on.
+    ///
+    /// This is useful for speculative decoding,
+    /// where the target model's logits at every draft position need to be masked in one go.
+    /// After this call, the allowed token IDs from last computation are those after the last accepted token.
+    ///
+    /// # Arguments
+    ///
+    /// * `token_ids` - The IDs of the draft tokens.
+    ///
+    /// # Returns
+    ///
+    /// * `usize` - The number of accepted draft tokens.
+    /// * `Vec<FixedBitSet>` - The allowed token IDs at each position, from before the first draft token
+    ///   to after the last accepted token. Its length is always the number of accepted draft tokens plus one.
+    fn verify_tokens(&mut self, token_ids: &[u32]) -> (usize, Vec<FixedBitSet>);
+
     /// Gets the allowed token IDs since last computation.
     /// Last computation is the last [`EngineLike::compute_allowed_token_ids`] or [`EngineLike::update_logits`] called.
     ///
@@ -163,9 +454,153 @@ pub trait EngineLike: sealed::Sealed {
         buffer: &mut [usize],
     ) -> Result<(), WriteBufferError>;
     /// Checks if the engine is finished.
+    ///
+    /// In [`TerminationMode::Eos`](crate::config::TerminationMode::Eos), the engine is finished only after an EOS token is accepted.
     fn is_finished(&self) -> bool;
+    /// Checks if the engine is accepting, i.e. the input so far is a complete sentence of the grammar.
+    ///
+    /// In [`TerminationMode::Eager`](crate::config::TerminationMode::Eager), this is the same as [`EngineLike::is_finished`].
+    fn is_accepting(&self) -> bool;
     /// Resets the engine to its initial state. Notably, the cache is preserved.
+    /// All checkpoints are invalidated.
     fn reset(&mut self);
+    /// Gets the statistics of the cache.
+    fn cache_stats(&self) -> CacheStats;
+    /// Removes all the entries from the cache, including those shared with other engines.
+    /// The statistics are preserved.
+    fn clear_cache(&mut self);
+    /// Exports the entries of the cache, so that they can be imported into engines created in other processes
+    /// by [`EngineLike::import_cache`].
+    ///
+    /// The exported cache is keyed by the fingerprints of the grammar and the vocabulary.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<u8>` - The exported cache.
+    fn export_cache(&self) -> Vec<u8>;
+    /// Imports the entries of a cache exported by [`EngineLike::export_cache`] into the cache of the engine.
+    /// The entries are subject to the limits in the cache config, and the existing entries are preserved.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The exported cache.
+    ///
+    /// # Returns
+    ///
+    /// * `usize` - The number of imported entries.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`ImportCacheError`] when the input is not a valid exported cache,
+    /// or it is exported from an engine with a different grammar, vocabulary, or value ranges.
+    /// The cache is not updated in this case.
+    fn import_cache(&mut self, bytes: &[u8]) -> Result<usize, ImportCacheError>;
+    /// Populates the cache by replaying representative outputs before traffic arrives.
+    ///
+    /// Each sample is tokenized greedily with the engine's vocabulary and accepted token by token from the initial state,
+    /// computing the allowed token IDs before every token. The state of the engine itself is not changed.
+    ///
+    /// # Arguments
+    ///
+    /// * `corpus` - The representative outputs.
+    ///
+    /// # Returns
+    ///
+    /// * `usize` - The number of samples accepted in full.
+    fn warm_up(&self, corpus: &[&[u8]]) -> usize;
+    /// Saves the current state of the engine so that it can be restored later by [`EngineLike::rollback_to`].
+    ///
+    /// This is useful for speculative decoding,
+    /// where draft tokens are accepted first and undone when the target model disagrees.
+    /// The state itself is not copied: while any checkpoint exists, the engine records the changes made to its state
+    /// and [`EngineLike::rollback_to`] undoes them.
+    ///
+    /// # Returns
+    ///
+    /// * [`Checkpoint`] - The handle to the saved state.
+    fn checkpoint(&mut self) -> Checkpoint;
+    /// Restores the engine to the state saved in the given checkpoint.
+    ///
+    /// The checkpoint stays valid and can be rolled back to again,
+    /// while all checkpoints created after it are discarded.
+    /// The allowed token IDs from last computation are cleared.
+    ///
+    /// # Arguments
+    ///
+    /// * `checkpoint` - The checkpoint to roll back to.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`RollbackError`] when the checkpoint is invalid.
+    /// The [`EngineLike`] internal states are not updated in this case.
+    fn rollback_to(&mut self, checkpoint: Checkpoint) -> Result<(), RollbackError>;
+    /// Discards the given checkpoint and frees the memory it holds.
+    ///
+    /// # Arguments
+    ///
+    /// * `checkpoint` - The checkpoint to discard.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`RollbackError`] when the checkpoint is invalid.
+    fn discard_checkpoint(&mut self, checkpoint: Checkpoint) -> Result<(), RollbackError>;
+    /// Computes a fingerprint of the engine's current parsing state.
+    ///
+    /// Engines created from the same grammar and vocabulary that have the same fingerprint
+    /// will, barring hash collisions, accept exactly the same inputs from now on.
+    /// The fingerprint does not depend on how the state is reached,
+    /// so it is useful for detecting converged hypotheses in beam search, which can then be merged.
+    ///
+    /// # Returns
+    ///
+    /// * `u64` - The fingerprint of the current state.
+    fn state_fingerprint(&self) -> u64;
+    /// Recovers the parse tree of the bytes accepted since the last reset.
+    ///
+    /// The tree is labelled with the nonterminal names and byte spans.
+    /// When the bytes can be parsed in more than one way, earlier alternatives in the grammar are preferred,
+    /// and then longer spans for the later nodes in an alternative.
+    ///
+    /// # Returns
+    ///
+    /// * `Some(ParseTree)` - The parse tree rooted at the start nonterminal.
+    /// * `None` - If [`EngineConfig::parse_tree_enabled`](crate::engine::EngineConfig::parse_tree_enabled) is disabled
+    ///   or the accepted bytes are not a complete sentence of the grammar yet.
+    fn parse_tree(&self) -> Option<ParseTree>;
+    /// Subscribes to the [`NonterminalEvent`]s of the given nonterminal.
+    ///
+    /// The events are emitted as the engine accepts tokens or bytes
+    /// and can be collected by [`EngineLike::drain_nonterminal_events`].
+    /// This is useful for streaming structured output, like acting on a JSON field as soon as its value is complete.
+    ///
+    /// # Arguments
+    ///
+    /// * `nonterminal` - The name of the nonterminal.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`SubscribeNonterminalError`] when the nonterminal does not exist in the grammar.
+    fn subscribe_nonterminal(&mut self, nonterminal: &str)
+        -> Result<(), SubscribeNonterminalError>;
+    /// Unsubscribes from the [`NonterminalEvent`]s of the given nonterminal.
+    /// Events that are already emitted are kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `nonterminal` - The name of the nonterminal.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`SubscribeNonterminalError`] when the nonterminal does not exist in the grammar.
+    fn unsubscribe_nonterminal(
+        &mut self,
+        nonterminal: &str,
+    ) -> Result<(), SubscribeNonterminalError>;
+    /// Takes the [`NonterminalEvent`]s emitted since the last call, in the order they are emitted.
+    ///
+    /// Events are only emitted for accepted input, so rejected tokens and bytes never emit events.
+    /// Resetting the engine discards the events not taken yet, while rolling back to a checkpoint does not.
+    fn drain_nonterminal_events(&mut self) -> Vec<NonterminalEvent>;
     /// Converts the engine to a boxed engine.
     fn into_boxed_engine(self) -> Box<dyn EngineLike>;
     /// Gets the vocabulary of the engine.
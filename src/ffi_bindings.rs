@@ -1,9 +1,22 @@
 #[cfg(any(feature = "python", feature = "wasm"))]
+use crate::compiled_grammar::CompiledGrammarError;
+#[cfg(any(feature = "python", feature = "wasm"))]
+use crate::config::TerminationConfig;
+#[cfg(any(feature = "python", feature = "wasm"))]
 use crate::engine::CreateEngineError;
+#[cfg(feature = "python")]
+use crate::engine_batch::{EngineBatch, EngineBatchError};
 #[cfg(any(feature = "python", feature = "wasm"))]
 use crate::engine_like::WriteBufferError;
 #[cfg(any(feature = "python", feature = "wasm"))]
-use crate::engine_like::{AcceptTokenError, MaskLogitsError, UpdateLogitsError};
+use crate::engine_like::{
+    AcceptTokenDiagnosticError, AcceptTokenError, CacheStats, Checkpoint, ImportCacheError,
+    MaskLogitsError, RollbackError, ShareCacheError, SubscribeNonterminalError, UpdateLogitsError,
+};
+#[cfg(feature = "python")]
+use crate::engine_like::{ExpectedSymbol, NonterminalEvent};
+#[cfg(feature = "python")]
+use crate::parse_tree::ParseTree;
 #[cfg(any(feature = "python", feature = "wasm"))]
 use crate::vocabulary::{CreateVocabularyError, Vocabulary};
 #[cfg(any(feature = "python", feature = "wasm"))]
@@ -13,6 +26,8 @@ use pyo3::exceptions::PyValueError;
 #[cfg(feature = "python")]
 use pyo3::types::PyDict;
 #[cfg(feature = "python")]
+use pyo3::IntoPy;
+#[cfg(feature = "python")]
 use pyo3::Python;
 #[cfg(feature = "python")]
 use pyo3::{pymethods, PyErr};
@@ -27,6 +42,18 @@ impl From<CreateEngineError> for JsValue {
         JsValue::from_str(error.to_string().as_str())
     }
 }
+#[cfg(feature = "wasm")]
+impl From<CompiledGrammarError> for JsValue {
+    fn from(error: CompiledGrammarError) -> Self {
+        JsValue::from_str(error.to_string().as_str())
+    }
+}
+#[cfg(feature = "wasm")]
+impl From<AcceptTokenDiagnosticError> for JsValue {
+    fn from(error: AcceptTokenDiagnosticError) -> Self {
+        JsValue::from_str(error.to_string().as_str())
+    }
+}
 #[cfg(feature = "python")]
 impl From<CreateVocabularyError> for PyErr {
     fn from(error: CreateVocabularyError) -> Self {
@@ -40,12 +67,24 @@ impl From<CreateEngineError> for PyErr {
     }
 }
 #[cfg(feature = "python")]
+impl From<CompiledGrammarError> for PyErr {
+    fn from(error: CompiledGrammarError) -> Self {
+        PyErr::new::<PyValueError, _>(error.to_string())
+    }
+}
+#[cfg(feature = "python")]
 impl From<AcceptTokenError> for PyErr {
     fn from(error: AcceptTokenError) -> Self {
         PyErr::new::<PyValueError, _>(error.to_string())
     }
 }
 #[cfg(feature = "python")]
+impl From<AcceptTokenDiagnosticError> for PyErr {
+    fn from(error: AcceptTokenDiagnosticError) -> Self {
+        PyErr::new::<PyValueError, _>(error.to_string())
+    }
+}
+#[cfg(feature = "python")]
 impl From<MaskLogitsError> for PyErr {
     fn from(error: MaskLogitsError) -> Self {
         PyErr::new::<PyValueError, _>(error.to_string())
@@ -63,6 +102,36 @@ impl From<WriteBufferError> for PyErr {
         PyErr::new::<PyValueError, _>(error.to_string())
     }
 }
+#[cfg(feature = "python")]
+impl From<EngineBatchError> for PyErr {
+    fn from(error: EngineBatchError) -> Self {
+        PyErr::new::<PyValueError, _>(error.to_string())
+    }
+}
+#[cfg(feature = "python")]
+impl From<RollbackError> for PyErr {
+    fn from(error: RollbackError) -> Self {
+        PyErr::new::<PyValueError, _>(error.to_string())
+    }
+}
+#[cfg(feature = "python")]
+impl From<ShareCacheError> for PyErr {
+    fn from(error: ShareCacheError) -> Self {
+        PyErr::new::<PyValueError, _>(error.to_string())
+    }
+}
+#[cfg(feature = "python")]
+impl From<ImportCacheError> for PyErr {
+    fn from(error: ImportCacheError) -> Self {
+        PyErr::new::<PyValueError, _>(error.to_string())
+    }
+}
+#[cfg(feature = "python")]
+impl From<SubscribeNonterminalError> for PyErr {
+    fn from(error: SubscribeNonterminalError) -> Self {
+        PyErr::new::<PyValueError, _>(error.to_string())
+    }
+}
 #[cfg(feature = "wasm")]
 impl From<CreateVocabularyErrorJs> for JsValue {
     fn from(error: CreateVocabularyErrorJs) -> Self {
@@ -121,6 +190,72 @@ impl Vocabulary {
         let id_to_token_string = serde_wasm_bindgen::from_value(id_to_token_string.into())?;
         Ok(Vocabulary::new(id_to_token, id_to_token_string)?)
     }
+    /// Creates a new instance of [`Vocabulary`] with special tokens.
+    ///
+    /// # Arguments
+    ///
+    /// * `id_to_token` - A Map<number, Uint8Array> from token IDs to tokens.
+    /// * `id_to_token_string` - A Map<number, string> from token IDs to tokens in UTF-8 String representation.
+    /// * `special_token_ids` - The IDs of the special tokens, which are only matched by special token references in the grammar.
+    #[wasm_bindgen(js_name = newWithSpecialTokens)]
+    pub fn with_special_tokens_js(
+        id_to_token: js_sys::Map,
+        id_to_token_string: js_sys::Map,
+        special_token_ids: Vec<u32>,
+    ) -> Result<Vocabulary, CreateVocabularyErrorJs> {
+        let id_to_token = serde_wasm_bindgen::from_value(id_to_token.into())?;
+        let id_to_token_string = serde_wasm_bindgen::from_value(id_to_token_string.into())?;
+        Ok(Vocabulary::with_special_tokens(
+            id_to_token,
+            id_to_token_string,
+            special_token_ids.into_iter().collect(),
+        )?)
+    }
+    /// Creates a new instance of [`Vocabulary`] from the content of a Hugging Face `tokenizer.json` file.
+    ///
+    /// # Arguments
+    ///
+    /// * `json` - The content of the `tokenizer.json` file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error when the content cannot be parsed, the model type is not supported,
+    /// or the vocabulary cannot be created from the tokens.
+    #[wasm_bindgen(js_name = fromTokenizerJson)]
+    pub fn from_tokenizer_json_js(json: &str) -> Result<Vocabulary, CreateVocabularyErrorJs> {
+        Ok(Vocabulary::from_tokenizer_json_str(json)?)
+    }
+    /// Creates a new instance of [`Vocabulary`] from the content of a tiktoken `.tiktoken` file.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The content of the `.tiktoken` file.
+    /// * `special_tokens` - A Map<string, number> from special tokens to their token IDs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error when a line is malformed or the vocabulary cannot be created from the tokens.
+    #[wasm_bindgen(js_name = fromTiktoken)]
+    pub fn from_tiktoken_js(
+        content: &str,
+        special_tokens: js_sys::Map,
+    ) -> Result<Vocabulary, CreateVocabularyErrorJs> {
+        let special_tokens = serde_wasm_bindgen::from_value(special_tokens.into())?;
+        Ok(Vocabulary::from_tiktoken_str(content, special_tokens)?)
+    }
+    /// Creates a new instance of [`Vocabulary`] from the content of a SentencePiece `.model` file.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The content of the `.model` file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error when the content is not a valid model or the vocabulary cannot be created from the tokens.
+    #[wasm_bindgen(js_name = fromSentencepiece)]
+    pub fn from_sentencepiece_js(model: &[u8]) -> Result<Vocabulary, CreateVocabularyErrorJs> {
+        Ok(Vocabulary::from_sentencepiece_bytes(model)?)
+    }
 }
 #[cfg(feature = "python")]
 #[pymethods]
@@ -129,7 +264,7 @@ impl Vocabulary {
     ///
     /// # Signature
     ///
-    /// (id_to_token: Dict[int, Token], id_to_token_string: Dict[int, str]) -> Vocabulary
+    /// (id_to_token: Dict[int, Token], id_to_token_string: Dict[int, str], special_token_ids: Optional[Set[int]] = None) -> Vocabulary
     ///
     /// # Arguments
     ///
@@ -137,15 +272,85 @@ impl Vocabulary {
     /// * `id_to_token_string` - A Map<number, string> from token IDs to tokens in UTF-8 String representation.
     /// This parameter is necessary because a token's UTF-8 representation may not be equivalent to the UTF-8 string decoded from its bytes,
     /// vice versa. For example, a token may contain `0xFF` byte.
+    /// * `special_token_ids` - The IDs of the special tokens, which are only matched by special token references in the grammar.
     #[new]
-    #[pyo3(text_signature = "(id_to_token, id_to_token_string)")]
+    #[pyo3(signature = (id_to_token, id_to_token_string, special_token_ids=None))]
     pub fn new_py(
         id_to_token: std::collections::HashMap<u32, Token>,
         id_to_token_string: std::collections::HashMap<u32, String>,
+        special_token_ids: Option<std::collections::HashSet<u32>>,
     ) -> Result<Vocabulary, CreateVocabularyError> {
         let id_to_token = id_to_token.into_iter().collect();
         let id_to_token_string = id_to_token_string.into_iter().collect();
-        Vocabulary::new(id_to_token, id_to_token_string)
+        let special_token_ids = special_token_ids.unwrap_or_default().into_iter().collect();
+        Vocabulary::with_special_tokens(id_to_token, id_to_token_string, special_token_ids)
+    }
+    /// Creates a new instance of [`Vocabulary`] from a Hugging Face `tokenizer.json` file.
+    /// BPE, WordPiece and Unigram models are supported.
+    ///
+    /// # Signature
+    ///
+    /// (path: str) -> Vocabulary
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the `tokenizer.json` file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error when the file cannot be read or parsed, the model type is not supported,
+    /// or the vocabulary cannot be created from the tokens.
+    #[staticmethod]
+    #[pyo3(name = "from_tokenizer_json")]
+    pub fn from_tokenizer_json_py(
+        path: std::path::PathBuf,
+    ) -> Result<Vocabulary, CreateVocabularyError> {
+        Vocabulary::from_tokenizer_json(path)
+    }
+    /// Creates a new instance of [`Vocabulary`] from a tiktoken `.tiktoken` file.
+    ///
+    /// # Signature
+    ///
+    /// (path: str, special_tokens: Optional[Dict[str, int]]) -> Vocabulary
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the `.tiktoken` file.
+    /// * `special_tokens` - A map from special tokens to their token IDs, e.g. `<|endoftext|>`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error when the file cannot be read, a line is malformed,
+    /// or the vocabulary cannot be created from the tokens.
+    #[staticmethod]
+    #[pyo3(name = "from_tiktoken", signature = (path, special_tokens=None))]
+    pub fn from_tiktoken_py(
+        path: std::path::PathBuf,
+        special_tokens: Option<std::collections::HashMap<String, u32>>,
+    ) -> Result<Vocabulary, CreateVocabularyError> {
+        let special_tokens = special_tokens.unwrap_or_default().into_iter().collect();
+        Vocabulary::from_tiktoken(path, special_tokens)
+    }
+    /// Creates a new instance of [`Vocabulary`] from a SentencePiece `.model` file.
+    ///
+    /// # Signature
+    ///
+    /// (path: str) -> Vocabulary
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the `.model` file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error when the file cannot be read or is not a valid model,
+    /// or the vocabulary cannot be created from the tokens.
+    #[staticmethod]
+    #[pyo3(name = "from_sentencepiece")]
+    pub fn from_sentencepiece_py(
+        path: std::path::PathBuf,
+    ) -> Result<Vocabulary, CreateVocabularyError> {
+        Vocabulary::from_sentencepiece(path)
     }
 }
 #[cfg(feature = "wasm")]
@@ -163,7 +368,12 @@ impl Vocabulary {
     /// * `None` - If the token does not exist in the vocabulary.
     #[wasm_bindgen(js_name = getTokenId)]
     pub fn token_id_js(&self, token: &Token) -> Option<u32> {
-        self.token_to_id.get(token).copied()
+        self.token_id(token)
+    }
+    /// Checks whether the token ID belongs to a special token.
+    #[wasm_bindgen(js_name = isSpecialToken)]
+    pub fn is_special_token_js(&self, token_id: u32) -> bool {
+        self.is_special_token(token_id)
     }
     /// Retrieves the size of the vocabulary.
     #[wasm_bindgen(js_name = getVocabSize)]
@@ -204,6 +414,21 @@ impl Vocabulary {
     pub fn token_js(&self, token_id: u32) -> Option<Token> {
         self.id_to_token.get(&token_id).cloned()
     }
+
+    /// Splits the given bytes into tokens by repeatedly taking the longest token that prefixes the remaining bytes.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The bytes to tokenize.
+    ///
+    /// # Returns
+    ///
+    /// * `Some(Vec<u32>)` - The token IDs, whose tokens concatenate to `bytes`.
+    /// * `None` - If some bytes cannot be covered by any token.
+    #[wasm_bindgen(js_name = tokenizeGreedily)]
+    pub fn tokenize_greedily_js(&self, bytes: &[u8]) -> Option<Vec<u32>> {
+        self.tokenize_greedily(bytes)
+    }
 }
 
 #[cfg(feature = "python")]
@@ -225,7 +450,16 @@ impl Vocabulary {
     /// * `None` - If the token does not exist in the vocabulary.
     #[pyo3(name = "get_token_id")]
     pub fn token_id_py(&self, token: &Token) -> Option<u32> {
-        self.token_to_id.get(token).copied()
+        self.token_id(token)
+    }
+    /// Checks whether the token ID belongs to a special token.
+    ///
+    /// # Signature
+    ///
+    /// (self, token_id: int) -> bool
+    #[pyo3(name = "is_special_token")]
+    pub fn is_special_token_py(&self, token_id: u32) -> bool {
+        self.is_special_token(token_id)
     }
     /// Retrieves the size of the vocabulary.
     #[pyo3(name = "get_vocab_size")]
@@ -274,6 +508,25 @@ impl Vocabulary {
     pub fn token_py(&self, token_id: u32) -> Option<Token> {
         self.id_to_token.get(&token_id).cloned()
     }
+
+    /// Splits the given bytes into tokens by repeatedly taking the longest token that prefixes the remaining bytes.
+    ///
+    /// # Signature
+    ///
+    /// (self, bytes: bytes) -> Optional[List[int]]
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The bytes to tokenize.
+    ///
+    /// # Returns
+    ///
+    /// * `Some(Vec<u32>)` - The token IDs, whose tokens concatenate to `bytes`.
+    /// * `None` - If some bytes cannot be covered by any token.
+    #[pyo3(name = "tokenize_greedily")]
+    pub fn tokenize_greedily_py(&self, bytes: &[u8]) -> Option<Vec<u32>> {
+        self.tokenize_greedily(bytes)
+    }
 }
 #[cfg(feature = "wasm")]
 #[wasm_bindgen]
@@ -344,6 +597,47 @@ impl Engine {
     ) -> Result<AcceptTokenResult, AcceptTokenError> {
         EngineLike::try_accept_new_token(self, token_id)
     }
+    /// Tries to accept a new token with the given token ID, explaining why it is rejected if it is.
+    ///
+    /// # Arguments
+    ///
+    /// * `token_id` - The ID of the token to be accepted.
+    ///
+    /// # Returns
+    ///
+    /// * [`AcceptTokenResult`] - The result of accepting the token.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error message with the rejected byte and the expected symbols when a token is not accepted.
+    /// The [`EngineLike`] internal states are not updated in this case.
+    #[wasm_bindgen(js_name = tryAcceptNewTokenWithDiagnostic)]
+    pub fn try_accept_new_token_with_diagnostic_js(
+        &mut self,
+        token_id: u32,
+    ) -> Result<AcceptTokenResult, AcceptTokenDiagnosticError> {
+        EngineLike::try_accept_new_token_with_diagnostic(self, token_id)
+    }
+    /// Tries to accept new bytes, explaining why they are rejected if they are.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The bytes to be accepted.
+    ///
+    /// # Returns
+    ///
+    /// * [`AcceptTokenResult`] - The result of accepting the bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error message with the rejected byte and the expected symbols when the bytes are not accepted.
+    #[wasm_bindgen(js_name = tryAcceptNewBytesWithDiagnostic)]
+    pub fn try_accept_new_bytes_with_diagnostic_js(
+        &mut self,
+        bytes: &[u8],
+    ) -> Result<AcceptTokenResult, AcceptTokenDiagnosticError> {
+        EngineLike::try_accept_new_bytes_with_diagnostic(self, bytes)
+    }
 
     /// Computes the allowed token IDs based on current states.
     #[wasm_bindgen(js_name = computeAllowedTokenIds)]
@@ -351,6 +645,13 @@ impl Engine {
         EngineLike::compute_allowed_token_ids(self)
     }
 
+    /// Computes the allowed token IDs based on current states,
+    /// excluding the tokens after which the grammar cannot be completed within `max_remaining_bytes` bytes.
+    #[wasm_bindgen(js_name = computeAllowedTokenIdsWithBudget)]
+    pub fn compute_allowed_token_ids_with_budget_js(&mut self, max_remaining_bytes: usize) {
+        EngineLike::compute_allowed_token_ids_with_budget(self, max_remaining_bytes)
+    }
+
     /// Gets the allowed token IDs since last computation.
     /// Last computation is the last [`EngineLike::compute_allowed_token_ids`] or [`EngineLike::update_logits`] called.
     ///
@@ -361,16 +662,106 @@ impl Engine {
             .ones()
             .collect()
     }
+    /// Accepts the given draft tokens in order until one of them is rejected,
+    /// computing the allowed token IDs before each of them.
+    ///
+    /// # Arguments
+    ///
+    /// * `token_ids` - The IDs of the draft tokens.
+    ///
+    /// # Returns
+    ///
+    /// A pair of the number of accepted draft tokens and the allowed token IDs at each position,
+    /// from before the first draft token to after the last accepted token.
+    #[wasm_bindgen(js_name = verifyTokens)]
+    pub fn verify_tokens_js(
+        &mut self,
+        token_ids: &[u32],
+    ) -> Result<JsValue, serde_wasm_bindgen::Error> {
+        let (accepted, masks) = EngineLike::verify_tokens(self, token_ids);
+        let masks: Vec<Vec<usize>> = masks.iter().map(|mask| mask.ones().collect()).collect();
+        serde_wasm_bindgen::to_value(&(accepted, masks))
+    }
+    /// Computes the longest byte string that every input accepted by the engine from now on must start with.
+    /// The engine's internal states are not updated.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_length` - The maximum number of bytes to compute.
+    #[wasm_bindgen(js_name = computeForcedBytes)]
+    pub fn compute_forced_bytes_js(&mut self, max_length: usize) -> Vec<u8> {
+        EngineLike::compute_forced_bytes(self, max_length)
+    }
+    /// Computes the shortest byte string that, appended to the current input, completes the grammar.
+    /// The engine's internal states are not updated.
+    ///
+    /// Returns `undefined` if the grammar cannot be completed by bytes alone.
+    #[wasm_bindgen(js_name = shortestCompletion)]
+    pub fn shortest_completion_js(&mut self) -> Option<Vec<u8>> {
+        EngineLike::shortest_completion(self)
+    }
+    /// Computes the bytes that can be accepted next, in ascending order.
+    #[wasm_bindgen(js_name = computeAllowedNextBytes)]
+    pub fn compute_allowed_next_bytes_js(&mut self) -> Vec<u8> {
+        EngineLike::compute_allowed_next_bytes(self)
+    }
+    /// Lists the grammar symbols that the engine is waiting on.
+    ///
+    /// # Returns
+    ///
+    /// An array of objects with `symbol` and `rule` fields.
+    #[wasm_bindgen(js_name = expectedSymbols)]
+    pub fn expected_symbols_js(&self) -> Result<JsValue, serde_wasm_bindgen::Error> {
+        serde_wasm_bindgen::to_value(&EngineLike::expected_symbols(self))
+    }
     /// Checks if the engine is finished.
     #[wasm_bindgen(js_name = isFinished)]
     pub fn is_finished_js(&self) -> bool {
         EngineLike::is_finished(self)
     }
+    /// Checks if the engine is accepting, i.e. the input so far is a complete sentence of the grammar.
+    #[wasm_bindgen(js_name = isAccepting)]
+    pub fn is_accepting_js(&self) -> bool {
+        EngineLike::is_accepting(self)
+    }
     /// Resets the engine to its initial state. Notably, the cache is preserved.
     #[wasm_bindgen(js_name = reset)]
     pub fn reset_js(&mut self) {
         EngineLike::reset(self)
     }
+    /// Gets the statistics of the cache.
+    #[wasm_bindgen(js_name = cacheStats)]
+    pub fn cache_stats_js(&self) -> CacheStats {
+        EngineLike::cache_stats(self)
+    }
+    /// Removes all the entries from the cache, including those shared with forked engines.
+    /// The statistics are preserved.
+    #[wasm_bindgen(js_name = clearCache)]
+    pub fn clear_cache_js(&mut self) {
+        EngineLike::clear_cache(self)
+    }
+    /// Exports the entries of the cache, keyed by the fingerprints of the grammar and the vocabulary.
+    #[wasm_bindgen(js_name = exportCache)]
+    pub fn export_cache_js(&self) -> Vec<u8> {
+        EngineLike::export_cache(self)
+    }
+    /// Imports the entries of a cache exported by `exportCache` and returns the number of imported entries.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`ImportCacheError`] when the input is not a valid exported cache,
+    /// or it is exported from an engine with a different grammar, vocabulary, or value ranges.
+    #[wasm_bindgen(js_name = importCache)]
+    pub fn import_cache_js(&mut self, bytes: &[u8]) -> Result<usize, ImportCacheError> {
+        EngineLike::import_cache(self, bytes)
+    }
+    /// Populates the cache by replaying representative outputs and returns the number of samples accepted in full.
+    #[wasm_bindgen(js_name = warmUp)]
+    pub fn warm_up_js(&self, corpus: Vec<js_sys::Uint8Array>) -> usize {
+        let corpus: Vec<Vec<u8>> = corpus.iter().map(|sample| sample.to_vec()).collect();
+        let corpus: Vec<&[u8]> = corpus.iter().map(Vec::as_slice).collect();
+        EngineLike::warm_up(self, &corpus)
+    }
     /// Gets the vocabulary of the engine.
     #[wasm_bindgen(js_name = getVocab)]
     pub fn vocab_js(&self) -> Vocabulary {
@@ -419,91 +810,337 @@ impl Engine {
     ) -> Result<AcceptTokenResult, UpdateLogitsError> {
         EngineLike::update_logits(self, token_id, logits)
     }
-}
-
-#[cfg(feature = "python")]
-#[pymethods]
-impl Engine {
-    /// Create a new [`Engine`] from an KBNF grammar string, a [`Vocabulary`], and a [`Config`].
+    /// Saves the current state of the engine so that it can be restored later by [`EngineLike::rollback_to`].
     ///
-    /// # Signature
+    /// # Returns
     ///
-    /// (kbnf_syntax_grammar_str: str, vocabulary: Vocabulary, config: Config) -> Engine
+    /// * [`Checkpoint`] - The handle to the saved state.
+    #[wasm_bindgen(js_name = checkpoint)]
+    pub fn checkpoint_js(&mut self) -> Checkpoint {
+        EngineLike::checkpoint(self)
+    }
+    /// Restores the engine to the state saved in the given checkpoint.
+    /// All checkpoints created after it are discarded.
     ///
     /// # Arguments
     ///
-    /// * `kbnf_syntax_grammar_str` - The KBNF grammar string.
-    /// * `vocabulary` - The [`Vocabulary`] object.
-    /// * `config` - The [`Config`] object.
-    ///
-    /// # Returns
-    ///
-    /// * [`Engine`] - The new [`Engine`] object.
+    /// * `checkpoint` - The checkpoint to roll back to.
     ///
     /// # Errors
     ///
-    /// Returns an [`CreateEngineError`] when the grammar is empty or the grammar and/or config's value range is not supported by the Engine.
-    #[pyo3(signature = (kbnf_syntax_grammar_str, vocabulary, config=None))]
-    #[new]
-    pub fn new_py(
-        kbnf_syntax_grammar_str: &str,
-        vocabulary: Vocabulary,
-        config: Option<Config>,
-    ) -> Result<Engine, CreateEngineError> {
-        match config {
-            Some(config) => Self::with_config(kbnf_syntax_grammar_str, vocabulary, config),
-            None => Self::new(kbnf_syntax_grammar_str, vocabulary),
-        }
+    /// Returns a [`RollbackError`] when the checkpoint is invalid.
+    #[wasm_bindgen(js_name = rollbackTo)]
+    pub fn rollback_to_js(&mut self, checkpoint: &Checkpoint) -> Result<(), RollbackError> {
+        EngineLike::rollback_to(self, *checkpoint)
     }
-    /// Tries to accept a new token with the given token ID.
+    /// Discards the given checkpoint and frees the memory it holds.
     ///
-    /// # Signature
+    /// # Arguments
     ///
-    /// (self, token_id: int) -> AcceptTokenResult
+    /// * `checkpoint` - The checkpoint to discard.
     ///
-    /// # Arguments
+    /// # Errors
     ///
-    /// * `token_id` - The ID of the token to be accepted.
+    /// Returns a [`RollbackError`] when the checkpoint is invalid.
+    #[wasm_bindgen(js_name = discardCheckpoint)]
+    pub fn discard_checkpoint_js(&mut self, checkpoint: &Checkpoint) -> Result<(), RollbackError> {
+        EngineLike::discard_checkpoint(self, *checkpoint)
+    }
+    /// Creates a new engine that continues from the current state.
+    /// The forked engine shares the parsing history with this engine until either of them accepts new input,
+    /// and it shares the cache with this engine for its whole lifetime.
+    #[wasm_bindgen(js_name = fork)]
+    pub fn fork_js(&self) -> Engine {
+        self.fork()
+    }
+    /// Makes this engine use the cache of `other`, so that the allowed token IDs computed by either engine
+    /// are reused by both.
     ///
-    /// # Returns
+    /// # Arguments
     ///
-    /// * [`AcceptTokenResult`] - The result of accepting the token.
+    /// * `other` - The engine whose cache will be shared.
     ///
     /// # Errors
     ///
-    /// Returns an [`AcceptTokenError`] when a token is not accepted. Check the error type docs for more details.
-    /// The [`EngineLike`] internal states are not updated in this case.
-    #[pyo3(name = "try_accept_new_token")]
-    pub fn try_accept_new_token_py(
-        &mut self,
-        token_id: u32,
-    ) -> Result<AcceptTokenResult, AcceptTokenError> {
-        EngineLike::try_accept_new_token(self, token_id)
+    /// Returns a [`ShareCacheError`] when the grammars or the vocabularies of the engines are different.
+    #[wasm_bindgen(js_name = shareCacheWith)]
+    pub fn share_cache_with_js(&mut self, other: &Engine) -> Result<(), ShareCacheError> {
+        self.share_cache_with(other)
     }
-    /// Tries to accept new bytes.
+    /// Serializes the grammar of the engine, along with the tables computed from its vocabulary,
+    /// into the compiled grammar format.
     ///
-    /// # Signature
+    /// # Errors
     ///
-    /// (self, bytes: bytes) -> AcceptTokenResult
+    /// Returns a [`CompiledGrammarError`] when a DFA cannot be serialized.
+    #[wasm_bindgen(js_name = toCompiled)]
+    pub fn to_compiled_js(&self) -> Result<Vec<u8>, CompiledGrammarError> {
+        self.to_compiled()
+    }
+    /// Create a new [`Engine`] from a compiled grammar, a [`Vocabulary`], and a [`Config`].
     ///
     /// # Arguments
     ///
-    /// * `bytes` - The bytes to be accepted.
-    ///
-    /// # Returns
-    ///
-    /// * [`AcceptTokenResult`] - The result of accepting the bytes.
+    /// * `compiled_grammar` - The compiled grammar created by `toCompiled`.
+    /// * `vocabulary` - The [`Vocabulary`] object the grammar is compiled with.
+    /// * `config` - The [`Config`] object.
     ///
     /// # Errors
     ///
-    /// Returns an [`AcceptTokenError`] when the bytes are not accepted. Check the error type docs for more details.
-    #[pyo3(name = "try_accept_new_bytes")]
-    pub fn try_accept_new_bytes_py(
-        &mut self,
+    /// Returns an [`CreateEngineError`] when the compiled grammar is invalid, created with a different vocabulary,
+    /// or the config's value range is not supported by the Engine.
+    #[wasm_bindgen(js_name = fromCompiled)]
+    pub fn from_compiled_js(
+        compiled_grammar: &[u8],
+        vocabulary: Vocabulary,
+        config: Config,
+    ) -> Result<Engine, CreateEngineError> {
+        Self::from_compiled(compiled_grammar, vocabulary, config)
+    }
+    /// Recovers the parse tree of the bytes accepted since the last reset.
+    ///
+    /// # Returns
+    ///
+    /// The parse tree rooted at the start nonterminal, or `null` if the parse tree is disabled
+    /// or the accepted bytes are not a complete sentence of the grammar yet.
+    #[wasm_bindgen(js_name = parseTree)]
+    pub fn parse_tree_js(&self) -> Result<JsValue, serde_wasm_bindgen::Error> {
+        serde_wasm_bindgen::to_value(&EngineLike::parse_tree(self))
+    }
+    /// Subscribes to the start and completion events of the given nonterminal.
+    ///
+    /// # Arguments
+    ///
+    /// * `nonterminal` - The name of the nonterminal.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`SubscribeNonterminalError`] when the nonterminal does not exist in the grammar.
+    #[wasm_bindgen(js_name = subscribeNonterminal)]
+    pub fn subscribe_nonterminal_js(
+        &mut self,
+        nonterminal: &str,
+    ) -> Result<(), SubscribeNonterminalError> {
+        EngineLike::subscribe_nonterminal(self, nonterminal)
+    }
+    /// Unsubscribes from the start and completion events of the given nonterminal.
+    ///
+    /// # Arguments
+    ///
+    /// * `nonterminal` - The name of the nonterminal.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`SubscribeNonterminalError`] when the nonterminal does not exist in the grammar.
+    #[wasm_bindgen(js_name = unsubscribeNonterminal)]
+    pub fn unsubscribe_nonterminal_js(
+        &mut self,
+        nonterminal: &str,
+    ) -> Result<(), SubscribeNonterminalError> {
+        EngineLike::unsubscribe_nonterminal(self, nonterminal)
+    }
+    /// Takes the nonterminal events emitted since the last call, in the order they are emitted.
+    ///
+    /// # Returns
+    ///
+    /// An array of objects with `kind`, `nonterminal`, `start` and `end` fields.
+    #[wasm_bindgen(js_name = drainNonterminalEvents)]
+    pub fn drain_nonterminal_events_js(&mut self) -> Result<JsValue, serde_wasm_bindgen::Error> {
+        serde_wasm_bindgen::to_value(&EngineLike::drain_nonterminal_events(self))
+    }
+    /// Computes a fingerprint of the engine's current parsing state.
+    /// Engines with the same fingerprint will accept exactly the same inputs from now on.
+    #[wasm_bindgen(js_name = stateFingerprint)]
+    pub fn state_fingerprint_js(&self) -> u64 {
+        EngineLike::state_fingerprint(self)
+    }
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl Engine {
+    /// Create a new [`Engine`] from an KBNF grammar string, a [`Vocabulary`], and a [`Config`].
+    ///
+    /// # Signature
+    ///
+    /// (kbnf_syntax_grammar_str: str, vocabulary: Vocabulary, config: Config) -> Engine
+    ///
+    /// # Arguments
+    ///
+    /// * `kbnf_syntax_grammar_str` - The KBNF grammar string.
+    /// * `vocabulary` - The [`Vocabulary`] object.
+    /// * `config` - The [`Config`] object.
+    ///
+    /// # Returns
+    ///
+    /// * [`Engine`] - The new [`Engine`] object.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`CreateEngineError`] when the grammar is empty or the grammar and/or config's value range is not supported by the Engine.
+    #[pyo3(signature = (kbnf_syntax_grammar_str, vocabulary, config=None))]
+    #[new]
+    pub fn new_py(
+        kbnf_syntax_grammar_str: &str,
+        vocabulary: Vocabulary,
+        config: Option<Config>,
+    ) -> Result<Engine, CreateEngineError> {
+        match config {
+            Some(config) => Self::with_config(kbnf_syntax_grammar_str, vocabulary, config),
+            None => Self::new(kbnf_syntax_grammar_str, vocabulary),
+        }
+    }
+    /// Tries to accept a new token with the given token ID.
+    ///
+    /// # Signature
+    ///
+    /// (self, token_id: int) -> AcceptTokenResult
+    ///
+    /// # Arguments
+    ///
+    /// * `token_id` - The ID of the token to be accepted.
+    ///
+    /// # Returns
+    ///
+    /// * [`AcceptTokenResult`] - The result of accepting the token.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`AcceptTokenError`] when a token is not accepted. Check the error type docs for more details.
+    /// The [`EngineLike`] internal states are not updated in this case.
+    #[pyo3(name = "try_accept_new_token")]
+    pub fn try_accept_new_token_py(
+        &mut self,
+        token_id: u32,
+    ) -> Result<AcceptTokenResult, AcceptTokenError> {
+        EngineLike::try_accept_new_token(self, token_id)
+    }
+    /// Tries to accept new bytes.
+    ///
+    /// # Signature
+    ///
+    /// (self, bytes: bytes) -> AcceptTokenResult
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The bytes to be accepted.
+    ///
+    /// # Returns
+    ///
+    /// * [`AcceptTokenResult`] - The result of accepting the bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`AcceptTokenError`] when the bytes are not accepted. Check the error type docs for more details.
+    #[pyo3(name = "try_accept_new_bytes")]
+    pub fn try_accept_new_bytes_py(
+        &mut self,
         bytes: &[u8],
     ) -> Result<AcceptTokenResult, AcceptTokenError> {
         EngineLike::try_accept_new_bytes(self, bytes)
     }
+    /// Tries to accept a new token with the given token ID, explaining why it is rejected if it is.
+    ///
+    /// # Signature
+    ///
+    /// (self, token_id: int) -> AcceptTokenResult
+    ///
+    /// # Arguments
+    ///
+    /// * `token_id` - The ID of the token to be accepted.
+    ///
+    /// # Returns
+    ///
+    /// * [`AcceptTokenResult`] - The result of accepting the token.
+    ///
+    /// # Errors
+    ///
+    /// Raises a `ValueError` with the rejected byte and the expected symbols when a token is not accepted.
+    /// The [`EngineLike`] internal states are not updated in this case.
+    #[pyo3(name = "try_accept_new_token_with_diagnostic")]
+    pub fn try_accept_new_token_with_diagnostic_py(
+        &mut self,
+        token_id: u32,
+    ) -> Result<AcceptTokenResult, AcceptTokenDiagnosticError> {
+        EngineLike::try_accept_new_token_with_diagnostic(self, token_id)
+    }
+    /// Tries to accept new bytes, explaining why they are rejected if they are.
+    ///
+    /// # Signature
+    ///
+    /// (self, bytes: bytes) -> AcceptTokenResult
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The bytes to be accepted.
+    ///
+    /// # Returns
+    ///
+    /// * [`AcceptTokenResult`] - The result of accepting the bytes.
+    ///
+    /// # Errors
+    ///
+    /// Raises a `ValueError` with the rejected byte and the expected symbols when the bytes are not accepted.
+    #[pyo3(name = "try_accept_new_bytes_with_diagnostic")]
+    pub fn try_accept_new_bytes_with_diagnostic_py(
+        &mut self,
+        bytes: &[u8],
+    ) -> Result<AcceptTokenResult, AcceptTokenDiagnosticError> {
+        EngineLike::try_accept_new_bytes_with_diagnostic(self, bytes)
+    }
+
+    /// Computes the longest byte string that every input accepted by the engine from now on must start with.
+    /// The engine's internal states are not updated.
+    ///
+    /// # Signature
+    ///
+    /// (self, max_length: int) -> bytes
+    ///
+    /// # Arguments
+    ///
+    /// * `max_length` - The maximum number of bytes to compute.
+    #[pyo3(name = "compute_forced_bytes")]
+    pub fn compute_forced_bytes_py(
+        &mut self,
+        max_length: usize,
+    ) -> std::borrow::Cow<'static, [u8]> {
+        std::borrow::Cow::Owned(EngineLike::compute_forced_bytes(self, max_length))
+    }
+
+    /// Computes the shortest byte string that, appended to the current input, completes the grammar.
+    /// The engine's internal states are not updated.
+    ///
+    /// # Signature
+    ///
+    /// (self) -> Optional[bytes]
+    ///
+    /// # Returns
+    ///
+    /// The shortest completion, or None if the grammar cannot be completed by bytes alone.
+    #[pyo3(name = "shortest_completion")]
+    pub fn shortest_completion_py(&mut self) -> Option<std::borrow::Cow<'static, [u8]>> {
+        EngineLike::shortest_completion(self).map(std::borrow::Cow::Owned)
+    }
+
+    /// Computes the bytes that can be accepted next, in ascending order.
+    ///
+    /// # Signature
+    ///
+    /// (self) -> bytes
+    #[pyo3(name = "compute_allowed_next_bytes")]
+    pub fn compute_allowed_next_bytes_py(&mut self) -> std::borrow::Cow<'static, [u8]> {
+        std::borrow::Cow::Owned(EngineLike::compute_allowed_next_bytes(self))
+    }
+
+    /// Lists the grammar symbols that the engine is waiting on.
+    ///
+    /// # Signature
+    ///
+    /// (self) -> List[ExpectedSymbol]
+    #[pyo3(name = "expected_symbols")]
+    pub fn expected_symbols_py(&self) -> Vec<ExpectedSymbol> {
+        EngineLike::expected_symbols(self)
+    }
 
     /// Computes the allowed token IDs based on current states.
     ///
@@ -515,6 +1152,23 @@ impl Engine {
         py.allow_threads(|| EngineLike::compute_allowed_token_ids(self));
     }
 
+    /// Computes the allowed token IDs based on current states,
+    /// excluding the tokens after which the grammar cannot be completed within `max_remaining_bytes` bytes.
+    ///
+    /// # Signature
+    ///
+    /// (self, max_remaining_bytes: int) -> None
+    #[pyo3(name = "compute_allowed_token_ids_with_budget")]
+    pub fn compute_allowed_token_ids_with_budget_py(
+        &mut self,
+        py: Python<'_>,
+        max_remaining_bytes: usize,
+    ) {
+        py.allow_threads(|| {
+            EngineLike::compute_allowed_token_ids_with_budget(self, max_remaining_bytes)
+        });
+    }
+
     /// Gets the allowed token IDs since last computation.
     /// Last computation is the last [`EngineLike::compute_allowed_token_ids`] or [`EngineLike::update_logits`] called.
     ///
@@ -529,6 +1183,29 @@ impl Engine {
             .ones()
             .collect()
     }
+    /// Accepts the given draft tokens in order until one of them is rejected,
+    /// computing the allowed token IDs before each of them.
+    ///
+    /// # Signature
+    ///
+    /// (self, token_ids: List[int]) -> Tuple[int, List[List[int]]]
+    ///
+    /// # Arguments
+    ///
+    /// * `token_ids` - The IDs of the draft tokens.
+    ///
+    /// # Returns
+    ///
+    /// A pair of the number of accepted draft tokens and the allowed token IDs at each position,
+    /// from before the first draft token to after the last accepted token.
+    #[pyo3(name = "verify_tokens")]
+    pub fn verify_tokens_py(&mut self, token_ids: Vec<u32>) -> (usize, Vec<Vec<usize>>) {
+        let (accepted, masks) = EngineLike::verify_tokens(self, &token_ids);
+        (
+            accepted,
+            masks.iter().map(|mask| mask.ones().collect()).collect(),
+        )
+    }
     /// Gets the disallowed token IDs since last computation.
     /// Last computation is the last [`EngineLike::compute_allowed_token_ids`] or [`EngineLike::update_logits`] called.
     ///
@@ -699,6 +1376,15 @@ impl Engine {
     pub fn is_finished_py(&self) -> bool {
         EngineLike::is_finished(self)
     }
+
+    /// Checks if the engine is accepting, i.e. the input so far is a complete sentence of the grammar.
+    /// # Signature
+    ///
+    /// (self) -> bool
+    #[pyo3(name = "is_accepting")]
+    pub fn is_accepting_py(&self) -> bool {
+        EngineLike::is_accepting(self)
+    }
     /// Resets the engine to its initial state. Notably, the cache is preserved.
     ///
     /// # Signature
@@ -708,6 +1394,60 @@ impl Engine {
     pub fn reset_py(&mut self) {
         EngineLike::reset(self)
     }
+    /// Gets the statistics of the cache.
+    ///
+    /// # Signature
+    ///
+    /// (self) -> CacheStats
+    #[pyo3(name = "cache_stats")]
+    pub fn cache_stats_py(&self) -> CacheStats {
+        EngineLike::cache_stats(self)
+    }
+    /// Removes all the entries from the cache, including those shared with forked engines.
+    /// The statistics are preserved.
+    ///
+    /// # Signature
+    ///
+    /// (self) -> None
+    #[pyo3(name = "clear_cache")]
+    pub fn clear_cache_py(&mut self) {
+        EngineLike::clear_cache(self)
+    }
+    /// Exports the entries of the cache, keyed by the fingerprints of the grammar and the vocabulary,
+    /// so that they can be imported into engines created in other processes.
+    ///
+    /// # Signature
+    ///
+    /// (self) -> bytes
+    #[pyo3(name = "export_cache")]
+    pub fn export_cache_py(&self) -> std::borrow::Cow<'static, [u8]> {
+        std::borrow::Cow::Owned(EngineLike::export_cache(self))
+    }
+    /// Imports the entries of a cache exported by `export_cache` and returns the number of imported entries.
+    ///
+    /// # Signature
+    ///
+    /// (self, bytes: bytes) -> int
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`ImportCacheError`] when the input is not a valid exported cache,
+    /// or it is exported from an engine with a different grammar, vocabulary, or value ranges.
+    #[pyo3(name = "import_cache")]
+    pub fn import_cache_py(&mut self, bytes: &[u8]) -> Result<usize, ImportCacheError> {
+        EngineLike::import_cache(self, bytes)
+    }
+    /// Populates the cache by replaying representative outputs before traffic arrives.
+    /// The state of the engine is not changed.
+    ///
+    /// # Signature
+    ///
+    /// (self, corpus: List[bytes]) -> int
+    #[pyo3(name = "warm_up")]
+    pub fn warm_up_py(&self, corpus: Vec<Vec<u8>>) -> usize {
+        let corpus: Vec<&[u8]> = corpus.iter().map(Vec::as_slice).collect();
+        EngineLike::warm_up(self, &corpus)
+    }
     /// Gets the vocabulary of the engine.
     ///
     /// # Signature
@@ -786,6 +1526,194 @@ impl Engine {
         EngineLike::update_logits(self, token_id, logits)
     }
 
+    /// Saves the current state of the engine so that it can be restored later by [`EngineLike::rollback_to`].
+    ///
+    /// # Signature
+    ///
+    /// (self) -> Checkpoint
+    #[pyo3(name = "checkpoint")]
+    pub fn checkpoint_py(&mut self) -> Checkpoint {
+        EngineLike::checkpoint(self)
+    }
+
+    /// Restores the engine to the state saved in the given checkpoint.
+    /// All checkpoints created after it are discarded.
+    ///
+    /// # Signature
+    ///
+    /// (self, checkpoint: Checkpoint) -> None
+    ///
+    /// # Arguments
+    ///
+    /// * `checkpoint` - The checkpoint to roll back to.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`RollbackError`] when the checkpoint is invalid.
+    #[pyo3(name = "rollback_to")]
+    pub fn rollback_to_py(&mut self, checkpoint: Checkpoint) -> Result<(), RollbackError> {
+        EngineLike::rollback_to(self, checkpoint)
+    }
+
+    /// Discards the given checkpoint and frees the memory it holds.
+    ///
+    /// # Signature
+    ///
+    /// (self, checkpoint: Checkpoint) -> None
+    ///
+    /// # Arguments
+    ///
+    /// * `checkpoint` - The checkpoint to discard.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`RollbackError`] when the checkpoint is invalid.
+    #[pyo3(name = "discard_checkpoint")]
+    pub fn discard_checkpoint_py(&mut self, checkpoint: Checkpoint) -> Result<(), RollbackError> {
+        EngineLike::discard_checkpoint(self, checkpoint)
+    }
+    /// Creates a new engine that continues from the current state.
+    /// The forked engine shares the parsing history with this engine until either of them accepts new input,
+    /// and it shares the cache with this engine for its whole lifetime.
+    ///
+    /// # Signature
+    ///
+    /// (self) -> InternalEngine
+    #[pyo3(name = "fork")]
+    pub fn fork_py(&self) -> Engine {
+        self.fork()
+    }
+    /// Makes this engine use the cache of `other`, so that the allowed token IDs computed by either engine
+    /// are reused by both, even across threads.
+    ///
+    /// # Signature
+    ///
+    /// (self, other: InternalEngine) -> None
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The engine whose cache will be shared.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`ShareCacheError`] when the grammars or the vocabularies of the engines are different.
+    #[pyo3(name = "share_cache_with")]
+    pub fn share_cache_with_py(&mut self, other: &Engine) -> Result<(), ShareCacheError> {
+        self.share_cache_with(other)
+    }
+    /// Serializes the grammar of the engine, along with the tables computed from its vocabulary,
+    /// into the compiled grammar format.
+    ///
+    /// # Signature
+    ///
+    /// (self) -> bytes
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`CompiledGrammarError`] when a DFA cannot be serialized.
+    #[pyo3(name = "to_compiled")]
+    pub fn to_compiled_py(&self) -> Result<std::borrow::Cow<'static, [u8]>, CompiledGrammarError> {
+        self.to_compiled().map(std::borrow::Cow::Owned)
+    }
+    /// Create a new [`Engine`] from a compiled grammar, a [`Vocabulary`], and a [`Config`].
+    ///
+    /// # Signature
+    ///
+    /// (compiled_grammar: bytes, vocabulary: Vocabulary, config: Optional[Config]) -> InternalEngine
+    ///
+    /// # Arguments
+    ///
+    /// * `compiled_grammar` - The compiled grammar created by `to_compiled`.
+    /// * `vocabulary` - The [`Vocabulary`] object the grammar is compiled with.
+    /// * `config` - The [`Config`] object.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`CreateEngineError`] when the compiled grammar is invalid, created with a different vocabulary,
+    /// or the config's value range is not supported by the Engine.
+    #[staticmethod]
+    #[pyo3(name = "from_compiled", signature = (compiled_grammar, vocabulary, config=None))]
+    pub fn from_compiled_py(
+        compiled_grammar: &[u8],
+        vocabulary: Vocabulary,
+        config: Option<Config>,
+    ) -> Result<Engine, CreateEngineError> {
+        Self::from_compiled(compiled_grammar, vocabulary, config.unwrap_or_default())
+    }
+    /// Recovers the parse tree of the bytes accepted since the last reset.
+    ///
+    /// # Signature
+    ///
+    /// (self) -> Optional[ParseTree]
+    ///
+    /// # Returns
+    ///
+    /// The parse tree rooted at the start nonterminal, or `None` if the parse tree is disabled
+    /// or the accepted bytes are not a complete sentence of the grammar yet.
+    #[pyo3(name = "parse_tree")]
+    pub fn parse_tree_py(&self) -> Option<ParseTree> {
+        EngineLike::parse_tree(self)
+    }
+    /// Subscribes to the start and completion events of the given nonterminal.
+    ///
+    /// # Signature
+    ///
+    /// (self, nonterminal: str) -> None
+    ///
+    /// # Arguments
+    ///
+    /// * `nonterminal` - The name of the nonterminal.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`SubscribeNonterminalError`] when the nonterminal does not exist in the grammar.
+    #[pyo3(name = "subscribe_nonterminal")]
+    pub fn subscribe_nonterminal_py(
+        &mut self,
+        nonterminal: &str,
+    ) -> Result<(), SubscribeNonterminalError> {
+        EngineLike::subscribe_nonterminal(self, nonterminal)
+    }
+    /// Unsubscribes from the start and completion events of the given nonterminal.
+    ///
+    /// # Signature
+    ///
+    /// (self, nonterminal: str) -> None
+    ///
+    /// # Arguments
+    ///
+    /// * `nonterminal` - The name of the nonterminal.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`SubscribeNonterminalError`] when the nonterminal does not exist in the grammar.
+    #[pyo3(name = "unsubscribe_nonterminal")]
+    pub fn unsubscribe_nonterminal_py(
+        &mut self,
+        nonterminal: &str,
+    ) -> Result<(), SubscribeNonterminalError> {
+        EngineLike::unsubscribe_nonterminal(self, nonterminal)
+    }
+    /// Takes the nonterminal events emitted since the last call, in the order they are emitted.
+    ///
+    /// # Signature
+    ///
+    /// (self) -> List[NonterminalEvent]
+    #[pyo3(name = "drain_nonterminal_events")]
+    pub fn drain_nonterminal_events_py(&mut self) -> Vec<NonterminalEvent> {
+        EngineLike::drain_nonterminal_events(self)
+    }
+    /// Computes a fingerprint of the engine's current parsing state.
+    /// Engines with the same fingerprint will accept exactly the same inputs from now on.
+    ///
+    /// # Signature
+    ///
+    /// (self) -> int
+    #[pyo3(name = "state_fingerprint")]
+    pub fn state_fingerprint_py(&self) -> u64 {
+        EngineLike::state_fingerprint(self)
+    }
+
     fn __repr__(&self) -> String {
         format!("Engine({:#?})", self)
     }
@@ -802,6 +1730,192 @@ impl Engine {
     }
 }
 
+#[cfg(feature = "python")]
+#[pymethods]
+impl EngineBatch {
+    /// Creates a new [`EngineBatch`] from the given engines.
+    ///
+    /// # Signature
+    ///
+    /// (engines: List[InternalEngine], worker_threads: int = 1) -> InternalEngineBatch
+    ///
+    /// # Arguments
+    ///
+    /// * `engines` - The engines in the batch. The i-th engine corresponds to the i-th row of the masks.
+    ///   The engines are copied into the batch, sharing the cache with the given engines.
+    /// * `worker_threads` - The number of threads used to compute the allowed token IDs of the engines.
+    ///   The threads are spawned once here and shared by the clones of the batch.
+    ///   Values above 1 require the `parallel` feature.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`EngineBatchError`] when the engines do not share the same vocabulary,
+    /// or more than one worker thread is requested while the `parallel` feature is disabled.
+    #[pyo3(signature = (engines, worker_threads=1))]
+    #[new]
+    pub fn new_py(engines: Vec<Engine>, worker_threads: usize) -> Result<Self, EngineBatchError> {
+        Self::new(engines, worker_threads)
+    }
+    /// Tries to accept one new token for each engine.
+    ///
+    /// # Signature
+    ///
+    /// (self, token_ids: List[int]) -> List[Union[AcceptTokenResult, AcceptTokenError]]
+    ///
+    /// # Arguments
+    ///
+    /// * `token_ids` - The token IDs, where the i-th token ID is accepted by the i-th engine.
+    ///
+    /// # Returns
+    ///
+    /// The result of accepting the token for each engine, or the error if the engine rejects its token.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`EngineBatchError`] when the number of token IDs is not equal to the batch size.
+    #[pyo3(name = "try_accept_new_tokens")]
+    pub fn try_accept_new_tokens_py(
+        &mut self,
+        py: Python<'_>,
+        token_ids: Vec<u32>,
+    ) -> Result<Vec<pyo3::PyObject>, EngineBatchError> {
+        Ok(self
+            .try_accept_new_tokens(&token_ids)?
+            .into_iter()
+            .map(|result| match result {
+                Ok(result) => result.into_py(py),
+                Err(error) => error.into_py(py),
+            })
+            .collect())
+    }
+    /// Computes the allowed token IDs of every engine.
+    ///
+    /// # Signature
+    ///
+    /// (self) -> None
+    #[pyo3(name = "compute_allowed_token_ids")]
+    pub fn compute_allowed_token_ids_py(&mut self, py: Python<'_>) {
+        py.allow_threads(|| self.compute_allowed_token_ids());
+    }
+    /// Masks the logits of the whole batch based on the last computed token IDs of each engine.
+    ///
+    /// # Signature
+    ///
+    /// (self, logits_ptr: int, length: int) -> None
+    ///
+    /// # Arguments
+    ///
+    /// * `logits_ptr` - The pointer to the row-major `[batch, n]` logits array, where `n` is at least the vocabulary size.
+    /// * `length` - The total length of the logits array.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`MaskLogitsError`] when the logits length is not a multiple of the batch size
+    /// or the rows are shorter than the vocabulary size.
+    /// The logits array is not updated in this case.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the pointer is on CPU, points to readable,aligned memory that contains float32 and the length is correct.
+    #[pyo3(name = "mask_logits")]
+    pub unsafe fn mask_logits_py(
+        &self,
+        logits_ptr: usize,
+        length: usize,
+    ) -> Result<(), MaskLogitsError> {
+        let logits = std::slice::from_raw_parts_mut(logits_ptr as *mut f32, length);
+        self.mask_logits(logits)
+    }
+    /// Writes the packed bitmask of the allowed token IDs of the whole batch.
+    ///
+    /// # Signature
+    ///
+    /// (self, bitmask_ptr: int, length: int) -> None
+    ///
+    /// # Arguments
+    ///
+    /// * `bitmask_ptr` - The pointer to the row-major `[batch, ceil(vocab_size / 32)]` int32 buffer.
+    ///   The token ID `t` of the i-th engine is allowed if and only if the bit `t % 32` of the word `t / 32` in the i-th row is set.
+    /// * `length` - The total length of the buffer.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`WriteBufferError`] when the buffer is too small.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the pointer is on CPU, points to writable, aligned memory that contains int32 and the length is correct.
+    #[pyo3(name = "write_bitmask")]
+    pub unsafe fn write_bitmask_py(
+        &self,
+        bitmask_ptr: usize,
+        length: usize,
+    ) -> Result<(), WriteBufferError> {
+        let bitmask = std::slice::from_raw_parts_mut(bitmask_ptr as *mut u32, length);
+        self.write_bitmask(bitmask)
+    }
+    /// Checks if each engine is finished.
+    ///
+    /// # Signature
+    ///
+    /// (self) -> List[bool]
+    #[pyo3(name = "is_finished")]
+    pub fn is_finished_py(&self) -> Vec<bool> {
+        self.engines().iter().map(EngineLike::is_finished).collect()
+    }
+    /// Checks if each engine is accepting.
+    ///
+    /// # Signature
+    ///
+    /// (self) -> List[bool]
+    #[pyo3(name = "is_accepting")]
+    pub fn is_accepting_py(&self) -> Vec<bool> {
+        self.engines()
+            .iter()
+            .map(EngineLike::is_accepting)
+            .collect()
+    }
+    /// Gets a copy of the engine at the given index.
+    ///
+    /// # Signature
+    ///
+    /// (self, index: int) -> Optional[InternalEngine]
+    #[pyo3(name = "get_engine")]
+    pub fn engine_py(&self, index: usize) -> Option<Engine> {
+        self.engines().get(index).cloned()
+    }
+    /// Gets the vocabulary size shared by the engines.
+    ///
+    /// # Signature
+    ///
+    /// (self) -> int
+    #[pyo3(name = "vocab_size")]
+    pub fn vocab_size_py(&self) -> usize {
+        self.vocab_size()
+    }
+    /// Resets every engine to its initial state.
+    ///
+    /// # Signature
+    ///
+    /// (self) -> None
+    #[pyo3(name = "reset")]
+    pub fn reset_py(&mut self) {
+        self.reset()
+    }
+
+    fn __len__(&self) -> usize {
+        self.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("EngineBatch({:#?})", self)
+    }
+
+    fn __str__(&self) -> String {
+        self.__repr__()
+    }
+}
+
 #[cfg(feature = "wasm")]
 #[wasm_bindgen]
 impl Config {
@@ -821,3 +1935,23 @@ impl Config {
         Config::default()
     }
 }
+
+#[cfg(feature = "wasm")]
+#[wasm_bindgen]
+impl TerminationConfig {
+    /// Creates a new instance of [`TerminationConfig`] with default values.
+    #[wasm_bindgen(constructor)]
+    pub fn new_js() -> TerminationConfig {
+        TerminationConfig::default()
+    }
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl TerminationConfig {
+    /// Creates a new instance of [`TerminationConfig`] with default values.
+    #[new]
+    pub fn new_py() -> TerminationConfig {
+        TerminationConfig::default()
+    }
+}
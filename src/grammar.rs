@@ -1,30 +1,36 @@
 //! The grammar module that contains the grammar struct in HIR form and its related functions and structs.
+use std::borrow::Cow;
 use std::fmt::Debug;
 use std::hash::Hash;
 
-use crate::config::RegexConfig;
-use crate::utils::{self, dispatch_by_dfa_state_status, ByteSet};
+use crate::binary::{BinaryReader, BinaryWriter};
+use crate::compiled_grammar::CompiledGrammarError;
+use crate::config::{Fsa, RegexConfig};
+use crate::regex::{FiniteStateAutomaton, LazyDfa};
+use crate::utils::{self, ByteSet, FsaStateStatus, StableHasher};
 use crate::Vocabulary;
-use ahash::AHashMap;
+use ahash::{AHashMap, AHashSet};
 use fixedbitset_stack::FixedBitSet;
 use general_sam::GeneralSamNodeID;
 use jaggedarray::jagged_array::JaggedArrayViewTrait;
 use jaggedarray::jagged_array::{JaggedArray, JaggedArrayView};
-use kbnf_regex_automata::dfa::Automaton;
+use kbnf_regex_automata::dfa::{dense, sparse};
 use kbnf_regex_automata::util::primitives::StateID;
+use kbnf_regex_automata::Anchored;
 use kbnf_syntax::node::{OperatorFlattenedNode, Rhs};
+use kbnf_syntax::semantic_error::SemanticError;
 use kbnf_syntax::simplified_grammar::SimplifiedGrammar;
 use kbnf_syntax::suffix_automaton::SuffixAutomaton;
 use kbnf_syntax::InternedStrings;
-use kbnf_syntax::{self, regex::FiniteStateAutomaton};
 use num::traits::{NumAssign, NumOps};
 use num::{
     cast::AsPrimitive,
     traits::{ConstOne, ConstZero},
     Num,
 };
+use string_interner::backend::StringBackend;
 use string_interner::symbol::SymbolU32;
-use string_interner::Symbol;
+use string_interner::{StringInterner, Symbol};
 #[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, PartialOrd, Ord)]
 #[repr(transparent)]
 /// The wrapper struct that represents the terminal id in the grammar.
@@ -142,6 +148,34 @@ where
         )
     }
 }
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(transparent)]
+/// The wrapper struct that represents the special token id in the grammar.
+///
+/// It is an index into the special tokens referenced by the grammar rather than the token ID itself,
+/// so that it fits in the generic parameter.
+pub struct SpecialTokenID<T>(pub T)
+where
+    T: Num + AsPrimitive<usize> + ConstOne + ConstZero;
+impl<T> SpecialTokenID<T>
+where
+    T: Num
+        + AsPrimitive<usize>
+        + ConstOne
+        + ConstZero
+        + NumAssign
+        + std::cmp::PartialOrd
+        + std::convert::TryFrom<usize>
+        + num::Bounded
+        + Hash
+        + Eq,
+    usize: num::traits::AsPrimitive<T>,
+{
+    /// Get the display form of the special token id.
+    pub fn to_display_form(&self, grammar: &Grammar<T>) -> String {
+        format!("#token({})[{}]", grammar.special_token(*self), self.0.as_())
+    }
+}
 /// The node of the grammar in HIR.
 #[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, PartialOrd, Ord)]
 pub enum HIRNode<T>
@@ -160,6 +194,8 @@ where
     Substrings(SuffixAutomataID<T>),
     /// The regex complement node.
     RegexComplement(RegexID<T>),
+    /// The special token node, which matches exactly one special token of the vocabulary.
+    SpecialToken(SpecialTokenID<T>),
 }
 
 impl<TI> HIRNode<TI>
@@ -197,6 +233,7 @@ where
             HIRNode::RegexComplement(x) => {
                 format!("#ex\"{}\"[{}]", grammar.regex_str(*x).unwrap(), x.0.as_())
             }
+            HIRNode::SpecialToken(x) => x.to_display_form(grammar),
         }
     }
 }
@@ -212,12 +249,23 @@ where
     rules: JaggedArray<HIRNode<TI>, Vec<usize>, 3>,
     interned_strings: InternedStrings,
     id_to_regexes: Vec<FiniteStateAutomaton>,
-    pub(crate) regex_to_token_ids: AHashMap<(RegexID<TI>, StateID, RegexType), FixedBitSet>,
+    /// The classification of the tokens scanned from a node in a given state,
+    /// where the state is the index for terminals, the DFA state ID for regexes and the node ID for suffix automata.
+    pub(crate) token_classifications: AHashMap<(HIRNode<TI>, usize), TokenClassification>,
     id_to_regex_first_bytes: AHashMap<(TI, StateID), ByteSet>,
     id_to_regex_complement_first_bytes: AHashMap<(TI, StateID), ByteSet>,
     id_to_terminals: JaggedArray<u8, Vec<usize>, 2>,
     id_to_suffix_automata: Vec<SuffixAutomaton>,
     id_to_suffix_automata_first_bytes: AHashMap<(usize, GeneralSamNodeID), ByteSet>,
+    /// The token IDs of the special tokens referenced by the grammar, indexed by [SpecialTokenID].
+    id_to_special_tokens: Vec<u32>,
+    /// The lengths in bytes of the special tokens referenced by the grammar, indexed by [SpecialTokenID].
+    id_to_special_token_lengths: Vec<usize>,
+    /// The minimal number of bytes to reach a match from each DFA state of the regexes that are not complements.
+    /// States that cannot reach a match are absent.
+    id_to_regex_min_lengths: AHashMap<(TI, StateID), usize>,
+    /// The minimal number of bytes of a string derived from each nonterminal, or [usize::MAX] if there is none.
+    id_to_nonterminal_min_lengths: Vec<usize>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -241,6 +289,12 @@ pub enum CreateGrammarError {
     #[error("Regex initialization error: {0}")]
     /// Error due to inefficient cache usage in a lazy DFA.
     LazyDfaCacheError(#[from] kbnf_regex_automata::hybrid::CacheError),
+    #[error("The special token {0:?} is not in the vocabulary.")]
+    /// Error due to a special token reference whose content is not a special token of the vocabulary.
+    UnknownSpecialToken(String),
+    #[error("The string {0:?} contains `__kbnf_special_token_`, which is reserved for special token references.")]
+    /// Error due to a string that contains the marker of special token references.
+    ReservedString(String),
 }
 impl<TI> Debug for Grammar<TI>
 where
@@ -357,6 +411,125 @@ pub(crate) enum RegexType {
     Complement,
 }
 
+/// A set of token positions in the order of [`Vocabulary::subtree_token_positions`].
+#[derive(Debug, Clone)]
+pub(crate) enum TokenPositions {
+    Sparse(Box<[u32]>),
+    Dense(FixedBitSet),
+}
+
+impl TokenPositions {
+    fn new(positions: Vec<u32>, len: usize) -> Self {
+        // A sparse position takes 32 bits while a dense position takes one bit.
+        if positions.len() * 32 < len {
+            Self::Sparse(positions.into_boxed_slice())
+        } else {
+            let mut set = FixedBitSet::with_capacity(len);
+            set.extend(positions.into_iter().map(|position| position as usize));
+            Self::Dense(set)
+        }
+    }
+
+    pub(crate) fn union_into(&self, set: &mut FixedBitSet) {
+        match self {
+            Self::Sparse(positions) => {
+                for &position in positions.iter() {
+                    set.insert(position as usize);
+                }
+            }
+            Self::Dense(positions) => set.union_with(positions),
+        }
+    }
+}
+
+/// The classification of the tokens scanned from a node in a given state, regardless of the rest of the Earley sets.
+///
+/// A token is accepted if all its bytes are consumed within the node,
+/// and rejected if the node rejects one of its bytes before the node can complete.
+/// Otherwise, the token crosses the node boundary and is undetermined.
+#[derive(Debug, Clone)]
+pub(crate) struct TokenClassification {
+    pub(crate) accepted: TokenPositions,
+    pub(crate) undetermined: TokenPositions,
+}
+
+impl TokenClassification {
+    /// Classifies the tokens by walking the token tries from the `start` state.
+    ///
+    /// `step` returns the state after consuming a byte, or `None` if the node cannot consume the byte,
+    /// along with whether the node can complete after the byte.
+    ///
+    /// Returns `None` as soon as fewer than `min_accepted` tokens can be accepted,
+    /// so the tries are not walked for a state whose first bytes already rule out enough tokens.
+    fn new<S: Copy>(
+        vocabulary: &Vocabulary,
+        start: S,
+        min_accepted: usize,
+        mut step: impl FnMut(S, u8) -> (Option<S>, bool),
+    ) -> Option<Self> {
+        // The number of tokens that are accepted or not classified yet
+        let mut acceptable = 0;
+        let mut first_bytes = ByteSet::with_capacity(256);
+        for first_byte in 0..=u8::MAX {
+            let Some(root) = vocabulary.token_trie(first_byte).first() else {
+                continue;
+            };
+            let (next_state, completed) = step(start, first_byte);
+            if next_state.is_some() || completed {
+                first_bytes.insert(first_byte as usize);
+                acceptable += vocabulary.subtree_token_positions(root).len();
+            }
+        }
+        if acceptable < min_accepted {
+            return None;
+        }
+        let mut accepted = Vec::new();
+        let mut undetermined = Vec::new();
+        // The state and whether the node can complete before the end of the prefix, for each prefix on the path
+        let mut path: Vec<(S, bool)> = Vec::new();
+        for first_byte in first_bytes.ones() {
+            let nodes = vocabulary.token_trie(first_byte as u8);
+            path.clear();
+            let mut i = 0;
+            while i < nodes.len() {
+                let node = &nodes[i];
+                path.truncate(node.depth as usize);
+                let (state, completable) = path.last().copied().unwrap_or((start, false));
+                let (next_state, completed) = step(state, node.byte);
+                let positions = vocabulary.token_positions(node);
+                if let Some(next_state) = next_state {
+                    accepted.extend(positions.map(|position| position as u32));
+                    path.push((next_state, completable || completed));
+                    i += 1;
+                    continue;
+                }
+                let subtree_positions = vocabulary.subtree_token_positions(node);
+                if completed {
+                    acceptable -= subtree_positions.len() - positions.len();
+                    accepted.extend(positions.clone().map(|position| position as u32));
+                    undetermined.extend(
+                        (positions.end..subtree_positions.end).map(|position| position as u32),
+                    );
+                } else {
+                    acceptable -= subtree_positions.len();
+                    if completable {
+                        undetermined.extend(subtree_positions.map(|position| position as u32));
+                    }
+                }
+                if acceptable < min_accepted {
+                    return None;
+                }
+                i = node.skip as usize;
+            }
+        }
+        let len = vocabulary.token_positions_len();
+        Some(Self {
+            accepted: TokenPositions::new(accepted, len),
+            undetermined: TokenPositions::new(undetermined, len),
+        })
+    }
+}
+
 impl<TI> Grammar<TI>
 where
     TI: Num
@@ -392,12 +565,8 @@ where
         vocabulary: &Vocabulary,
         regex_config: RegexConfig,
     ) -> Result<Self, CreateGrammarError> {
-        let mut id_to_terminals = JaggedArray::<u8, Vec<usize>, 2>::new();
-        for (id, terminal) in grammar.interned_strings.terminals.iter() {
-            id_to_terminals.new_row::<0>();
-            id_to_terminals.extend_last_row_from_slice(terminal.as_bytes());
-            assert!(id_to_terminals.len() - 1 == id.to_usize());
-        }
+        let id_to_terminals = Self::construct_id_to_terminals(&grammar.interned_strings);
+        let mut id_to_special_tokens = Vec::new();
         let mut rules = JaggedArray::<HIRNode<TI>, Vec<usize>, 3>::with_capacity([
             grammar.expressions.len(),
             1,
@@ -421,15 +590,46 @@ where
                                     )
                                 })?,
                             )),
-                            OperatorFlattenedNode::RegexString(x) => HIRNode::RegexString(RegexID(
-                                x.to_usize().try_into().map_err(|_| {
-                                    CreateGrammarError::IntConversionError(
-                                        "regex".to_string(),
-                                        x.to_usize(),
-                                        TI::max_value().as_(),
-                                    )
-                                })?,
-                            )),
+                            OperatorFlattenedNode::RegexString(x) => {
+                                // Special token references are replaced with marker regexes before parsing.
+                                match grammar
+                                    .interned_strings
+                                    .regex_strings
+                                    .resolve(*x)
+                                    .and_then(utils::parse_special_token_marker)
+                                {
+                                    Some(token_id) => {
+                                        let index = match id_to_special_tokens
+                                            .iter()
+                                            .position(|&id| id == token_id)
+                                        {
+                                            Some(index) => index,
+                                            None => {
+                                                id_to_special_tokens.push(token_id);
+                                                id_to_special_tokens.len() - 1
+                                            }
+                                        };
+                                        HIRNode::SpecialToken(SpecialTokenID(
+                                            index.try_into().map_err(|_| {
+                                                CreateGrammarError::IntConversionError(
+                                                    "special token".to_string(),
+                                                    index,
+                                                    TI::max_value().as_(),
+                                                )
+                                            })?,
+                                        ))
+                                    }
+                                    None => HIRNode::RegexString(RegexID(
+                                        x.to_usize().try_into().map_err(|_| {
+                                            CreateGrammarError::IntConversionError(
+                                                "regex".to_string(),
+                                                x.to_usize(),
+                                                TI::max_value().as_(),
+                                            )
+                                        })?,
+                                    )),
+                                }
+                            }
                             OperatorFlattenedNode::Nonterminal(x) => HIRNode::Nonterminal(
                                 NonterminalID(x.to_usize().try_into().map_err(|_| {
                                     CreateGrammarError::IntConversionError(
@@ -473,17 +673,22 @@ where
                 }
             }
         }
-        let id_to_regexes = grammar.id_to_regex;
+        let mut interned_strings = grammar.interned_strings;
+        let id_to_regexes =
+            Self::construct_regexes(grammar.id_to_regex, &mut interned_strings, regex_config)?;
         let id_to_suffix_automata = grammar.id_to_suffix_automaton;
         let (id_to_regex_first_bytes, id_to_regex_complement_first_bytes) =
             Self::construct_regex_first_bytes(&rules, &id_to_regexes);
         let id_to_suffix_automata_first_bytes =
             Self::construct_suffix_automata_first_bytes(&id_to_suffix_automata);
-        let mut regex_to_token_ids = AHashMap::default();
-        if let Some(limit) = regex_config.min_tokens_required_for_eager_regex_cache {
-            regex_to_token_ids =
-                Self::construct_regex_to_token_ids(vocabulary, &rules, &id_to_regexes, limit);
-        }
+        let token_classifications = Self::construct_token_classifications(
+            vocabulary,
+            &rules,
+            &id_to_terminals,
+            &id_to_regexes,
+            &id_to_suffix_automata,
+            regex_config.min_tokens_required_for_eager_regex_cache,
+        );
         Ok(Self {
             start_nonterminal_id: NonterminalID(
                 grammar.start_symbol.to_usize().try_into().map_err(|_| {
@@ -495,100 +700,214 @@ where
                 })?,
             ),
             rules,
-            interned_strings: grammar.interned_strings,
+            interned_strings,
             id_to_regexes,
             id_to_terminals,
             id_to_regex_first_bytes,
             id_to_regex_complement_first_bytes,
             id_to_suffix_automata,
             id_to_suffix_automata_first_bytes,
-            regex_to_token_ids,
-        })
+            token_classifications,
+            id_to_special_tokens,
+            id_to_special_token_lengths: Vec::new(),
+            id_to_regex_min_lengths: AHashMap::default(),
+            id_to_nonterminal_min_lengths: Vec::new(),
+        }
+        .with_min_lengths(vocabulary))
     }
 
-    fn construct_regex_to_token_ids(
+    /// Computes the minimal lengths of the special tokens, the regex states and the nonterminals.
+    fn with_min_lengths(mut self, vocabulary: &Vocabulary) -> Self {
+        self.id_to_special_token_lengths = self
+            .id_to_special_tokens
+            .iter()
+            .map(|&token_id| vocabulary.token(token_id).map_or(0, |token| token.0.len()))
+            .collect();
+        self.id_to_regex_min_lengths =
+            Self::construct_regex_min_lengths(&self.rules, &self.id_to_regexes);
+        self.id_to_nonterminal_min_lengths = self.construct_nonterminal_min_lengths();
+        self
+    }
+
+    /// Converts the regexes compiled by kbnf-syntax into the finite state automata of `regex_config`.
+    ///
+    /// For lazy DFAs, kbnf-syntax only compiles the markers of the regexes created by [`utils::construct_kbnf_syntax_grammar`],
+    /// so the regexes are parsed from the markers and put back in place of the markers in `interned_strings`.
+    fn construct_regexes(
+        id_to_regex: Vec<kbnf_syntax::regex::FiniteStateAutomaton>,
+        interned_strings: &mut InternedStrings,
+        regex_config: RegexConfig,
+    ) -> Result<Vec<FiniteStateAutomaton>, CreateGrammarError> {
+        let dense_dfas = id_to_regex.into_iter().map(|regex| match regex {
+            kbnf_syntax::regex::FiniteStateAutomaton::Dfa(dfa) => dfa,
+        });
+        match regex_config.fsa_type {
+            Fsa::Dfa => Ok(dense_dfas.map(FiniteStateAutomaton::Dfa).collect()),
+            Fsa::SparseDfa => dense_dfas
+                .map(|dfa| {
+                    dfa.to_sparse()
+                        .map(FiniteStateAutomaton::SparseDfa)
+                        .map_err(|e| Box::new(SemanticError::DfaRegexBuildError(e)).into())
+                })
+                .collect(),
+            Fsa::LazyDfa => {
+                // A regex that kbnf-syntax creates from terminals is not a marker,
+                // and a marker whose regex coincides with it is kept so that the IDs of the regexes do not shift.
+                let unmarked: AHashSet<&str> = interned_strings
+                    .regex_strings
+                    .iter()
+                    .map(|(_, regex)| regex)
+                    .filter(|regex| utils::parse_regex_marker(regex).is_none())
+                    .collect();
+                let mut regex_strings = StringInterner::<StringBackend<SymbolU32>>::new();
+                let mut id_to_regexes = Vec::with_capacity(interned_strings.regex_strings.len());
+                for (_, marker) in interned_strings.regex_strings.iter() {
+                    let regex = utils::parse_regex_marker(marker);
+                    let regex = regex.as_deref().unwrap_or(marker);
+                    id_to_regexes.push(FiniteStateAutomaton::LazyDfa(LazyDfa::new(
+                        regex,
+                        regex_config.max_memory_usage,
+                    )?));
+                    if regex != marker && unmarked.contains(regex) {
+                        regex_strings.get_or_intern(marker);
+                    } else {
+                        regex_strings.get_or_intern(regex);
+                    }
+                }
+                interned_strings.regex_strings = regex_strings;
+                Ok(id_to_regexes)
+            }
+        }
+    }
+
+    fn construct_id_to_terminals(
+        interned_strings: &InternedStrings,
+    ) -> JaggedArray<u8, Vec<usize>, 2> {
+        let mut id_to_terminals = JaggedArray::<u8, Vec<usize>, 2>::new();
+        for (id, terminal) in interned_strings.terminals.iter() {
+            id_to_terminals.new_row::<0>();
+            id_to_terminals.extend_last_row_from_slice(terminal.as_bytes());
+            assert!(id_to_terminals.len() - 1 == id.to_usize());
+        }
+        id_to_terminals
+    }
+
+    fn construct_token_classifications(
         vocabulary: &Vocabulary,
         rules: &JaggedArray<HIRNode<TI>, Vec<usize>, 3>,
+        id_to_terminals: &JaggedArray<u8, Vec<usize>, 2>,
         id_to_regexes: &[FiniteStateAutomaton],
-        limit: usize,
-    ) -> AHashMap<(RegexID<TI>, StateID, RegexType), FixedBitSet> {
-        let mut regex_to_token_ids = AHashMap::default();
+        id_to_suffix_automata: &[SuffixAutomaton],
+        limit: Option<usize>,
+    ) -> AHashMap<(HIRNode<TI>, usize), TokenClassification> {
+        let mut token_classifications = AHashMap::default();
+        let mut classified_nodes = AHashSet::default();
         for i in 0..rules.len() {
             let view = rules.view::<1, 2>([i]);
             for j in 0..view.len() {
                 let view = view.view::<1, 1>([j]);
                 for k in 0..view.len() {
-                    let regex_type;
-                    let regex_id = match view[[k]] {
-                        HIRNode::RegexString(regex_id) => {
-                            regex_type = RegexType::Normal;
-                            regex_id
-                        }
-                        HIRNode::EarlyEndRegexString(regex_id) => {
-                            regex_type = RegexType::Early;
-                            regex_id
-                        }
-                        HIRNode::RegexComplement(regex_id) => {
-                            regex_type = RegexType::Complement;
-                            regex_id
-                        }
-                        _ => continue,
-                    };
-                    let regex = &id_to_regexes[regex_id.0.as_()];
-                    match regex {
-                        FiniteStateAutomaton::Dfa(dfa) => {
-                            for state in dfa.states() {
-                                let mut set = FixedBitSet::with_capacity(vocabulary.vocab_size());
-                                let start_state = state.id();
-                                if regex_to_token_ids.contains_key(&(
-                                    regex_id,
-                                    start_state,
-                                    regex_type,
-                                )) {
-                                    continue;
-                                }
-                                for (token_id, token) in vocabulary.id_to_token.iter() {
-                                    let mut state_id = start_state;
-                                    let mut acceptable = true;
-                                    let mut accepted = false;
-                                    for byte in token.0.iter() {
-                                        if accepted && regex_type == RegexType::Early {
-                                            acceptable = false;
-                                            break;
-                                        }
-                                        if accepted && regex_type == RegexType::Complement {
-                                            break;
+                    let node = view[[k]];
+                    if !classified_nodes.insert(node) {
+                        continue;
+                    }
+                    match node {
+                        HIRNode::Terminal(terminal_id) => {
+                            let terminal = id_to_terminals.view::<1, 1>([terminal_id.0.as_()]);
+                            let terminal = terminal.as_slice();
+                            for index in 0..terminal.len() {
+                                let classification = TokenClassification::new(
+                                    vocabulary,
+                                    index,
+                                    0,
+                                    |index, byte| {
+                                        if terminal[index] != byte {
+                                            (None, false)
+                                        } else if index + 1 == terminal.len() {
+                                            (None, true)
+                                        } else {
+                                            (Some(index + 1), false)
                                         }
-                                        state_id = dfa.next_state(state_id, *byte);
-                                        dispatch_by_dfa_state_status!(state_id,
-                                            dfa,
-                                            accept=>{
-                                                        accepted=true;
+                                    },
+                                );
+                                if let Some(classification) = classification {
+                                    token_classifications.insert((node, index), classification);
+                                }
+                            }
+                        }
+                        HIRNode::RegexString(regex_id)
+                        | HIRNode::EarlyEndRegexString(regex_id)
+                        | HIRNode::RegexComplement(regex_id) => {
+                            let Some(limit) = limit else {
+                                continue;
+                            };
+                            let regex_type = match node {
+                                HIRNode::RegexString(_) => RegexType::Normal,
+                                HIRNode::EarlyEndRegexString(_) => RegexType::Early,
+                                _ => RegexType::Complement,
+                            };
+                            // The states of a lazy DFA are unknown in advance, so its tokens are not classified.
+                            let regex = &id_to_regexes[regex_id.0.as_()];
+                            for state_id in regex.dfa_states() {
+                                let classification = TokenClassification::new(
+                                    vocabulary,
+                                    state_id,
+                                    limit,
+                                    |state_id, byte| {
+                                        let (state_id, status) =
+                                            regex.next_dfa_state(state_id, byte);
+                                        match status {
+                                            FsaStateStatus::Accept => match regex_type {
+                                                RegexType::Normal => (Some(state_id), true),
+                                                RegexType::Early => (None, true),
+                                                RegexType::Complement => (None, false),
                                             },
-                                            reject=>{
-                                                        acceptable=false;
-                                                        break;
-                                                    },
-                                            in_progress=>{}
-                                        );
-                                    }
-                                    if acceptable
-                                        && (!accepted || regex_type != RegexType::Complement)
-                                    {
-                                        set.insert(token_id.as_());
-                                    }
+                                            FsaStateStatus::Reject => (None, false),
+                                            FsaStateStatus::InProgress => (
+                                                Some(state_id),
+                                                regex_type == RegexType::Complement,
+                                            ),
+                                        }
+                                    },
+                                );
+                                if let Some(classification) = classification {
+                                    token_classifications
+                                        .insert((node, state_id.as_usize()), classification);
                                 }
-                                if set.count_ones(..) < limit {
-                                    continue;
+                            }
+                        }
+                        HIRNode::Substrings(suffix_automata_id) => {
+                            let Some(limit) = limit else {
+                                continue;
+                            };
+                            let suffix_automata =
+                                &id_to_suffix_automata[suffix_automata_id.0.as_()];
+                            for &node_id in suffix_automata.get_topo_and_suf_len_sorted_node_ids() {
+                                let classification = TokenClassification::new(
+                                    vocabulary,
+                                    node_id,
+                                    limit,
+                                    |node_id, byte| {
+                                        let mut state = suffix_automata.get_state(node_id);
+                                        state.feed([byte]);
+                                        if state.is_nil() {
+                                            (None, false)
+                                        } else {
+                                            (Some(state.node_id), true)
+                                        }
+                                    },
+                                );
+                                if let Some(classification) = classification {
+                                    token_classifications.insert((node, node_id), classification);
                                 }
-                                regex_to_token_ids.insert((regex_id, start_state, regex_type), set);
                             }
                         }
+                        HIRNode::Nonterminal(_) | HIRNode::SpecialToken(_) => {}
                     }
                 }
             }
         }
-        regex_to_token_ids
+        token_classifications
     }
 
     fn construct_regex_first_bytes(
@@ -621,44 +940,32 @@ where
                         }
                         _ => continue,
                     };
+                    // The first bytes of a lazy DFA are computed when its states are reached.
                     let regex = &id_to_regexes[regex_id.0.as_()];
-                    match regex {
-                        FiniteStateAutomaton::Dfa(dfa) => {
-                            for state in dfa.states() {
-                                let mut set = ByteSet::with_capacity(256);
-                                let mut set_complement = ByteSet::with_capacity(256);
-                                let state_id = state.id();
-                                for byte in 0..u8::MAX {
-                                    let next_state = dfa.next_state(state_id, byte);
-                                    let mut accepted = false;
-                                    let mut acceptable = true;
-                                    dispatch_by_dfa_state_status!(next_state,
-                                            dfa,
-                                            accept=>{accepted=true},
-                                            reject=>{acceptable=false},
-                                            in_progress=>{}
-                                    );
-                                    if acceptable {
-                                        if regex_type == RegexType::Complement && !accepted {
-                                            set_complement.insert(byte as usize);
-                                        } else {
-                                            set.insert(byte as usize);
-                                        }
-                                    }
-                                }
-                                if !set.is_clear()
-                                    && (regex_type == RegexType::Normal
-                                        || regex_type == RegexType::Early)
-                                {
-                                    id_to_regex_first_bytes.insert((regex_id.0, state_id), set);
-                                }
-                                if !set_complement.is_clear() && regex_type == RegexType::Complement
+                    for state_id in regex.dfa_states() {
+                        let mut set = ByteSet::with_capacity(256);
+                        let mut set_complement = ByteSet::with_capacity(256);
+                        for byte in 0..u8::MAX {
+                            let (_, status) = regex.next_dfa_state(state_id, byte);
+                            match status {
+                                FsaStateStatus::Reject => {}
+                                FsaStateStatus::InProgress
+                                    if regex_type == RegexType::Complement =>
                                 {
-                                    id_to_regex_complement_first_bytes
-                                        .insert((regex_id.0, state_id), set_complement);
+                                    set_complement.insert(byte as usize)
                                 }
+                                _ => set.insert(byte as usize),
                             }
                         }
+                        if !set.is_clear()
+                            && (regex_type == RegexType::Normal || regex_type == RegexType::Early)
+                        {
+                            id_to_regex_first_bytes.insert((regex_id.0, state_id), set);
+                        }
+                        if !set_complement.is_clear() && regex_type == RegexType::Complement {
+                            id_to_regex_complement_first_bytes
+                                .insert((regex_id.0, state_id), set_complement);
+                        }
                     }
                 }
             }
@@ -686,6 +993,98 @@ where
         }
         id_to_suffix_automata_first_bytes
     }
+    /// Computes the minimal number of bytes to reach a match from each DFA state of the regexes that are not complements,
+    /// by a breadth-first search from the states that match after one byte.
+    fn construct_regex_min_lengths(
+        rules: &JaggedArray<HIRNode<TI>, Vec<usize>, 3>,
+        id_to_regexes: &[FiniteStateAutomaton],
+    ) -> AHashMap<(TI, StateID), usize> {
+        let mut id_to_regex_min_lengths = AHashMap::default();
+        let mut visited_regexes = AHashSet::default();
+        for i in 0..rules.len() {
+            let view = rules.view::<1, 2>([i]);
+            for j in 0..view.len() {
+                let view = view.view::<1, 1>([j]);
+                for k in 0..view.len() {
+                    let regex_id = match view[[k]] {
+                        HIRNode::RegexString(regex_id) | HIRNode::EarlyEndRegexString(regex_id) => {
+                            regex_id
+                        }
+                        _ => continue,
+                    };
+                    if !visited_regexes.insert(regex_id.0) {
+                        continue;
+                    }
+                    // A lazy DFA has no states in advance, see [`Grammar::regex_min_length`].
+                    let regex = &id_to_regexes[regex_id.0.as_()];
+                    let bytes = regex.representative_bytes();
+                    let mut predecessors: AHashMap<StateID, Vec<StateID>> = AHashMap::default();
+                    let mut queue = std::collections::VecDeque::new();
+                    for state_id in regex.dfa_states() {
+                        let mut accepted = false;
+                        for &byte in bytes.iter() {
+                            let (next_state, status) = regex.next_dfa_state(state_id, byte);
+                            match status {
+                                FsaStateStatus::Accept => accepted = true,
+                                FsaStateStatus::Reject => continue,
+                                FsaStateStatus::InProgress => {}
+                            }
+                            predecessors.entry(next_state).or_default().push(state_id);
+                        }
+                        if accepted {
+                            id_to_regex_min_lengths.insert((regex_id.0, state_id), 1);
+                            queue.push_back(state_id);
+                        }
+                    }
+                    while let Some(state_id) = queue.pop_front() {
+                        let length = id_to_regex_min_lengths[&(regex_id.0, state_id)];
+                        for &predecessor in predecessors.get(&state_id).into_iter().flatten() {
+                            if let std::collections::hash_map::Entry::Vacant(entry) =
+                                id_to_regex_min_lengths.entry((regex_id.0, predecessor))
+                            {
+                                entry.insert(length + 1);
+                                queue.push_back(predecessor);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        id_to_regex_min_lengths
+    }
+
+    /// Computes the minimal number of bytes of a string derived from each nonterminal by iterating to a fixed point.
+    fn construct_nonterminal_min_lengths(&self) -> Vec<usize> {
+        let mut min_lengths = vec![usize::MAX; self.rules.len()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for i in 0..self.rules.len() {
+                let view = self.rules.view::<1, 2>([i]);
+                for production_id in 0..view.view::<1, 1>([0]).len() {
+                    let mut length = 0usize;
+                    for dot_position in 0..view.len() {
+                        let view = view.view::<1, 1>([dot_position]);
+                        if production_id >= view.len() {
+                            break;
+                        }
+                        length = length.saturating_add(match view[[production_id]] {
+                            HIRNode::Nonterminal(nonterminal_id) => {
+                                min_lengths[nonterminal_id.0.as_()]
+                            }
+                            node => self.node_min_length(node),
+                        });
+                    }
+                    if length < min_lengths[i] {
+                        min_lengths[i] = length;
+                        changed = true;
+                    }
+                }
+            }
+        }
+        min_lengths
+    }
+
     #[inline]
     /// Get the start nonterminal id.
     pub fn get_start_nonterminal_id(&self) -> NonterminalID<TI> {
@@ -748,6 +1147,14 @@ where
             .resolve(SymbolU32::try_from_usize(nonterminal_id.0.as_()).unwrap())
     }
     #[inline]
+    /// Get the nonterminal id from its name in the grammar.
+    pub fn nonterminal_id(&self, name: &str) -> Option<NonterminalID<TI>> {
+        self.interned_strings
+            .nonterminals
+            .get(name)
+            .map(|symbol| NonterminalID(symbol.to_usize().as_()))
+    }
+    #[inline]
     /// Get the terminal string from the grammar.
     pub fn terminal_str(&self, terminal_id: TerminalID<TI>) -> Option<&str> {
         self.interned_strings
@@ -769,6 +1176,16 @@ where
             .resolve(SymbolU32::try_from_usize(suffix_automata_id.0.as_()).unwrap())
     }
     #[inline]
+    /// Get the token ID of the special token from the grammar.
+    pub fn special_token(&self, special_token_id: SpecialTokenID<TI>) -> u32 {
+        self.id_to_special_tokens[special_token_id.0.as_()]
+    }
+    #[inline]
+    /// Get the token IDs of the special tokens referenced by the grammar.
+    pub fn id_to_special_tokens(&self) -> &[u32] {
+        &self.id_to_special_tokens
+    }
+    #[inline]
     /// Get the regex from the grammar.
     pub fn regex(&self, regex_id: RegexID<TI>) -> &FiniteStateAutomaton {
         &self.id_to_regexes[regex_id.0.as_()]
@@ -840,18 +1257,137 @@ where
     pub(crate) fn first_bytes_from_regex(
         &self,
         regex_id: RegexID<TI>,
-        state_id: StateID,
-    ) -> Option<&ByteSet> {
-        self.id_to_regex_first_bytes.get(&(regex_id.0, state_id))
+        state: usize,
+    ) -> Option<Cow<'_, ByteSet>> {
+        match self.regex(regex_id) {
+            FiniteStateAutomaton::LazyDfa(dfa) => Some(Cow::Owned(dfa.first_bytes(state, false))),
+            regex => self
+                .id_to_regex_first_bytes
+                .get(&(regex_id.0, regex.dfa_state_id(state)))
+                .map(Cow::Borrowed),
+        }
     }
     #[inline]
     pub(crate) fn complement_first_bytes_from_regex(
         &self,
         regex_id: RegexID<TI>,
-        state_id: StateID,
-    ) -> Option<&ByteSet> {
-        self.id_to_regex_complement_first_bytes
+        state: usize,
+    ) -> Option<Cow<'_, ByteSet>> {
+        match self.regex(regex_id) {
+            FiniteStateAutomaton::LazyDfa(dfa) => Some(Cow::Owned(dfa.first_bytes(state, true))),
+            regex => self
+                .id_to_regex_complement_first_bytes
+                .get(&(regex_id.0, regex.dfa_state_id(state)))
+                .map(Cow::Borrowed),
+        }
+    }
+
+    /// Get the minimal number of bytes of a string derived from the nonterminal,
+    /// or [usize::MAX] if the nonterminal cannot derive any finite string.
+    pub fn nonterminal_min_length(&self, nonterminal_id: NonterminalID<TI>) -> usize {
+        self.id_to_nonterminal_min_lengths[nonterminal_id.0.as_()]
+    }
+    #[inline]
+    /// Get the minimal number of bytes the regex needs to reach a match from the given DFA state,
+    /// or [usize::MAX] if no match can be reached.
+    ///
+    /// The complement of the regex is not considered. See [`Grammar::regex_complement_min_length`].
+    ///
+    /// The states of a lazy DFA are not explored in advance, so 1 is returned for them as a lower bound,
+    /// and `state_id` is the index of the state instead.
+    pub fn regex_min_length(&self, regex_id: RegexID<TI>, state_id: StateID) -> usize {
+        if let FiniteStateAutomaton::LazyDfa(_) = self.regex(regex_id) {
+            return 1;
+        }
+        self.id_to_regex_min_lengths
             .get(&(regex_id.0, state_id))
+            .copied()
+            .unwrap_or(usize::MAX)
+    }
+    #[inline]
+    /// Get the minimal number of bytes the complement of the regex needs to match from the given DFA state,
+    /// or [usize::MAX] if no match can be reached.
+    ///
+    /// For a lazy DFA, `state_id` is the index of the state instead.
+    pub fn regex_complement_min_length(&self, regex_id: RegexID<TI>, state_id: StateID) -> usize {
+        // The complement matches after any byte that does not lead to a match or a dead state.
+        if self
+            .complement_first_bytes_from_regex(regex_id, state_id.as_usize())
+            .is_some_and(|first_bytes| !first_bytes.is_clear())
+        {
+            1
+        } else {
+            usize::MAX
+        }
+    }
+    #[inline]
+    /// Get the minimal number of bytes the suffix automaton needs to match one more substring from the given node,
+    /// or [usize::MAX] if the substring cannot be extended.
+    pub fn suffix_automaton_min_length(
+        &self,
+        suffix_automata_id: SuffixAutomataID<TI>,
+        node_id: GeneralSamNodeID,
+    ) -> usize {
+        // Every nonempty extension of a substring is accepted, so one byte suffices if any byte can follow.
+        if self
+            .id_to_suffix_automata_first_bytes
+            .get(&(suffix_automata_id.0.as_(), node_id))
+            .is_some_and(|first_bytes| !first_bytes.is_clear())
+        {
+            1
+        } else {
+            usize::MAX
+        }
+    }
+    /// Get the minimal number of bytes matched by the node from its initial state,
+    /// or [usize::MAX] if the node cannot match any finite string.
+    pub fn node_min_length(&self, node: HIRNode<TI>) -> usize {
+        match node {
+            HIRNode::Terminal(terminal_id) => self.terminal(terminal_id).len(),
+            HIRNode::RegexString(regex_id) | HIRNode::EarlyEndRegexString(regex_id) => {
+                let regex = self.regex(regex_id);
+                self.regex_min_length(
+                    regex_id,
+                    regex.dfa_state_id(regex.start_state(Anchored::Yes)),
+                )
+            }
+            HIRNode::RegexComplement(regex_id) => {
+                let regex = self.regex(regex_id);
+                self.regex_complement_min_length(
+                    regex_id,
+                    regex.dfa_state_id(regex.start_state(Anchored::No)),
+                )
+            }
+            HIRNode::Substrings(suffix_automata_id) => {
+                self.suffix_automaton_min_length(suffix_automata_id, general_sam::SAM_ROOT_NODE_ID)
+            }
+            HIRNode::Nonterminal(nonterminal_id) => self.nonterminal_min_length(nonterminal_id),
+            HIRNode::SpecialToken(special_token_id) => {
+                self.id_to_special_token_lengths[special_token_id.0.as_()]
+            }
+        }
+    }
+    /// Get the minimal number of bytes matched by the nodes of the production from the dot position to its end.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the nonterminal id is out of bounds.
+    pub fn production_suffix_min_length(
+        &self,
+        nonterminal_id: NonterminalID<TI>,
+        dot_position: usize,
+        production_id: usize,
+    ) -> usize {
+        let view = self.rules.view::<1, 2>([nonterminal_id.0.as_()]);
+        let mut length = 0usize;
+        for dot_position in dot_position..view.len() {
+            let view = view.view::<1, 1>([dot_position]);
+            if production_id >= view.len() {
+                break;
+            }
+            length = length.saturating_add(self.node_min_length(view[[production_id]]));
+        }
+        length
     }
 
     #[inline]
@@ -869,4 +1405,465 @@ where
     pub(crate) fn rules(&self) -> &JaggedArray<HIRNode<TI>, Vec<usize>, 3> {
         &self.rules
     }
+    /// Computes a fingerprint of the grammar, including its compiled regular expressions.
+    ///
+    /// The fingerprint is stable across processes and platforms,
+    /// so it can be used to check whether two grammars are identical, e.g. before importing an exported cache.
+    /// The exception is a grammar with lazy DFAs, whose states are indexed in the order they are determinized,
+    /// so its fingerprint is shared only by its clones.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = StableHasher::new();
+        hasher.write_u64(self.start_nonterminal_id.0.as_() as u64);
+        for interner in [
+            &self.interned_strings.nonterminals,
+            &self.interned_strings.terminals,
+            &self.interned_strings.regex_strings,
+            &self.interned_strings.sub_strings,
+        ] {
+            hasher.write_u64(interner.len() as u64);
+            for (_, string) in interner.iter() {
+                hasher.write_bytes(string.as_bytes());
+            }
+        }
+        for i in 0..self.rules.len() {
+            let view = self.rules.view::<1, 2>([i]);
+            hasher.write_u64(view.len() as u64);
+            for j in 0..view.len() {
+                let view = view.view::<1, 1>([j]);
+                hasher.write_u64(view.len() as u64);
+                for k in 0..view.len() {
+                    let (kind, id) = Self::node_kind_and_id(view[[k]]);
+                    hasher.write(&[kind]);
+                    hasher.write_u64(id.as_() as u64);
+                }
+            }
+        }
+        hasher.write_u64(self.id_to_special_tokens.len() as u64);
+        for &token_id in self.id_to_special_tokens.iter() {
+            hasher.write_u64(token_id as u64);
+        }
+        // The state IDs of a DFA depend on the regex config, so the DFAs themselves are hashed.
+        for regex in self.id_to_regexes.iter() {
+            match regex {
+                FiniteStateAutomaton::Dfa(dfa) => {
+                    hasher.write(&[0]);
+                    let (bytes, padding) = dfa.to_bytes_little_endian();
+                    hasher.write_bytes(&bytes[padding..]);
+                }
+                FiniteStateAutomaton::SparseDfa(dfa) => {
+                    hasher.write(&[1]);
+                    hasher.write_bytes(&dfa.to_bytes_little_endian());
+                }
+                FiniteStateAutomaton::LazyDfa(dfa) => {
+                    hasher.write(&[2]);
+                    hasher.write_bytes(dfa.pattern().as_bytes());
+                    hasher.write_u64(dfa.instance());
+                }
+            }
+        }
+        hasher.finish()
+    }
+
+    fn node_kind_and_id(node: HIRNode<TI>) -> (u8, TI) {
+        match node {
+            HIRNode::Terminal(x) => (0, x.0),
+            HIRNode::RegexString(x) => (1, x.0),
+            HIRNode::Nonterminal(x) => (2, x.0),
+            HIRNode::EarlyEndRegexString(x) => (3, x.0),
+            HIRNode::Substrings(x) => (4, x.0),
+            HIRNode::RegexComplement(x) => (5, x.0),
+            HIRNode::SpecialToken(x) => (6, x.0),
+        }
+    }
+}
+
+impl TokenPositions {
+    fn write_compiled(&self, writer: &mut BinaryWriter) {
+        match self {
+            Self::Sparse(positions) => {
+                writer.write_u8(0);
+                writer.write_usize(positions.len());
+                for &position in positions.iter() {
+                    writer.write_u32(position);
+                }
+            }
+            Self::Dense(set) => {
+                writer.write_u8(1);
+                writer.write_usize(set.len());
+                let mut bytes = vec![0u8; set.len().div_ceil(8)];
+                for position in set.ones() {
+                    bytes[position / 8] |= 1 << (position % 8);
+                }
+                writer.write_bytes(&bytes);
+            }
+        }
+    }
+
+    fn read_compiled(reader: &mut BinaryReader, len: usize) -> Result<Self, CompiledGrammarError> {
+        match reader.read_u8()? {
+            0 => {
+                let count = reader.read_usize()?;
+                let mut positions = Vec::with_capacity(count.min(len));
+                for _ in 0..count {
+                    let position = reader.read_u32()?;
+                    if position as usize >= len {
+                        return Err(CompiledGrammarError::Corrupted);
+                    }
+                    positions.push(position);
+                }
+                Ok(Self::Sparse(positions.into_boxed_slice()))
+            }
+            1 => {
+                if reader.read_usize()? != len {
+                    return Err(CompiledGrammarError::Corrupted);
+                }
+                let bytes = reader.read_bytes()?;
+                if bytes.len() != len.div_ceil(8) {
+                    return Err(CompiledGrammarError::Corrupted);
+                }
+                let mut set = FixedBitSet::with_capacity(len);
+                for (i, &byte) in bytes.iter().enumerate() {
+                    for bit in 0..8 {
+                        if byte & (1 << bit) != 0 {
+                            let position = i * 8 + bit;
+                            if position >= len {
+                                return Err(CompiledGrammarError::Corrupted);
+                            }
+                            set.insert(position);
+                        }
+                    }
+                }
+                Ok(Self::Dense(set))
+            }
+            _ => Err(CompiledGrammarError::Corrupted),
+        }
+    }
+}
+
+impl<TI> Grammar<TI>
+where
+    TI: Num
+        + AsPrimitive<usize>
+        + ConstOne
+        + ConstZero
+        + NumOps
+        + NumAssign
+        + std::cmp::PartialOrd
+        + std::convert::TryFrom<usize>
+        + num::Bounded
+        + Hash
+        + Eq
+        + Ord,
+    usize: num::traits::AsPrimitive<TI>,
+{
+    /// Writes the grammar and its vocabulary-dependent tables in the compiled grammar format.
+    ///
+    /// The entries of the hash maps are written in sorted order, so that the same grammar is always compiled to the same bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a DFA cannot be serialized.
+    pub(crate) fn write_compiled(
+        &self,
+        writer: &mut BinaryWriter,
+    ) -> Result<(), CompiledGrammarError> {
+        writer.write_usize(self.start_nonterminal_id.0.as_());
+        for interner in [
+            &self.interned_strings.nonterminals,
+            &self.interned_strings.terminals,
+            &self.interned_strings.regex_strings,
+            &self.interned_strings.sub_strings,
+        ] {
+            writer.write_usize(interner.len());
+            for (_, string) in interner.iter() {
+                writer.write_bytes(string.as_bytes());
+            }
+        }
+        writer.write_usize(self.id_to_special_tokens.len());
+        for &token_id in self.id_to_special_tokens.iter() {
+            writer.write_u32(token_id);
+        }
+        writer.write_usize(self.rules.len());
+        for i in 0..self.rules.len() {
+            let view = self.rules.view::<1, 2>([i]);
+            writer.write_usize(view.len());
+            for j in 0..view.len() {
+                let view = view.view::<1, 1>([j]);
+                writer.write_usize(view.len());
+                for k in 0..view.len() {
+                    Self::write_node(writer, view[[k]]);
+                }
+            }
+        }
+        writer.write_usize(self.id_to_regexes.len());
+        for regex in self.id_to_regexes.iter() {
+            match regex {
+                FiniteStateAutomaton::Dfa(dfa) => {
+                    writer.write_u8(0);
+                    writer
+                        .write_aligned(dfa.write_to_len(), |dst| dfa.write_to_native_endian(dst))?;
+                }
+                FiniteStateAutomaton::SparseDfa(dfa) => {
+                    writer.write_u8(1);
+                    writer
+                        .write_aligned(dfa.write_to_len(), |dst| dfa.write_to_native_endian(dst))?;
+                }
+                // The states of a lazy DFA are determinized while matching, so only its regex is stored.
+                FiniteStateAutomaton::LazyDfa(dfa) => {
+                    writer.write_u8(2);
+                    writer.write_bytes(dfa.pattern().as_bytes());
+                    writer.write_usize(dfa.cache_capacity());
+                }
+            }
+        }
+        for first_bytes in [
+            &self.id_to_regex_first_bytes,
+            &self.id_to_regex_complement_first_bytes,
+        ] {
+            let mut entries: Vec<_> = first_bytes.iter().collect();
+            entries.sort_unstable_by_key(|(key, _)| **key);
+            writer.write_usize(entries.len());
+            for ((regex_id, state_id), set) in entries {
+                writer.write_usize(regex_id.as_());
+                writer.write_u32(state_id.as_u32());
+                let mut bytes = [0u8; 32];
+                for byte in 0..=u8::MAX as usize {
+                    if set.contains(byte) {
+                        bytes[byte / 8] |= 1 << (byte % 8);
+                    }
+                }
+                writer.write_bytes(&bytes);
+            }
+        }
+        let mut entries: Vec<_> = self.token_classifications.iter().collect();
+        entries.sort_unstable_by_key(|(key, _)| **key);
+        writer.write_usize(entries.len());
+        for ((node, state), classification) in entries {
+            Self::write_node(writer, *node);
+            writer.write_usize(*state);
+            classification.accepted.write_compiled(writer);
+            classification.undetermined.write_compiled(writer);
+        }
+        Ok(())
+    }
+
+    fn write_node(writer: &mut BinaryWriter, node: HIRNode<TI>) {
+        let (kind, id) = Self::node_kind_and_id(node);
+        writer.write_u8(kind);
+        writer.write_usize(id.as_());
+    }
+
+    /// Reads a grammar written by [`Grammar::write_compiled`].
+    ///
+    /// The caller must ensure that `vocabulary` is the vocabulary the grammar is compiled with.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the compiled grammar is corrupted, or if a DFA cannot be deserialized.
+    pub(crate) fn read_compiled(
+        reader: &mut BinaryReader,
+        vocabulary: &Vocabulary,
+    ) -> Result<Self, CompiledGrammarError> {
+        let start_nonterminal_id = NonterminalID(Self::read_id(reader)?);
+        let mut interners = Vec::with_capacity(4);
+        for _ in 0..4 {
+            let len = reader.read_usize()?;
+            let mut strings = Vec::new();
+            for _ in 0..len {
+                let string = std::str::from_utf8(reader.read_bytes()?)
+                    .map_err(|_| CompiledGrammarError::Corrupted)?;
+                strings.push(string);
+            }
+            interners.push(strings);
+        }
+        let [nonterminals, terminals, regex_strings, sub_strings] =
+            <[Vec<&str>; 4]>::try_from(interners).unwrap();
+        let interned_strings = InternedStrings {
+            nonterminals: nonterminals.iter().collect(),
+            terminals: terminals.iter().collect(),
+            regex_strings: regex_strings.iter().collect(),
+            sub_strings: sub_strings.iter().collect(),
+        };
+        // Duplicated strings would shift the IDs of the strings after them.
+        if interned_strings.nonterminals.len() != nonterminals.len()
+            || interned_strings.terminals.len() != terminals.len()
+            || interned_strings.regex_strings.len() != regex_strings.len()
+            || interned_strings.sub_strings.len() != sub_strings.len()
+        {
+            return Err(CompiledGrammarError::Corrupted);
+        }
+        let id_to_terminals = Self::construct_id_to_terminals(&interned_strings);
+        let special_tokens_len = reader.read_usize()?;
+        let mut id_to_special_tokens = Vec::new();
+        for _ in 0..special_tokens_len {
+            let token_id = reader.read_u32()?;
+            if !vocabulary.is_special_token(token_id) {
+                return Err(CompiledGrammarError::Corrupted);
+            }
+            id_to_special_tokens.push(token_id);
+        }
+        let nonterminals_len = reader.read_usize()?;
+        if nonterminals_len != nonterminals.len()
+            || start_nonterminal_id.0.as_() >= nonterminals_len
+        {
+            return Err(CompiledGrammarError::Corrupted);
+        }
+        let mut rules = JaggedArray::<HIRNode<TI>, Vec<usize>, 3>::new();
+        for _ in 0..nonterminals_len {
+            rules.new_row::<0>();
+            let dots_len = reader.read_usize()?;
+            let mut max_nodes_len = usize::MAX;
+            for _ in 0..dots_len {
+                rules.new_row::<1>();
+                let nodes_len = reader.read_usize()?;
+                // The productions are sorted by their lengths in descending order,
+                // so a dot position never has more nodes than the previous one.
+                if nodes_len == 0 || nodes_len > max_nodes_len {
+                    return Err(CompiledGrammarError::Corrupted);
+                }
+                max_nodes_len = nodes_len;
+                for _ in 0..nodes_len {
+                    let node = Self::read_node(reader)?;
+                    let valid = match node {
+                        HIRNode::Terminal(x) => x.0.as_() < terminals.len(),
+                        HIRNode::Nonterminal(x) => x.0.as_() < nonterminals_len,
+                        HIRNode::RegexString(x)
+                        | HIRNode::EarlyEndRegexString(x)
+                        | HIRNode::RegexComplement(x) => x.0.as_() < regex_strings.len(),
+                        HIRNode::Substrings(x) => x.0.as_() < sub_strings.len(),
+                        HIRNode::SpecialToken(x) => x.0.as_() < id_to_special_tokens.len(),
+                    };
+                    if !valid {
+                        return Err(CompiledGrammarError::Corrupted);
+                    }
+                    rules.push_to_last_row(node);
+                }
+            }
+        }
+        if reader.read_usize()? != regex_strings.len() {
+            return Err(CompiledGrammarError::Corrupted);
+        }
+        let mut id_to_regexes = Vec::with_capacity(regex_strings.len());
+        for _ in 0..regex_strings.len() {
+            let regex = match reader.read_u8()? {
+                0 => {
+                    let bytes = reader.read_aligned()?;
+                    let dfa = if (bytes.as_ptr() as usize).is_multiple_of(4) {
+                        dense::DFA::from_bytes(bytes)?.0.to_owned()
+                    } else {
+                        // The DFA has to be deserialized from a buffer aligned to 4 bytes.
+                        let mut buffer = vec![0u8; bytes.len() + 3];
+                        let offset = buffer.as_ptr().align_offset(4);
+                        buffer[offset..offset + bytes.len()].copy_from_slice(bytes);
+                        dense::DFA::from_bytes(&buffer[offset..offset + bytes.len()])?
+                            .0
+                            .to_owned()
+                    };
+                    FiniteStateAutomaton::Dfa(dfa)
+                }
+                1 => FiniteStateAutomaton::SparseDfa(
+                    sparse::DFA::from_bytes(reader.read_aligned()?)?
+                        .0
+                        .to_owned(),
+                ),
+                2 => {
+                    let pattern = std::str::from_utf8(reader.read_bytes()?)
+                        .map_err(|_| CompiledGrammarError::Corrupted)?;
+                    let cache_capacity = reader.read_usize()?;
+                    FiniteStateAutomaton::LazyDfa(
+                        LazyDfa::new(pattern, Some(cache_capacity))
+                            .map_err(|_| CompiledGrammarError::Corrupted)?,
+                    )
+                }
+                _ => return Err(CompiledGrammarError::Corrupted),
+            };
+            id_to_regexes.push(regex);
+        }
+        let mut first_bytes_tables = Vec::with_capacity(2);
+        for _ in 0..2 {
+            let len = reader.read_usize()?;
+            let mut first_bytes = AHashMap::default();
+            for _ in 0..len {
+                let regex_id = Self::read_id(reader)?;
+                let state_id = StateID::new(reader.read_u32()? as usize)
+                    .map_err(|_| CompiledGrammarError::Corrupted)?;
+                let bytes = reader.read_bytes()?;
+                if bytes.len() != 32 {
+                    return Err(CompiledGrammarError::Corrupted);
+                }
+                let mut set = ByteSet::with_capacity(256);
+                for byte in 0..=u8::MAX as usize {
+                    if bytes[byte / 8] & (1 << (byte % 8)) != 0 {
+                        set.insert(byte);
+                    }
+                }
+                first_bytes.insert((regex_id, state_id), set);
+            }
+            first_bytes_tables.push(first_bytes);
+        }
+        let id_to_regex_complement_first_bytes = first_bytes_tables.pop().unwrap();
+        let id_to_regex_first_bytes = first_bytes_tables.pop().unwrap();
+        // The suffix automata are rebuilt from the strings, the same way kbnf-syntax builds them.
+        let id_to_suffix_automata: Vec<_> = sub_strings
+            .iter()
+            .map(SuffixAutomaton::from_bytes)
+            .collect();
+        let id_to_suffix_automata_first_bytes =
+            Self::construct_suffix_automata_first_bytes(&id_to_suffix_automata);
+        let len = reader.read_usize()?;
+        let token_positions_len = vocabulary.token_positions_len();
+        let mut token_classifications = AHashMap::default();
+        for _ in 0..len {
+            let node = Self::read_node(reader)?;
+            let state = reader.read_usize()?;
+            let accepted = TokenPositions::read_compiled(reader, token_positions_len)?;
+            let undetermined = TokenPositions::read_compiled(reader, token_positions_len)?;
+            token_classifications.insert(
+                (node, state),
+                TokenClassification {
+                    accepted,
+                    undetermined,
+                },
+            );
+        }
+        Ok(Self {
+            start_nonterminal_id,
+            rules,
+            interned_strings,
+            id_to_regexes,
+            token_classifications,
+            id_to_regex_first_bytes,
+            id_to_regex_complement_first_bytes,
+            id_to_terminals,
+            id_to_suffix_automata,
+            id_to_suffix_automata_first_bytes,
+            id_to_special_tokens,
+            id_to_special_token_lengths: Vec::new(),
+            id_to_regex_min_lengths: AHashMap::default(),
+            id_to_nonterminal_min_lengths: Vec::new(),
+        }
+        .with_min_lengths(vocabulary))
+    }
+
+    fn read_id(reader: &mut BinaryReader) -> Result<TI, CompiledGrammarError> {
+        reader
+            .read_usize()?
+            .try_into()
+            .map_err(|_| CompiledGrammarError::Corrupted)
+    }
+
+    fn read_node(reader: &mut BinaryReader) -> Result<HIRNode<TI>, CompiledGrammarError> {
+        let kind = reader.read_u8()?;
+        let id = Self::read_id(reader)?;
+        Ok(match kind {
+            0 => HIRNode::Terminal(TerminalID(id)),
+            1 => HIRNode::RegexString(RegexID(id)),
+            2 => HIRNode::Nonterminal(NonterminalID(id)),
+            3 => HIRNode::EarlyEndRegexString(RegexID(id)),
+            4 => HIRNode::Substrings(SuffixAutomataID(id)),
+            5 => HIRNode::RegexComplement(RegexID(id)),
+            6 => HIRNode::SpecialToken(SpecialTokenID(id)),
+            _ => return Err(CompiledGrammarError::Corrupted),
+        })
+    }
 }
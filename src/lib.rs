@@ -206,6 +206,58 @@ assert_eq!(
 assert_eq!(&format!("{:?}", logits), "[-inf, 0.0, 0.0, -inf, 0.0, 0.0]");
 ```
 
+## Let the language model decide when to stop
+
+By default, the engine finishes as soon as the grammar cannot be extended.
+In [`TerminationMode::Eos`](config::TerminationMode::Eos), the EOS tokens are allowed exactly when the engine is accepting,
+and the engine finishes only when one of them is accepted.
+
+```rust
+use ahash::AHashMap;
+use kbnf::config::{Config, TerminationMode};
+use kbnf::{AcceptTokenResult, Engine, EngineLike, Token, Vocabulary};
+let grammar_str = r##"
+start ::= "A"{"A"};
+"##;
+let mut token_strings: AHashMap<u32, String> = AHashMap::default();
+token_strings.extend([(1, "A".to_string()), (2, "</s>".to_string())]);
+let tokens = token_strings
+    .iter()
+    .map(|(k, v)| (*k, Token(v.as_bytes().to_vec().into_boxed_slice())))
+    .collect::<AHashMap<u32, _>>();
+let vocab = Vocabulary::new(tokens, token_strings).unwrap();
+let mut config = Config::default();
+config.termination_config.mode = TerminationMode::Eos;
+config.termination_config.eos_token_ids = vec![2];
+let mut engine = Engine::with_config(grammar_str, vocab, config).unwrap();
+engine.compute_allowed_token_ids();
+assert_eq!(
+    engine
+        .allowed_token_ids_from_last_computation()
+        .ones()
+        .collect::<Vec<usize>>(),
+    vec![1]
+); // the EOS token is not allowed before the first "A"
+assert_eq!(
+    engine.try_accept_new_token(1).unwrap(),
+    AcceptTokenResult::Ongoing
+);
+assert!(engine.is_accepting());
+engine.compute_allowed_token_ids();
+assert_eq!(
+    engine
+        .allowed_token_ids_from_last_computation()
+        .ones()
+        .collect::<Vec<usize>>(),
+    vec![1, 2]
+); // more "A"s or the EOS token
+assert_eq!(
+    engine.try_accept_new_token(2).unwrap(),
+    AcceptTokenResult::Finished
+);
+assert!(engine.is_finished());
+```
+
 # KBNF Grammar
 
 KBNF is roughly a superset of [EBNF](https://en.wikipedia.org/wiki/Extended_Backus%E2%80%93Naur_form). The syntax of KBNF is as follows:
@@ -337,6 +389,8 @@ start ::= "A"{"A"};
 ```
 
 **NOTE THAT KBNF ends eagerly, so the engine will constrain the output to be exactly one "A".**
+Set [`TerminationMode::Eos`](config::TerminationMode::Eos) in [`Config::termination_config`](config::Config::termination_config)
+to let the language model decide when to stop with its EOS token instead.
 
 ```ebnf
 start ::= {"A"|"C"} "B";
@@ -401,7 +455,8 @@ which means [the syntax supported](https://docs.rs/regex/latest/regex/index.html
 Notably, the regex crate does not support arbitrary lookarounds. In exchange, linear time matching is guaranteed.
 **WARNING: the regular expression is compiled into a DFA which, by its nature, has worst case exponential time and space complexity.**
 If you are dealing with untrusted regular expressions,
-you should set a memory limit in [Config::regex_config] to prevent DoS attacks.
+you should use [config::Fsa::LazyDfa] in [Config::regex_config], which determinizes the states within a bounded cache while matching,
+or set a memory limit there to prevent DoS attacks.
 
 ## Substrings
 
@@ -416,6 +471,21 @@ since empty string is a substring of any string.
 *)
 ```
 
+## Special tokens
+
+A UTF-8 string enclosed in `#token""` or `#token''` is a special token symbol.
+A special token symbol matches exactly one special token of the [Vocabulary], whose content is the given string.
+Special tokens, like `<|im_end|>`, are never produced by matching bytes against terminals or regular expressions,
+so a special token symbol is the only way to allow one.
+
+```ebnf
+start ::= #"[a-z]+" #token"<|im_end|>";
+(*
+The engine will constrain the output to be a sequence of lowercase letters followed by the special token <|im_end|>.
+A normal token spelling "<|im_end|>" will not be allowed.
+*)
+```
+
 # Performance
 
 ## Reducing ambuguity
@@ -446,14 +516,22 @@ it still introduces a constant factor overhead.
 */
 #![warn(missing_docs)]
 #![warn(rustdoc::broken_intra_doc_links)]
+mod binary;
+mod cache;
+pub mod compiled_grammar;
 pub mod config;
 pub mod engine;
 pub mod engine_base;
+pub mod engine_batch;
 pub mod engine_like;
 mod ffi_bindings;
 pub mod grammar;
+pub mod parse_tree;
+pub mod regex;
 pub mod utils;
 pub mod vocabulary;
+#[cfg(feature = "parallel")]
+mod worker_pool;
 mod zero;
 pub use config::Config;
 pub use engine::Engine;
@@ -480,12 +558,29 @@ fn kbnf(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<config::CompressionConfig>()?;
     m.add_class::<config::Fsa>()?;
     m.add_class::<config::RegexConfig>()?;
+    m.add_class::<config::TerminationConfig>()?;
+    m.add_class::<config::TerminationMode>()?;
     m.add_class::<engine::EngineConfig>()?;
+    m.add_class::<engine::CacheConfig>()?;
+    m.add_class::<engine::CacheEviction>()?;
     m.add_class::<Engine>()?;
+    m.add_class::<engine_batch::EngineBatch>()?;
+    m.add_class::<engine_batch::EngineBatchError>()?;
     m.add_class::<AcceptTokenResult>()?;
     m.add_class::<engine_like::AcceptTokenError>()?;
     m.add_class::<engine_like::MaskLogitsError>()?;
     m.add_class::<engine_like::UpdateLogitsError>()?;
+    m.add_class::<engine_like::RejectionDiagnostic>()?;
+    m.add_class::<engine_like::ExpectedSymbol>()?;
+    m.add_class::<engine_like::Checkpoint>()?;
+    m.add_class::<engine_like::RollbackError>()?;
+    m.add_class::<engine_like::CacheStats>()?;
+    m.add_class::<engine_like::ShareCacheError>()?;
+    m.add_class::<engine_like::ImportCacheError>()?;
+    m.add_class::<engine_like::SubscribeNonterminalError>()?;
+    m.add_class::<engine_like::NonterminalEventKind>()?;
+    m.add_class::<engine_like::NonterminalEvent>()?;
+    m.add_class::<parse_tree::ParseTree>()?;
     m.add_class::<Vocabulary>()?;
     m.add_class::<Token>()?;
     Ok(())
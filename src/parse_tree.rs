@@ -0,0 +1,647 @@
+//! This module contains the [`ParseTree`] struct and the chart of back-pointers it is built from.
+use std::hash::Hash;
+use std::sync::Arc;
+
+use ahash::{AHashMap, AHashSet};
+use jaggedarray::jagged_array::JaggedArrayViewTrait;
+use num::{
+    cast::AsPrimitive,
+    traits::{ConstOne, ConstZero, NumAssign},
+    Num,
+};
+#[cfg(feature = "python")]
+use pyo3::pyclass;
+use serde::Serialize;
+
+use crate::engine_base::LeoLink;
+use crate::grammar::{Grammar, HIRNode, NonterminalID};
+
+/// A node of the parse tree of the bytes accepted by an [`EngineLike`](crate::engine_like::EngineLike).
+///
+/// Each node corresponds to one nonterminal that produced the bytes in `[start, end)`.
+/// Terminals, regexes, substrings and special tokens do not have their own nodes;
+/// the bytes they matched are the bytes in the span that are not covered by any child.
+#[cfg_attr(feature = "python", pyclass(get_all))]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct ParseTree {
+    /// The name of the nonterminal, as returned by [`Grammar::nonterminal_str`].
+    pub nonterminal: String,
+    /// The byte offset where the span starts, inclusive.
+    pub start: usize,
+    /// The byte offset where the span ends, exclusive.
+    pub end: usize,
+    /// The nonterminals directly used by the nonterminal, in input order.
+    pub children: Vec<ParseTree>,
+}
+
+impl Drop for ParseTree {
+    fn drop(&mut self) {
+        // A right recursion nests a node per list item, so dropping the tree recursively could overflow the stack.
+        let mut nodes = std::mem::take(&mut self.children);
+        while let Some(mut node) = nodes.pop() {
+            nodes.append(&mut node.children);
+        }
+    }
+}
+
+/// An Earley item whose start position is the byte offset where its nonterminal starts,
+/// which stays valid after compaction removes the column it starts at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct ChartItem<TI, TD, TP, TS>
+where
+    TI: Num + AsPrimitive<usize> + ConstOne + ConstZero,
+{
+    pub(crate) nonterminal_id: NonterminalID<TI>,
+    pub(crate) dot_position: TD,
+    pub(crate) production_index: TP,
+    pub(crate) state_id: TS,
+    pub(crate) start: usize,
+}
+
+/// The back-pointers of the items created in one column.
+#[allow(clippy::type_complexity)]
+#[derive(Debug, Clone)]
+pub(crate) struct ChartColumn<TI, TD, TP, TS>
+where
+    TI: Num + AsPrimitive<usize> + ConstOne + ConstZero,
+{
+    offset: usize,
+    /// The items each item is created from, paired with the byte offsets of the columns they are in.
+    ///
+    /// A completed item has its dot after its last node and a zero state.
+    /// An item whose start position is folded by compaction points to itself with its original start position
+    /// in the same column.
+    back_pointers: AHashMap<ChartItem<TI, TD, TP, TS>, Vec<(usize, ChartItem<TI, TD, TP, TS>)>>,
+    /// The chains of Leo items followed to complete nonterminals in the column,
+    /// paired with the byte offsets where the completed items start before following their Leo items.
+    leo_chains: Vec<(usize, Arc<LeoLink<TI, TD, TP, TS>>)>,
+}
+
+impl<TI, TD, TP, TS> ChartColumn<TI, TD, TP, TS>
+where
+    TI: Num + AsPrimitive<usize> + ConstOne + ConstZero + Hash + Eq,
+    TD: Hash + Eq,
+    TP: Hash + Eq,
+    TS: Hash + Eq,
+{
+    pub(crate) fn new(offset: usize) -> Self {
+        Self {
+            offset,
+            back_pointers: AHashMap::default(),
+            leo_chains: Vec::new(),
+        }
+    }
+
+    /// Records that `item` is created from `previous` in the column at `previous_offset`.
+    pub(crate) fn record(
+        &mut self,
+        previous_offset: usize,
+        previous: ChartItem<TI, TD, TP, TS>,
+        item: ChartItem<TI, TD, TP, TS>,
+    ) {
+        self.back_pointers
+            .entry(item)
+            .or_default()
+            .push((previous_offset, previous));
+    }
+
+    /// Records that the start position of `item` is folded to the one of `folded` by compaction.
+    pub(crate) fn record_fold(
+        &mut self,
+        item: ChartItem<TI, TD, TP, TS>,
+        folded: ChartItem<TI, TD, TP, TS>,
+    ) {
+        self.record(self.offset, item, folded);
+    }
+
+    /// Records that the chain of Leo items is followed to complete a nonterminal starting at `start`.
+    pub(crate) fn record_leo_chain(&mut self, start: usize, chain: Arc<LeoLink<TI, TD, TP, TS>>) {
+        self.leo_chains.push((start, chain));
+    }
+}
+
+/// The back-pointers of the columns created since the last reset, which are recorded
+/// when [`EngineConfig::parse_tree_enabled`](crate::engine::EngineConfig::parse_tree_enabled) is enabled.
+///
+/// Unlike the Earley sets, the chart keeps one column per accepted byte or special token,
+/// and the columns are shared with the forks of the engine.
+#[derive(Debug, Clone)]
+pub(crate) struct Chart<TI, TD, TP, TS>
+where
+    TI: Num + AsPrimitive<usize> + ConstOne + ConstZero,
+{
+    columns: Vec<Arc<ChartColumn<TI, TD, TP, TS>>>,
+}
+
+impl<TI, TD, TP, TS> Chart<TI, TD, TP, TS>
+where
+    TI: Num
+        + AsPrimitive<usize>
+        + ConstOne
+        + ConstZero
+        + NumAssign
+        + std::cmp::PartialOrd
+        + std::convert::TryFrom<usize>
+        + num::Bounded
+        + Hash
+        + Eq,
+    TD: Num + AsPrimitive<usize> + ConstOne + ConstZero + Copy + Hash + Eq,
+    TP: Num + AsPrimitive<usize> + ConstOne + ConstZero + Copy + Hash + Eq,
+    TS: ConstZero + Copy + Hash + Eq,
+    usize:
+        num::traits::AsPrimitive<TI> + num::traits::AsPrimitive<TD> + num::traits::AsPrimitive<TP>,
+{
+    pub(crate) fn new() -> Self {
+        Self {
+            columns: vec![Arc::new(ChartColumn::new(0))],
+        }
+    }
+
+    /// Removes every column except the first one, which has no back-pointers.
+    pub(crate) fn reset(&mut self) {
+        self.columns.truncate(1);
+    }
+
+    pub(crate) fn push(&mut self, column: ChartColumn<TI, TD, TP, TS>) {
+        self.columns.push(Arc::new(column));
+    }
+
+    pub(crate) fn pop(&mut self) {
+        self.columns.pop();
+    }
+
+    /// Builds the parse tree rooted at the start nonterminal from the first column to the last one.
+    ///
+    /// When the bytes can be parsed in more than one way, earlier alternatives in the grammar are preferred,
+    /// and then longer spans for the later nodes in an alternative.
+    ///
+    /// Returns `None` if the start nonterminal is not completed in the last column.
+    pub(crate) fn parse_tree(&self, grammar: &Grammar<TI>) -> Option<ParseTree> {
+        let mut builder = TreeBuilder {
+            grammar,
+            columns: &self.columns,
+            leo_completions: AHashMap::default(),
+            failed_items: AHashSet::default(),
+            failed_nonterminals: AHashSet::default(),
+        };
+        builder.build(
+            grammar.get_start_nonterminal_id(),
+            0,
+            self.columns.len() - 1,
+        )
+    }
+}
+
+/// A way a nonterminal is completed in a column.
+#[derive(Debug, Clone, Copy)]
+enum Completion<TI, TD, TP, TS>
+where
+    TI: Num + AsPrimitive<usize> + ConstOne + ConstZero,
+{
+    /// The completed item is recorded in the column.
+    Recorded(ChartItem<TI, TD, TP, TS>),
+    /// The nonterminal is completed through a Leo item,
+    /// which advances `item` in the column at `offset` over the nonterminal `child` completed in the column.
+    Leo {
+        offset: usize,
+        item: ChartItem<TI, TD, TP, TS>,
+        child: NonterminalID<TI>,
+    },
+}
+
+/// A way a nonterminal is completed through the Leo chains of a column.
+#[derive(Debug, Clone, Copy)]
+enum LeoCompletion<TI, TD, TP, TS>
+where
+    TI: Num + AsPrimitive<usize> + ConstOne + ConstZero,
+{
+    /// The completed item is recorded in the column with its start position folded to `start` by compaction.
+    Folded(usize),
+    /// The nonterminal is completed as [`Completion::Leo`].
+    Advanced {
+        offset: usize,
+        item: ChartItem<TI, TD, TP, TS>,
+        child: NonterminalID<TI>,
+    },
+}
+
+/// A nonterminal or an item on the stack of [`TreeBuilder::build`].
+#[allow(clippy::type_complexity)]
+enum Frame<TI, TD, TP, TS>
+where
+    TI: Num + AsPrimitive<usize> + ConstOne + ConstZero,
+{
+    /// A nonterminal being built from `start` to the column.
+    Nonterminal {
+        nonterminal_id: NonterminalID<TI>,
+        start: usize,
+        /// The index of the column where the nonterminal is completed.
+        column: usize,
+        /// The completions that are not tried yet, with the next one to try at the end.
+        completions: Vec<Completion<TI, TD, TP, TS>>,
+        /// The child and its start offset to build after the item of a [`Completion::Leo`] is traced.
+        leo_child: Option<(NonterminalID<TI>, usize)>,
+        /// The number of nodes built before the nonterminal.
+        children_len: usize,
+    },
+    /// An item being traced back to where it starts.
+    Item {
+        /// The index of the column of the item.
+        column: usize,
+        item: ChartItem<TI, TD, TP, TS>,
+        /// The byte offset where the trace ends.
+        start: usize,
+        /// The back-pointers of the item that are not followed yet, with the next one to follow at the end.
+        back_pointers: Vec<(usize, ChartItem<TI, TD, TP, TS>)>,
+        /// The back-pointer that waits for the node of the nonterminal before its dot.
+        pending: Option<(usize, ChartItem<TI, TD, TP, TS>)>,
+        /// The number of nodes built before the item.
+        children_len: usize,
+    },
+}
+
+/// The next step of [`TreeBuilder::build`].
+enum Step<TI, TD, TP, TS>
+where
+    TI: Num + AsPrimitive<usize> + ConstOne + ConstZero,
+{
+    /// Builds the node of the nonterminal that starts at the offset and is completed in the column.
+    Build(NonterminalID<TI>, usize, usize),
+    /// Tries the next completion of the nonterminal on top of the stack.
+    Complete,
+    /// Traces the item in the column back to the offset.
+    Trace(usize, ChartItem<TI, TD, TP, TS>, usize),
+    /// Follows the next back-pointer of the item on top of the stack.
+    Backtrack,
+    /// Returns whether the item is traced back to where it starts.
+    Traced(bool),
+    /// Returns the node of the nonterminal.
+    Built(Option<ParseTree>),
+}
+
+#[allow(clippy::type_complexity)]
+struct TreeBuilder<'a, TI, TD, TP, TS>
+where
+    TI: Num + AsPrimitive<usize> + ConstOne + ConstZero,
+{
+    grammar: &'a Grammar<TI>,
+    columns: &'a [Arc<ChartColumn<TI, TD, TP, TS>>],
+    /// The nonterminals completed through the Leo chains of each column, indexed by their start offsets on demand.
+    leo_completions:
+        AHashMap<usize, AHashMap<(NonterminalID<TI>, usize), Vec<LeoCompletion<TI, TD, TP, TS>>>>,
+    /// The items in the columns that cannot be traced back to the given start offsets.
+    failed_items: AHashSet<(usize, ChartItem<TI, TD, TP, TS>, usize)>,
+    /// The nonterminals that cannot be built from the given start offsets to the columns.
+    failed_nonterminals: AHashSet<(NonterminalID<TI>, usize, usize)>,
+}
+
+impl<TI, TD, TP, TS> TreeBuilder<'_, TI, TD, TP, TS>
+where
+    TI: Num
+        + AsPrimitive<usize>
+        + ConstOne
+        + ConstZero
+        + NumAssign
+        + std::cmp::PartialOrd
+        + std::convert::TryFrom<usize>
+        + num::Bounded
+        + Hash
+        + Eq,
+    TD: Num + AsPrimitive<usize> + ConstOne + ConstZero + Copy + Hash + Eq,
+    TP: Num + AsPrimitive<usize> + ConstOne + ConstZero + Copy + Hash + Eq,
+    TS: ConstZero + Copy + Hash + Eq,
+    usize:
+        num::traits::AsPrimitive<TI> + num::traits::AsPrimitive<TD> + num::traits::AsPrimitive<TP>,
+{
+    fn productions_len(&self, nonterminal_id: NonterminalID<TI>) -> usize {
+        // SAFETY: nonterminal_id always comes from the grammar
+        let view = unsafe { self.grammar.dotted_productions(nonterminal_id) };
+        view.view::<1, 1>([0]).len()
+    }
+
+    fn production_len(&self, nonterminal_id: NonterminalID<TI>, production_index: usize) -> usize {
+        // SAFETY: nonterminal_id always comes from the grammar
+        let view = unsafe { self.grammar.dotted_productions(nonterminal_id) };
+        (0..view.len())
+            .take_while(|&dot_position| production_index < view.view::<1, 1>([dot_position]).len())
+            .count()
+    }
+
+    /// Finds the index of the column at `offset`.
+    fn column(&self, offset: usize) -> usize {
+        // The offsets of the columns are strictly increasing, and the back-pointers only point to recorded columns.
+        self.columns
+            .binary_search_by_key(&offset, |column| column.offset)
+            .unwrap()
+    }
+
+    /// Indexes the nonterminals completed through the Leo chains of the column.
+    fn index_leo_completions(&mut self, column: usize) {
+        if self.leo_completions.contains_key(&column) {
+            return;
+        }
+        let mut completions: AHashMap<_, Vec<_>> = AHashMap::default();
+        let mut visited = AHashSet::default();
+        for (start, chain) in self.columns[column].leo_chains.iter() {
+            if chain.start != *start {
+                completions
+                    .entry((chain.nonterminal_id, chain.start))
+                    .or_default()
+                    .push(LeoCompletion::Folded(*start));
+            }
+            let mut link = &**chain;
+            while let Some(next) = link.next.as_deref() {
+                // Every link except the topmost one has the item waiting for it.
+                let item = link.parent.unwrap();
+                completions
+                    .entry((next.nonterminal_id, next.start))
+                    .or_default()
+                    .push(LeoCompletion::Advanced {
+                        offset: link.start,
+                        item,
+                        child: link.nonterminal_id,
+                    });
+                // The rest of the chain is shared with a chain that is already indexed.
+                if !visited.insert(next as *const LeoLink<TI, TD, TP, TS>) {
+                    break;
+                }
+                link = next;
+            }
+        }
+        self.leo_completions.insert(column, completions);
+    }
+
+    /// Collects the ways the nonterminal that starts at `start` is completed in the column,
+    /// with the preferred one at the end.
+    fn completions(
+        &mut self,
+        nonterminal_id: NonterminalID<TI>,
+        start: usize,
+        column: usize,
+    ) -> Vec<Completion<TI, TD, TP, TS>> {
+        self.index_leo_completions(column);
+        let mut completions = Vec::new();
+        let mut recorded_starts = vec![start];
+        for &completion in self.leo_completions[&column]
+            .get(&(nonterminal_id, start))
+            .into_iter()
+            .flatten()
+        {
+            match completion {
+                LeoCompletion::Folded(start) => recorded_starts.push(start),
+                LeoCompletion::Advanced {
+                    offset,
+                    item,
+                    child,
+                } => completions.push((
+                    item.production_index.as_(),
+                    Completion::Leo {
+                        offset,
+                        item,
+                        child,
+                    },
+                )),
+            }
+        }
+        for production_index in 0..self.productions_len(nonterminal_id) {
+            for &recorded_start in recorded_starts.iter() {
+                let item = ChartItem {
+                    nonterminal_id,
+                    dot_position: self.production_len(nonterminal_id, production_index).as_(),
+                    production_index: production_index.as_(),
+                    state_id: TS::ZERO,
+                    start: recorded_start,
+                };
+                if self.columns[column].back_pointers.contains_key(&item) {
+                    completions.push((production_index, Completion::Recorded(item)));
+                }
+            }
+        }
+        completions.sort_by_key(|&(production_index, _)| production_index);
+        completions
+            .into_iter()
+            .rev()
+            .map(|(_, completion)| completion)
+            .collect()
+    }
+
+    /// Builds the node of the nonterminal that starts at `start` and is completed in the column.
+    ///
+    /// Each nonterminal is built by following the back-pointers of its completed item to where it starts,
+    /// which builds the nodes of the nonterminals the item is advanced over.
+    /// Both are done on one explicit stack since a right recursion nests a node per list item
+    /// and a regex may span many columns.
+    fn build(
+        &mut self,
+        nonterminal_id: NonterminalID<TI>,
+        start: usize,
+        column: usize,
+    ) -> Option<ParseTree> {
+        let mut stack: Vec<Frame<TI, TD, TP, TS>> = Vec::new();
+        // The nodes built for the nonterminals on the stack, in reverse input order while their items are traced.
+        let mut children = Vec::new();
+        let mut step = Step::Build(nonterminal_id, start, column);
+        loop {
+            step = match step {
+                Step::Build(nonterminal_id, start, column) => {
+                    if self
+                        .failed_nonterminals
+                        .contains(&(nonterminal_id, start, column))
+                    {
+                        Step::Built(None)
+                    } else {
+                        let completions = self.completions(nonterminal_id, start, column);
+                        stack.push(Frame::Nonterminal {
+                            nonterminal_id,
+                            start,
+                            column,
+                            completions,
+                            leo_child: None,
+                            children_len: children.len(),
+                        });
+                        Step::Complete
+                    }
+                }
+                Step::Complete => {
+                    let Some(Frame::Nonterminal {
+                        nonterminal_id,
+                        start,
+                        column,
+                        completions,
+                        leo_child,
+                        children_len,
+                    }) = stack.last_mut()
+                    else {
+                        unreachable!("only a nonterminal tries its completions");
+                    };
+                    children.truncate(*children_len);
+                    match completions.pop() {
+                        Some(Completion::Recorded(item)) => {
+                            *leo_child = None;
+                            Step::Trace(*column, item, *start)
+                        }
+                        Some(Completion::Leo {
+                            offset,
+                            item,
+                            child,
+                        }) => {
+                            *leo_child = Some((child, offset));
+                            Step::Trace(self.column(offset), item, *start)
+                        }
+                        None => {
+                            self.failed_nonterminals
+                                .insert((*nonterminal_id, *start, *column));
+                            stack.pop();
+                            Step::Built(None)
+                        }
+                    }
+                }
+                Step::Trace(column, item, start) => {
+                    if item.start == self.columns[column].offset {
+                        // Only the items predicted in a column start at it.
+                        if item.start == start {
+                            Step::Traced(true)
+                        } else {
+                            Step::Backtrack
+                        }
+                    } else {
+                        if !self.failed_items.contains(&(column, item, start)) {
+                            if let Some(back_pointers) =
+                                self.columns[column].back_pointers.get(&item)
+                            {
+                                let mut back_pointers = back_pointers.clone();
+                                // The back-pointer with the smallest offset leaves the longest span to the node before the dot.
+                                back_pointers.sort_by_key(|&(offset, _)| std::cmp::Reverse(offset));
+                                stack.push(Frame::Item {
+                                    column,
+                                    item,
+                                    start,
+                                    back_pointers,
+                                    pending: None,
+                                    children_len: children.len(),
+                                });
+                            }
+                        }
+                        Step::Backtrack
+                    }
+                }
+                Step::Backtrack => match stack.last_mut() {
+                    Some(Frame::Item {
+                        column,
+                        item,
+                        start,
+                        back_pointers,
+                        children_len,
+                        pending,
+                    }) => {
+                        children.truncate(*children_len);
+                        let (column, item, start) = (*column, *item, *start);
+                        match back_pointers.pop() {
+                            None => {
+                                stack.pop();
+                                self.failed_items.insert((column, item, start));
+                                Step::Backtrack
+                            }
+                            Some((offset, previous)) if offset == self.columns[column].offset => {
+                                // The start position of the item is folded by compaction.
+                                Step::Trace(column, previous, start)
+                            }
+                            Some((offset, previous)) => {
+                                let node =
+                                    (previous.dot_position != item.dot_position).then(|| {
+                                        *self.grammar.node(
+                                            previous.nonterminal_id,
+                                            previous.dot_position,
+                                            previous.production_index,
+                                        )
+                                    });
+                                if let Some(HIRNode::Nonterminal(child)) = node {
+                                    *pending = Some((offset, previous));
+                                    Step::Build(child, offset, column)
+                                } else {
+                                    Step::Trace(self.column(offset), previous, start)
+                                }
+                            }
+                        }
+                    }
+                    // Every item of the trace is exhausted.
+                    _ => Step::Traced(false),
+                },
+                Step::Traced(traced) => {
+                    if traced {
+                        while let Some(Frame::Item { .. }) = stack.last() {
+                            stack.pop();
+                        }
+                    }
+                    let Some(Frame::Nonterminal {
+                        column,
+                        leo_child,
+                        children_len,
+                        ..
+                    }) = stack.last_mut()
+                    else {
+                        unreachable!("only a nonterminal traces its completed items");
+                    };
+                    if !traced {
+                        Step::Complete
+                    } else {
+                        children[*children_len..].reverse();
+                        match leo_child.take() {
+                            Some((child, offset)) => Step::Build(child, offset, *column),
+                            None => Step::Built(self.finish(&mut stack, &mut children)),
+                        }
+                    }
+                }
+                Step::Built(node) => match stack.last_mut() {
+                    None => return node,
+                    Some(Frame::Item { start, pending, .. }) => {
+                        let (offset, previous) = pending.take().unwrap();
+                        match node {
+                            Some(node) => {
+                                children.push(node);
+                                Step::Trace(self.column(offset), previous, *start)
+                            }
+                            None => Step::Backtrack,
+                        }
+                    }
+                    // The nonterminal waits for the child of its Leo completion.
+                    Some(Frame::Nonterminal { .. }) => match node {
+                        Some(node) => {
+                            children.push(node);
+                            Step::Built(self.finish(&mut stack, &mut children))
+                        }
+                        None => Step::Complete,
+                    },
+                },
+            };
+        }
+    }
+
+    /// Pops the nonterminal on top of the stack and builds its node from the nodes built for it.
+    fn finish(
+        &self,
+        stack: &mut Vec<Frame<TI, TD, TP, TS>>,
+        children: &mut Vec<ParseTree>,
+    ) -> Option<ParseTree> {
+        let Some(Frame::Nonterminal {
+            nonterminal_id,
+            start,
+            column,
+            children_len,
+            ..
+        }) = stack.pop()
+        else {
+            unreachable!("only a nonterminal is finished");
+        };
+        Some(ParseTree {
+            nonterminal: self
+                .grammar
+                .nonterminal_str(nonterminal_id)
+                .unwrap_or_default()
+                .to_string(),
+            start,
+            end: self.columns[column].offset,
+            children: children.split_off(children_len),
+        })
+    }
+}
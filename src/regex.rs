@@ -0,0 +1,367 @@
+//! The finite state automata that the regular expressions of a [`Grammar`](crate::grammar::Grammar) are compiled into.
+//!
+//! The state IDs stored in Earley items are not the state IDs of the automata themselves.
+//! They are the state indices of dense DFAs, the state IDs of sparse DFAs,
+//! and the indices a lazy DFA assigns to its states in the order they are determinized.
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use ahash::AHashMap;
+use kbnf_regex_automata::dfa::{dense, sparse, Automaton};
+use kbnf_regex_automata::hybrid::{self, LazyStateID};
+use kbnf_regex_automata::util::primitives::StateID;
+use kbnf_regex_automata::util::start;
+use kbnf_regex_automata::Anchored;
+use kbnf_syntax::semantic_error::SemanticError;
+
+use crate::grammar::CreateGrammarError;
+use crate::utils::{self, ByteSet, FsaStateStatus};
+
+/// A regular expression compiled into a finite state automaton.
+///
+/// See [`Fsa`](crate::config::Fsa) for the trade-offs between the variants.
+#[derive(Debug, Clone)]
+pub enum FiniteStateAutomaton {
+    /// A dense DFA.
+    Dfa(dense::DFA<Vec<u32>>),
+    /// A sparse DFA.
+    SparseDfa(sparse::DFA<Vec<u8>>),
+    /// A lazy DFA.
+    LazyDfa(LazyDfa),
+}
+
+impl FiniteStateAutomaton {
+    /// Gets the start state of the automaton.
+    ///
+    /// `Anchored::Yes` starts a regex and `Anchored::No` starts the complement of a regex.
+    pub(crate) fn start_state(&self, anchored: Anchored) -> usize {
+        let config = start::Config::new().anchored(anchored);
+        match self {
+            // SAFETY: start_error will not happen since that will result in an error in Grammar::new() method
+            Self::Dfa(dfa) => unsafe {
+                dfa.start_state(&config).unwrap_unchecked().as_usize() >> dfa.stride2()
+            },
+            Self::SparseDfa(dfa) => unsafe {
+                dfa.start_state(&config).unwrap_unchecked().as_usize()
+            },
+            Self::LazyDfa(dfa) => match anchored {
+                Anchored::No => dfa.unanchored_start,
+                _ => dfa.anchored_start,
+            },
+        }
+    }
+
+    /// Gets the state after `byte` is fed to `state`, along with the status of the new state.
+    #[inline]
+    pub(crate) fn next_state(&self, state: usize, byte: u8) -> (usize, FsaStateStatus) {
+        match self {
+            Self::Dfa(dfa) => {
+                let state_id = dfa.next_state(self.dfa_state_id(state), byte);
+                (
+                    state_id.as_usize() >> dfa.stride2(),
+                    utils::check_dfa_state_status(state_id, dfa),
+                )
+            }
+            Self::SparseDfa(dfa) => {
+                let state_id = dfa.next_state(self.dfa_state_id(state), byte);
+                (
+                    state_id.as_usize(),
+                    utils::check_dfa_state_status(state_id, dfa),
+                )
+            }
+            Self::LazyDfa(dfa) => dfa.next_state(state, byte),
+        }
+    }
+
+    /// Gets the status of `state`.
+    pub(crate) fn status(&self, state: usize) -> FsaStateStatus {
+        match self {
+            Self::Dfa(dfa) => utils::check_dfa_state_status(self.dfa_state_id(state), dfa),
+            Self::SparseDfa(dfa) => utils::check_dfa_state_status(self.dfa_state_id(state), dfa),
+            Self::LazyDfa(dfa) => dfa.status(state),
+        }
+    }
+
+    /// Converts `state` to the state ID of the DFA, which keys the tables of the [`Grammar`](crate::grammar::Grammar).
+    ///
+    /// A lazy DFA has no such tables, so its state index is returned as is.
+    #[inline]
+    pub(crate) fn dfa_state_id(&self, state: usize) -> StateID {
+        match self {
+            Self::Dfa(dfa) => StateID::new_unchecked(state << dfa.stride2()),
+            Self::SparseDfa(_) | Self::LazyDfa(_) => StateID::new_unchecked(state),
+        }
+    }
+
+    /// Gets the state IDs of the DFA, or nothing for a lazy DFA since its states are only known while matching.
+    pub(crate) fn dfa_states(&self) -> Vec<StateID> {
+        match self {
+            Self::Dfa(dfa) => dfa.states().map(|state| state.id()).collect(),
+            Self::SparseDfa(dfa) => {
+                // A sparse DFA cannot iterate over its states, so they are found by a search from the start states.
+                let mut states = Vec::new();
+                let mut visited = ahash::AHashSet::default();
+                for anchored in [Anchored::Yes, Anchored::No] {
+                    if let Ok(state_id) = dfa.start_state(&start::Config::new().anchored(anchored))
+                    {
+                        if visited.insert(state_id) {
+                            states.push(state_id);
+                        }
+                    }
+                }
+                let mut i = 0;
+                while i < states.len() {
+                    let state_id = states[i];
+                    for byte in 0..=u8::MAX {
+                        let next_state = dfa.next_state(state_id, byte);
+                        if visited.insert(next_state) {
+                            states.push(next_state);
+                        }
+                    }
+                    i += 1;
+                }
+                states
+            }
+            Self::LazyDfa(_) => Vec::new(),
+        }
+    }
+
+    /// Gets the state ID of the DFA after `byte` is fed to `state_id`, along with the status of the new state.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the automaton is a lazy DFA.
+    pub(crate) fn next_dfa_state(&self, state_id: StateID, byte: u8) -> (StateID, FsaStateStatus) {
+        match self {
+            Self::Dfa(dfa) => {
+                let state_id = dfa.next_state(state_id, byte);
+                (state_id, utils::check_dfa_state_status(state_id, dfa))
+            }
+            Self::SparseDfa(dfa) => {
+                let state_id = dfa.next_state(state_id, byte);
+                (state_id, utils::check_dfa_state_status(state_id, dfa))
+            }
+            Self::LazyDfa(_) => unreachable!("a lazy DFA has no DFA state IDs"),
+        }
+    }
+
+    /// Gets one byte of each equivalence class of the DFA's alphabet, or every byte for a lazy DFA.
+    pub(crate) fn representative_bytes(&self) -> Vec<u8> {
+        let classes = match self {
+            Self::Dfa(dfa) => dfa.byte_classes(),
+            Self::SparseDfa(dfa) => dfa.byte_classes(),
+            Self::LazyDfa(_) => return (0..=u8::MAX).collect(),
+        };
+        classes
+            .representatives(0..=u8::MAX)
+            .filter_map(|unit| unit.as_u8())
+            .collect()
+    }
+}
+
+/// The source of the IDs that tell apart the lazy DFAs of different grammars.
+static LAZY_DFA_INSTANCES: AtomicU64 = AtomicU64::new(0);
+
+/// A lazy DFA that determinizes its states while matching and stores them in a bounded cache.
+///
+/// Earley items refer to the states of the lazy DFA, so the cache is never cleared.
+/// Once the cache is full, every transition to a state that is not cached yet is rejected.
+/// The cache is shared by all the clones of the lazy DFA, so engines forked or cloned from each other
+/// and the threads computing their allowed tokens see the same states.
+#[derive(Clone)]
+pub struct LazyDfa {
+    pattern: String,
+    dfa: hybrid::dfa::DFA,
+    states: Arc<Mutex<LazyDfaStates>>,
+    instance: u64,
+    anchored_start: usize,
+    unanchored_start: usize,
+}
+
+/// The cache of a [`LazyDfa`] along with the indices assigned to its states.
+struct LazyDfaStates {
+    cache: hybrid::dfa::Cache,
+    ids: Vec<LazyStateID>,
+    indices: AHashMap<LazyStateID, usize>,
+    first_bytes: AHashMap<(usize, bool), ByteSet>,
+}
+
+impl LazyDfaStates {
+    fn index(&mut self, state_id: LazyStateID) -> usize {
+        *self.indices.entry(state_id).or_insert_with(|| {
+            self.ids.push(state_id);
+            self.ids.len() - 1
+        })
+    }
+
+    fn status(&mut self, dfa: &hybrid::dfa::DFA, state_id: LazyStateID) -> FsaStateStatus {
+        if state_id.is_dead() || state_id.is_quit() {
+            return FsaStateStatus::Reject;
+        }
+        match dfa.next_eoi_state(&mut self.cache, state_id) {
+            Ok(state_id) if state_id.is_match() => FsaStateStatus::Accept,
+            Ok(_) => FsaStateStatus::InProgress,
+            Err(_) => FsaStateStatus::Reject,
+        }
+    }
+
+    fn next_state(
+        &mut self,
+        dfa: &hybrid::dfa::DFA,
+        state: usize,
+        byte: u8,
+    ) -> (usize, FsaStateStatus) {
+        match dfa.next_state(&mut self.cache, self.ids[state], byte) {
+            Ok(state_id) => {
+                let status = self.status(dfa, state_id);
+                (self.index(state_id), status)
+            }
+            Err(_) => (state, FsaStateStatus::Reject),
+        }
+    }
+}
+
+impl LazyDfa {
+    /// Creates a lazy DFA from a regular expression.
+    ///
+    /// `cache_capacity` is the maximum memory usage of the cache in bytes,
+    /// or `None` for the default capacity of [`hybrid::dfa::Config::cache_capacity`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the regular expression is invalid, if the cache capacity is too small,
+    /// or if the start states do not fit in the cache.
+    pub fn new(pattern: &str, cache_capacity: Option<usize>) -> Result<Self, CreateGrammarError> {
+        let mut config = hybrid::dfa::DFA::config().minimum_cache_clear_count(Some(0));
+        if let Some(cache_capacity) = cache_capacity {
+            config = config.cache_capacity(cache_capacity);
+        }
+        let dfa = hybrid::dfa::DFA::builder()
+            .configure(config)
+            .build(pattern)
+            .map_err(|e| Box::new(SemanticError::LazyDfaRegexBuildError(e)))?;
+        let mut states = LazyDfaStates {
+            cache: dfa.create_cache(),
+            ids: Vec::new(),
+            indices: AHashMap::default(),
+            first_bytes: AHashMap::default(),
+        };
+        let mut start_state = |anchored| -> Result<usize, CreateGrammarError> {
+            let state_id =
+                dfa.start_state(&mut states.cache, &start::Config::new().anchored(anchored))?;
+            Ok(states.index(state_id))
+        };
+        let anchored_start = start_state(Anchored::Yes)?;
+        let unanchored_start = start_state(Anchored::No)?;
+        Ok(Self {
+            pattern: pattern.to_string(),
+            dfa,
+            states: Arc::new(Mutex::new(states)),
+            instance: LAZY_DFA_INSTANCES.fetch_add(1, Ordering::Relaxed),
+            anchored_start,
+            unanchored_start,
+        })
+    }
+
+    /// Gets the regular expression of the lazy DFA.
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Gets the maximum memory usage of the cache in bytes.
+    pub fn cache_capacity(&self) -> usize {
+        self.dfa.get_config().get_cache_capacity()
+    }
+
+    /// Gets the number of states determinized so far.
+    pub fn state_len(&self) -> usize {
+        self.states.lock().unwrap().ids.len()
+    }
+
+    /// Gets an ID that is unique to this lazy DFA and its clones,
+    /// since the indices of the states depend on the order they are determinized.
+    pub(crate) fn instance(&self) -> u64 {
+        self.instance
+    }
+
+    /// Checks whether the regular expression matches the empty string.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache is full.
+    pub(crate) fn has_empty(&self) -> Result<bool, CreateGrammarError> {
+        let mut states = self.states.lock().unwrap();
+        let state_id = states.ids[self.anchored_start];
+        Ok(self
+            .dfa
+            .next_eoi_state(&mut states.cache, state_id)?
+            .is_match())
+    }
+
+    /// Checks whether the regular expression matches the empty string and nothing else.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache is full.
+    pub(crate) fn only_empty(&self) -> Result<bool, CreateGrammarError> {
+        if !self.has_empty()? {
+            return Ok(false);
+        }
+        let mut states = self.states.lock().unwrap();
+        let state_id = states.ids[self.anchored_start];
+        for byte in 0..=u8::MAX {
+            let next_state = self.dfa.next_state(&mut states.cache, state_id, byte)?;
+            if !next_state.is_dead() && !next_state.is_quit() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn next_state(&self, state: usize, byte: u8) -> (usize, FsaStateStatus) {
+        self.states
+            .lock()
+            .unwrap()
+            .next_state(&self.dfa, state, byte)
+    }
+
+    fn status(&self, state: usize) -> FsaStateStatus {
+        let mut states = self.states.lock().unwrap();
+        let state_id = states.ids[state];
+        states.status(&self.dfa, state_id)
+    }
+
+    /// Gets the bytes that `state` can accept, the same way as the first-byte tables of the grammar.
+    ///
+    /// For the complement of the regex, the bytes that lead to a match are excluded.
+    /// The bytes are computed once for each state and then cached.
+    pub(crate) fn first_bytes(&self, state: usize, complement: bool) -> ByteSet {
+        let mut states = self.states.lock().unwrap();
+        if let Some(first_bytes) = states.first_bytes.get(&(state, complement)) {
+            return first_bytes.clone();
+        }
+        let mut first_bytes = ByteSet::with_capacity(256);
+        for byte in 0..=u8::MAX {
+            match states.next_state(&self.dfa, state, byte).1 {
+                FsaStateStatus::Reject => {}
+                FsaStateStatus::Accept if complement => {}
+                _ => first_bytes.insert(byte as usize),
+            }
+        }
+        states
+            .first_bytes
+            .insert((state, complement), first_bytes.clone());
+        first_bytes
+    }
+}
+
+impl Debug for LazyDfa {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LazyDfa")
+            .field("pattern", &self.pattern)
+            .field("cache_capacity", &self.cache_capacity())
+            .field("state_len", &self.state_len())
+            .finish()
+    }
+}
@@ -1,14 +1,22 @@
 //! Utility functions for the library.
+use std::borrow::Cow;
+
 use ahash::{AHashMap, AHashSet};
 use fixedbitset_stack::on_stack::{get_nblock, FixedBitSet};
 use kbnf_regex_automata::dfa::Automaton;
 use kbnf_regex_automata::util::primitives::StateID;
 use kbnf_syntax::regex::FiniteStateAutomaton;
 use kbnf_syntax::simplified_grammar::SimplifiedGrammar;
+use kbnf_syntax::InternedStrings;
 use nom::error::VerboseError;
+use string_interner::backend::StringBackend;
+use string_interner::symbol::SymbolU32;
+use string_interner::StringInterner;
 
-use crate::config::InternalConfig;
+use crate::config::{Fsa, InternalConfig};
 use crate::grammar::CreateGrammarError;
+use crate::regex::LazyDfa;
+use crate::vocabulary::Vocabulary;
 
 pub(crate) type ByteSet = FixedBitSet<{ get_nblock(u8::MAX as usize) }>;
 #[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display)]
@@ -22,7 +30,7 @@ pub fn construct_kbnf_syntax_grammar(
     input: &str,
     config: InternalConfig,
 ) -> Result<SimplifiedGrammar, CreateGrammarError> {
-    let grammar = kbnf_syntax::get_grammar(input).map_err(|e| match e {
+    let mut grammar = kbnf_syntax::get_grammar(input).map_err(|e| match e {
         nom::Err::Error(e) => nom::Err::Error(VerboseError {
             errors: e
                 .errors
@@ -39,6 +47,12 @@ pub fn construct_kbnf_syntax_grammar(
         }),
         nom::Err::Incomplete(e) => nom::Err::Incomplete(e),
     })?;
+    if config.fsa_type == Fsa::LazyDfa {
+        replace_regex_strings_with_markers(
+            &mut grammar.interned_strings,
+            config.lazy_dfa_cache_capacity,
+        )?;
+    }
     let grammar = grammar.validate_grammar(&config.start_nonterminal, config.regex_config)?;
     let grammar = grammar.simplify_grammar(
         config.compression_config,
@@ -47,6 +61,189 @@ pub fn construct_kbnf_syntax_grammar(
     );
     Ok(grammar)
 }
+/// The prefix of the regular expressions that special token references are replaced with.
+const SPECIAL_TOKEN_MARKER_PREFIX: &str = "__kbnf_special_token_";
+/// The suffix of the regular expressions that special token references are replaced with.
+const SPECIAL_TOKEN_MARKER_SUFFIX: &str = "__";
+/// Helper function to replace the special token references like `#token'<|im_end|>'` in an KBNF grammar string
+/// with marker regular expressions that contain the special token IDs, since kbnf-syntax does not parse special token references.
+/// The markers are converted back to special token nodes in [Grammar::new](crate::grammar::Grammar::new).
+///
+/// Strings and comments are skipped, so `#token` inside them is left untouched.
+/// Strings containing the marker are rejected, since they would be indistinguishable from the special token references.
+///
+/// # Errors
+///
+/// Returns [CreateGrammarError::UnknownSpecialToken] if a referenced special token is not in the vocabulary,
+/// or [CreateGrammarError::ReservedString] if a string contains the marker.
+pub(crate) fn replace_special_token_references<'a>(
+    input: &'a str,
+    vocabulary: &Vocabulary,
+) -> Result<Cow<'a, str>, CreateGrammarError> {
+    let mut output = String::with_capacity(input.len());
+    let mut replaced = false;
+    let mut rest = input;
+    while let Some(c) = rest.chars().next() {
+        if let Some(reference) = rest.strip_prefix("#token") {
+            if let Some(quote @ ('\'' | '"')) = reference.chars().next() {
+                if let Some((content, len)) = scan_quoted_string(&reference[1..], quote) {
+                    let token_id = vocabulary
+                        .special_token_id(content.as_bytes())
+                        .ok_or(CreateGrammarError::UnknownSpecialToken(content))?;
+                    output.push_str(&format!(
+                        "#'{SPECIAL_TOKEN_MARKER_PREFIX}{token_id}{SPECIAL_TOKEN_MARKER_SUFFIX}'"
+                    ));
+                    replaced = true;
+                    rest = &reference[1 + len..];
+                    continue;
+                }
+            }
+        }
+        let len = match c {
+            '\'' | '"' => match scan_quoted_string(&rest[1..], c) {
+                Some((content, _)) if content.contains(SPECIAL_TOKEN_MARKER_PREFIX) => {
+                    return Err(CreateGrammarError::ReservedString(content));
+                }
+                Some((_, len)) => 1 + len,
+                None => rest.len(),
+            },
+            '(' if rest.starts_with("(*") => rest[2..].find("*)").map_or(rest.len(), |i| i + 4),
+            _ => c.len_utf8(),
+        };
+        output.push_str(&rest[..len]);
+        rest = &rest[len..];
+    }
+    Ok(if replaced {
+        Cow::Owned(output)
+    } else {
+        Cow::Borrowed(input)
+    })
+}
+/// Scans a string literal after its opening quote.
+///
+/// # Returns
+///
+/// The unescaped content of the string and the length of the string literal after its opening quote,
+/// or [None] if the string literal is not terminated.
+fn scan_quoted_string(input: &str, quote: char) -> Option<(String, usize)> {
+    let mut content = String::new();
+    let mut chars = input.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == quote {
+            return Some((content, i + c.len_utf8()));
+        }
+        if c != '\\' {
+            content.push(c);
+            continue;
+        }
+        let (_, escaped) = chars.next()?;
+        let hex = |digits: &str| {
+            u32::from_str_radix(digits, 16)
+                .ok()
+                .and_then(char::from_u32)
+        };
+        // The length of the hexadecimal escape after the escaped character and the character it represents
+        let hex_escape = match escaped {
+            'x' => chars.as_str().get(..2).map(|digits| (2, hex(digits))),
+            'u' if chars.as_str().starts_with('{') => chars
+                .as_str()
+                .find('}')
+                .map(|end| (end + 1, hex(&chars.as_str()[1..end]))),
+            'u' => chars.as_str().get(..4).map(|digits| (4, hex(digits))),
+            _ => None,
+        };
+        if let Some((len, Some(c))) = hex_escape {
+            chars.nth(len - 1);
+            content.push(c);
+            continue;
+        }
+        let unescaped = match escaped {
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            'v' => '\u{b}',
+            'f' => '\u{c}',
+            'b' => '\u{8}',
+            '0' => '\0',
+            c => c,
+        };
+        content.push(unescaped);
+    }
+    None
+}
+/// The prefix of the markers that regular expressions compiled into lazy DFAs are replaced with.
+const REGEX_MARKER_PREFIX: &str = "__kbnf_regex_";
+/// The suffix of the markers that regular expressions compiled into lazy DFAs are replaced with.
+const REGEX_MARKER_SUFFIX: &str = "__";
+/// Helper function to replace the regular expressions of an KBNF grammar with markers that spell them in hexadecimal,
+/// since kbnf-syntax compiles every regular expression into a dense DFA, which is what lazy DFAs avoid.
+/// The markers are converted back and compiled into lazy DFAs in [Grammar::new](crate::grammar::Grammar::new).
+///
+/// A marker matches the empty string exactly when its regular expression does, which is all kbnf-syntax needs to simplify the grammar.
+/// Regular expressions that match only the empty string are removed by kbnf-syntax, so they are kept as they are,
+/// and so are the markers of special token references.
+///
+/// # Errors
+///
+/// Returns an error if a regular expression cannot be compiled into a lazy DFA.
+fn replace_regex_strings_with_markers(
+    interned_strings: &mut InternedStrings,
+    cache_capacity: Option<usize>,
+) -> Result<(), CreateGrammarError> {
+    let mut regex_strings = StringInterner::<StringBackend<SymbolU32>>::new();
+    for (_, regex) in interned_strings.regex_strings.iter() {
+        let marker = if parse_special_token_marker(regex).is_some() {
+            regex.to_string()
+        } else {
+            let dfa = LazyDfa::new(regex, cache_capacity)?;
+            let hex: String = regex.bytes().map(|byte| format!("{byte:02x}")).collect();
+            let marker = format!("{REGEX_MARKER_PREFIX}{hex}{REGEX_MARKER_SUFFIX}");
+            if dfa.only_empty()? {
+                regex.to_string()
+            } else if dfa.has_empty()? {
+                format!("(?:{marker})?")
+            } else {
+                marker
+            }
+        };
+        regex_strings.get_or_intern(marker);
+    }
+    interned_strings.regex_strings = regex_strings;
+    Ok(())
+}
+/// Parses the regular expression from a marker created by [replace_regex_strings_with_markers].
+pub(crate) fn parse_regex_marker(marker: &str) -> Option<String> {
+    let marker = marker
+        .strip_prefix("(?:")
+        .and_then(|marker| marker.strip_suffix(")?"))
+        .unwrap_or(marker);
+    let hex = marker
+        .strip_prefix(REGEX_MARKER_PREFIX)?
+        .strip_suffix(REGEX_MARKER_SUFFIX)?;
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<_>>>()?;
+    String::from_utf8(bytes).ok()
+}
+/// Parses the special token ID from a regular expression created by [replace_special_token_references].
+///
+/// kbnf-syntax may wrap the regular expression in anchors or empty groups, which are ignored.
+pub(crate) fn parse_special_token_marker(regex: &str) -> Option<u32> {
+    let start = regex.find(SPECIAL_TOKEN_MARKER_PREFIX)?;
+    let rest = &regex[start + SPECIAL_TOKEN_MARKER_PREFIX.len()..];
+    let digits_len = rest.bytes().take_while(u8::is_ascii_digit).count();
+    let token_id = rest[..digits_len].parse().ok()?;
+    let rest = rest[digits_len..].strip_prefix(SPECIAL_TOKEN_MARKER_SUFFIX)?;
+    regex[..start]
+        .chars()
+        .chain(rest.chars())
+        .all(|c| matches!(c, '\\' | 'A' | 'z' | '(' | '?' | ':' | ')' | '^' | '$'))
+        .then_some(token_id)
+}
 /// Helper function to find the maximum state ID from an KBNF grammar.
 /// This is useful for determining [EngineBase](crate::engine_base::EngineBase) and [Grammar](crate::grammar::Grammar)'s generic parameter(TS).
 pub fn find_max_state_id_from_kbnf_syntax_grammar(grammar: &SimplifiedGrammar) -> usize {
@@ -88,10 +285,7 @@ pub fn find_max_production_id_from_kbnf_syntax_grammar(grammar: &SimplifiedGramm
     max_production_id
 }
 #[inline]
-pub(crate) fn check_dfa_state_status(
-    dfa_state: StateID,
-    dfa: &kbnf_regex_automata::dfa::dense::DFA<Vec<u32>>,
-) -> FsaStateStatus {
+pub(crate) fn check_dfa_state_status(dfa_state: StateID, dfa: &impl Automaton) -> FsaStateStatus {
     if dfa.is_special_state(dfa_state)
         && (dfa.is_dead_state(dfa_state) || dfa.is_quit_state(dfa_state))
     {
@@ -103,18 +297,6 @@ pub(crate) fn check_dfa_state_status(
         FsaStateStatus::InProgress
     }
 }
-macro_rules! dispatch_by_dfa_state_status {
-    ($dfa_state:ident, $dfa:ident , accept=>$accept:block , reject=>$reject:block ,in_progress=>$in_progress:block) => {
-        if $dfa.is_special_state($dfa_state) && ($dfa.is_dead_state($dfa_state)||$dfa.is_quit_state($dfa_state))
-            $reject
-        else if $dfa.is_match_state($dfa.next_eoi_state($dfa_state))
-            $accept
-        else
-            $in_progress
-
-    };
-}
-pub(crate) use dispatch_by_dfa_state_status;
 
 pub(crate) fn get_display_form_from_bitset_on_stack<const NBLOCK: usize>(
     bitset: &FixedBitSet<NBLOCK>,
@@ -150,3 +332,65 @@ pub(crate) fn fill_debug_form_of_id_to_x<'a, T: std::fmt::Debug>(
 ) -> AHashMap<String, T> {
     id_to_x.enumerate().map(|(i, x)| (get_str(i), x)).collect()
 }
+
+/// A 64-bit FNV-1a hasher, which unlike the hashers of the standard library and `ahash` is not seeded,
+/// so its results are stable across processes and platforms and can be persisted as fingerprints.
+pub(crate) struct StableHasher(u64);
+
+impl StableHasher {
+    pub(crate) fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    pub(crate) fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+
+    /// Writes the length of `bytes` followed by `bytes`, so that adjacent byte strings cannot be confused.
+    pub(crate) fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u64(bytes.len() as u64);
+        self.write(bytes);
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// A 128-bit digest built from two independent splitmix64 lanes, which is stable across processes like [`StableHasher`].
+///
+/// Digests of distinct elements can be summed with wrapping addition to digest a set regardless of its order.
+pub(crate) struct StableDigest(u64, u64);
+
+impl StableDigest {
+    pub(crate) fn new() -> Self {
+        Self(0x243f6a8885a308d3, 0x13198a2e03707344)
+    }
+
+    pub(crate) fn write_u64(&mut self, value: u64) {
+        fn splitmix64(mut x: u64) -> u64 {
+            x = x.wrapping_add(0x9e3779b97f4a7c15);
+            x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+            x ^ (x >> 31)
+        }
+        self.0 = splitmix64(self.0 ^ value);
+        self.1 = splitmix64(self.1 ^ value.rotate_left(32) ^ 0xa4093822299f31d0);
+    }
+
+    pub(crate) fn write_u128(&mut self, value: u128) {
+        self.write_u64(value as u64);
+        self.write_u64((value >> 64) as u64);
+    }
+
+    pub(crate) fn finish(&self) -> u128 {
+        ((self.1 as u128) << 64) | self.0 as u128
+    }
+}
@@ -1,24 +1,19 @@
 //! This module contains the `Vocabulary` struct, which represents a language model's vocabulary.
-use ahash::AHashMap;
-use jaggedarray::jagged_array::JaggedArray;
-use jaggedarray::jagged_array::JaggedArrayViewTrait;
-use nonmax::NonMaxU8;
-use num::ToPrimitive;
+use ahash::{AHashMap, AHashSet};
 #[cfg(feature = "python")]
 use pyo3::prelude::*;
 use serde::Deserialize;
 use std::array;
+use std::borrow::Cow;
 use std::collections::hash_map::Entry;
 use std::fmt::Debug;
-use tinyvec::ArrayVec;
+use std::ops::Range;
+use std::path::Path;
 #[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 
 use crate::utils;
-use crate::utils::ByteSet;
-
-const TOKEN_SEPARATOR: u8 = 0xFF;
-const BYTES_NUM: usize = 257; // 256 + 1 because jagged array's implementation requires one additional index.
+use crate::utils::{ByteSet, StableHasher};
 
 /// A wrapper struct that represents a token in bytes in a language model's vocabulary.
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
@@ -26,23 +21,25 @@ const BYTES_NUM: usize = 257; // 256 + 1 because jagged array's implementation r
 #[cfg_attr(feature = "wasm", wasm_bindgen(getter_with_clone))]
 #[cfg_attr(feature = "python", pyclass)]
 pub struct Token(pub Box<[u8]>);
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub(crate) struct FirstBytes([u32; BYTES_NUM]);
-impl tinyvec::Array for FirstBytes {
-    type Item = u32;
-    const CAPACITY: usize = BYTES_NUM;
-
-    fn as_slice(&self) -> &[Self::Item] {
+impl std::borrow::Borrow<[u8]> for Token {
+    fn borrow(&self) -> &[u8] {
         &self.0
     }
-
-    fn as_slice_mut(&mut self) -> &mut [Self::Item] {
-        &mut self.0
-    }
-
-    fn default() -> Self {
-        Self([0; BYTES_NUM])
-    }
+}
+/// A node of a token trie, which is stored in depth-first preorder.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct TokenTrieNode {
+    /// The last byte of the prefix represented by this node.
+    pub(crate) byte: u8,
+    /// The length of the prefix represented by this node minus one, so the root has depth zero.
+    pub(crate) depth: u32,
+    /// The index of the first node after the subtree rooted at this node.
+    pub(crate) skip: u32,
+    /// The range in `Vocabulary::token_trie_token_ids` of the IDs of the tokens equal to the prefix.
+    token_ids: Range<u32>,
+    /// The end of the range in `Vocabulary::token_trie_token_ids` of the IDs of the tokens in the subtree,
+    /// which starts at `token_ids.start`.
+    subtree_token_ids_end: u32,
 }
 /// The struct represents a language model's vocabulary.
 #[derive(Clone)]
@@ -52,13 +49,16 @@ pub struct Vocabulary {
     pub(crate) token_to_id: AHashMap<Token, u32>,
     pub(crate) id_to_token: AHashMap<u32, Token>,
     pub(crate) id_to_token_string: AHashMap<u32, String>,
-    /// This field represents a map from the first byte of a token to the token id and token that DO NOT contain byte 0xFF.
-    /// memory representation: \[Unicode unused byte\]\[token_id(3 bytes little endian)\]\[token(remaining bytes)\]
-    // TODO: check whether a variable length token_id encoding is better
-    first_byte_to_normal_tokens: JaggedArray<u8, ArrayVec<FirstBytes>, 2>,
-    /// This field represents a map from the token id to the token that contains the Unicode unused byte in `first_byte_to_normal_tokens``.
-    /// The number of such tokens is expected to be small so we probably do not need a jagged array(which does have some overhead).
-    tokens_containing_separators: Vec<(u32, Token)>,
+    /// The special tokens, which are excluded from `token_to_id` and the token tries
+    /// so that they are never produced by matching bytes.
+    special_token_to_id: AHashMap<Token, u32>,
+    /// The tries of the tokens grouped by their first bytes, concatenated in the order of the first bytes.
+    /// Each trie is stored in depth-first preorder, so a subtree can be skipped by jumping to its `skip` index.
+    token_trie: Vec<TokenTrieNode>,
+    /// The start of the trie of each first byte in `token_trie`, followed by the length of `token_trie`.
+    first_byte_to_token_trie: Box<[u32; 257]>,
+    /// The token IDs referred by the nodes in `token_trie`.
+    token_trie_token_ids: Vec<u32>,
 }
 
 impl Debug for Vocabulary {
@@ -67,37 +67,45 @@ impl Debug for Vocabulary {
             .field("token_to_id", &self.token_to_id)
             .field("id_to_token", &self.id_to_token)
             .field("id_to_token_string", &self.id_to_token_string)
-            .field("first_byte_to_normal_tokens", {
+            .field("special_token_to_id", &self.special_token_to_id)
+            .field("token_trie", {
                 let mut hash_map = AHashMap::new();
                 for byte in 0..u8::MAX as usize + 1 {
-                    let mut iter = self.normal_tokens_from_first_byte(byte as u8);
-                    while let Some(item) = iter.next() {
-                        if let TokenIterItem::TokenByte(byte) = item {
-                            hash_map
-                                .entry(iter.current_token_id())
-                                .or_insert_with(Vec::new)
-                                .push(byte.get());
-                        }
+                    let nodes = self.token_trie(byte as u8);
+                    if !nodes.is_empty() {
+                        hash_map.insert(byte as u8, nodes);
                     }
                 }
                 &Box::new(hash_map)
             })
-            .field(
-                "tokens_containing_separators",
-                &self.tokens_containing_separators,
-            )
+            .field("token_trie_token_ids", &self.token_trie_token_ids)
             .finish()
     }
 }
 #[derive(Debug, thiserror::Error)]
 /// The error type for [Vocabulary] creation.
 pub enum CreateVocabularyError {
-    /// The vocabulary size exceeds the maximum supported size.
-    #[error("The vocabulary size is {0}, while the maximum supported is {1}.")]
+    /// The vocabulary size, or the total length of the tokens in bytes, exceeds the maximum supported size.
+    #[error("The vocabulary is too large: {0} exceeds the maximum supported {1}.")]
     VocabularyTooLarge(usize, usize),
     /// The token's length exceeds the maximum supported length.
     #[error("The token's length is {0}, while the maximum supported is {1}.")]
     TokenTooLong(usize, usize),
+    /// The tokenizer file cannot be read.
+    #[error("Failed to read the tokenizer file: {0}")]
+    IoError(#[from] std::io::Error),
+    /// The tokenizer file is not valid JSON or misses required fields.
+    #[error("Failed to parse the tokenizer file: {0}")]
+    JsonError(#[from] serde_json::Error),
+    /// The tokenizer's model type is not supported.
+    #[error("The tokenizer model type {0} is not supported.")]
+    UnsupportedModel(String),
+    /// A token in the tokenizer cannot be converted to bytes.
+    #[error("The token {0:?} cannot be converted to bytes.")]
+    InvalidToken(String),
+    /// The vocabulary file does not follow its format.
+    #[error("The vocabulary file is malformed: {0}")]
+    MalformedFile(String),
 }
 
 impl Vocabulary {
@@ -113,16 +121,48 @@ impl Vocabulary {
         id_to_token: AHashMap<u32, Token>,
         id_to_token_string: AHashMap<u32, String>,
     ) -> Result<Vocabulary, CreateVocabularyError> {
-        if id_to_token.len() >= 0x1000000 {
+        Self::with_special_tokens(id_to_token, id_to_token_string, AHashSet::default())
+    }
+
+    /// Creates a new instance of [Vocabulary] with special tokens.
+    ///
+    /// Special tokens, like the EOS token or `<|im_end|>`, are never produced by matching bytes,
+    /// so a grammar terminal spelling the same characters does not allow them.
+    /// They can only be matched by a special token reference in the grammar, e.g. `#token'<|im_end|>'`.
+    ///
+    /// # Arguments
+    ///
+    /// * `id_to_token` - A map from token IDs to tokens.
+    /// * `id_to_token_string` - A map from token IDs to tokens in UTF-8 String representation.
+    /// * `special_token_ids` - The IDs of the special tokens. IDs not in `id_to_token` are ignored.
+    pub fn with_special_tokens(
+        id_to_token: AHashMap<u32, Token>,
+        id_to_token_string: AHashMap<u32, String>,
+        special_token_ids: AHashSet<u32>,
+    ) -> Result<Vocabulary, CreateVocabularyError> {
+        // The token IDs, the trie nodes and the depths of the trie nodes are indexed by u32.
+        if id_to_token.len() > u32::MAX as usize {
             return Err(CreateVocabularyError::VocabularyTooLarge(
                 id_to_token.len(),
-                0x1000000,
+                u32::MAX as usize,
+            ));
+        }
+        let total_len: usize = id_to_token.values().map(|token| token.0.len()).sum();
+        if total_len > u32::MAX as usize {
+            return Err(CreateVocabularyError::VocabularyTooLarge(
+                total_len,
+                u32::MAX as usize,
             ));
         }
         
         let mut token_to_id = AHashMap::with_capacity(id_to_token.len());
+        let mut special_token_to_id = AHashMap::with_capacity(special_token_ids.len());
         let mut conflicting_token_ids: Vec<(u32, u32)> = Vec::new();
         for (&token_id, token) in id_to_token.iter() {
+            if special_token_ids.contains(&token_id) {
+                special_token_to_id.insert(token.clone(), token_id);
+                continue;
+            }
             match token_to_id.entry(token.clone()) {
                 Entry::Occupied(entry) => {
                     conflicting_token_ids.push((token_id, *entry.get()));
@@ -151,9 +191,11 @@ impl Vocabulary {
             );
         }
 
-        let mut first_byte_to_token = JaggedArray::with_capacity([256, 256]);
-        let mut temp: [Vec<(u32, &Token)>; 256] = array::from_fn(|_| (vec![]));
+        let mut temp: [Vec<(&Token, u32)>; 256] = array::from_fn(|_| (vec![]));
         for (&token_id, token) in id_to_token.iter() {
+            if special_token_ids.contains(&token_id) {
+                continue;
+            }
             if token.0.is_empty() {
                 log::warn!(
                     "Token ID {} corresponds to an empty token. 
@@ -163,43 +205,74 @@ impl Vocabulary {
                 continue;
             }
             let first_byte = token.0[0];
-            temp[first_byte as usize].push((token_id, token));
+            temp[first_byte as usize].push((token, token_id));
         }
-        let mut tokens_containing_separators = Vec::new();
-        for tokens in temp.iter() {
-            first_byte_to_token.new_row::<0>();
-            for &(token_id, token) in tokens.iter() {
-                let mut buffer = vec![TOKEN_SEPARATOR];
-                if token.0.contains(&TOKEN_SEPARATOR) {
-                    tokens_containing_separators.push((token_id, token.clone()));
-                    continue;
-                }
-                buffer.extend(token_id.to_le_bytes().into_iter().take(3));
-                let token_len =
-                    token
-                        .0
-                        .len()
-                        .to_u8()
-                        .ok_or(CreateVocabularyError::TokenTooLong(
-                            token.0.len(),
-                            u8::MAX as usize,
-                        ))?
-                        - 1;
-                buffer.push(token_len);
-                buffer.extend(token.0.iter().skip(1));
-                first_byte_to_token.extend_last_row(buffer.into_iter());
-            }
+        let mut token_trie = Vec::new();
+        let mut first_byte_to_token_trie = Box::new([0; 257]);
+        let mut token_trie_token_ids = Vec::with_capacity(id_to_token.len());
+        for (first_byte, tokens) in temp.iter_mut().enumerate() {
+            first_byte_to_token_trie[first_byte] = token_trie.len() as u32;
+            // Sorting the tokens makes the preorder of the trie nodes the order in which the nodes are first seen.
+            tokens.sort_unstable_by_key(|&(token, token_id)| (&token.0, token_id));
+            Self::build_token_trie(tokens, &mut token_trie, &mut token_trie_token_ids);
         }
+        first_byte_to_token_trie[256] = token_trie.len() as u32;
         Self::check_vocabulary_utf8_support(&token_to_id);
         Ok(Self {
             token_to_id,
             id_to_token,
             id_to_token_string,
-            first_byte_to_normal_tokens: first_byte_to_token,
-            tokens_containing_separators,
+            special_token_to_id,
+            token_trie,
+            first_byte_to_token_trie,
+            token_trie_token_ids,
         })
     }
 
+    /// Appends the trie of `tokens`, which share the same first byte and are sorted, to `token_trie`.
+    fn build_token_trie(
+        tokens: &[(&Token, u32)],
+        token_trie: &mut Vec<TokenTrieNode>,
+        token_trie_token_ids: &mut Vec<u32>,
+    ) {
+        let trie_start = token_trie.len();
+        // The nodes on the path from the root to the node of the previous token
+        let mut path: Vec<usize> = Vec::new();
+        let mut previous_token: &[u8] = &[];
+        for &(token, token_id) in tokens {
+            let token = &token.0[..];
+            let common_prefix_len = token
+                .iter()
+                .zip(previous_token)
+                .take_while(|(a, b)| a == b)
+                .count();
+            for node in path.drain(common_prefix_len..) {
+                token_trie[node].skip = (token_trie.len() - trie_start) as u32;
+                token_trie[node].subtree_token_ids_end = token_trie_token_ids.len() as u32;
+            }
+            let token_ids_start = token_trie_token_ids.len() as u32;
+            for (depth, &byte) in token.iter().enumerate().skip(common_prefix_len) {
+                path.push(token_trie.len());
+                token_trie.push(TokenTrieNode {
+                    byte,
+                    depth: depth as u32,
+                    skip: 0,
+                    token_ids: token_ids_start..token_ids_start,
+                    subtree_token_ids_end: 0,
+                });
+            }
+            // A token precedes the longer tokens it prefixes and equal tokens are adjacent after sorting,
+            // so the IDs of the tokens in a subtree are contiguous and begin with the IDs of its root.
+            token_trie_token_ids.push(token_id);
+            token_trie[*path.last().unwrap()].token_ids.end += 1;
+            previous_token = token;
+        }
+        for node in path {
+            token_trie[node].skip = (token_trie.len() - trie_start) as u32;
+            token_trie[node].subtree_token_ids_end = token_trie_token_ids.len() as u32;
+        }
+    }
+
     fn check_vocabulary_utf8_support(token_to_id: &AHashMap<Token, u32>) {
         let mut not_existing_bytes = ByteSet::with_capacity(256);
         fn check_non_existing_byte_in_range(
@@ -264,7 +337,7 @@ processing the vocab like the tokenizer.",
         self.id_to_token_string.get(&token_id).map(|x| x.as_str())
     }
 
-    /// Retrieves an iterator over the normal tokens that have the given first byte.
+    /// Retrieves the trie of the tokens that have the given first byte.
     ///
     /// # Arguments
     ///
@@ -272,31 +345,40 @@ processing the vocab like the tokenizer.",
     ///
     /// # Returns
     ///
-    /// An iterator over the normal tokens with the given first byte.
-    pub(crate) fn normal_tokens_from_first_byte(&self, first_byte: u8) -> TokensIter {
-        let slice = self
-            .first_byte_to_normal_tokens
-            .view::<1, 1>([first_byte as usize])
-            .as_slice();
-        TokensIter {
-            current_token_id: usize::MAX,
-            current_token_remaining_length: usize::MAX,
-            current: slice.as_ptr(),
-            // SAFETY: the existence of this slice guarantees that this add is safe
-            end: unsafe { slice.as_ptr().add(slice.len()) },
-            placeholder: std::marker::PhantomData,
-        }
+    /// The trie nodes in depth-first preorder, where the first node, if any, is the root representing `first_byte`.
+    /// The `skip` indices are relative to the returned slice.
+    pub(crate) fn token_trie(&self, first_byte: u8) -> &[TokenTrieNode] {
+        let start = self.first_byte_to_token_trie[first_byte as usize] as usize;
+        let end = self.first_byte_to_token_trie[first_byte as usize + 1] as usize;
+        &self.token_trie[start..end]
     }
 
-    /// Retrieves an iterator over the tokens that contain separators.
-    ///
-    /// # Returns
+    /// Retrieves the IDs of the tokens that end at the given trie node.
+    pub(crate) fn token_ids_of_trie_node(&self, node: &TokenTrieNode) -> &[u32] {
+        &self.token_trie_token_ids[node.token_ids.start as usize..node.token_ids.end as usize]
+    }
+
+    /// Retrieves the positions of the tokens in the subtree rooted at the given trie node.
     ///
-    /// An iterator over the tokens that contain separators.
-    pub(crate) fn tokens_containing_separators(&self) -> impl Iterator<Item = (u32, &Token)> {
-        self.tokens_containing_separators
-            .iter()
-            .map(|(x, y)| (*x, y))
+    /// A position is an index into the token IDs of all the tokens in trie order,
+    /// where the tokens in every subtree occupy consecutive positions.
+    pub(crate) fn subtree_token_positions(&self, node: &TokenTrieNode) -> Range<usize> {
+        node.token_ids.start as usize..node.subtree_token_ids_end as usize
+    }
+
+    /// Retrieves the positions of the tokens that end at the given trie node.
+    pub(crate) fn token_positions(&self, node: &TokenTrieNode) -> Range<usize> {
+        node.token_ids.start as usize..node.token_ids.end as usize
+    }
+
+    /// Retrieves the number of token positions in the token tries.
+    pub(crate) fn token_positions_len(&self) -> usize {
+        self.token_trie_token_ids.len()
+    }
+
+    /// Retrieves the ID of the token at the given position in trie order.
+    pub(crate) fn token_id_at_position(&self, position: usize) -> u32 {
+        self.token_trie_token_ids[position]
     }
 }
 impl Vocabulary {
@@ -311,7 +393,94 @@ impl Vocabulary {
     /// * `Some(u32)` - The token ID if it exists.
     /// * `None` - If the token does not exist in the vocabulary.
     pub fn token_id(&self, token: &Token) -> Option<u32> {
-        self.token_to_id.get(token).copied()
+        self.token_to_id
+            .get(token)
+            .or_else(|| self.special_token_to_id.get(token))
+            .copied()
+    }
+    /// Retrieves the ID of the special token with the given content.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The special token to retrieve the ID for.
+    ///
+    /// # Returns
+    ///
+    /// * `Some(u32)` - The token ID if it exists.
+    /// * `None` - If the token is not a special token of the vocabulary.
+    pub fn special_token_id(&self, token: &[u8]) -> Option<u32> {
+        self.special_token_to_id.get(token).copied()
+    }
+    /// Checks whether the given token ID is a special token, which is never produced by matching bytes.
+    pub fn is_special_token(&self, token_id: u32) -> bool {
+        self.id_to_token
+            .get(&token_id)
+            .is_some_and(|token| self.special_token_to_id.get(token) == Some(&token_id))
+    }
+    /// Splits the given bytes into tokens by repeatedly taking the longest token that prefixes the remaining bytes.
+    ///
+    /// This is typically used to feed the bytes from [`EngineLike::compute_forced_bytes`](crate::engine_like::EngineLike::compute_forced_bytes)
+    /// to a language model. Note that the result may differ from the tokenization the language model's tokenizer would produce.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The bytes to tokenize.
+    ///
+    /// # Returns
+    ///
+    /// * `Some(Vec<u32>)` - The token IDs, whose tokens concatenate to `bytes`.
+    /// * `None` - If some bytes cannot be covered by any token.
+    pub fn tokenize_greedily(&self, bytes: &[u8]) -> Option<Vec<u32>> {
+        let mut token_ids = Vec::new();
+        let mut remaining = bytes;
+        while !remaining.is_empty() {
+            let length = self.longest_token_prefix_len(remaining)?;
+            token_ids.push(self.token_to_id[&remaining[..length]]);
+            remaining = &remaining[length..];
+        }
+        Some(token_ids)
+    }
+    /// Finds the length of the longest token that prefixes `bytes` by walking down the token trie,
+    /// so the cost does not depend on the length of the longest token in the vocabulary.
+    fn longest_token_prefix_len(&self, bytes: &[u8]) -> Option<usize> {
+        let trie = self.token_trie(*bytes.first()?);
+        let mut longest = None;
+        let mut node_index = 0;
+        while let Some(node) = trie.get(node_index) {
+            let length = node.depth as usize + 1;
+            if !self.token_ids_of_trie_node(node).is_empty() {
+                longest = Some(length);
+            }
+            let Some(&next_byte) = bytes.get(length) else {
+                break;
+            };
+            // The children of a node precede its skip index, and each child is followed by its subtree.
+            let mut child_index = node_index + 1;
+            node_index = usize::MAX;
+            while child_index < node.skip as usize {
+                if trie[child_index].byte == next_byte {
+                    node_index = child_index;
+                    break;
+                }
+                child_index = trie[child_index].skip as usize;
+            }
+        }
+        longest
+    }
+    /// Computes a fingerprint of the tokens and their IDs.
+    ///
+    /// The fingerprint is stable across processes and platforms,
+    /// so it can be used to check whether two vocabularies are identical without comparing their tokens.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = StableHasher::new();
+        let mut token_ids: Vec<_> = self.id_to_token.keys().copied().collect();
+        token_ids.sort_unstable();
+        for token_id in token_ids {
+            hasher.write_u64(token_id as u64);
+            hasher.write_bytes(&self.id_to_token[&token_id].0);
+            hasher.write(&[self.is_special_token(token_id) as u8]);
+        }
+        hasher.finish()
     }
     /// Retrieves the size of the vocabulary.
     pub fn vocab_size(&self) -> usize {
@@ -319,72 +488,426 @@ impl Vocabulary {
             .keys()
             .copied()
             .max()
-            .map(|x| x + 1)
-            .unwrap_or(0) as usize
+            .map(|x| x as usize + 1)
+            .unwrap_or(0)
     }
 }
 
-#[derive(Debug, Clone)]
-pub(crate) struct TokensIter<'a> {
-    current_token_id: usize,
-    current_token_remaining_length: usize,
-    current: *const u8,
-    end: *const u8,
-    placeholder: std::marker::PhantomData<&'a u8>,
-}
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub(crate) enum TokenIterItem {
-    TokenByte(NonMaxU8),
-    NewToken,
+impl Vocabulary {
+    /// Creates a new instance of [Vocabulary] from a Hugging Face `tokenizer.json` file.
+    ///
+    /// BPE, WordPiece and Unigram models are supported. The tokens are converted to the bytes they decode to:
+    /// the GPT-2 byte-to-unicode mapping is reversed for byte-level tokenizers, `▁` is replaced with a space otherwise,
+    /// byte fallback tokens like `<0x0A>` are converted to the byte they represent,
+    /// and WordPiece tokens are decoded as the WordPiece decoder joins them, i.e. a word-initial token starts with a space
+    /// and a continuing token has its continuing subword prefix removed.
+    /// The tokens in `added_tokens` are included as their content, and those marked `special` are special tokens.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the `tokenizer.json` file.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`CreateVocabularyError`] when the file cannot be read or parsed,
+    /// the model type is not supported, or the vocabulary cannot be created from the tokens.
+    pub fn from_tokenizer_json(
+        path: impl AsRef<Path>,
+    ) -> Result<Vocabulary, CreateVocabularyError> {
+        Self::from_tokenizer_json_str(&std::fs::read_to_string(path)?)
+    }
+
+    /// Creates a new instance of [Vocabulary] from the content of a Hugging Face `tokenizer.json` file.
+    /// See [`Vocabulary::from_tokenizer_json`] for more details.
+    ///
+    /// # Arguments
+    ///
+    /// * `json` - The content of the `tokenizer.json` file.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`CreateVocabularyError`] when the content cannot be parsed,
+    /// the model type is not supported, or the vocabulary cannot be created from the tokens.
+    pub fn from_tokenizer_json_str(json: &str) -> Result<Vocabulary, CreateVocabularyError> {
+        let tokenizer: TokenizerJson = serde_json::from_str(json)?;
+        let model_type = tokenizer
+            .model
+            .get("type")
+            .and_then(|model_type| model_type.as_str())
+            .unwrap_or_default()
+            .to_string();
+        let byte_level = contains_byte_level(&tokenizer.pre_tokenizer)
+            || contains_byte_level(&tokenizer.decoder);
+        let (pieces, byte_fallback, continuing_subword_prefix): (
+            Vec<(String, u32)>,
+            bool,
+            Option<String>,
+        ) = match model_type.as_str() {
+            "BPE" => {
+                let model: BpeModel = serde_json::from_value(tokenizer.model)?;
+                (model.vocab.into_iter().collect(), model.byte_fallback, None)
+            }
+            "WordPiece" => {
+                let model: WordPieceModel = serde_json::from_value(tokenizer.model)?;
+                (
+                    model.vocab.into_iter().collect(),
+                    false,
+                    Some(model.continuing_subword_prefix),
+                )
+            }
+            "Unigram" => {
+                let model: UnigramModel = serde_json::from_value(tokenizer.model)?;
+                let pieces = model
+                    .vocab
+                    .into_iter()
+                    .enumerate()
+                    .map(|(token_id, (piece, _))| (piece, token_id as u32))
+                    .collect();
+                (pieces, model.byte_fallback, None)
+            }
+            _ => return Err(CreateVocabularyError::UnsupportedModel(model_type)),
+        };
+        let unicode_to_byte = byte_level.then(gpt2_unicode_to_byte);
+        let mut id_to_token = AHashMap::with_capacity(pieces.len());
+        let mut id_to_token_string = AHashMap::with_capacity(pieces.len());
+        for (piece, token_id) in pieces {
+            let bytes = if let Some(unicode_to_byte) = &unicode_to_byte {
+                piece
+                    .chars()
+                    .map(|c| unicode_to_byte.get(&c).copied())
+                    .collect::<Option<Vec<u8>>>()
+                    .ok_or_else(|| CreateVocabularyError::InvalidToken(piece.clone()))?
+            } else if let Some(byte) = byte_fallback.then(|| parse_byte_fallback(&piece)).flatten()
+            {
+                vec![byte]
+            } else {
+                let text = match &continuing_subword_prefix {
+                    Some(prefix) => match piece.strip_prefix(prefix.as_str()) {
+                        Some(text) => Cow::Borrowed(text),
+                        None => Cow::Owned(format!(" {piece}")),
+                    },
+                    None => Cow::Borrowed(piece.as_str()),
+                };
+                text.replace('\u{2581}', " ").into_bytes()
+            };
+            id_to_token.insert(token_id, Token(bytes.into_boxed_slice()));
+            id_to_token_string.insert(token_id, piece);
+        }
+        let mut special_token_ids = AHashSet::default();
+        for added_token in tokenizer.added_tokens {
+            if added_token.special {
+                special_token_ids.insert(added_token.id);
+            }
+            id_to_token.insert(
+                added_token.id,
+                Token(added_token.content.as_bytes().to_vec().into_boxed_slice()),
+            );
+            id_to_token_string.insert(added_token.id, added_token.content);
+        }
+        Vocabulary::with_special_tokens(id_to_token, id_to_token_string, special_token_ids)
+    }
 }
 
-impl Iterator for TokensIter<'_> {
-    type Item = TokenIterItem; // We excludes 0xFF from the token before
-    #[inline]
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.current == self.end {
-            return None;
+impl Vocabulary {
+    /// Creates a new instance of [Vocabulary] from a tiktoken `.tiktoken` file,
+    /// where each line contains a base64-encoded token and its rank, which is used as the token ID.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the `.tiktoken` file.
+    /// * `special_tokens` - A map from special tokens to their token IDs,
+    ///   since they are defined by the encoding rather than stored in the file, e.g. `<|endoftext|>`.
+    ///   They are special tokens of the created vocabulary.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`CreateVocabularyError`] when the file cannot be read, a line is malformed,
+    /// or the vocabulary cannot be created from the tokens.
+    pub fn from_tiktoken(
+        path: impl AsRef<Path>,
+        special_tokens: AHashMap<String, u32>,
+    ) -> Result<Vocabulary, CreateVocabularyError> {
+        Self::from_tiktoken_str(&std::fs::read_to_string(path)?, special_tokens)
+    }
+
+    /// Creates a new instance of [Vocabulary] from the content of a tiktoken `.tiktoken` file.
+    /// See [`Vocabulary::from_tiktoken`] for more details.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The content of the `.tiktoken` file.
+    /// * `special_tokens` - A map from special tokens to their token IDs.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`CreateVocabularyError`] when a line is malformed or the vocabulary cannot be created from the tokens.
+    pub fn from_tiktoken_str(
+        content: &str,
+        special_tokens: AHashMap<String, u32>,
+    ) -> Result<Vocabulary, CreateVocabularyError> {
+        let mut id_to_token = AHashMap::new();
+        let mut id_to_token_string = AHashMap::new();
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            let malformed =
+                || CreateVocabularyError::MalformedFile(format!("invalid line {line:?}"));
+            let (encoded, rank) = line.trim().split_once(' ').ok_or_else(malformed)?;
+            let bytes = decode_base64(encoded).ok_or_else(malformed)?;
+            let token_id = rank.trim().parse::<u32>().map_err(|_| malformed())?;
+            id_to_token_string.insert(token_id, String::from_utf8_lossy(&bytes).into_owned());
+            id_to_token.insert(token_id, Token(bytes.into_boxed_slice()));
         }
-        // SAFETY: We have checked that self.current != self.end
-        let x = unsafe { self.next_unchecked() };
-        if x == TOKEN_SEPARATOR {
-            // SAFETY: TOKEN_SEPARATOR must be followed by 3 bytes of token id and 1 byte of token length
-            let buffer = unsafe {
-                [
-                    self.next_unchecked(),
-                    self.next_unchecked(),
-                    self.next_unchecked(),
-                    0x00,
-                ]
+        let special_token_ids = special_tokens.values().copied().collect();
+        for (token, token_id) in special_tokens {
+            id_to_token.insert(
+                token_id,
+                Token(token.as_bytes().to_vec().into_boxed_slice()),
+            );
+            id_to_token_string.insert(token_id, token);
+        }
+        Vocabulary::with_special_tokens(id_to_token, id_to_token_string, special_token_ids)
+    }
+
+    /// Creates a new instance of [Vocabulary] from a SentencePiece `.model` file.
+    ///
+    /// The index of a piece is used as its token ID. `▁` is replaced with a space,
+    /// and byte pieces like `<0x0A>` are converted to the byte they represent.
+    /// Control and unknown pieces like `<s>` are included as their text and are special tokens.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the `.model` file.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`CreateVocabularyError`] when the file cannot be read or is not a valid model,
+    /// or the vocabulary cannot be created from the tokens.
+    pub fn from_sentencepiece(path: impl AsRef<Path>) -> Result<Vocabulary, CreateVocabularyError> {
+        Self::from_sentencepiece_bytes(&std::fs::read(path)?)
+    }
+
+    /// Creates a new instance of [Vocabulary] from the content of a SentencePiece `.model` file.
+    /// See [`Vocabulary::from_sentencepiece`] for more details.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The content of the `.model` file.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`CreateVocabularyError`] when the content is not a valid model
+    /// or the vocabulary cannot be created from the tokens.
+    pub fn from_sentencepiece_bytes(model: &[u8]) -> Result<Vocabulary, CreateVocabularyError> {
+        /// The field number of `ModelProto.pieces`.
+        const PIECES_FIELD: u64 = 1;
+        /// The field number of `SentencePiece.piece`.
+        const PIECE_FIELD: u64 = 1;
+        /// The field number of `SentencePiece.type`.
+        const TYPE_FIELD: u64 = 3;
+        /// The value of `SentencePiece.Type.UNKNOWN`.
+        const UNKNOWN_TYPE: u64 = 2;
+        /// The value of `SentencePiece.Type.CONTROL`.
+        const CONTROL_TYPE: u64 = 3;
+        /// The value of `SentencePiece.Type.BYTE`.
+        const BYTE_TYPE: u64 = 6;
+        let mut id_to_token = AHashMap::new();
+        let mut id_to_token_string = AHashMap::new();
+        let mut special_token_ids = AHashSet::default();
+        let mut token_id = 0u32;
+        for (field, value) in parse_protobuf(model)? {
+            let (PIECES_FIELD, ProtobufValue::Bytes(message)) = (field, value) else {
+                continue;
             };
-            self.current_token_remaining_length = unsafe { self.next_unchecked() } as usize;
-            self.current_token_id = u32::from_le_bytes(buffer) as usize;
-            Some(TokenIterItem::NewToken)
-        } else {
-            self.current_token_remaining_length -= 1;
-            // SAFETY: We excludes 0xFF from the token before
-            Some(TokenIterItem::TokenByte(unsafe {
-                NonMaxU8::new_unchecked(x)
-            }))
+            let mut piece = None;
+            let mut piece_type = None;
+            for (field, value) in parse_protobuf(message)? {
+                match (field, value) {
+                    (PIECE_FIELD, ProtobufValue::Bytes(bytes)) => {
+                        piece = Some(String::from_utf8(bytes.to_vec()).map_err(|_| {
+                            CreateVocabularyError::MalformedFile(format!(
+                                "piece {token_id} is not valid UTF-8"
+                            ))
+                        })?);
+                    }
+                    (TYPE_FIELD, ProtobufValue::Varint(value)) => piece_type = Some(value),
+                    _ => {}
+                }
+            }
+            let piece = piece.ok_or_else(|| {
+                CreateVocabularyError::MalformedFile(format!("piece {token_id} has no text"))
+            })?;
+            let bytes = if piece_type == Some(BYTE_TYPE) {
+                vec![parse_byte_fallback(&piece)
+                    .ok_or_else(|| CreateVocabularyError::InvalidToken(piece.clone()))?]
+            } else {
+                piece.replace('\u{2581}', " ").into_bytes()
+            };
+            if matches!(piece_type, Some(UNKNOWN_TYPE | CONTROL_TYPE)) {
+                special_token_ids.insert(token_id);
+            }
+            id_to_token.insert(token_id, Token(bytes.into_boxed_slice()));
+            id_to_token_string.insert(token_id, piece);
+            token_id += 1;
         }
+        Vocabulary::with_special_tokens(id_to_token, id_to_token_string, special_token_ids)
     }
 }
 
-impl TokensIter<'_> {
-    /// SAFETY: The caller must ensure that self.current != self.end
-    unsafe fn next_unchecked(&mut self) -> u8 {
-        let value = self.current.read();
-        self.current = self.current.add(1);
-        value
+/// The fields of a Hugging Face `tokenizer.json` file that are needed to recover the tokens.
+#[derive(Deserialize)]
+struct TokenizerJson {
+    model: serde_json::Value,
+    #[serde(default)]
+    added_tokens: Vec<AddedToken>,
+    #[serde(default)]
+    pre_tokenizer: serde_json::Value,
+    #[serde(default)]
+    decoder: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct AddedToken {
+    id: u32,
+    content: String,
+    #[serde(default)]
+    special: bool,
+}
+
+#[derive(Deserialize)]
+struct BpeModel {
+    vocab: AHashMap<String, u32>,
+    #[serde(default)]
+    byte_fallback: bool,
+}
+
+#[derive(Deserialize)]
+struct WordPieceModel {
+    vocab: AHashMap<String, u32>,
+    #[serde(default = "default_continuing_subword_prefix")]
+    continuing_subword_prefix: String,
+}
+
+fn default_continuing_subword_prefix() -> String {
+    "##".to_string()
+}
+
+#[derive(Deserialize)]
+struct UnigramModel {
+    vocab: Vec<(String, f64)>,
+    #[serde(default)]
+    byte_fallback: bool,
+}
+
+/// Checks whether a pre-tokenizer or a decoder, possibly nested in a sequence, is `ByteLevel`.
+fn contains_byte_level(value: &serde_json::Value) -> bool {
+    match value {
+        serde_json::Value::Object(map) => {
+            map.get("type").and_then(|x| x.as_str()) == Some("ByteLevel")
+                || map.values().any(contains_byte_level)
+        }
+        serde_json::Value::Array(values) => values.iter().any(contains_byte_level),
+        _ => false,
     }
+}
 
-    #[inline]
-    pub fn current_token_id(&self) -> usize {
-        self.current_token_id
+/// Builds the reverse of the GPT-2 byte-to-unicode mapping, where the printable bytes map to themselves
+/// and the other bytes map to the characters from U+0100 in ascending order.
+fn gpt2_unicode_to_byte() -> AHashMap<char, u8> {
+    let mut unicode_to_byte = AHashMap::with_capacity(256);
+    let mut next_char = 256;
+    for byte in 0..=u8::MAX {
+        let c = if matches!(byte, b'!'..=b'~' | 0xA1..=0xAC | 0xAE..=0xFF) {
+            byte as char
+        } else {
+            next_char += 1;
+            char::from_u32(next_char - 1).unwrap()
+        };
+        unicode_to_byte.insert(c, byte);
+    }
+    unicode_to_byte
+}
+
+/// Parses a byte fallback token like `<0x0A>`.
+fn parse_byte_fallback(piece: &str) -> Option<u8> {
+    let hex = piece.strip_prefix("<0x")?.strip_suffix('>')?;
+    if hex.len() != 2 {
+        return None;
+    }
+    u8::from_str_radix(hex, 16).ok()
+}
+
+/// Decodes standard base64 with optional padding.
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=');
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in text.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+/// A field value in the protobuf wire format.
+enum ProtobufValue<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// Parses the fields of a protobuf message into their field numbers and values.
+fn parse_protobuf(
+    mut message: &[u8],
+) -> Result<Vec<(u64, ProtobufValue<'_>)>, CreateVocabularyError> {
+    fn read_varint(message: &mut &[u8]) -> Option<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = message.split_first()?;
+            *message = rest;
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+    fn read_field<'a>(message: &mut &'a [u8]) -> Option<(u64, ProtobufValue<'a>)> {
+        let key = read_varint(message)?;
+        let value = match key & 0x7 {
+            0 => ProtobufValue::Varint(read_varint(message)?),
+            1 | 5 => {
+                let len = if key & 0x7 == 1 { 8 } else { 4 };
+                *message = message.get(len..)?;
+                ProtobufValue::Fixed
+            }
+            2 => {
+                let len = usize::try_from(read_varint(message)?).ok()?;
+                let bytes = message.get(..len)?;
+                *message = &message[len..];
+                ProtobufValue::Bytes(bytes)
+            }
+            _ => return None,
+        };
+        Some((key >> 3, value))
     }
-    #[inline]
-    pub fn next_token(&mut self) {
-        // SAFETY: current_token_remaining_length<=u8::MAX
-        self.current = unsafe { self.current.add(self.current_token_remaining_length) };
+    let mut fields = Vec::new();
+    while !message.is_empty() {
+        fields.push(read_field(&mut message).ok_or_else(|| {
+            CreateVocabularyError::MalformedFile("invalid protobuf message".to_string())
+        })?);
     }
+    Ok(fields)
 }
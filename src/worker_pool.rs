@@ -0,0 +1,118 @@
+//! This module contains the [`WorkerPool`] struct, which runs jobs on a fixed set of threads.
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::JoinHandle;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed set of threads that wait for jobs until the pool is dropped.
+///
+/// The pool is created once and shared by the clones of its owner,
+/// so that running jobs does not spawn threads on every call.
+pub(crate) struct WorkerPool {
+    /// The sender of the jobs, which is dropped first to stop the threads.
+    sender: Option<Sender<Job>>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    /// Spawns the given number of threads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a thread cannot be spawned.
+    pub(crate) fn new(threads: usize) -> Self {
+        let (sender, receiver) = channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let threads = (0..threads)
+            .map(|index| {
+                let receiver = receiver.clone();
+                std::thread::Builder::new()
+                    .name(format!("kbnf-worker-{index}"))
+                    .spawn(move || loop {
+                        // The lock is released before the job runs, so the other threads can take the next jobs.
+                        let job = receiver
+                            .lock()
+                            .unwrap_or_else(PoisonError::into_inner)
+                            .recv();
+                        match job {
+                            Ok(job) => job(),
+                            Err(_) => break,
+                        }
+                    })
+                    .expect("failed to spawn a worker thread")
+            })
+            .collect();
+        Self {
+            sender: Some(sender),
+            threads,
+        }
+    }
+
+    /// Gets the number of threads in the pool.
+    pub(crate) fn threads(&self) -> usize {
+        self.threads.len()
+    }
+
+    /// Runs the jobs on the threads and waits for all of them.
+    ///
+    /// # Returns
+    ///
+    /// The results of the jobs in the order of the jobs.
+    ///
+    /// # Panics
+    ///
+    /// Resumes the panic of a job after every job has finished. The threads survive the panic.
+    pub(crate) fn run<T, F>(&self, jobs: impl IntoIterator<Item = F>) -> Vec<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let (result_sender, result_receiver) = channel();
+        let mut results = Vec::new();
+        for (index, job) in jobs.into_iter().enumerate() {
+            let result_sender = result_sender.clone();
+            self.sender
+                .as_ref()
+                .unwrap()
+                .send(Box::new(move || {
+                    let result = catch_unwind(AssertUnwindSafe(job));
+                    // The receiver lives until every job has sent its result.
+                    let _ = result_sender.send((index, result));
+                }))
+                .expect("the worker threads only stop when the pool is dropped");
+            results.push(None);
+        }
+        drop(result_sender);
+        let mut panic = None;
+        for (index, result) in result_receiver {
+            match result {
+                Ok(result) => results[index] = Some(result),
+                Err(payload) => panic = Some(payload),
+            }
+        }
+        if let Some(payload) = panic {
+            resume_unwind(payload);
+        }
+        results.into_iter().map(Option::unwrap).collect()
+    }
+}
+
+impl std::fmt::Debug for WorkerPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WorkerPool")
+            .field("threads", &self.threads.len())
+            .finish()
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        self.sender.take();
+        for thread in self.threads.drain(..) {
+            // A job never panics its thread, so joining only waits for the thread to stop.
+            let _ = thread.join();
+        }
+    }
+}
@@ -64,6 +64,25 @@ mod tests {
         Ok(Vocabulary::new(id_to_token, id_to_token_string).unwrap())
     }
 
+    /// Creates a vocabulary whose token IDs are the indices of the tokens.
+    fn vocab_from_tokens(tokens: &[&[u8]], special_token_ids: &[u32]) -> Vocabulary {
+        let id_to_token: AHashMap<u32, Token> = tokens
+            .iter()
+            .enumerate()
+            .map(|(i, token)| (i as u32, Token(token.to_vec().into_boxed_slice())))
+            .collect();
+        let id_to_token_string = id_to_token
+            .iter()
+            .map(|(&i, token)| (i, String::from_utf8_lossy(&token.0).into_owned()))
+            .collect();
+        Vocabulary::with_special_tokens(
+            id_to_token,
+            id_to_token_string,
+            special_token_ids.iter().copied().collect(),
+        )
+        .unwrap()
+    }
+
     fn get_token_id_from_str(vocab: &Vocabulary, token: &str) -> Option<u32> {
         vocab.token_id(&Token(token.as_bytes().to_vec().into_boxed_slice()))
     }
@@ -144,6 +163,13 @@ mod tests {
             engine_config: EngineConfig {
                 cache_enabled: true,
                 compaction_enabled: false,
+                parse_tree_enabled: false,
+                cache_config: kbnf::engine::CacheConfig {
+                    max_entries: None,
+                    max_bytes: None,
+                    eviction: kbnf::engine::CacheEviction::Lru,
+                },
+                worker_threads: 1,
             },
             ..Default::default()
         };
@@ -319,6 +345,13 @@ mod tests {
             engine_config: EngineConfig {
                 cache_enabled: true,
                 compaction_enabled: true,
+                parse_tree_enabled: false,
+                cache_config: kbnf::engine::CacheConfig {
+                    max_entries: None,
+                    max_bytes: None,
+                    eviction: kbnf::engine::CacheEviction::Lru,
+                },
+                worker_threads: 1,
             },
             ..Default::default()
         };
@@ -355,6 +388,13 @@ mod tests {
             engine_config: EngineConfig {
                 cache_enabled: true,
                 compaction_enabled: true,
+                parse_tree_enabled: false,
+                cache_config: kbnf::engine::CacheConfig {
+                    max_entries: None,
+                    max_bytes: None,
+                    eviction: kbnf::engine::CacheEviction::Lru,
+                },
+                worker_threads: 1,
             },
             ..Default::default()
         };
@@ -390,6 +430,13 @@ mod tests {
             engine_config: EngineConfig {
                 cache_enabled: true,
                 compaction_enabled: true,
+                parse_tree_enabled: false,
+                cache_config: kbnf::engine::CacheConfig {
+                    max_entries: None,
+                    max_bytes: None,
+                    eviction: kbnf::engine::CacheEviction::Lru,
+                },
+                worker_threads: 1,
             },
             ..Default::default()
         };
@@ -684,4 +731,1195 @@ __schema_json_1_next ::=
             "Should reject sequence containing invalid byte 'a'"
         );
     }
+
+    #[test]
+    fn checkpoint_and_rollback() {
+        let input = "start::=C'\n';C::='c'|#'c' C;";
+        let vocab = read_rwkv_world_vocab("tests/rwkv_vocab_v20230424.json").unwrap();
+        let c = get_token_id_from_str(&vocab, "c").unwrap();
+        let newline = get_token_id_from_str(&vocab, "\n").unwrap();
+        let mut engine = kbnf::engine::Engine::new(input, vocab.clone()).unwrap();
+        engine.try_accept_new_token(c).unwrap();
+        engine.compute_allowed_token_ids();
+        let expected = engine.allowed_token_ids_from_last_computation().clone();
+        let fingerprint = engine.state_fingerprint();
+        let checkpoint = engine.checkpoint();
+        for _ in 0..5 {
+            assert_eq!(
+                engine.try_accept_new_token(c).unwrap(),
+                AcceptTokenResult::Ongoing
+            );
+        }
+        let later_checkpoint = engine.checkpoint();
+        assert_eq!(
+            engine.try_accept_new_token(newline).unwrap(),
+            AcceptTokenResult::Finished
+        );
+        engine.rollback_to(checkpoint).unwrap();
+        assert!(!engine.is_finished());
+        // The compacted Earley sets are restored as well
+        assert_eq!(engine.state_fingerprint(), fingerprint);
+        engine.compute_allowed_token_ids();
+        assert_eq!(engine.allowed_token_ids_from_last_computation(), &expected);
+        assert_eq!(
+            engine.rollback_to(later_checkpoint),
+            Err(kbnf::engine_like::RollbackError::InvalidCheckpoint)
+        );
+        // The checkpoint is still valid after rolling back to it
+        assert_eq!(
+            engine.try_accept_new_token(newline).unwrap(),
+            AcceptTokenResult::Finished
+        );
+        engine.rollback_to(checkpoint).unwrap();
+        engine.discard_checkpoint(checkpoint).unwrap();
+        assert_eq!(
+            engine.rollback_to(checkpoint),
+            Err(kbnf::engine_like::RollbackError::InvalidCheckpoint)
+        );
+        let checkpoint = engine.checkpoint();
+        engine.reset();
+        assert_eq!(
+            engine.rollback_to(checkpoint),
+            Err(kbnf::engine_like::RollbackError::InvalidCheckpoint)
+        );
+    }
+
+    #[test]
+    fn fork_and_state_fingerprint() {
+        let input = "start::=C'\n';C::='c'|#'c' C;";
+        let vocab = read_rwkv_world_vocab("tests/rwkv_vocab_v20230424.json").unwrap();
+        let c = get_token_id_from_str(&vocab, "c").unwrap();
+        let newline = get_token_id_from_str(&vocab, "\n").unwrap();
+        let mut engine = kbnf::engine::Engine::new(input, vocab.clone()).unwrap();
+        engine.try_accept_new_token(c).unwrap();
+        let mut forked = engine.fork();
+        assert_eq!(engine.state_fingerprint(), forked.state_fingerprint());
+        assert_eq!(
+            forked.try_accept_new_token(newline).unwrap(),
+            AcceptTokenResult::Finished
+        );
+        assert!(!engine.is_finished());
+        assert_ne!(engine.state_fingerprint(), forked.state_fingerprint());
+        let mut other = engine.fork();
+        engine.try_accept_new_token(c).unwrap();
+        other.try_accept_new_bytes(b"c").unwrap();
+        assert_eq!(engine.state_fingerprint(), other.state_fingerprint());
+        engine.compute_allowed_token_ids();
+        other.compute_allowed_token_ids();
+        assert_eq!(
+            engine.allowed_token_ids_from_last_computation(),
+            other.allowed_token_ids_from_last_computation()
+        );
+    }
+
+    #[test]
+    fn state_fingerprint_of_separate_engines() {
+        let input = "start::=A B;A::='x'|'yy';B::='z';";
+        let vocab = read_rwkv_world_vocab("tests/rwkv_vocab_v20230424.json").unwrap();
+        let mut engine = kbnf::engine::Engine::new(input, vocab.clone()).unwrap();
+        let mut other = kbnf::engine::Engine::new(input, vocab.clone()).unwrap();
+        assert_eq!(engine.state_fingerprint(), other.state_fingerprint());
+        engine.try_accept_new_bytes(b"x").unwrap();
+        assert_ne!(engine.state_fingerprint(), other.state_fingerprint());
+        other.try_accept_new_bytes(b"y").unwrap();
+        assert_ne!(engine.state_fingerprint(), other.state_fingerprint());
+        other.try_accept_new_bytes(b"y").unwrap();
+        assert_eq!(engine.state_fingerprint(), other.state_fingerprint());
+        engine.try_accept_new_bytes(b"z").unwrap();
+        other.try_accept_new_bytes(b"z").unwrap();
+        assert!(engine.is_finished());
+        assert_eq!(engine.state_fingerprint(), other.state_fingerprint());
+    }
+
+    #[test]
+    fn verify_draft_tokens() {
+        let input = "start::=C'\n';C::='c'|#'c' C;";
+        let vocab = read_rwkv_world_vocab("tests/rwkv_vocab_v20230424.json").unwrap();
+        let c = get_token_id_from_str(&vocab, "c").unwrap();
+        let b = get_token_id_from_str(&vocab, "b").unwrap();
+        let newline = get_token_id_from_str(&vocab, "\n").unwrap();
+        let mut engine = kbnf::engine::Engine::new(input, vocab.clone()).unwrap();
+        let mut reference = engine.clone();
+        let (accepted, masks) = engine.verify_tokens(&[c, c, b, c]);
+        assert_eq!(accepted, 2);
+        // The state after the second `c` is the one after the first, so its mask is not computed again.
+        let stats = engine.cache_stats();
+        assert_eq!((stats.hits, stats.misses), (0, 2));
+        assert_eq!(masks.len(), 3);
+        for (i, mask) in masks.iter().enumerate() {
+            if i > 0 {
+                reference.try_accept_new_token(c).unwrap();
+            }
+            reference.compute_allowed_token_ids();
+            assert_eq!(mask, reference.allowed_token_ids_from_last_computation());
+        }
+        assert_eq!(engine.allowed_token_ids_from_last_computation(), &masks[2]);
+        let (accepted, masks) = engine.verify_tokens(&[newline, c]);
+        assert_eq!(accepted, 1);
+        assert!(engine.is_finished());
+        assert!(masks[1].is_clear());
+    }
+
+    #[test]
+    fn forced_bytes() {
+        let input = "start::='{\"name\": '#'[a-z]+''}';";
+        let vocab = read_rwkv_world_vocab("tests/rwkv_vocab_v20230424.json").unwrap();
+        let mut engine = kbnf::engine::Engine::new(input, vocab.clone()).unwrap();
+        let forced = engine.compute_forced_bytes(usize::MAX);
+        assert_eq!(forced, b"{\"name\": ");
+        assert_eq!(engine.compute_forced_bytes(3), b"{\"n");
+        let token_ids = vocab.tokenize_greedily(&forced).unwrap();
+        for token_id in token_ids {
+            engine.try_accept_new_token(token_id).unwrap();
+        }
+        assert!(engine.compute_forced_bytes(usize::MAX).is_empty());
+        engine.try_accept_new_bytes(b"kbnf}").unwrap();
+        assert!(engine.compute_forced_bytes(usize::MAX).is_empty());
+        // A pending special token is an alternative to the only allowed byte.
+        let tokens: [&[u8]; 2] = [b"a", b"<|im_end|>"];
+        let mut engine = kbnf::engine::Engine::new(
+            "start::=#token'<|im_end|>'|'a';",
+            vocab_from_tokens(&tokens, &[1]),
+        )
+        .unwrap();
+        assert_eq!(engine.compute_allowed_next_bytes(), b"a");
+        assert!(engine.compute_forced_bytes(usize::MAX).is_empty());
+    }
+
+    #[test]
+    fn shortest_completion() {
+        let vocab = read_rwkv_world_vocab("tests/rwkv_vocab_v20230424.json").unwrap();
+        let mut engine =
+            kbnf::engine::Engine::new("start::='['start']'|'a';", vocab.clone()).unwrap();
+        assert_eq!(engine.shortest_completion().unwrap(), b"a");
+        engine.try_accept_new_bytes(b"[[").unwrap();
+        let completion = engine.shortest_completion().unwrap();
+        assert_eq!(completion, b"a]]");
+        // The engine's states are not updated.
+        assert_eq!(engine.shortest_completion().unwrap(), b"a]]");
+        assert_eq!(
+            engine.try_accept_new_bytes(&completion).unwrap(),
+            AcceptTokenResult::Finished
+        );
+        assert!(engine.shortest_completion().unwrap().is_empty());
+        let mut engine =
+            kbnf::engine::Engine::new("start::='{\"name\": '#'[a-z]+''}';", vocab.clone()).unwrap();
+        assert_eq!(engine.shortest_completion().unwrap(), b"{\"name\": a}");
+        engine.try_accept_new_bytes(b"{\"na").unwrap();
+        assert_eq!(engine.shortest_completion().unwrap(), b"me\": a}");
+        let mut engine =
+            kbnf::engine::Engine::new("start::=#substrs'abcbc''\n';", vocab.clone()).unwrap();
+        // The empty substring is a substring.
+        assert_eq!(engine.shortest_completion().unwrap(), b"\n");
+        engine.try_accept_new_bytes(b"abcbc").unwrap();
+        assert_eq!(engine.shortest_completion().unwrap(), b"\n");
+        let mut engine = kbnf::engine::Engine::new("start::=#substrs'cab';", vocab).unwrap();
+        assert_eq!(engine.shortest_completion().unwrap(), b"a");
+    }
+
+    #[test]
+    fn parse_tree() {
+        let input = "start::=C'\n';C::='c'|'c' C;";
+        let vocab = read_rwkv_world_vocab("tests/rwkv_vocab_v20230424.json").unwrap();
+        let config = kbnf::config::Config {
+            engine_config: EngineConfig {
+                cache_enabled: true,
+                compaction_enabled: true,
+                parse_tree_enabled: true,
+                cache_config: kbnf::engine::CacheConfig {
+                    max_entries: None,
+                    max_bytes: None,
+                    eviction: kbnf::engine::CacheEviction::Lru,
+                },
+                worker_threads: 1,
+            },
+            ..Default::default()
+        };
+        let mut engine = kbnf::engine::Engine::with_config(input, vocab.clone(), config).unwrap();
+        engine.try_accept_new_bytes(b"ccc").unwrap();
+        assert_eq!(engine.parse_tree(), None);
+        engine.try_accept_new_bytes(b"\n").unwrap();
+        let tree = engine.parse_tree().unwrap();
+        assert_eq!(
+            (tree.nonterminal.as_str(), tree.start, tree.end),
+            ("start", 0, 4)
+        );
+        let mut node = &tree.children[0];
+        for start in 0..3 {
+            assert_eq!(
+                (node.nonterminal.as_str(), node.start, node.end),
+                ("C", start, 3)
+            );
+            if start < 2 {
+                node = &node.children[0];
+            } else {
+                assert!(node.children.is_empty());
+            }
+        }
+        let mut engine = kbnf::engine::Engine::new(input, vocab.clone()).unwrap();
+        engine.try_accept_new_bytes(b"c\n").unwrap();
+        assert_eq!(engine.parse_tree(), None);
+    }
+
+    #[test]
+    fn right_recursion_parse_tree() {
+        fn flatten(tree: &kbnf::parse_tree::ParseTree, nodes: &mut Vec<(String, usize, usize)>) {
+            nodes.push((tree.nonterminal.clone(), tree.start, tree.end));
+            for child in tree.children.iter() {
+                flatten(child, nodes);
+            }
+        }
+        let input = "start::=A'\n';A::=#'[0-9]+'|#'[0-9]+' B;B::=','|',' A;";
+        let vocab = read_rwkv_world_vocab("tests/rwkv_vocab_v20230424.json").unwrap();
+        for compaction_enabled in [true, false] {
+            let mut config = kbnf::config::Config::default();
+            config.engine_config.compaction_enabled = compaction_enabled;
+            config.engine_config.parse_tree_enabled = true;
+            let mut engine =
+                kbnf::engine::Engine::with_config(input, vocab.clone(), config).unwrap();
+            engine.try_accept_new_bytes(b"12,3").unwrap();
+            // A rejected token leaves no back-pointers behind.
+            assert!(engine.try_accept_new_bytes(b",x").is_err());
+            engine.try_accept_new_bytes(b",\n").unwrap();
+            let mut nodes = Vec::new();
+            flatten(&engine.parse_tree().unwrap(), &mut nodes);
+            let node = |nonterminal: &str, start, end| (nonterminal.to_string(), start, end);
+            assert_eq!(
+                nodes,
+                vec![
+                    node("start", 0, 6),
+                    node("A", 0, 5),
+                    node("B", 2, 5),
+                    node("A", 3, 5),
+                    node("B", 4, 5),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn long_right_recursion_parse_tree() {
+        let input = "start::=A'\n';A::='a'|'a,' A;";
+        let vocab = read_rwkv_world_vocab("tests/rwkv_vocab_v20230424.json").unwrap();
+        let mut config = kbnf::config::Config::default();
+        config.engine_config.parse_tree_enabled = true;
+        let mut engine = kbnf::engine::Engine::with_config(input, vocab, config).unwrap();
+        let items = 100_000;
+        let mut bytes = "a,".repeat(items - 1).into_bytes();
+        bytes.extend_from_slice(b"a\n");
+        assert_eq!(
+            engine.try_accept_new_bytes(&bytes).unwrap(),
+            AcceptTokenResult::Finished
+        );
+        let tree = engine.parse_tree().unwrap();
+        let mut node = &tree.children[0];
+        for item in 0..items {
+            assert_eq!(
+                (node.nonterminal.as_str(), node.start, node.end),
+                ("A", item * 2, bytes.len() - 1)
+            );
+            match node.children.first() {
+                Some(child) => node = child,
+                None => assert_eq!(item, items - 1),
+            }
+        }
+    }
+
+    #[test]
+    fn nonterminal_events() {
+        use kbnf::engine_like::{NonterminalEventKind, SubscribeNonterminalError};
+        let input = "start::=C'\n';C::='c'|'c' C;";
+        let vocab = read_rwkv_world_vocab("tests/rwkv_vocab_v20230424.json").unwrap();
+        let mut engine = kbnf::engine::Engine::new(input, vocab.clone()).unwrap();
+        assert_eq!(
+            engine.subscribe_nonterminal("D"),
+            Err(SubscribeNonterminalError::UnknownNonterminal)
+        );
+        engine.subscribe_nonterminal("C").unwrap();
+        assert!(engine.try_accept_new_bytes(b"cx").is_err());
+        assert!(engine.drain_nonterminal_events().is_empty());
+        engine.try_accept_new_bytes(b"ccc\n").unwrap();
+        let events: Vec<_> = engine
+            .drain_nonterminal_events()
+            .into_iter()
+            .map(|event| (event.kind, event.nonterminal, event.start, event.end))
+            .collect();
+        let started = |start, end| (NonterminalEventKind::Started, "C".to_string(), start, end);
+        let completed = |start, end| (NonterminalEventKind::Completed, "C".to_string(), start, end);
+        assert_eq!(
+            events,
+            vec![
+                started(0, 1),
+                completed(0, 1),
+                started(1, 2),
+                completed(1, 2),
+                completed(0, 2),
+                started(2, 3),
+                completed(2, 3),
+                completed(1, 3),
+                completed(0, 3),
+            ]
+        );
+        assert!(engine.drain_nonterminal_events().is_empty());
+    }
+
+    #[test]
+    fn right_recursion_nonterminal_events() {
+        use kbnf::engine_like::NonterminalEventKind;
+        let input = "start::=A'\n';A::='a'|'a' B;B::='b'|'b' A;";
+        let vocab = read_rwkv_world_vocab("tests/rwkv_vocab_v20230424.json").unwrap();
+        for compaction_enabled in [true, false] {
+            let mut config = kbnf::config::Config::default();
+            config.engine_config.compaction_enabled = compaction_enabled;
+            let mut engine =
+                kbnf::engine::Engine::with_config(input, vocab.clone(), config).unwrap();
+            engine.subscribe_nonterminal("A").unwrap();
+            engine.subscribe_nonterminal("B").unwrap();
+            engine.try_accept_new_bytes(b"abab").unwrap();
+            // Every nonterminal folded by the Leo items completes with the innermost one.
+            let completed: Vec<_> = engine
+                .drain_nonterminal_events()
+                .into_iter()
+                .filter(|event| event.kind == NonterminalEventKind::Completed)
+                .map(|event| (event.nonterminal, event.start, event.end))
+                .collect();
+            let a = |start, end| ("A".to_string(), start, end);
+            let b = |start, end| ("B".to_string(), start, end);
+            assert_eq!(
+                completed,
+                vec![
+                    a(0, 1),
+                    b(1, 2),
+                    a(0, 2),
+                    a(2, 3),
+                    b(1, 3),
+                    a(0, 3),
+                    b(3, 4),
+                    a(2, 4),
+                    b(1, 4),
+                    a(0, 4),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn rejected_token() {
+        let input = "start::=#e'[0-9]+'#substrs'abcbc'C;C::='\n'|'c' C;";
+        let vocab = read_rwkv_world_vocab("tests/rwkv_vocab_v20230424.json").unwrap();
+        let mut engine = kbnf::engine::Engine::new(input, vocab.clone()).unwrap();
+        engine.try_accept_new_bytes(b"1").unwrap();
+        let fingerprint = engine.state_fingerprint();
+        engine.compute_allowed_token_ids();
+        let allowed = engine.allowed_token_ids_from_last_computation().clone();
+        // The bytes accepted before the rejected one are undone as well, even after compaction
+        assert!(engine.try_accept_new_bytes(b"aa").is_err());
+        assert_eq!(engine.state_fingerprint(), fingerprint);
+        engine.compute_allowed_token_ids();
+        assert_eq!(engine.allowed_token_ids_from_last_computation(), &allowed);
+        assert_eq!(
+            engine.try_accept_new_bytes(b"ab").unwrap(),
+            AcceptTokenResult::Ongoing
+        );
+    }
+
+    #[test]
+    fn rejection_diagnostic() {
+        use kbnf::engine_like::AcceptTokenDiagnosticError;
+        let input = "start::=C'\n';C::='c'|'c' C;";
+        let vocab = read_rwkv_world_vocab("tests/rwkv_vocab_v20230424.json").unwrap();
+        let mut engine = kbnf::engine::Engine::new(input, vocab.clone()).unwrap();
+        assert_eq!(
+            engine.try_accept_new_token_with_diagnostic(u32::MAX),
+            Err(AcceptTokenDiagnosticError::UnknownTokenID)
+        );
+        let diagnostic = match engine.try_accept_new_bytes_with_diagnostic(b"ccx") {
+            Err(AcceptTokenDiagnosticError::Rejected(diagnostic)) => diagnostic,
+            result => panic!("unexpected result: {:?}", result),
+        };
+        assert_eq!((diagnostic.offset, diagnostic.byte), (2, b'x'));
+        assert!(diagnostic
+            .expected
+            .iter()
+            .any(|x| x.symbol.starts_with("\"\n\"") && x.rule.starts_with("start")));
+        assert!(diagnostic
+            .expected
+            .iter()
+            .any(|x| x.symbol.starts_with("\"c\"") && x.rule.starts_with("C")));
+        assert!(diagnostic
+            .to_string()
+            .starts_with("The byte 'x' at offset 2 is rejected."));
+        let diagnostic = match engine.try_accept_new_bytes_with_diagnostic("cé".as_bytes()) {
+            Err(AcceptTokenDiagnosticError::Rejected(diagnostic)) => diagnostic,
+            result => panic!("unexpected result: {:?}", result),
+        };
+        assert!(diagnostic
+            .to_string()
+            .starts_with("The byte '\\xc3' at offset 1 is rejected."));
+        assert_eq!(
+            engine.try_accept_new_bytes_with_diagnostic(b"cc\n"),
+            Ok(AcceptTokenResult::Finished)
+        );
+    }
+
+    #[test]
+    fn allowed_next_bytes_and_expected_symbols() {
+        let input = "start::=C'\n';C::='c'|'c' C;";
+        let vocab = read_rwkv_world_vocab("tests/rwkv_vocab_v20230424.json").unwrap();
+        let mut engine = kbnf::engine::Engine::new(input, vocab.clone()).unwrap();
+        assert_eq!(engine.compute_allowed_next_bytes(), b"c");
+        engine.try_accept_new_bytes(b"c").unwrap();
+        assert_eq!(engine.compute_allowed_next_bytes(), b"\nc");
+        let expected = engine.expected_symbols();
+        assert!(expected
+            .iter()
+            .any(|x| x.symbol.starts_with("C[") && x.rule.starts_with("C")));
+        assert!(expected
+            .iter()
+            .any(|x| x.symbol.starts_with("\"\n\"") && x.rule.starts_with("start")));
+        engine.try_accept_new_bytes(b"\n").unwrap();
+        assert!(engine.compute_allowed_next_bytes().is_empty());
+        assert!(engine.expected_symbols().is_empty());
+    }
+
+    #[test]
+    fn bounded_cache() {
+        let input = "start::=C'\n';C::='c'|'c' C;";
+        let vocab = read_rwkv_world_vocab("tests/rwkv_vocab_v20230424.json").unwrap();
+        let mut config = kbnf::config::Config::default();
+        config.engine_config.cache_config.max_entries = Some(1);
+        let mut engine = kbnf::engine::Engine::with_config(input, vocab.clone(), config).unwrap();
+        engine.compute_allowed_token_ids();
+        engine.try_accept_new_bytes(b"c").unwrap();
+        engine.compute_allowed_token_ids();
+        let stats = engine.cache_stats();
+        assert_eq!(stats.misses, 2);
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(stats.entries, 1);
+        assert!(stats.bytes > 0);
+        engine.reset();
+        engine.compute_allowed_token_ids();
+        let stats = engine.cache_stats();
+        assert_eq!(stats.misses, 3);
+        assert_eq!(stats.evictions, 2);
+        engine.compute_allowed_token_ids();
+        let stats = engine.cache_stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.entries, 1);
+        engine.clear_cache();
+        let stats = engine.cache_stats();
+        assert_eq!(stats.entries, 0);
+        assert_eq!(stats.bytes, 0);
+        assert_eq!(stats.hits, 1);
+    }
+
+    #[test]
+    fn cache_eviction_order() {
+        let input = "start::='a''b''c''d';";
+        let vocab = read_rwkv_world_vocab("tests/rwkv_vocab_v20230424.json").unwrap();
+        // The initial state is used the most frequently, while the state after `a` is used the most recently.
+        for (eviction, evicted) in [
+            (kbnf::engine::CacheEviction::Lru, &b""[..]),
+            (kbnf::engine::CacheEviction::Lfu, b"ab"),
+        ] {
+            let mut config = kbnf::config::Config::default();
+            config.engine_config.cache_config.max_entries = Some(2);
+            config.engine_config.cache_config.eviction = eviction;
+            let mut engine =
+                kbnf::engine::Engine::with_config(input, vocab.clone(), config).unwrap();
+            let mut compute = |bytes: &[u8]| {
+                engine.reset();
+                engine.try_accept_new_bytes(bytes).unwrap();
+                engine.compute_allowed_token_ids();
+                engine.cache_stats()
+            };
+            for bytes in [&b""[..], b"", b"", b"a", b"a"] {
+                compute(bytes);
+            }
+            let stats = compute(b"ab");
+            assert_eq!((stats.hits, stats.misses, stats.evictions), (3, 3, 1));
+            for bytes in [&b"a"[..], b"", b"ab"] {
+                if bytes != evicted {
+                    assert_eq!(compute(bytes).misses, 3);
+                }
+            }
+            assert_eq!(compute(evicted).misses, 4);
+        }
+    }
+
+    #[test]
+    fn position_independent_cache() {
+        let input = "start::=X X;X::=#'[a-z]+'',';";
+        let vocab = read_rwkv_world_vocab("tests/rwkv_vocab_v20230424.json").unwrap();
+        let mut engine = kbnf::engine::Engine::new(input, vocab.clone()).unwrap();
+        engine.try_accept_new_bytes(b"ab,").unwrap();
+        engine.compute_allowed_token_ids();
+        let allowed = engine.allowed_token_ids_from_last_computation().clone();
+        assert_eq!(engine.cache_stats().misses, 1);
+        engine.reset();
+        // The same state reached at a different offset is looked up by the same signature.
+        engine.try_accept_new_bytes(b"abcd,").unwrap();
+        engine.compute_allowed_token_ids();
+        assert_eq!(engine.cache_stats().hits, 1);
+        assert_eq!(engine.cache_stats().entries, 1);
+        assert_eq!(engine.allowed_token_ids_from_last_computation(), &allowed);
+        // The first and the second X are completed in different contexts.
+        engine.reset();
+        engine.try_accept_new_bytes(b"ab").unwrap();
+        engine.compute_allowed_token_ids();
+        engine.try_accept_new_bytes(b",ab").unwrap();
+        engine.compute_allowed_token_ids();
+        assert_eq!(engine.cache_stats().hits, 1);
+        assert_eq!(engine.cache_stats().entries, 3);
+    }
+
+    #[test]
+    fn shared_cache() {
+        let input = "start::=C'\n';C::='c'|'c' C;";
+        let vocab = read_rwkv_world_vocab("tests/rwkv_vocab_v20230424.json").unwrap();
+        let mut engine = kbnf::engine::Engine::new(input, vocab.clone()).unwrap();
+        let mut cloned = engine.clone();
+        engine.compute_allowed_token_ids();
+        assert_eq!(cloned.cache_stats().entries, 1);
+        cloned.compute_allowed_token_ids();
+        assert_eq!(engine.cache_stats().hits, 1);
+        assert_eq!(
+            engine.allowed_token_ids_from_last_computation(),
+            cloned.allowed_token_ids_from_last_computation()
+        );
+        // Engines created separately from identical grammars and vocabularies can share the cache.
+        let mut other = kbnf::engine::Engine::new(input, vocab.clone()).unwrap();
+        assert_eq!(other.share_cache_with(&engine), Ok(()));
+        assert_eq!(other.cache_stats().entries, 1);
+        other.compute_allowed_token_ids();
+        assert_eq!(engine.cache_stats().hits, 2);
+        let mut different = kbnf::engine::Engine::new("start::=C'\n';C::='c';", vocab).unwrap();
+        assert_eq!(
+            different.share_cache_with(&engine),
+            Err(kbnf::engine_like::ShareCacheError::IncompatibleGrammar)
+        );
+        let mut different =
+            kbnf::engine::Engine::new(input, vocab_from_tokens(&[b"c", b"\n"], &[])).unwrap();
+        assert_eq!(
+            different.share_cache_with(&engine),
+            Err(kbnf::engine_like::ShareCacheError::IncompatibleVocabulary)
+        );
+        assert_eq!(different.cache_stats().entries, 0);
+        let mut forked = engine.fork();
+        forked.clear_cache();
+        assert_eq!(forked.share_cache_with(&engine), Ok(()));
+        assert_eq!(cloned.cache_stats().entries, 0);
+    }
+
+    #[test]
+    fn exported_cache() {
+        let input = "start::=C'\n';C::='c'|'c' C;";
+        let vocab = read_rwkv_world_vocab("tests/rwkv_vocab_v20230424.json").unwrap();
+        let mut engine = kbnf::engine::Engine::new(input, vocab.clone()).unwrap();
+        engine.compute_allowed_token_ids();
+        engine.try_accept_new_bytes(b"c").unwrap();
+        engine.compute_allowed_token_ids();
+        let entries = engine.cache_stats().entries;
+        let exported = engine.export_cache();
+        let mut imported = kbnf::engine::Engine::new(input, vocab.clone()).unwrap();
+        assert_eq!(imported.import_cache(&exported), Ok(entries));
+        assert_eq!(imported.cache_stats().entries, entries);
+        imported.try_accept_new_bytes(b"c").unwrap();
+        imported.compute_allowed_token_ids();
+        assert_eq!(imported.cache_stats().hits, 1);
+        assert_eq!(
+            imported.allowed_token_ids_from_last_computation(),
+            engine.allowed_token_ids_from_last_computation()
+        );
+        let mut other = kbnf::engine::Engine::new("start::='c';", vocab.clone()).unwrap();
+        assert_eq!(
+            other.import_cache(&exported),
+            Err(kbnf::engine_like::ImportCacheError::IncompatibleGrammar)
+        );
+        assert_eq!(other.cache_stats().entries, 0);
+        let mut warmed = kbnf::engine::Engine::new(input, vocab.clone()).unwrap();
+        assert_eq!(
+            warmed.import_cache(&exported[..exported.len() - 1]),
+            Err(kbnf::engine_like::ImportCacheError::InvalidFormat)
+        );
+        assert_eq!(warmed.cache_stats().entries, 0);
+        assert_eq!(warmed.warm_up(&[b"cc\n", b"x"]), 1);
+        assert!(warmed.cache_stats().entries > 0);
+        assert!(!warmed.is_finished());
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_allowed_token_ids() {
+        let input = "start::=#'[a-z ]+'C;C::='\n'|'c' C;";
+        let vocab = read_rwkv_world_vocab("tests/rwkv_vocab_v20230424.json").unwrap();
+        let mut serial = kbnf::engine::Engine::new(input, vocab.clone()).unwrap();
+        let mut config = kbnf::config::Config::default();
+        config.engine_config.worker_threads = 4;
+        let mut parallel = kbnf::engine::Engine::with_config(input, vocab.clone(), config).unwrap();
+        for bytes in [&b"ab"[..], b" c", b"c"] {
+            serial.compute_allowed_token_ids();
+            parallel.compute_allowed_token_ids();
+            assert_eq!(
+                serial.allowed_token_ids_from_last_computation(),
+                parallel.allowed_token_ids_from_last_computation()
+            );
+            // The fork computes on the threads shared with the engine.
+            let mut forked = parallel.fork();
+            forked.compute_allowed_token_ids();
+            assert_eq!(
+                serial.allowed_token_ids_from_last_computation(),
+                forked.allowed_token_ids_from_last_computation()
+            );
+            serial.try_accept_new_bytes(bytes).unwrap();
+            parallel.try_accept_new_bytes(bytes).unwrap();
+        }
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    #[test]
+    fn worker_threads_without_parallel_feature() {
+        use kbnf::engine::CreateEngineError;
+        use kbnf::engine_base::CreateEngineBaseError;
+        let vocab = read_rwkv_world_vocab("tests/rwkv_vocab_v20230424.json").unwrap();
+        let mut config = kbnf::config::Config::default();
+        config.engine_config.worker_threads = 4;
+        assert!(matches!(
+            kbnf::engine::Engine::with_config("start::='a';", vocab, config),
+            Err(CreateEngineError::EngineBaseError(
+                CreateEngineBaseError::ParallelFeatureDisabled(4)
+            ))
+        ));
+        assert_eq!(
+            kbnf::engine_batch::EngineBatch::new(Vec::new(), 4).unwrap_err(),
+            kbnf::engine_batch::EngineBatchError::ParallelFeatureDisabled
+        );
+    }
+
+    #[test]
+    fn engine_batch() {
+        let vocab = read_rwkv_world_vocab("tests/rwkv_vocab_v20230424.json").unwrap();
+        let engines = vec![
+            kbnf::engine::Engine::new("start::='a' 'b';", vocab.clone()).unwrap(),
+            kbnf::engine::Engine::new("start::='c'|'d';", vocab.clone()).unwrap(),
+        ];
+        let worker_threads = if cfg!(feature = "parallel") { 2 } else { 1 };
+        let mut batch = kbnf::engine_batch::EngineBatch::new(engines, worker_threads).unwrap();
+        let a = get_token_id_from_str(&vocab, "a").unwrap();
+        let c = get_token_id_from_str(&vocab, "c").unwrap();
+        assert_eq!(
+            batch.try_accept_new_tokens(&[a]),
+            Err(kbnf::engine_batch::EngineBatchError::InvalidBatchSize)
+        );
+        assert_eq!(
+            batch.try_accept_new_tokens(&[a, a]).unwrap(),
+            vec![
+                Ok(AcceptTokenResult::Ongoing),
+                Err(kbnf::engine_like::AcceptTokenError::Rejected)
+            ]
+        );
+        batch.compute_allowed_token_ids();
+        let vocab_size = batch.vocab_size();
+        let mut logits = vec![0.0; 2 * vocab_size];
+        batch.mask_logits(&mut logits).unwrap();
+        let b = get_token_id_from_str(&vocab, "b").unwrap() as usize;
+        assert_eq!(logits[b], 0.0);
+        assert_eq!(logits[c as usize], f32::NEG_INFINITY);
+        assert_eq!(logits[vocab_size + c as usize], 0.0);
+        assert_eq!(logits[vocab_size + b], f32::NEG_INFINITY);
+        let row_len = vocab_size.div_ceil(32);
+        let mut bitmask = vec![0; 2 * row_len];
+        batch.write_bitmask(&mut bitmask).unwrap();
+        for (i, engine) in batch.engines().iter().enumerate() {
+            let allowed = engine.allowed_token_ids_from_last_computation();
+            for token_id in 0..vocab_size {
+                let bit = bitmask[i * row_len + token_id / 32] >> (token_id % 32) & 1;
+                assert_eq!(bit == 1, allowed.contains(token_id));
+            }
+        }
+        assert_eq!(
+            batch.mask_logits(&mut logits[1..]),
+            Err(kbnf::engine_like::MaskLogitsError::InvalidLogitsLength)
+        );
+        // The vocabularies have the same size but different tokens.
+        let engines = vec![
+            kbnf::engine::Engine::new("start::='a';", vocab_from_tokens(&[b"a", b"b"], &[]))
+                .unwrap(),
+            kbnf::engine::Engine::new("start::='a';", vocab_from_tokens(&[b"a", b"c"], &[]))
+                .unwrap(),
+        ];
+        assert_eq!(
+            kbnf::engine_batch::EngineBatch::new(engines, 1).unwrap_err(),
+            kbnf::engine_batch::EngineBatchError::IncompatibleVocabulary
+        );
+    }
+    #[test]
+    fn token_trie() {
+        let tokens: [&[u8]; 8] = [b"a", b"aa", b"aab", b"ab", b"b", b"a\xFF", b"\xFFa", b"aa"];
+        let vocab = vocab_from_tokens(&tokens, &[]);
+        let mut engine = kbnf::engine::Engine::new("start::=#'a+b';", vocab).unwrap();
+        engine.compute_allowed_token_ids();
+        assert_eq!(
+            engine
+                .allowed_token_ids_from_last_computation()
+                .ones()
+                .collect::<Vec<_>>(),
+            vec![0, 1, 2, 3, 7]
+        );
+        engine.try_accept_new_token(1).unwrap();
+        engine.compute_allowed_token_ids();
+        assert_eq!(
+            engine
+                .allowed_token_ids_from_last_computation()
+                .ones()
+                .collect::<Vec<_>>(),
+            vec![0, 1, 2, 3, 4, 7]
+        );
+    }
+    #[test]
+    fn long_tokens() {
+        let long_token = vec![b' '; 300];
+        let tokens: [&[u8]; 4] = [b" ", &long_token, &long_token[..299], b"a"];
+        let vocab = vocab_from_tokens(&tokens, &[]);
+        assert_eq!(
+            vocab.tokenize_greedily(&[b' '; 602]),
+            Some(vec![1, 1, 0, 0])
+        );
+        assert_eq!(vocab.tokenize_greedily(b"  a"), Some(vec![0, 0, 3]));
+        assert_eq!(vocab.tokenize_greedily(b"b"), None);
+        let mut engine = kbnf::engine::Engine::new("start::=#' +''\n';", vocab).unwrap();
+        engine.compute_allowed_token_ids();
+        assert_eq!(
+            engine
+                .allowed_token_ids_from_last_computation()
+                .ones()
+                .collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+        assert_eq!(
+            engine.try_accept_new_token(1).unwrap(),
+            AcceptTokenResult::Ongoing
+        );
+    }
+    #[test]
+    fn token_classification() {
+        let input = "start::=#'[a-z ]+'C|#e'[0-9]+'#substrs'abcbc'C;C::='\n'|'c' C;";
+        let vocab = read_rwkv_world_vocab("tests/rwkv_vocab_v20230424.json").unwrap();
+        // Every regex and substrings state that accepts a token is classified.
+        let mut config = kbnf::config::Config::default();
+        config
+            .regex_config
+            .min_tokens_required_for_eager_regex_cache = Some(1);
+        let mut classified =
+            kbnf::engine::Engine::with_config(input, vocab.clone(), config.clone()).unwrap();
+        config
+            .regex_config
+            .min_tokens_required_for_eager_regex_cache = None;
+        let mut simulated =
+            kbnf::engine::Engine::with_config(input, vocab.clone(), config).unwrap();
+        for bytes in [&b"ab"[..], b" c", b"c"] {
+            classified.compute_allowed_token_ids();
+            simulated.compute_allowed_token_ids();
+            assert_eq!(
+                classified.allowed_token_ids_from_last_computation(),
+                simulated.allowed_token_ids_from_last_computation()
+            );
+            classified.try_accept_new_bytes(bytes).unwrap();
+            simulated.try_accept_new_bytes(bytes).unwrap();
+        }
+        classified.reset();
+        for bytes in [&b"1"[..], b"b", b"cbc"] {
+            classified.compute_allowed_token_ids();
+            let allowed = classified.allowed_token_ids_from_last_computation().clone();
+            let mut engine = classified.clone();
+            for token_id in 0..vocab.vocab_size() as u32 {
+                let accepted = engine.try_accept_new_token(token_id).is_ok();
+                if accepted {
+                    engine = classified.clone();
+                }
+                assert_eq!(accepted, allowed.contains(token_id as usize));
+            }
+            classified.try_accept_new_bytes(bytes).unwrap();
+        }
+    }
+
+    #[test]
+    fn regex_backends() {
+        use kbnf::config::Fsa;
+        let input = "start::=#'[a-z ]+'C|#e'[0-9]+'C|#ex'abc'C|#'(de)*'#substrs'abcbc'C;C::='\n'|'c' C;";
+        let vocab = read_rwkv_world_vocab("tests/rwkv_vocab_v20230424.json").unwrap();
+        let mut config = kbnf::config::Config::default();
+        config
+            .regex_config
+            .min_tokens_required_for_eager_regex_cache = Some(1);
+        let mut dfa =
+            kbnf::engine::Engine::with_config(input, vocab.clone(), config.clone()).unwrap();
+        for fsa_type in [Fsa::SparseDfa, Fsa::LazyDfa] {
+            config.regex_config.fsa_type = fsa_type;
+            let engine =
+                kbnf::engine::Engine::with_config(input, vocab.clone(), config.clone()).unwrap();
+            // A compiled grammar keeps the backend.
+            let compiled = kbnf::engine::Engine::from_compiled(
+                &engine.to_compiled().unwrap(),
+                vocab.clone(),
+                config.clone(),
+            )
+            .unwrap();
+            for mut engine in [engine, compiled] {
+                dfa.reset();
+                for bytes in [&b"ab"[..], b"x", b" c", b"c", b"\n"] {
+                    dfa.compute_allowed_token_ids();
+                    engine.compute_allowed_token_ids();
+                    assert_eq!(
+                        engine.allowed_token_ids_from_last_computation(),
+                        dfa.allowed_token_ids_from_last_computation()
+                    );
+                    assert_eq!(
+                        engine.try_accept_new_bytes(bytes),
+                        dfa.try_accept_new_bytes(bytes)
+                    );
+                }
+                for input in [&b"12c\n"[..], b"xyzab\n", b"dedeab\n", b"abcd"] {
+                    dfa.reset();
+                    engine.reset();
+                    for &byte in input {
+                        assert_eq!(
+                            engine.try_accept_new_bytes(&[byte]),
+                            dfa.try_accept_new_bytes(&[byte])
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lazy_dfa_exponential_regex() {
+        use kbnf::config::Fsa;
+        // The dense DFA of the regex has millions of states.
+        let input = "start::=#'(a|b)*a(a|b){20}' '\n';";
+        let vocab = read_rwkv_world_vocab("tests/rwkv_vocab_v20230424.json").unwrap();
+        let mut config = kbnf::config::Config::default();
+        config.regex_config.fsa_type = Fsa::LazyDfa;
+        config.regex_config.max_memory_usage = Some(1 << 20);
+        let mut engine = kbnf::engine::Engine::with_config(input, vocab.clone(), config).unwrap();
+        let matched = format!("ba{}\n", "ab".repeat(10));
+        assert_eq!(
+            engine.try_accept_new_bytes(matched.as_bytes()).unwrap(),
+            AcceptTokenResult::Finished
+        );
+        engine.reset();
+        engine.try_accept_new_bytes("b".repeat(21).as_bytes()).unwrap();
+        engine.compute_allowed_token_ids();
+        assert!(engine.try_accept_new_bytes(b"\n").is_err());
+    }
+
+    #[test]
+    fn compiled_grammar() {
+        use kbnf::compiled_grammar::CompiledGrammarError;
+        use kbnf::engine::CreateEngineError;
+        let input = "start::=#'[a-z ]+'C|#e'[0-9]+'C|#ex'abc'C|#substrs'abcbc'C;C::='\n'|'c' C;";
+        let vocab = read_rwkv_world_vocab("tests/rwkv_vocab_v20230424.json").unwrap();
+        let mut engine = kbnf::engine::Engine::new(input, vocab.clone()).unwrap();
+        let compiled = engine.to_compiled().unwrap();
+        assert_eq!(
+            compiled,
+            kbnf::engine::Engine::new(input, vocab.clone())
+                .unwrap()
+                .to_compiled()
+                .unwrap()
+        );
+        let config = kbnf::config::Config::default();
+        let mut loaded =
+            kbnf::engine::Engine::from_compiled(&compiled, vocab.clone(), config.clone()).unwrap();
+        let mut unaligned = vec![0];
+        unaligned.extend_from_slice(&compiled);
+        let mut loaded_unaligned =
+            kbnf::engine::Engine::from_compiled(&unaligned[1..], vocab.clone(), config.clone())
+                .unwrap();
+        for bytes in [&b"ab"[..], b"cb", b"c"] {
+            engine.compute_allowed_token_ids();
+            loaded.compute_allowed_token_ids();
+            loaded_unaligned.compute_allowed_token_ids();
+            assert_eq!(
+                engine.allowed_token_ids_from_last_computation(),
+                loaded.allowed_token_ids_from_last_computation()
+            );
+            assert_eq!(
+                engine.allowed_token_ids_from_last_computation(),
+                loaded_unaligned.allowed_token_ids_from_last_computation()
+            );
+            engine.try_accept_new_bytes(bytes).unwrap();
+            loaded.try_accept_new_bytes(bytes).unwrap();
+            loaded_unaligned.try_accept_new_bytes(bytes).unwrap();
+        }
+        assert!(matches!(
+            kbnf::engine::Engine::from_compiled(
+                &compiled[..compiled.len() - 1],
+                vocab.clone(),
+                config.clone()
+            ),
+            Err(CreateEngineError::CompiledGrammarError(
+                CompiledGrammarError::Corrupted
+            ))
+        ));
+        let other_vocab = vocab_from_tokens(&[b"a"], &[]);
+        assert!(matches!(
+            kbnf::engine::Engine::from_compiled(&compiled, other_vocab, config),
+            Err(CreateEngineError::CompiledGrammarError(
+                CompiledGrammarError::VocabularyMismatch
+            ))
+        ));
+    }
+
+    #[test]
+    fn vocabulary_from_tokenizer_json() {
+        let byte_level_bpe = r#"{
+            "added_tokens": [{"id": 3, "content": "<|endoftext|>", "special": true}],
+            "pre_tokenizer": {"type": "ByteLevel", "add_prefix_space": false},
+            "decoder": {"type": "ByteLevel"},
+            "model": {"type": "BPE", "vocab": {"a": 0, "Ġb": 1, "Ċ": 2}, "merges": []}
+        }"#;
+        let vocab = Vocabulary::from_tokenizer_json_str(byte_level_bpe).unwrap();
+        assert_eq!(vocab.token(1).unwrap().0.as_ref(), b" b");
+        assert_eq!(vocab.token(2).unwrap().0.as_ref(), b"\n");
+        assert_eq!(vocab.token(3).unwrap().0.as_ref(), b"<|endoftext|>");
+        assert_eq!(vocab.token_string(1), Some("Ġb"));
+        let sentencepiece_bpe = r#"{
+            "decoder": {"type": "Sequence", "decoders": [{"type": "Replace"}, {"type": "ByteFallback"}]},
+            "model": {"type": "BPE", "vocab": {"<0x0A>": 0, "▁b": 1}, "byte_fallback": true}
+        }"#;
+        let vocab = Vocabulary::from_tokenizer_json_str(sentencepiece_bpe).unwrap();
+        assert_eq!(vocab.token(0).unwrap().0.as_ref(), b"\n");
+        assert_eq!(vocab.token(1).unwrap().0.as_ref(), b" b");
+        let word_piece = r###"{
+            "model": {"type": "WordPiece", "vocab": {"a": 0, "##b": 1}, "continuing_subword_prefix": "##"}
+        }"###;
+        let vocab = Vocabulary::from_tokenizer_json_str(word_piece).unwrap();
+        assert_eq!(vocab.token(0).unwrap().0.as_ref(), b" a");
+        assert_eq!(vocab.token(1).unwrap().0.as_ref(), b"b");
+        let unigram = r#"{
+            "model": {"type": "Unigram", "vocab": [["<unk>", 0.0], ["▁a", -1.0], ["<0xFF>", -2.0]], "byte_fallback": true}
+        }"#;
+        let vocab = Vocabulary::from_tokenizer_json_str(unigram).unwrap();
+        assert_eq!(vocab.token(1).unwrap().0.as_ref(), b" a");
+        assert_eq!(vocab.token(2).unwrap().0.as_ref(), b"\xFF");
+        assert!(matches!(
+            Vocabulary::from_tokenizer_json_str(r#"{"model": {"type": "WordLevel", "vocab": {}}}"#),
+            Err(kbnf::vocabulary::CreateVocabularyError::UnsupportedModel(_))
+        ));
+        assert!(matches!(
+            Vocabulary::from_tokenizer_json_str("{"),
+            Err(kbnf::vocabulary::CreateVocabularyError::JsonError(_))
+        ));
+        assert!(matches!(
+            Vocabulary::from_tokenizer_json("tests/missing_tokenizer.json"),
+            Err(kbnf::vocabulary::CreateVocabularyError::IoError(_))
+        ));
+    }
+
+    #[test]
+    fn vocabulary_from_tiktoken_and_sentencepiece() {
+        let special_tokens = [("<|endoftext|>".to_string(), 3)].into_iter().collect();
+        let vocab =
+            Vocabulary::from_tiktoken_str("YQ== 0\nIGI= 1\nCg== 2\n", special_tokens).unwrap();
+        assert_eq!(vocab.token(1).unwrap().0.as_ref(), b" b");
+        assert_eq!(vocab.token(2).unwrap().0.as_ref(), b"\n");
+        assert_eq!(vocab.token(3).unwrap().0.as_ref(), b"<|endoftext|>");
+        assert!(matches!(
+            Vocabulary::from_tiktoken_str("YQ==\n", AHashMap::default()),
+            Err(kbnf::vocabulary::CreateVocabularyError::MalformedFile(_))
+        ));
+        fn piece(text: &str, piece_type: u8) -> Vec<u8> {
+            let mut message = vec![0x0A, text.len() as u8];
+            message.extend_from_slice(text.as_bytes());
+            // The score, a fixed32 field, is skipped.
+            message.extend_from_slice(&[0x15, 0, 0, 0, 0, 0x18, piece_type]);
+            let mut field = vec![0x0A, message.len() as u8];
+            field.extend(message);
+            field
+        }
+        let mut model = Vec::new();
+        model.extend(piece("<unk>", 2));
+        model.extend(piece("▁a", 1));
+        model.extend(piece("<0x0A>", 6));
+        // The trainer spec, a length-delimited field, is skipped.
+        model.extend_from_slice(&[0x12, 2, 0x08, 0x01]);
+        let vocab = Vocabulary::from_sentencepiece_bytes(&model).unwrap();
+        assert_eq!(vocab.token(0).unwrap().0.as_ref(), b"<unk>");
+        assert_eq!(vocab.token(1).unwrap().0.as_ref(), b" a");
+        assert_eq!(vocab.token(2).unwrap().0.as_ref(), b"\n");
+        assert_eq!(vocab.token_string(1), Some("▁a"));
+        assert!(matches!(
+            Vocabulary::from_sentencepiece_bytes(&model[..model.len() - 1]),
+            Err(kbnf::vocabulary::CreateVocabularyError::MalformedFile(_))
+        ));
+    }
+
+    #[test]
+    fn special_tokens() {
+        use kbnf::engine::CreateEngineError;
+        use kbnf::engine_like::AcceptTokenDiagnosticError;
+        use kbnf::grammar::CreateGrammarError;
+        let tokens: [&[u8]; 5] = [b"a", b"<", b"|", b"<|end|>", b"<|end|>"];
+        let vocab = vocab_from_tokens(&tokens, &[3]);
+        assert!(vocab.is_special_token(3) && !vocab.is_special_token(4));
+        assert_eq!(vocab.special_token_id(b"<|end|>"), Some(3));
+        assert_eq!(vocab.tokenize_greedily(b"<|end|>"), Some(vec![4]));
+        let input = "start::=#'[a-z<|>]+'#token'<|end|>';";
+        let mut engine = kbnf::engine::Engine::new(input, vocab.clone()).unwrap();
+        engine.compute_allowed_token_ids();
+        assert_eq!(
+            engine
+                .allowed_token_ids_from_last_computation()
+                .ones()
+                .collect::<Vec<_>>(),
+            vec![0, 1, 2, 4]
+        );
+        match engine.try_accept_new_token_with_diagnostic(3) {
+            Err(AcceptTokenDiagnosticError::Rejected(diagnostic)) => {
+                assert_eq!((diagnostic.offset, diagnostic.byte), (0, b'<'));
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+        // The bytes of the special token are matched by the regex rather than the special token reference.
+        assert_eq!(
+            engine.try_accept_new_token(4).unwrap(),
+            AcceptTokenResult::Ongoing
+        );
+        engine.compute_allowed_token_ids();
+        assert_eq!(
+            engine
+                .allowed_token_ids_from_last_computation()
+                .ones()
+                .collect::<Vec<_>>(),
+            vec![0, 1, 2, 3, 4]
+        );
+        assert_eq!(
+            engine.try_accept_new_token(3).unwrap(),
+            AcceptTokenResult::Finished
+        );
+        assert!(matches!(
+            kbnf::engine::Engine::new("start::='a'#token'<|none|>';", vocab.clone()),
+            Err(CreateEngineError::GrammarError(
+                CreateGrammarError::UnknownSpecialToken(_)
+            ))
+        ));
+        // References inside strings and comments are left untouched.
+        let mut engine =
+            kbnf::engine::Engine::new("(*#token'<|end|>'*)start::='#token\\'a';", vocab.clone())
+                .unwrap();
+        assert_eq!(
+            engine.try_accept_new_bytes(b"#token'a").unwrap(),
+            AcceptTokenResult::Finished
+        );
+        // The regular expressions that special token references are replaced with cannot be spelled in the grammar.
+        for input in [
+            "start::='a'#'__kbnf_special_token_3__';",
+            "start::='a'#'\\x5F_kbnf_special_token_3__';",
+        ] {
+            assert!(matches!(
+                kbnf::engine::Engine::new(input, vocab.clone()),
+                Err(CreateEngineError::GrammarError(
+                    CreateGrammarError::ReservedString(_)
+                ))
+            ));
+        }
+    }
+
+    #[test]
+    fn eos_termination() {
+        use kbnf::config::{Config, TerminationMode};
+        use kbnf::engine_like::AcceptTokenError;
+        let tokens: [&[u8]; 4] = [b"a", b"aa", b"b", b"</s>"];
+        let vocab = vocab_from_tokens(&tokens, &[]);
+        let input = "start::='a'{'a'};";
+        // KBNF ends eagerly by default.
+        let mut engine = kbnf::engine::Engine::new(input, vocab.clone()).unwrap();
+        assert_eq!(
+            engine.try_accept_new_token(0).unwrap(),
+            AcceptTokenResult::Finished
+        );
+        assert!(engine.is_finished() && engine.is_accepting());
+        let mut config = Config::default();
+        config.termination_config.mode = TerminationMode::Eos;
+        config.termination_config.eos_token_ids = vec![3, 100];
+        let mut engine = kbnf::engine::Engine::with_config(input, vocab, config).unwrap();
+        let allowed_token_ids = |engine: &mut kbnf::engine::Engine| {
+            engine.compute_allowed_token_ids();
+            engine
+                .allowed_token_ids_from_last_computation()
+                .ones()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(allowed_token_ids(&mut engine), vec![0, 1]);
+        assert!(!engine.is_accepting());
+        assert_eq!(
+            engine.try_accept_new_token(3),
+            Err(AcceptTokenError::Rejected)
+        );
+        assert_eq!(
+            engine.try_accept_new_token(0).unwrap(),
+            AcceptTokenResult::Ongoing
+        );
+        assert!(engine.is_accepting() && !engine.is_finished());
+        assert_eq!(allowed_token_ids(&mut engine), vec![0, 1, 3]);
+        // A rejected token leaves the engine accepting.
+        assert_eq!(
+            engine.try_accept_new_token(2),
+            Err(AcceptTokenError::Rejected)
+        );
+        assert!(engine.is_accepting());
+        assert_eq!(
+            engine.try_accept_new_token(1).unwrap(),
+            AcceptTokenResult::Ongoing
+        );
+        assert_eq!(allowed_token_ids(&mut engine), vec![0, 1, 3]);
+        assert_eq!(
+            engine.try_accept_new_token(3).unwrap(),
+            AcceptTokenResult::Finished
+        );
+        assert!(engine.is_finished());
+        assert_eq!(
+            engine.try_accept_new_token(0),
+            Err(AcceptTokenError::Finished)
+        );
+        assert_eq!(allowed_token_ids(&mut engine), Vec::<usize>::new());
+        engine.reset();
+        assert!(!engine.is_finished() && !engine.is_accepting());
+        assert_eq!(allowed_token_ids(&mut engine), vec![0, 1]);
+    }
+
+    #[test]
+    fn length_budget() {
+        let tokens: [&[u8]; 8] = [b"[", b"]", b"a", b"[[", b"a]", b"1", b"12", b"123"];
+        let vocab = vocab_from_tokens(&tokens, &[]);
+        let allowed_token_ids = |engine: &mut kbnf::engine::Engine, budget: Option<usize>| {
+            match budget {
+                Some(budget) => engine.compute_allowed_token_ids_with_budget(budget),
+                None => engine.compute_allowed_token_ids(),
+            }
+            engine
+                .allowed_token_ids_from_last_computation()
+                .ones()
+                .collect::<Vec<_>>()
+        };
+        let mut engine =
+            kbnf::engine::Engine::new("start::='['start']'|'a';", vocab.clone()).unwrap();
+        assert_eq!(allowed_token_ids(&mut engine, None), vec![0, 2, 3]);
+        assert_eq!(allowed_token_ids(&mut engine, Some(5)), vec![0, 2, 3]);
+        assert_eq!(allowed_token_ids(&mut engine, Some(3)), vec![0, 2]);
+        assert_eq!(allowed_token_ids(&mut engine, Some(1)), vec![2]);
+        assert_eq!(
+            engine.try_accept_new_token(0).unwrap(),
+            AcceptTokenResult::Ongoing
+        );
+        assert_eq!(allowed_token_ids(&mut engine, None), vec![0, 2, 3, 4]);
+        assert_eq!(allowed_token_ids(&mut engine, Some(2)), vec![2, 4]);
+        // The grammar cannot be completed within one byte.
+        assert_eq!(allowed_token_ids(&mut engine, Some(1)), Vec::<usize>::new());
+        let mut engine = kbnf::engine::Engine::new("start::=#'[0-9]{3}';", vocab).unwrap();
+        assert_eq!(allowed_token_ids(&mut engine, Some(3)), vec![5, 6, 7]);
+        assert_eq!(allowed_token_ids(&mut engine, Some(2)), Vec::<usize>::new());
+    }
 }